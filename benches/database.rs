@@ -0,0 +1,130 @@
+//! Database and search benchmarks on every backend, run with
+//! `cargo bench --bench database`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memory::{
+    bench::{self, BenchOptions},
+    heed::{heed::EnvOpenOptions, SynxHeedDatabase},
+    in_memory::SynxInMemory,
+    Db, SearchRequest,
+};
+use synx_domain::thread::CreateThread;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+/// A database of each backend, the heed one in a directory removed on drop.
+fn backends() -> Vec<(&'static str, Arc<dyn Db>, Option<TempDir>)> {
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .map_size(1024 * 1024 * 1024)
+            .max_dbs(SynxHeedDatabase::MAX_DBS)
+            .open(dir.path())
+            .unwrap()
+    };
+
+    vec![
+        ("in-memory", Arc::new(SynxInMemory::new()), None),
+        (
+            "heed",
+            Arc::new(SynxHeedDatabase::new(Arc::new(env), true).unwrap()),
+            Some(dir),
+        ),
+    ]
+}
+
+fn create_message(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("create_message");
+    for (name, db, _dir) in backends() {
+        let thread = runtime
+            .block_on(db.create_thread(CreateThread::default()))
+            .unwrap();
+        let mut seed = 0;
+        group.bench_function(name, |b| {
+            b.to_async(&runtime).iter(|| {
+                seed += 1;
+                let db = db.clone();
+                let message = bench::message(seed);
+                async move { db.create_message(thread.id, message).await.unwrap() }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn get_thread_messages(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("get_thread_messages");
+    for (name, db, _dir) in backends() {
+        for messages in [10, 100, 1000] {
+            let (thread_ids, _) = runtime
+                .block_on(bench::seed(
+                    db.as_ref(),
+                    BenchOptions {
+                        threads: 1,
+                        messages_per_thread: messages,
+                        samples: 0,
+                    },
+                ))
+                .unwrap();
+            group.bench_with_input(
+                BenchmarkId::new(name, messages),
+                &thread_ids[0],
+                |b, &id| {
+                    b.to_async(&runtime)
+                        .iter(|| async { db.get_thread_messages(id, None, None).await.unwrap() })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("search");
+    group.sample_size(20);
+    for (name, db, _dir) in backends() {
+        let synx = runtime
+            .block_on(async { bench::bench_synx(db.clone()) })
+            .unwrap();
+        let mut thread_ids = Vec::new();
+        for threads in [100, 1000, 5000] {
+            let (seeded, _) = runtime
+                .block_on(bench::seed(
+                    db.as_ref(),
+                    BenchOptions {
+                        threads: threads - thread_ids.len(),
+                        messages_per_thread: 1,
+                        samples: 0,
+                    },
+                ))
+                .unwrap();
+            thread_ids.extend(seeded);
+
+            group.bench_with_input(BenchmarkId::new(name, threads), &thread_ids, |b, ids| {
+                b.to_async(&runtime).iter(|| {
+                    synx.search_threads(SearchRequest {
+                        query: bench::text(threads, 4),
+                        thread_ids: ids.clone(),
+                        tenant: None,
+                        reader: None,
+                        diversity: None,
+                        expand: false,
+                        rerank: false,
+                        metric: None,
+                        page: Default::default(),
+                        shape: Default::default(),
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, create_message, get_thread_messages, search);
+criterion_main!(benches);
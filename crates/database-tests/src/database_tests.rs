@@ -0,0 +1,1807 @@
+//! Behaviours every [`Db`] implementation must share. Backends run the whole
+//! suite with [`db_conformance_tests!`].
+
+use std::time::Duration;
+
+use futures::{future::join_all, StreamExt};
+use synx_database::{DatabaseError, Db, WriteBatch};
+use synx_domain::{
+    annotation::{AnnotationKind, CreateAnnotation, CreateSearchFeedback},
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
+    content::ContentKind,
+    embedding::Embedding,
+    export::ExportRecord,
+    message::{CreateMessage, Message, MessageStatus, PatchMessage, UpdateMessage},
+    role::Role,
+    sync::{Operation, OperationFilter, OperationKind, SyncCursor},
+    thread::{
+        CreateThread, PatchThread, PendingSummary, ShadowSummary, SummaryProvenance,
+        SummaryRedactions, SummarySpan, SummaryWatermark, Thread, ThreadFilter, ThreadVector,
+        UpdateThread,
+    },
+    usage::{DailyUsage, NaiveDate, Usage, UsageFilter},
+    Uuid,
+};
+
+/// Gives the suite access to the database under test, while letting the
+/// fixture own whatever the backend needs to stay alive (e.g. a temporary
+/// directory).
+pub trait DbFixture {
+    fn db(&self) -> &dyn Db;
+}
+
+impl<T: Db> DbFixture for T {
+    fn db(&self) -> &dyn Db {
+        self
+    }
+}
+
+/// Expands into one `#[tokio::test]` per conformance case, each one running
+/// against a fresh fixture built by `$fixture`.
+#[macro_export]
+macro_rules! db_conformance_tests {
+    ($fixture:expr) => {
+        $crate::db_conformance_tests!(
+            @cases $fixture,
+            created_thread_can_be_fetched,
+            threads_are_listed,
+            threads_are_fetched_in_the_order_given,
+            thread_update_replaces_title,
+            thread_update_keeps_the_summary_language_left_out,
+            thread_update_clears_the_summary_language_set_to_none,
+            unknown_thread_is_not_found,
+            messages_are_listed_in_chronological_order,
+            messages_are_paginated,
+            pagination_past_the_end_is_empty,
+            message_update_replaces_content,
+            unknown_message_is_not_found,
+            deleted_message_is_not_listed,
+            deleting_thread_deletes_its_messages,
+            threads_are_bulk_deleted_by_filter,
+            bulk_delete_dry_run_keeps_threads,
+            pinned_threads_are_listed_first,
+            unread_messages_are_counted_per_reader,
+            annotations_are_deleted_with_their_message,
+            search_feedback_is_replaced_per_reader,
+            pending_summaries_are_queued_oldest_first,
+            blobs_are_stored_once_per_hash,
+            replies_must_be_in_the_same_thread,
+            patched_message_keeps_its_content,
+            message_patches_expecting_another_status_conflict,
+            message_cannot_be_updated_through_another_thread,
+            message_cannot_be_deleted_through_another_thread,
+            summary_and_embedding_are_stored,
+            summary_of_deleted_thread_is_rejected,
+            summary_is_logged_atomically,
+            concurrent_message_writes_are_all_kept,
+            audit_entries_are_filtered,
+            put_thread_creates_or_replaces_thread,
+            put_message_creates_or_replaces_message,
+            put_message_requires_its_thread,
+            shadow_summary_is_kept_apart_and_deleted_with_its_thread,
+            cleared_summary_takes_its_embedding_and_shadow,
+            summary_redactions_are_deleted_with_their_thread,
+            summary_lock_is_kept_across_summaries,
+            operations_are_numbered_in_order,
+            operations_are_filtered,
+            purges_drop_the_operations_holding_purged_content,
+            deletions_keep_tombstones,
+            sync_cursors_are_kept_per_remote,
+            usage_is_aggregated_per_day_tenant_and_thread,
+            write_batch_applies_every_operation,
+            write_batch_is_all_or_nothing,
+            pruned_messages_are_counted_on_their_thread,
+            thread_vectors_are_replaced_by_id,
+            export_is_a_snapshot,
+            streamed_threads_match_the_listing,
+        );
+    };
+    (@cases $fixture:expr, $($case:ident),* $(,)?) => {
+        $(
+            #[tokio::test]
+            async fn $case() {
+                let fixture = $fixture;
+                $crate::$case($crate::DbFixture::db(&fixture)).await;
+            }
+        )*
+    };
+}
+
+fn text_message(text: &str) -> CreateMessage {
+    CreateMessage {
+        role: Role::User,
+        content: text.to_string().into(),
+        parent_message_id: None,
+        metadata: Default::default(),
+        status: Default::default(),
+        memorize: true,
+    }
+}
+
+/// Creates messages a few milliseconds apart, so their creation times are
+/// strictly increasing.
+async fn create_messages(db: &dyn Db, thread_id: Uuid, count: usize) -> Vec<Message> {
+    let mut messages = Vec::with_capacity(count);
+    for i in 0..count {
+        messages.push(
+            db.create_message(thread_id, text_message(&format!("message {}", i)))
+                .await
+                .unwrap(),
+        );
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    messages
+}
+
+fn ids(messages: &[Message]) -> Vec<Uuid> {
+    messages.iter().map(|message| message.id).collect()
+}
+
+pub async fn created_thread_can_be_fetched(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let fetched = db.get_thread(thread.id).await.unwrap();
+
+    assert_eq!(fetched.id, thread.id);
+    assert_eq!(fetched.title, None);
+    assert_eq!(fetched.summary, None);
+}
+
+pub async fn threads_are_listed(db: &dyn Db) {
+    let first = db.create_thread(CreateThread::default()).await.unwrap();
+    let second = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let threads = db.list_threads().await.unwrap();
+
+    assert_eq!(threads.len(), 2);
+    assert!(threads.iter().any(|thread| thread.id == first.id));
+    assert!(threads.iter().any(|thread| thread.id == second.id));
+}
+
+pub async fn threads_are_fetched_in_the_order_given(db: &dyn Db) {
+    let first = db.create_thread(CreateThread::default()).await.unwrap();
+    let second = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let threads = db
+        .get_threads(&[second.id, Uuid::new_v4(), first.id])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        threads.iter().map(|thread| thread.id).collect::<Vec<_>>(),
+        vec![second.id, first.id]
+    );
+    assert!(db.get_threads(&[]).await.unwrap().is_empty());
+}
+
+pub async fn thread_update_replaces_title(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let updated = db
+        .update_thread(
+            thread.id,
+            UpdateThread {
+                title: Some("Weather".to_string()),
+                summary_language: None,
+                tags: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(updated.title.as_deref(), Some("Weather"));
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().title.as_deref(),
+        Some("Weather")
+    );
+}
+
+pub async fn thread_update_keeps_the_summary_language_left_out(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    db.update_thread(
+        thread.id,
+        UpdateThread {
+            title: None,
+            summary_language: Some(Some("French".to_string())),
+            tags: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let updated = db
+        .update_thread(
+            thread.id,
+            UpdateThread {
+                title: Some("Weather".to_string()),
+                summary_language: None,
+                tags: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(updated.summary_language.as_deref(), Some("French"));
+    assert_eq!(
+        db.get_thread(thread.id)
+            .await
+            .unwrap()
+            .summary_language
+            .as_deref(),
+        Some("French")
+    );
+}
+
+pub async fn thread_update_clears_the_summary_language_set_to_none(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let update = |summary_language| UpdateThread {
+        title: None,
+        summary_language: Some(summary_language),
+        tags: None,
+    };
+    db.update_thread(thread.id, update(Some("French".to_string())))
+        .await
+        .unwrap();
+
+    let updated = db.update_thread(thread.id, update(None)).await.unwrap();
+
+    assert_eq!(updated.summary_language, None);
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().summary_language,
+        None
+    );
+}
+
+pub async fn unknown_thread_is_not_found(db: &dyn Db) {
+    let id = Uuid::new_v4();
+
+    assert!(matches!(
+        db.get_thread(id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.update_thread(
+            id,
+            UpdateThread {
+                title: None,
+                summary_language: None,
+                tags: None,
+            }
+        )
+        .await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.delete_thread(id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.get_thread_messages(id, None, None).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.create_message(id, text_message("hello")).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn messages_are_listed_in_chronological_order(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 5).await;
+
+    let listed = db.get_thread_messages(thread.id, None, None).await.unwrap();
+
+    assert_eq!(ids(&listed.messages), ids(&messages));
+    assert_eq!(listed.total, 5);
+}
+
+pub async fn messages_are_paginated(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 5).await;
+
+    let page = db
+        .get_thread_messages(thread.id, Some(2), Some(1))
+        .await
+        .unwrap();
+
+    assert_eq!(ids(&page.messages), ids(&messages[1..3]));
+    assert_eq!(page.total, 5);
+    assert_eq!(page.offset, 1);
+    assert_eq!(page.limit, 2);
+}
+
+pub async fn pagination_past_the_end_is_empty(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    create_messages(db, thread.id, 3).await;
+
+    let page = db
+        .get_thread_messages(thread.id, Some(10), Some(5))
+        .await
+        .unwrap();
+
+    assert!(page.messages.is_empty());
+    assert_eq!(page.total, 3);
+}
+
+pub async fn message_update_replaces_content(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let message = db
+        .create_message(thread.id, text_message("original"))
+        .await
+        .unwrap();
+
+    let updated = db
+        .update_message(
+            thread.id,
+            message.id,
+            UpdateMessage {
+                content: "edited".to_string().into(),
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(updated.content.to_string(), "edited");
+    let listed = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(listed.messages[0].content.to_string(), "edited");
+}
+
+pub async fn unknown_message_is_not_found(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let id = Uuid::new_v4();
+
+    assert!(matches!(
+        db.update_message(
+            thread.id,
+            id,
+            UpdateMessage {
+                content: "edited".to_string().into(),
+            },
+        )
+        .await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.delete_message(thread.id, id).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn deleted_message_is_not_listed(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 3).await;
+
+    db.delete_message(thread.id, messages[1].id).await.unwrap();
+
+    let listed = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(ids(&listed.messages), vec![messages[0].id, messages[2].id]);
+    assert_eq!(listed.total, 2);
+}
+
+pub async fn deleting_thread_deletes_its_messages(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let deleted = create_messages(db, thread.id, 3).await;
+    let kept = create_messages(db, other.id, 2).await;
+
+    db.delete_thread(thread.id).await.unwrap();
+
+    assert!(matches!(
+        db.get_thread(thread.id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.get_thread_messages(thread.id, None, None).await,
+        Err(DatabaseError::NotFound)
+    ));
+    for message in deleted {
+        assert!(matches!(
+            db.update_message(
+                thread.id,
+                message.id,
+                UpdateMessage {
+                    content: "edited".to_string().into(),
+                },
+            )
+            .await,
+            Err(DatabaseError::NotFound)
+        ));
+        assert!(matches!(
+            db.delete_message(thread.id, message.id).await,
+            Err(DatabaseError::NotFound)
+        ));
+    }
+    let listed = db.get_thread_messages(other.id, None, None).await.unwrap();
+    assert_eq!(ids(&listed.messages), ids(&kept));
+}
+
+pub async fn summary_and_embedding_are_stored(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let provenance = SummaryProvenance {
+        model: Some("claude-3-haiku-20240307".to_string()),
+        prompt_version: Some("0123456789ab".to_string()),
+        input_tokens: 120,
+        output_tokens: 30,
+        manual: false,
+        watermark: Some(SummaryWatermark {
+            message_id: Uuid::new_v4(),
+            final_at: 1_700_000_000_000,
+            pending: vec![Uuid::new_v4()],
+        }),
+    };
+
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "I asked about the weather".to_string(),
+        provenance.clone(),
+        Embedding::from(vec![0.6, 0.8]),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let summarized = db.get_thread(thread.id).await.unwrap();
+    assert_eq!(
+        summarized.summary.as_deref(),
+        Some("I asked about the weather")
+    );
+    assert_eq!(summarized.summary_provenance, Some(provenance));
+    let threads = db.get_threads_with_embeddings(&[thread.id]).await.unwrap();
+    assert_eq!(threads.len(), 1);
+    assert_eq!(
+        threads[0].embedding.as_ref().unwrap().to_vec(),
+        vec![0.6, 0.8]
+    );
+    assert!(matches!(
+        db.update_thread_summary_and_embedding(
+            Uuid::new_v4(),
+            "summary".to_string(),
+            Default::default(),
+            Embedding::from(vec![1.0, 0.0]),
+            None,
+        )
+        .await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+/// A summary finishing after its thread was deleted must not bring the
+/// thread back.
+pub async fn summary_of_deleted_thread_is_rejected(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    db.delete_thread(thread.id).await.unwrap();
+
+    let result = db
+        .update_thread_summary_and_embedding(
+            thread.id,
+            "I asked about the weather".to_string(),
+            Default::default(),
+            Embedding::from(vec![1.0, 0.0]),
+            None,
+        )
+        .await;
+
+    assert!(matches!(result, Err(DatabaseError::NotFound)));
+    assert!(matches!(
+        db.get_thread(thread.id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(db
+        .get_threads_with_embeddings(&[thread.id])
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+pub async fn summary_is_logged_atomically(db: &dyn Db) {
+    let origin = Uuid::new_v4();
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "I asked about the weather".to_string(),
+        Default::default(),
+        Embedding::from(vec![0.6, 0.8]),
+        Some(origin),
+    )
+    .await
+    .unwrap();
+    let missing = db
+        .update_thread_summary_and_embedding(
+            Uuid::new_v4(),
+            "summary".to_string(),
+            Default::default(),
+            Embedding::from(vec![1.0, 0.0]),
+            Some(origin),
+        )
+        .await;
+
+    assert!(matches!(missing, Err(DatabaseError::NotFound)));
+    let summarized = db.get_thread(thread.id).await.unwrap();
+    let operations = db
+        .list_operations(OperationFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(operations.len(), 1);
+    assert_eq!(operations[0].origin, origin);
+    assert_eq!(operations[0].timestamp, summarized.summarized_at);
+    match &operations[0].kind {
+        OperationKind::SetSummary {
+            thread_id, summary, ..
+        } => {
+            assert_eq!(*thread_id, thread.id);
+            assert_eq!(summary, "I asked about the weather");
+        }
+        kind => panic!("unexpected operation {:?}", kind),
+    }
+}
+
+pub async fn concurrent_message_writes_are_all_kept(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let results = join_all(
+        (0..20).map(|i| db.create_message(thread.id, text_message(&format!("message {}", i)))),
+    )
+    .await;
+
+    assert!(results.iter().all(Result::is_ok));
+    let listed = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(listed.total, 20);
+}
+
+pub async fn audit_entries_are_filtered(db: &dyn Db) {
+    let entity_id = Uuid::new_v4();
+    for (timestamp, entity_ids) in [
+        (1_000, vec![entity_id]),
+        (2_000, vec![]),
+        (3_000, vec![entity_id]),
+    ] {
+        db.append_audit_entry(AuditEntry {
+            id: Uuid::new_v4(),
+            timestamp,
+            actor: "key:test".to_string(),
+            tenant: None,
+            method: "POST".to_string(),
+            route: "/threads".to_string(),
+            entity_ids,
+            status: 201,
+            request_hash: String::new(),
+        })
+        .await
+        .unwrap();
+    }
+
+    let all = db.list_audit_entries(AuditFilter::default()).await.unwrap();
+    let by_entity = db
+        .list_audit_entries(AuditFilter {
+            entity_id: Some(entity_id),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let by_time = db
+        .list_audit_entries(AuditFilter {
+            from: Some(1_500),
+            to: Some(3_000),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(all.len(), 3);
+    assert_eq!(
+        by_entity
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect::<Vec<_>>(),
+        vec![1_000, 3_000]
+    );
+    assert_eq!(
+        by_time
+            .iter()
+            .map(|entry| entry.timestamp)
+            .collect::<Vec<_>>(),
+        vec![2_000, 3_000]
+    );
+}
+
+async fn tagged_thread(db: &dyn Db, tag: &str) -> Thread {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    db.update_thread(
+        thread.id,
+        UpdateThread {
+            title: None,
+            summary_language: None,
+            tags: Some(vec![tag.to_string()]),
+        },
+    )
+    .await
+    .unwrap()
+}
+
+pub async fn threads_are_bulk_deleted_by_filter(db: &dyn Db) {
+    let stale = tagged_thread(db, "scratch").await;
+    let kept = tagged_thread(db, "keep").await;
+    create_messages(db, stale.id, 2).await;
+    let cutoff = stale.updated_at.max(kept.updated_at) + 1;
+    let recent = tagged_thread(db, "scratch").await;
+    // A thread changed at the cutoff itself isn't older than it.
+    assert!(recent.updated_at >= cutoff);
+
+    let deleted = db
+        .delete_threads(
+            ThreadFilter {
+                tag: Some("scratch".to_string()),
+                older_than: Some(cutoff),
+                ..Default::default()
+            },
+            false,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(deleted, vec![stale.id]);
+    assert!(matches!(
+        db.get_thread(stale.id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.get_thread_messages(stale.id, None, None).await,
+        Err(DatabaseError::NotFound)
+    ));
+    db.get_thread(kept.id).await.unwrap();
+    db.get_thread(recent.id).await.unwrap();
+}
+
+pub async fn bulk_delete_dry_run_keeps_threads(db: &dyn Db) {
+    let first = db.create_thread(CreateThread::default()).await.unwrap();
+    let second = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let mut matched = db
+        .delete_threads(
+            ThreadFilter {
+                ids: Some(vec![first.id, second.id, Uuid::new_v4()]),
+                ..Default::default()
+            },
+            true,
+        )
+        .await
+        .unwrap();
+    matched.sort();
+    let mut expected = vec![first.id, second.id];
+    expected.sort();
+
+    assert_eq!(matched, expected);
+    assert_eq!(db.list_threads().await.unwrap().len(), 2);
+}
+
+pub async fn pinned_threads_are_listed_first(db: &dyn Db) {
+    let mut threads = Vec::new();
+    for _ in 0..4 {
+        threads.push(db.create_thread(CreateThread::default()).await.unwrap());
+    }
+    let pin = |pinned| PatchThread {
+        pinned: Some(pinned),
+        ..Default::default()
+    };
+    db.patch_thread(threads[1].id, pin(true)).await.unwrap();
+    db.patch_thread(threads[3].id, pin(true)).await.unwrap();
+    let unpinned = db.patch_thread(threads[3].id, pin(false)).await.unwrap();
+    assert!(!unpinned.pinned);
+    let pinned = db.patch_thread(threads[2].id, pin(true)).await.unwrap();
+    assert!(pinned.pinned);
+    // Leaving a field out keeps it.
+    let kept = db
+        .patch_thread(threads[2].id, PatchThread::default())
+        .await
+        .unwrap();
+    assert!(kept.pinned);
+
+    let listed = db.list_threads().await.unwrap();
+    let mut leading = listed[..2].iter().map(|t| t.id).collect::<Vec<_>>();
+    leading.sort();
+    let mut expected = vec![threads[1].id, threads[2].id];
+    expected.sort();
+    assert_eq!(leading, expected);
+    assert!(listed[2..].iter().all(|thread| !thread.pinned));
+
+    db.delete_thread(threads[1].id).await.unwrap();
+    let listed = db.list_threads().await.unwrap();
+    assert_eq!(listed.len(), 3);
+    assert_eq!(listed[0].id, threads[2].id);
+    assert!(matches!(
+        db.patch_thread(threads[1].id, pin(true)).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn unread_messages_are_counted_per_reader(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 4).await;
+    let other_messages = create_messages(db, other.id, 1).await;
+
+    let marker = db
+        .mark_read(thread.id, "alice".to_string(), messages[1].id)
+        .await
+        .unwrap();
+    assert_eq!(marker.last_read_message_id, messages[1].id);
+    // A message from another thread can't mark this one read.
+    assert!(matches!(
+        db.mark_read(thread.id, "alice".to_string(), other_messages[0].id)
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    let alice = db.unread_counts("alice".to_string()).await.unwrap();
+    assert_eq!(alice[&thread.id], 2);
+    assert_eq!(alice[&other.id], 1);
+    let bob = db.unread_counts("bob".to_string()).await.unwrap();
+    assert_eq!(bob[&thread.id], 4);
+
+    // The marker keeps its position once the read message is deleted.
+    db.delete_message(thread.id, messages[1].id).await.unwrap();
+    let alice = db.unread_counts("alice".to_string()).await.unwrap();
+    assert_eq!(alice[&thread.id], 2);
+
+    db.delete_thread(thread.id).await.unwrap();
+    let alice = db.unread_counts("alice".to_string()).await.unwrap();
+    assert!(!alice.contains_key(&thread.id));
+}
+
+pub async fn annotations_are_deleted_with_their_message(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 2).await;
+    let annotate = |message: &Message, kind| {
+        CreateAnnotation { kind }.into_annotation(thread.id, message.id, "alice".to_string())
+    };
+    let useful = annotate(&messages[0], AnnotationKind::ThumbsUp);
+    let note = annotate(
+        &messages[1],
+        AnnotationKind::Note {
+            text: "check this".to_string(),
+        },
+    );
+    db.add_annotation(useful.clone()).await.unwrap();
+    db.add_annotation(note.clone()).await.unwrap();
+    assert!(matches!(
+        db.add_annotation(
+            CreateAnnotation {
+                kind: AnnotationKind::ThumbsDown
+            }
+            .into_annotation(thread.id, Uuid::new_v4(), "alice".to_string())
+        )
+        .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    let annotations = db.list_annotations(thread.id).await.unwrap();
+    assert_eq!(annotations.len(), 2);
+
+    db.delete_message(thread.id, messages[1].id).await.unwrap();
+    let annotations = db.list_annotations(thread.id).await.unwrap();
+    assert_eq!(annotations.len(), 1);
+    assert_eq!(annotations[0].kind, AnnotationKind::ThumbsUp);
+
+    db.delete_annotation(thread.id, messages[0].id, useful.id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.delete_annotation(thread.id, messages[0].id, useful.id)
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    db.add_annotation(useful).await.unwrap();
+    db.delete_thread(thread.id).await.unwrap();
+    assert!(db.list_annotations(thread.id).await.unwrap().is_empty());
+}
+
+pub async fn search_feedback_is_replaced_per_reader(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let feedback = |thread_id, relevant, reader: &str| {
+        CreateSearchFeedback {
+            thread_id,
+            relevant,
+            query: None,
+        }
+        .into_feedback(reader.to_string())
+    };
+
+    db.put_search_feedback(feedback(thread.id, true, "alice"))
+        .await
+        .unwrap();
+    db.put_search_feedback(feedback(thread.id, false, "alice"))
+        .await
+        .unwrap();
+    db.put_search_feedback(feedback(other.id, true, "alice"))
+        .await
+        .unwrap();
+    db.put_search_feedback(feedback(thread.id, true, "bob"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.put_search_feedback(feedback(Uuid::new_v4(), true, "alice"))
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    let alice = db.list_search_feedback("alice".to_string()).await.unwrap();
+    assert_eq!(alice.len(), 2);
+    let on_thread = alice.iter().find(|f| f.thread_id == thread.id).unwrap();
+    assert!(!on_thread.relevant);
+
+    db.delete_thread(thread.id).await.unwrap();
+    let alice = db.list_search_feedback("alice".to_string()).await.unwrap();
+    assert_eq!(alice.len(), 1);
+    assert!(db
+        .list_search_feedback("bob".to_string())
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+pub async fn pending_summaries_are_queued_oldest_first(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let pending = |thread_id, message_id, queued_at, attempts| PendingSummary {
+        thread_id,
+        message_id,
+        queued_at,
+        attempts,
+    };
+    let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    db.put_pending_summary(pending(thread.id, first, 20, 1))
+        .await
+        .unwrap();
+    db.put_pending_summary(pending(other.id, second, 10, 1))
+        .await
+        .unwrap();
+    db.put_pending_summary(pending(thread.id, third, 30, 1))
+        .await
+        .unwrap();
+    db.put_pending_summary(pending(thread.id, first, 20, 2))
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.put_pending_summary(pending(Uuid::new_v4(), first, 0, 1))
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    let queued = db.list_pending_summaries().await.unwrap();
+    assert_eq!(
+        queued
+            .iter()
+            .map(|pending| (pending.message_id, pending.attempts))
+            .collect::<Vec<_>>(),
+        vec![(second, 1), (first, 2), (third, 1)]
+    );
+
+    db.delete_pending_summary(thread.id, third).await.unwrap();
+    assert!(matches!(
+        db.delete_pending_summary(thread.id, third).await,
+        Err(DatabaseError::NotFound)
+    ));
+    db.delete_thread(thread.id).await.unwrap();
+    let queued = db.list_pending_summaries().await.unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].message_id, second);
+}
+
+pub async fn blobs_are_stored_once_per_hash(db: &dyn Db) {
+    let hash = "ab".repeat(32);
+    let first = Blob::new(hash.clone(), Some("image/png".to_string()), 3);
+    let stored = db.put_blob(first.clone(), vec![1, 2, 3]).await.unwrap();
+    assert_eq!(stored, first);
+
+    let again = db
+        .put_blob(Blob::new(hash.clone(), None, 3), vec![1, 2, 3])
+        .await
+        .unwrap();
+    assert_eq!(again, first);
+
+    let (blob, data) = db.get_blob(hash).await.unwrap();
+    assert_eq!(blob, first);
+    assert_eq!(data, vec![1, 2, 3]);
+    assert!(matches!(
+        db.get_blob("cd".repeat(32)).await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    db.delete_blob(hash.clone()).await.unwrap();
+    assert!(matches!(
+        db.get_blob(hash.clone()).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(matches!(
+        db.delete_blob(hash).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn replies_must_be_in_the_same_thread(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let parent = db
+        .create_message(thread.id, text_message("question"))
+        .await
+        .unwrap();
+    let reply = |parent_message_id| CreateMessage {
+        parent_message_id: Some(parent_message_id),
+        ..text_message("answer")
+    };
+
+    let stored = db
+        .create_message(thread.id, reply(parent.id))
+        .await
+        .unwrap();
+    assert_eq!(stored.parent_message_id, Some(parent.id));
+    let fetched = db.get_message(thread.id, stored.id).await.unwrap();
+    assert_eq!(fetched.parent_message_id, Some(parent.id));
+
+    assert!(matches!(
+        db.create_message(other.id, reply(parent.id)).await,
+        Err(DatabaseError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        db.create_message(thread.id, reply(Uuid::new_v4())).await,
+        Err(DatabaseError::InvalidInput(_))
+    ));
+}
+
+pub async fn patched_message_keeps_its_content(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let message = db
+        .create_message(
+            thread.id,
+            CreateMessage {
+                metadata: serde_json::json!({ "model": "a", "draft": true })
+                    .as_object()
+                    .cloned()
+                    .unwrap(),
+                ..text_message("Hello")
+            },
+        )
+        .await
+        .unwrap();
+
+    let patched = db
+        .patch_message(
+            thread.id,
+            message.id,
+            PatchMessage {
+                append: vec![ContentKind::Text {
+                    text: ", world".to_string(),
+                }],
+                metadata: serde_json::json!({ "model": "b", "draft": null })
+                    .as_object()
+                    .cloned(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert!(patched.updated_at > 0);
+
+    let stored = db.get_message(thread.id, message.id).await.unwrap();
+    assert_eq!(stored.content.to_string(), "Hello\n, world");
+    assert_eq!(
+        serde_json::Value::Object(stored.metadata),
+        serde_json::json!({ "model": "b" })
+    );
+    assert!(matches!(
+        db.patch_message(thread.id, Uuid::new_v4(), PatchMessage::default())
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn message_patches_expecting_another_status_conflict(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let message = db
+        .create_message(
+            thread.id,
+            CreateMessage {
+                status: MessageStatus::Streaming,
+                ..text_message("Hello")
+            },
+        )
+        .await
+        .unwrap();
+    let append = |text: &str| PatchMessage {
+        append_text: Some(text.to_string()),
+        expected_status: Some(MessageStatus::Streaming),
+        ..Default::default()
+    };
+
+    db.patch_message(
+        thread.id,
+        message.id,
+        PatchMessage {
+            status: Some(MessageStatus::Final),
+            ..append(", world")
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        db.patch_message(thread.id, message.id, append("!")).await,
+        Err(DatabaseError::Conflict(_))
+    ));
+
+    let stored = db.get_message(thread.id, message.id).await.unwrap();
+    assert_eq!(stored.content.to_string(), "Hello, world");
+    assert_eq!(stored.status, MessageStatus::Final);
+}
+
+pub async fn message_cannot_be_updated_through_another_thread(db: &dyn Db) {
+    let owner = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let message = db
+        .create_message(owner.id, text_message("original"))
+        .await
+        .unwrap();
+
+    let result = db
+        .update_message(
+            other.id,
+            message.id,
+            UpdateMessage {
+                content: "tampered".to_string().into(),
+            },
+        )
+        .await;
+
+    assert!(matches!(result, Err(DatabaseError::NotFound)));
+    let messages = db.get_thread_messages(owner.id, None, None).await.unwrap();
+    assert_eq!(messages.messages[0].content.to_string(), "original");
+}
+
+pub async fn message_cannot_be_deleted_through_another_thread(db: &dyn Db) {
+    let owner = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let message = db
+        .create_message(owner.id, text_message("keep me"))
+        .await
+        .unwrap();
+
+    let result = db.delete_message(other.id, message.id).await;
+
+    assert!(matches!(result, Err(DatabaseError::NotFound)));
+    let messages = db.get_thread_messages(owner.id, None, None).await.unwrap();
+    assert_eq!(messages.total, 1);
+    assert_eq!(messages.messages[0].id, message.id);
+}
+
+pub async fn put_thread_creates_or_replaces_thread(db: &dyn Db) {
+    let mut thread = Thread::new();
+    thread.set_title(Some("remote".to_string()));
+
+    db.put_thread(thread.clone()).await.unwrap();
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().title.as_deref(),
+        Some("remote")
+    );
+    assert_eq!(
+        db.get_thread_messages(thread.id, None, None)
+            .await
+            .unwrap()
+            .total,
+        0
+    );
+
+    thread.set_title(Some("renamed".to_string()));
+    db.put_thread(thread.clone()).await.unwrap();
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().title.as_deref(),
+        Some("renamed")
+    );
+    assert_eq!(db.list_threads().await.unwrap().len(), 1);
+}
+
+pub async fn put_message_creates_or_replaces_message(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let existing = create_messages(db, thread.id, 2).await;
+    let mut message = text_message("remote").into_message(thread.id);
+
+    db.put_message(message.clone()).await.unwrap();
+    message.update_content(UpdateMessage {
+        content: "edited remotely".to_string().into(),
+    });
+    db.put_message(message.clone()).await.unwrap();
+
+    let listed = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(
+        ids(&listed.messages),
+        vec![existing[0].id, existing[1].id, message.id]
+    );
+    assert_eq!(listed.total, 3);
+    assert_eq!(
+        db.get_message(thread.id, message.id)
+            .await
+            .unwrap()
+            .content
+            .to_string(),
+        "edited remotely"
+    );
+}
+
+pub async fn put_message_requires_its_thread(db: &dyn Db) {
+    let message = text_message("orphan").into_message(Uuid::new_v4());
+
+    assert!(matches!(
+        db.put_message(message).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn operations_are_numbered_in_order(db: &dyn Db) {
+    let mut seqs = Vec::new();
+    for _ in 0..3 {
+        seqs.push(
+            db.append_operation(Operation::new(
+                Uuid::new_v4(),
+                0,
+                OperationKind::DeleteThread {
+                    thread_id: Uuid::new_v4(),
+                },
+            ))
+            .await
+            .unwrap(),
+        );
+    }
+
+    let listed = db
+        .list_operations(OperationFilter::default())
+        .await
+        .unwrap();
+
+    assert_eq!(seqs, vec![1, 2, 3]);
+    assert_eq!(
+        listed
+            .iter()
+            .map(|operation| operation.seq)
+            .collect::<Vec<_>>(),
+        seqs
+    );
+}
+
+pub async fn purges_drop_the_operations_holding_purged_content(db: &dyn Db) {
+    let origin = Uuid::new_v4();
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let other = db.create_thread(CreateThread::default()).await.unwrap();
+    let purged = db
+        .create_message(thread.id, text_message("my number is 555-0100"))
+        .await
+        .unwrap();
+    let kept = db
+        .create_message(thread.id, text_message("Hello"))
+        .await
+        .unwrap();
+    let elsewhere = db
+        .create_message(other.id, text_message("my number is 555-0100"))
+        .await
+        .unwrap();
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "I shared my number, 555-0100".to_string(),
+        Default::default(),
+        Embedding::from(vec![0.6, 0.8]),
+        Some(origin),
+    )
+    .await
+    .unwrap();
+    let summarized = db.get_thread(thread.id).await.unwrap();
+    for kind in [
+        OperationKind::PutThread { thread: summarized },
+        OperationKind::PutMessage {
+            message: purged.clone(),
+        },
+        OperationKind::PutMessage {
+            message: kept.clone(),
+        },
+        OperationKind::PutMessage {
+            message: elsewhere.clone(),
+        },
+    ] {
+        db.append_operation(Operation::new(origin, 0, kind))
+            .await
+            .unwrap();
+    }
+
+    let seq = db
+        .append_operation(Operation::new(
+            origin,
+            0,
+            OperationKind::Purge {
+                thread_id: thread.id,
+                message_ids: vec![purged.id],
+            },
+        ))
+        .await
+        .unwrap();
+
+    let operations = db
+        .list_operations(OperationFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(seq, 6);
+    assert_eq!(operations.last().unwrap().seq, seq);
+    assert_eq!(operations.len(), 4);
+    let serialized = serde_json::to_string(&operations).unwrap();
+    assert!(!serialized.contains("I shared my number"));
+    assert_eq!(serialized.matches("555-0100").count(), 1);
+    for operation in &operations {
+        match &operation.kind {
+            OperationKind::PutThread { thread: put } => {
+                assert_eq!(put.id, thread.id);
+                assert_eq!(put.summary, None);
+                assert!(put.embedding.is_none());
+            }
+            OperationKind::PutMessage { message } => {
+                assert!(message.id == kept.id || message.id == elsewhere.id)
+            }
+            OperationKind::Purge { .. } => {}
+            kind => panic!("unexpected operation {:?}", kind),
+        }
+    }
+
+    // Sequence numbers carry on past the purge.
+    let next = db
+        .append_operation(Operation::new(
+            origin,
+            0,
+            OperationKind::DeleteThread {
+                thread_id: other.id,
+            },
+        ))
+        .await
+        .unwrap();
+    assert_eq!(next, seq + 1);
+}
+
+pub async fn deletions_keep_tombstones(db: &dyn Db) {
+    let origin = Uuid::new_v4();
+    let (thread_id, message_id, purged_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    for (timestamp, kind) in [
+        (1, OperationKind::DeleteThread { thread_id }),
+        (
+            2,
+            OperationKind::DeleteMessage {
+                thread_id,
+                message_id,
+            },
+        ),
+        (
+            3,
+            OperationKind::Purge {
+                thread_id,
+                message_ids: vec![purged_id],
+            },
+        ),
+        // The first deletion is kept.
+        (4, OperationKind::DeleteThread { thread_id }),
+    ] {
+        db.append_operation(Operation::new(origin, timestamp, kind))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(db.get_tombstone(thread_id).await.unwrap(), Some(1));
+    assert_eq!(db.get_tombstone(message_id).await.unwrap(), Some(2));
+    assert_eq!(db.get_tombstone(purged_id).await.unwrap(), Some(3));
+    assert_eq!(db.get_tombstone(Uuid::new_v4()).await.unwrap(), None);
+}
+
+pub async fn sync_cursors_are_kept_per_remote(db: &dyn Db) {
+    let remote = "http://primary:3000".to_string();
+    assert_eq!(db.get_sync_cursor(remote.clone()).await.unwrap(), None);
+
+    for pulled_seq in [3, 7] {
+        db.put_sync_cursor(
+            remote.clone(),
+            SyncCursor {
+                pushed_seq: 2,
+                pulled_seq,
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(
+        db.get_sync_cursor(remote).await.unwrap(),
+        Some(SyncCursor {
+            pushed_seq: 2,
+            pulled_seq: 7,
+        })
+    );
+    assert_eq!(
+        db.get_sync_cursor("http://other:3000".to_string())
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+pub async fn operations_are_filtered(db: &dyn Db) {
+    let local = Uuid::new_v4();
+    let remote = Uuid::new_v4();
+    for origin in [local, remote, local] {
+        db.append_operation(Operation::new(
+            origin,
+            0,
+            OperationKind::DeleteThread {
+                thread_id: Uuid::new_v4(),
+            },
+        ))
+        .await
+        .unwrap();
+    }
+
+    let seqs = |operations: Vec<Operation>| {
+        operations
+            .iter()
+            .map(|operation| operation.seq)
+            .collect::<Vec<_>>()
+    };
+    let since = db
+        .list_operations(OperationFilter {
+            since_seq: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let from_local = db
+        .list_operations(OperationFilter {
+            origin: Some(local),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let not_local = db
+        .list_operations(OperationFilter {
+            exclude_origin: Some(local),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let limited = db
+        .list_operations(OperationFilter {
+            limit: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(seqs(since), vec![2, 3]);
+    assert_eq!(seqs(from_local), vec![1, 3]);
+    assert_eq!(seqs(not_local), vec![2]);
+    assert_eq!(seqs(limited), vec![1, 2]);
+}
+
+pub async fn shadow_summary_is_kept_apart_and_deleted_with_its_thread(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let shadow = ShadowSummary {
+        thread_id: thread.id,
+        summary: "I asked about the weather, in a new style".to_string(),
+        embedding: Embedding::from(vec![0.6, 0.8]),
+        updated_at: 1_000,
+    };
+
+    db.put_shadow_summary(shadow).await.unwrap();
+
+    let stored = db.get_shadow_summary(thread.id).await.unwrap().unwrap();
+    assert_eq!(stored.summary, "I asked about the weather, in a new style");
+    assert!(db.get_thread(thread.id).await.unwrap().summary.is_none());
+
+    db.delete_thread(thread.id).await.unwrap();
+    assert!(db.get_shadow_summary(thread.id).await.unwrap().is_none());
+    assert!(matches!(
+        db.put_shadow_summary(ShadowSummary {
+            thread_id: thread.id,
+            ..stored
+        })
+        .await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn cleared_summary_takes_its_embedding_and_shadow(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "I asked about the weather".to_string(),
+        Default::default(),
+        Embedding::from(vec![0.6, 0.8]),
+        None,
+    )
+    .await
+    .unwrap();
+    db.put_shadow_summary(ShadowSummary {
+        thread_id: thread.id,
+        summary: "I asked about the weather, in a new style".to_string(),
+        embedding: Embedding::from(vec![0.6, 0.8]),
+        updated_at: 1_000,
+    })
+    .await
+    .unwrap();
+
+    db.clear_thread_summary(thread.id).await.unwrap();
+
+    assert!(db.get_thread(thread.id).await.unwrap().summary.is_none());
+    let threads = db.get_threads_with_embeddings(&[thread.id]).await.unwrap();
+    assert!(threads.iter().all(|thread| thread.embedding.is_none()));
+    assert!(db.get_shadow_summary(thread.id).await.unwrap().is_none());
+    assert!(matches!(
+        db.clear_thread_summary(Uuid::new_v4()).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn summary_redactions_are_deleted_with_their_thread(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let redactions = SummaryRedactions {
+        thread_id: thread.id,
+        spans: vec![SummarySpan { start: 2, end: 7 }],
+        summarized_at: thread.summarized_at,
+        updated_at: 1_000,
+    };
+
+    db.put_summary_redactions(redactions.clone()).await.unwrap();
+
+    let stored = db.get_summary_redactions(thread.id).await.unwrap().unwrap();
+    assert_eq!(stored.spans, redactions.spans);
+
+    db.delete_thread(thread.id).await.unwrap();
+    assert!(db
+        .get_summary_redactions(thread.id)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        db.put_summary_redactions(redactions).await,
+        Err(DatabaseError::NotFound)
+    ));
+}
+
+pub async fn summary_lock_is_kept_across_summaries(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let lock = |locked| PatchThread {
+        summary_locked: Some(locked),
+        ..Default::default()
+    };
+
+    let locked = db.patch_thread(thread.id, lock(true)).await.unwrap();
+    assert!(locked.summary_locked);
+    assert!(!locked.pinned);
+
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "Curated by hand".to_string(),
+        Default::default(),
+        Embedding::from(vec![0.6, 0.8]),
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(db.get_thread(thread.id).await.unwrap().summary_locked);
+
+    let unlocked = db.patch_thread(thread.id, lock(false)).await.unwrap();
+    assert!(!unlocked.summary_locked);
+    assert_eq!(unlocked.summary.as_deref(), Some("Curated by hand"));
+}
+
+pub async fn usage_is_aggregated_per_day_tenant_and_thread(db: &dyn Db) {
+    let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+    let next_day = day.succ_opt().unwrap();
+    let thread_id = Uuid::new_v4();
+    let usage = |day, tenant: Option<&str>, thread_id, tokens| DailyUsage {
+        day,
+        tenant: tenant.map(str::to_string),
+        thread_id,
+        usage: Usage {
+            completion_requests: 1,
+            completion_input_tokens: tokens,
+            ..Default::default()
+        },
+    };
+    for entry in [
+        usage(day, Some("acme"), Some(thread_id), 10),
+        usage(day, Some("acme"), Some(thread_id), 5),
+        usage(day, Some("acme"), None, 7),
+        usage(day, None, Some(thread_id), 3),
+        usage(next_day, Some("acme"), Some(thread_id), 1),
+    ] {
+        db.add_usage(entry).await.unwrap();
+    }
+
+    let all = db.list_usage(UsageFilter::default()).await.unwrap();
+    let acme_first_day = db
+        .list_usage(UsageFilter {
+            to: Some(day),
+            tenant: Some("acme".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let thread_next_day = db
+        .list_usage(UsageFilter {
+            from: Some(next_day),
+            thread_id: Some(thread_id),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(all.len(), 4);
+    assert_eq!(all.last().unwrap().day, next_day);
+    assert_eq!(acme_first_day.len(), 2);
+    let aggregate = acme_first_day
+        .iter()
+        .find(|entry| entry.thread_id == Some(thread_id))
+        .unwrap();
+    assert_eq!(aggregate.usage.completion_requests, 2);
+    assert_eq!(aggregate.usage.completion_input_tokens, 15);
+    assert_eq!(thread_next_day.len(), 1);
+    assert_eq!(thread_next_day[0].usage.tokens(), 1);
+}
+
+pub async fn write_batch_applies_every_operation(db: &dyn Db) {
+    let existing = db.create_thread(CreateThread::default()).await.unwrap();
+    let doomed = db
+        .create_message(existing.id, text_message("Forget me"))
+        .await
+        .unwrap();
+    db.update_thread_summary_and_embedding(
+        existing.id,
+        "Someone asked to be forgotten".to_string(),
+        Default::default(),
+        Embedding::from(vec![1.0, 0.0]),
+        None,
+    )
+    .await
+    .unwrap();
+
+    let thread = CreateThread::default().into_thread();
+    let question = text_message("Is it raining?").into_message(thread.id);
+    let answer = CreateMessage {
+        parent_message_id: Some(question.id),
+        ..text_message("It is")
+    }
+    .into_message(thread.id);
+    let mut batch = WriteBatch::new();
+    batch
+        .create_thread(thread.clone())
+        .create_message(question.clone())
+        .create_message(answer.clone())
+        .delete_message(existing.id, doomed.id)
+        .clear_summary(existing.id);
+    db.write_batch(batch).await.unwrap();
+
+    let messages = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(messages.messages.len(), 2);
+    assert!(db.get_message(thread.id, answer.id).await.is_ok());
+    assert!(matches!(
+        db.get_message(existing.id, doomed.id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert_eq!(db.get_thread(existing.id).await.unwrap().summary, None);
+}
+
+pub async fn write_batch_is_all_or_nothing(db: &dyn Db) {
+    let existing = db.create_thread(CreateThread::default()).await.unwrap();
+    let kept = db
+        .create_message(existing.id, text_message("Keep me"))
+        .await
+        .unwrap();
+
+    let thread = CreateThread::default().into_thread();
+    let mut batch = WriteBatch::new();
+    batch
+        .create_thread(thread.clone())
+        .create_message(text_message("Hello").into_message(thread.id))
+        .delete_message(existing.id, kept.id)
+        .delete_message(existing.id, Uuid::new_v4());
+    let result = db.write_batch(batch).await;
+
+    assert!(matches!(result, Err(DatabaseError::NotFound)));
+    assert!(matches!(
+        db.get_thread(thread.id).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert!(db.get_message(existing.id, kept.id).await.is_ok());
+    assert_eq!(
+        db.get_thread_messages(existing.id, None, None)
+            .await
+            .unwrap()
+            .messages
+            .len(),
+        1
+    );
+}
+
+pub async fn pruned_messages_are_counted_on_their_thread(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 3).await;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .prune_message(thread.id, messages[0].id)
+        .prune_message(thread.id, messages[1].id);
+    db.write_batch(batch).await.unwrap();
+
+    let remaining = db.get_thread_messages(thread.id, None, None).await.unwrap();
+    assert_eq!(remaining.messages.len(), 1);
+    assert_eq!(remaining.messages[0].id, messages[2].id);
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().pruned_message_count,
+        2
+    );
+
+    let mut batch = WriteBatch::new();
+    batch
+        .prune_message(thread.id, messages[2].id)
+        .prune_message(thread.id, messages[0].id);
+    assert!(matches!(
+        db.write_batch(batch).await,
+        Err(DatabaseError::NotFound)
+    ));
+    assert_eq!(
+        db.get_thread(thread.id).await.unwrap().pruned_message_count,
+        2
+    );
+}
+
+pub async fn thread_vectors_are_replaced_by_id(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "We talked about the weather, then about dinner".to_string(),
+        Default::default(),
+        Embedding::from(vec![0.6, 0.8]),
+        None,
+    )
+    .await
+    .unwrap();
+    let weather = Uuid::new_v4();
+    let dinner = Uuid::new_v4();
+
+    let mut batch = WriteBatch::new();
+    batch
+        .put_thread_vector(
+            thread.id,
+            ThreadVector {
+                id: weather,
+                embedding: Embedding::from(vec![1.0, 0.0]),
+            },
+        )
+        .put_thread_vector(
+            thread.id,
+            ThreadVector {
+                id: dinner,
+                embedding: Embedding::from(vec![0.0, 1.0]),
+            },
+        )
+        .put_thread_vector(
+            thread.id,
+            ThreadVector {
+                id: weather,
+                embedding: Embedding::from(vec![0.8, 0.6]),
+            },
+        );
+    db.write_batch(batch).await.unwrap();
+
+    let threads = db.get_threads_with_embeddings(&[thread.id]).await.unwrap();
+    let mut vectors = threads[0]
+        .vectors
+        .iter()
+        .map(|vector| (vector.id, vector.embedding.to_vec()))
+        .collect::<Vec<_>>();
+    vectors.sort_by_key(|(id, _)| *id);
+    let mut expected = vec![(weather, vec![0.8, 0.6]), (dinner, vec![0.0, 1.0])];
+    expected.sort_by_key(|(id, _)| *id);
+    assert_eq!(vectors, expected);
+
+    let mut batch = WriteBatch::new();
+    batch.put_thread_vector(
+        Uuid::new_v4(),
+        ThreadVector {
+            id: weather,
+            embedding: Embedding::from(vec![1.0, 0.0]),
+        },
+    );
+    assert!(matches!(
+        db.write_batch(batch).await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    db.clear_thread_summary(thread.id).await.unwrap();
+    let threads = db.get_threads_with_embeddings(&[thread.id]).await.unwrap();
+    assert!(threads.iter().all(|thread| thread.vectors.is_empty()));
+}
+
+pub async fn export_is_a_snapshot(db: &dyn Db) {
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let messages = create_messages(db, thread.id, 2).await;
+    let empty = db.create_thread(CreateThread::default()).await.unwrap();
+
+    let export = db.export().await.unwrap();
+    db.create_message(thread.id, text_message("Too late"))
+        .await
+        .unwrap();
+    db.delete_thread(empty.id).await.unwrap();
+    db.create_thread(CreateThread::default()).await.unwrap();
+    let records = export
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    let mut thread_ids = records
+        .iter()
+        .filter_map(|record| match record {
+            ExportRecord::Thread(thread) => Some(thread.id),
+            ExportRecord::Message(_) => None,
+        })
+        .collect::<Vec<_>>();
+    thread_ids.sort();
+    let mut expected = vec![thread.id, empty.id];
+    expected.sort();
+    assert_eq!(thread_ids, expected);
+
+    let position = records
+        .iter()
+        .position(
+            |record| matches!(record, ExportRecord::Thread(exported) if exported.id == thread.id),
+        )
+        .unwrap();
+    let exported_messages = records[position + 1..]
+        .iter()
+        .take(2)
+        .map(|record| match record {
+            ExportRecord::Message(message) => message.id,
+            ExportRecord::Thread(_) => panic!("expected the thread's messages"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(exported_messages, ids(&messages));
+    assert_eq!(records.len(), 4);
+}
+
+pub async fn streamed_threads_match_the_listing(db: &dyn Db) {
+    let mut threads = Vec::new();
+    for _ in 0..3 {
+        threads.push(db.create_thread(CreateThread::default()).await.unwrap());
+    }
+    db.patch_thread(
+        threads[2].id,
+        PatchThread {
+            pinned: Some(true),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let streamed = db
+        .stream_threads()
+        .await
+        .unwrap()
+        .map(|thread| thread.unwrap().id)
+        .collect::<Vec<_>>()
+        .await;
+
+    let listed = db.list_threads().await.unwrap();
+    assert_eq!(streamed[0], threads[2].id);
+    let mut streamed = streamed;
+    streamed.sort();
+    let mut listed = listed.iter().map(|thread| thread.id).collect::<Vec<_>>();
+    listed.sort();
+    assert_eq!(streamed, listed);
+    assert_eq!(streamed.len(), 3);
+}
@@ -0,0 +1,120 @@
+use synx_domain::{
+    message::Message,
+    thread::{Thread, ThreadVector},
+};
+use uuid::Uuid;
+
+/// A write applied by [`Db::write_batch`](crate::Db::write_batch).
+#[derive(Clone, Debug)]
+pub enum BatchOp {
+    /// Stores the thread along with its embedding, failing the batch with
+    /// [`DatabaseError::InvalidInput`](crate::DatabaseError::InvalidInput)
+    /// when a thread with the same id exists.
+    CreateThread(Thread),
+    /// Fails the batch like [`Db::create_message`](crate::Db::create_message)
+    /// when the thread or the parent message is missing.
+    CreateMessage(Message),
+    DeleteMessage {
+        thread_id: Uuid,
+        message_id: Uuid,
+    },
+    DeleteThread(Uuid),
+    /// Deletes the message like [`BatchOp::DeleteMessage`], counting it in
+    /// the thread's `pruned_message_count`.
+    PruneMessage {
+        thread_id: Uuid,
+        message_id: Uuid,
+    },
+    /// See [`Db::clear_thread_summary`](crate::Db::clear_thread_summary).
+    ClearSummary(Uuid),
+    /// Stores a vector of the thread, replacing the one with the same id,
+    /// failing the batch with [`DatabaseError::NotFound`](crate::DatabaseError::NotFound)
+    /// when the thread is missing.
+    PutThreadVector {
+        thread_id: Uuid,
+        vector: ThreadVector,
+    },
+    /// Deletes every vector of the thread.
+    DeleteThreadVectors(Uuid),
+}
+
+/// Writes applied together, in order, by
+/// [`Db::write_batch`](crate::Db::write_batch), so operations made of
+/// several writes, e.g. merging, forking, importing, or purging threads,
+/// can't be left half done.
+///
+/// Later operations see the effects of earlier ones: a message may be
+/// created in a thread created earlier in the same batch.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn create_thread(&mut self, thread: Thread) -> &mut Self {
+        self.ops.push(BatchOp::CreateThread(thread));
+        self
+    }
+
+    pub fn create_message(&mut self, message: Message) -> &mut Self {
+        self.ops.push(BatchOp::CreateMessage(message));
+        self
+    }
+
+    pub fn delete_message(&mut self, thread_id: Uuid, message_id: Uuid) -> &mut Self {
+        self.ops.push(BatchOp::DeleteMessage {
+            thread_id,
+            message_id,
+        });
+        self
+    }
+
+    pub fn prune_message(&mut self, thread_id: Uuid, message_id: Uuid) -> &mut Self {
+        self.ops.push(BatchOp::PruneMessage {
+            thread_id,
+            message_id,
+        });
+        self
+    }
+
+    pub fn delete_thread(&mut self, thread_id: Uuid) -> &mut Self {
+        self.ops.push(BatchOp::DeleteThread(thread_id));
+        self
+    }
+
+    pub fn clear_summary(&mut self, thread_id: Uuid) -> &mut Self {
+        self.ops.push(BatchOp::ClearSummary(thread_id));
+        self
+    }
+
+    pub fn put_thread_vector(&mut self, thread_id: Uuid, vector: ThreadVector) -> &mut Self {
+        self.ops
+            .push(BatchOp::PutThreadVector { thread_id, vector });
+        self
+    }
+
+    pub fn delete_thread_vectors(&mut self, thread_id: Uuid) -> &mut Self {
+        self.ops.push(BatchOp::DeleteThreadVectors(thread_id));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn into_ops(self) -> Vec<BatchOp> {
+        self.ops
+    }
+}
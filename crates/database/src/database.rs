@@ -1,35 +1,82 @@
+pub mod batch;
 pub mod error;
 
 pub use async_trait::async_trait;
+pub use batch::{BatchOp, WriteBatch};
 pub use error::DatabaseError;
 
+use std::{collections::HashMap, pin::Pin};
+
+use futures_core::Stream;
+
 use synx_domain::{
+    annotation::{Annotation, SearchFeedback},
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
     embedding::Embedding,
-    message::{CreateMessage, Message, ThreadMessagesResponse, UpdateMessage},
-    thread::{Thread, UpdateThread},
+    export::ExportRecord,
+    message::{CreateMessage, Message, PatchMessage, ThreadMessagesResponse, UpdateMessage},
+    read::ReadMarker,
+    sync::{Operation, OperationFilter, SyncCursor},
+    thread::{
+        CreateThread, PatchThread, PendingSummary, ShadowSummary, SummaryProvenance,
+        SummaryRedactions, Thread, ThreadFilter, UpdateThread,
+    },
+    usage::{DailyUsage, UsageFilter},
 };
 use uuid::Uuid;
 
+/// The records of an export, see [`Db::export`].
+pub type ExportStream = Pin<Box<dyn Stream<Item = Result<ExportRecord, DatabaseError>> + Send>>;
+
+/// The threads of a listing, see [`Db::stream_threads`].
+pub type ThreadStream = Pin<Box<dyn Stream<Item = Result<Thread, DatabaseError>> + Send>>;
+
 #[async_trait]
 pub trait Db: Send + Sync {
     async fn debug_state(&self) -> Result<serde_json::Value, DatabaseError>;
 
+    /// Statistics of the backend's storage, e.g. LMDB readers and pages, to
+    /// tune it.
+    async fn storage_stats(&self) -> Result<serde_json::Value, DatabaseError>;
+
+    /// The threads found among `thread_ids`, along with their embedding and
+    /// their [vectors](synx_domain::thread::ThreadVector).
     async fn get_threads_with_embeddings(
         &self,
         thread_ids: &[Uuid],
     ) -> Result<Vec<Thread>, DatabaseError>;
 
+    /// Stores the summary, its provenance, and its embedding together. When
+    /// `log_origin` is set, the
+    /// [`SetSummary`](synx_domain::sync::OperationKind::SetSummary) operation
+    /// recording the change is appended to the op-log in the same
+    /// transaction, so neither the stored state nor the op-log can end up
+    /// with a summary the other lacks.
     async fn update_thread_summary_and_embedding(
         &self,
         thread_id: Uuid,
         summary: String,
+        provenance: SummaryProvenance,
         embedding: Embedding,
+        log_origin: Option<Uuid>,
     ) -> Result<(), DatabaseError>;
 
-    async fn create_thread(&self) -> Result<Thread, DatabaseError>;
+    async fn create_thread(&self, input: CreateThread) -> Result<Thread, DatabaseError>;
 
     async fn delete_thread(&self, thread_id: Uuid) -> Result<(), DatabaseError>;
 
+    /// Deletes every thread matching the filter, along with their messages,
+    /// in a single transaction, returning their ids. With `dry_run`, returns
+    /// the threads which would be deleted without deleting them.
+    async fn delete_threads(
+        &self,
+        filter: ThreadFilter,
+        dry_run: bool,
+    ) -> Result<Vec<Uuid>, DatabaseError>;
+
+    /// Fails with [`DatabaseError::InvalidInput`] when the message replies
+    /// to one that isn't in the thread.
     async fn create_message(
         &self,
         thread_id: Uuid,
@@ -43,16 +90,39 @@ pub trait Db: Send + Sync {
         content: UpdateMessage,
     ) -> Result<Message, DatabaseError>;
 
+    async fn patch_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        patch: PatchMessage,
+    ) -> Result<Message, DatabaseError>;
+
     async fn update_thread(
         &self,
         thread_id: Uuid,
         update: UpdateThread,
     ) -> Result<Thread, DatabaseError>;
 
+    async fn patch_thread(
+        &self,
+        thread_id: Uuid,
+        patch: PatchThread,
+    ) -> Result<Thread, DatabaseError>;
+
+    /// Every thread, pinned threads first.
     async fn list_threads(&self) -> Result<Vec<Thread>, DatabaseError>;
 
+    /// Every thread like [`Db::list_threads`], read in batches as the
+    /// stream is consumed rather than held in memory at once. Threads
+    /// written meanwhile may or may not be listed.
+    async fn stream_threads(&self) -> Result<ThreadStream, DatabaseError>;
+
     async fn get_thread(&self, thread_id: Uuid) -> Result<Thread, DatabaseError>;
 
+    /// The threads among `thread_ids` in the order given, leaving out those
+    /// which don't exist, read at once.
+    async fn get_threads(&self, thread_ids: &[Uuid]) -> Result<Vec<Thread>, DatabaseError>;
+
     async fn get_thread_messages(
         &self,
         thread_id: Uuid,
@@ -61,4 +131,164 @@ pub trait Db: Send + Sync {
     ) -> Result<ThreadMessagesResponse, DatabaseError>;
 
     async fn delete_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<(), DatabaseError>;
+
+    async fn append_audit_entry(&self, entry: AuditEntry) -> Result<(), DatabaseError>;
+
+    async fn list_audit_entries(
+        &self,
+        filter: AuditFilter,
+    ) -> Result<Vec<AuditEntry>, DatabaseError>;
+
+    async fn get_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Message, DatabaseError>;
+
+    /// The shadow summary of a thread, if the shadow pipeline produced one.
+    async fn get_shadow_summary(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<ShadowSummary>, DatabaseError>;
+
+    /// Stores a shadow summary, failing with [`DatabaseError::NotFound`]
+    /// when its thread doesn't exist. Deleting the thread deletes it.
+    async fn put_shadow_summary(&self, shadow: ShadowSummary) -> Result<(), DatabaseError>;
+
+    /// The spans of a thread's summary marked as redacted, if any.
+    async fn get_summary_redactions(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<SummaryRedactions>, DatabaseError>;
+
+    /// Replaces the redacted spans of a thread's summary, failing with
+    /// [`DatabaseError::NotFound`] when the thread doesn't exist. Deleting
+    /// the thread deletes them.
+    async fn put_summary_redactions(
+        &self,
+        redactions: SummaryRedactions,
+    ) -> Result<(), DatabaseError>;
+
+    /// Deletes the summary of a thread, its embedding, its vectors, its
+    /// shadow summary, and its redacted spans. The change isn't recorded in the op-log.
+    async fn clear_thread_summary(&self, thread_id: Uuid) -> Result<(), DatabaseError>;
+
+    /// Applies the operations of the batch in order, in a single
+    /// transaction: either every one of them is applied, or, when one fails
+    /// as it would on its own, e.g. with [`DatabaseError::NotFound`] for a
+    /// missing message, none is. The changes aren't recorded in the op-log.
+    async fn write_batch(&self, batch: WriteBatch) -> Result<(), DatabaseError>;
+
+    /// Stores a thread as is, creating it if needed, along with its
+    /// embedding when it has one. Used to apply changes from other replicas.
+    async fn put_thread(&self, thread: Thread) -> Result<(), DatabaseError>;
+
+    /// Stores a message as is, creating it if needed. Fails with
+    /// [`DatabaseError::NotFound`] when its thread doesn't exist.
+    async fn put_message(&self, message: Message) -> Result<(), DatabaseError>;
+
+    /// Appends the operation with the next sequence number, which is
+    /// returned. Sequence numbers are persisted and never reused.
+    /// Appending an [`OperationKind::Purge`](synx_domain::sync::OperationKind::Purge)
+    /// rewrites or drops the earlier operations in the same transaction,
+    /// see [`Operation::purged_by`]. Appending an operation deleting
+    /// threads or messages keeps a tombstone for each of them in the same
+    /// transaction, see [`Db::get_tombstone`].
+    async fn append_operation(&self, operation: Operation) -> Result<u64, DatabaseError>;
+
+    /// The timestamp of the operation which deleted the thread or message,
+    /// if one was appended to the op-log. Tombstones are kept for good.
+    async fn get_tombstone(&self, id: Uuid) -> Result<Option<u64>, DatabaseError>;
+
+    /// How far the instance synced with the remote, if it ever did.
+    async fn get_sync_cursor(&self, remote: String) -> Result<Option<SyncCursor>, DatabaseError>;
+
+    async fn put_sync_cursor(
+        &self,
+        remote: String,
+        cursor: SyncCursor,
+    ) -> Result<(), DatabaseError>;
+
+    /// Operations in sequence order.
+    async fn list_operations(
+        &self,
+        filter: OperationFilter,
+    ) -> Result<Vec<Operation>, DatabaseError>;
+
+    /// Adds to the aggregate of the same day, tenant, and thread, creating
+    /// it if needed.
+    async fn add_usage(&self, usage: DailyUsage) -> Result<(), DatabaseError>;
+
+    /// Daily aggregates, oldest first.
+    async fn list_usage(&self, filter: UsageFilter) -> Result<Vec<DailyUsage>, DatabaseError>;
+
+    /// Records the message as the last one `reader` read in its thread,
+    /// failing with [`DatabaseError::NotFound`] when the message isn't in the
+    /// thread. Deleting the thread deletes its markers.
+    async fn mark_read(
+        &self,
+        thread_id: Uuid,
+        reader: String,
+        message_id: Uuid,
+    ) -> Result<ReadMarker, DatabaseError>;
+
+    /// Number of messages `reader` hasn't read in every thread, all of a
+    /// thread's messages being unread until it is first marked read.
+    async fn unread_counts(&self, reader: String) -> Result<HashMap<Uuid, usize>, DatabaseError>;
+
+    /// Stores an annotation, failing with [`DatabaseError::NotFound`] when
+    /// its message isn't in its thread. Deleting the message deletes it.
+    async fn add_annotation(&self, annotation: Annotation) -> Result<(), DatabaseError>;
+
+    async fn delete_annotation(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        annotation_id: Uuid,
+    ) -> Result<(), DatabaseError>;
+
+    /// Annotations of every message of the thread, oldest first.
+    async fn list_annotations(&self, thread_id: Uuid) -> Result<Vec<Annotation>, DatabaseError>;
+
+    /// Stores the feedback, replacing the reader's earlier feedback on the
+    /// same thread. Fails with [`DatabaseError::NotFound`] when the thread
+    /// doesn't exist. Deleting the thread deletes it.
+    async fn put_search_feedback(&self, feedback: SearchFeedback) -> Result<(), DatabaseError>;
+
+    /// Every feedback given by `reader`.
+    async fn list_search_feedback(
+        &self,
+        reader: String,
+    ) -> Result<Vec<SearchFeedback>, DatabaseError>;
+
+    /// Queues the summary of a message, replacing the one queued for the
+    /// same message. Fails with [`DatabaseError::NotFound`] when its thread
+    /// doesn't exist. Deleting the thread deletes it.
+    async fn put_pending_summary(&self, pending: PendingSummary) -> Result<(), DatabaseError>;
+
+    /// Every queued summary, the oldest first.
+    async fn list_pending_summaries(&self) -> Result<Vec<PendingSummary>, DatabaseError>;
+
+    async fn delete_pending_summary(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), DatabaseError>;
+
+    /// Stores a blob under its hash. Blobs are content-addressed, so storing
+    /// one that already exists keeps the existing one and returns it. Blobs
+    /// are shared between threads and are never deleted along with them.
+    async fn put_blob(&self, blob: Blob, data: Vec<u8>) -> Result<Blob, DatabaseError>;
+
+    /// Deletes a blob and its data, e.g. once no message refers to it after
+    /// a purge.
+    async fn delete_blob(&self, hash: String) -> Result<(), DatabaseError>;
+
+    async fn get_blob(&self, hash: String) -> Result<(Blob, Vec<u8>), DatabaseError>;
+
+    /// Every thread and message as of the call, while writes carry on, the
+    /// snapshot being held until the stream is dropped. Fails with
+    /// [`DatabaseError::Unavailable`] when too many exports are in progress.
+    /// A stream ending early, e.g. on a timeout, yields an error last.
+    async fn export(&self) -> Result<ExportStream, DatabaseError>;
 }
@@ -16,4 +16,12 @@ pub enum DatabaseError {
     InvalidInput(String),
     #[error("Internal error: {0}")]
     InternalError(String),
+    /// The backend is at capacity, e.g. too many exports hold a snapshot.
+    /// Retrying later may succeed.
+    #[error("Unavailable: {0}")]
+    Unavailable(String),
+    /// The entity isn't in the state the write expected, e.g. a message
+    /// appended to once final.
+    #[error("Conflict: {0}")]
+    Conflict(String),
 }
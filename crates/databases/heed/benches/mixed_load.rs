@@ -0,0 +1,148 @@
+//! Mixed read/write load against the heed backend, reporting the latency of
+//! reads and of a heartbeat task sharing the runtime.
+//!
+//! The `inline` run blocks the calling worker thread for the whole database
+//! operation, as the backend did before it moved LMDB work to the blocking
+//! pool; the `offloaded` run awaits the operations as the API does.
+//!
+//! ```text
+//! cargo bench -p synx_heed_database --bench mixed_load
+//! ```
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use synx_database::Db;
+use synx_domain::{message::CreateMessage, role::Role, thread::CreateThread};
+use synx_heed_database::{heed::EnvOpenOptions, SynxHeedDatabase};
+use tokio::{sync::Mutex, task::JoinSet};
+
+const WORKERS: usize = 2;
+const WRITERS: usize = 4;
+const READERS: usize = 4;
+const DURATION: Duration = Duration::from_secs(5);
+const HEARTBEAT: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Inline,
+    Offloaded,
+}
+
+impl Mode {
+    async fn run<T>(self, operation: impl Future<Output = T>) -> T {
+        match self {
+            Mode::Inline => futures::executor::block_on(operation),
+            Mode::Offloaded => operation.await,
+        }
+    }
+}
+
+fn main() {
+    for mode in [Mode::Inline, Mode::Offloaded] {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(WORKERS)
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(bench(mode));
+    }
+}
+
+async fn bench(mode: Mode) {
+    let dir = tempfile::tempdir().unwrap();
+    let env = unsafe {
+        EnvOpenOptions::new()
+            .map_size(1024 * 1024 * 1024)
+            .max_dbs(SynxHeedDatabase::MAX_DBS)
+            .open(dir.path())
+            .unwrap()
+    };
+    let db = Arc::new(SynxHeedDatabase::new(Arc::new(env), true).unwrap());
+    let thread_id = db.create_thread(CreateThread::default()).await.unwrap().id;
+    let deadline = Instant::now() + DURATION;
+
+    let read_latencies = Arc::new(Mutex::new(Vec::new()));
+    let heartbeat_delays = Arc::new(Mutex::new(Vec::new()));
+    let mut tasks = JoinSet::new();
+
+    for _ in 0..WRITERS {
+        let db = db.clone();
+        tasks.spawn(async move {
+            while Instant::now() < deadline {
+                let message = CreateMessage {
+                    role: Role::User,
+                    content: "The quick brown fox jumps over the lazy dog"
+                        .to_string()
+                        .into(),
+                    parent_message_id: None,
+                    metadata: Default::default(),
+                    status: Default::default(),
+                    memorize: true,
+                };
+                mode.run(db.create_message(thread_id, message))
+                    .await
+                    .unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+
+    for _ in 0..READERS {
+        let db = db.clone();
+        let read_latencies = read_latencies.clone();
+        tasks.spawn(async move {
+            while Instant::now() < deadline {
+                let start = Instant::now();
+                mode.run(db.get_thread_messages(thread_id, Some(20), Some(0)))
+                    .await
+                    .unwrap();
+                read_latencies.lock().await.push(start.elapsed());
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+
+    {
+        let heartbeat_delays = heartbeat_delays.clone();
+        tasks.spawn(async move {
+            while Instant::now() < deadline {
+                let start = Instant::now();
+                tokio::time::sleep(HEARTBEAT).await;
+                heartbeat_delays
+                    .lock()
+                    .await
+                    .push(start.elapsed().saturating_sub(HEARTBEAT));
+            }
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap();
+    }
+
+    report(mode, "read", &mut read_latencies.lock().await);
+    report(mode, "heartbeat delay", &mut heartbeat_delays.lock().await);
+}
+
+fn report(mode: Mode, name: &str, samples: &mut [Duration]) {
+    samples.sort();
+    let percentile = |p: f64| {
+        samples
+            .get(((samples.len() as f64 * p) as usize).min(samples.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "{:?} {}: n={} p50={:?} p99={:?} max={:?}",
+        mode,
+        name,
+        samples.len(),
+        percentile(0.50),
+        percentile(0.99),
+        samples.last().copied().unwrap_or_default(),
+    );
+}
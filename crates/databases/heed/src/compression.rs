@@ -0,0 +1,89 @@
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    sync::OnceLock,
+};
+
+use heed::BoxedError;
+use zstd::{
+    dict::{DecoderDictionary, EncoderDictionary},
+    stream::{Decoder, Encoder},
+};
+
+/// JSON never starts with this byte either, so compressed values are told
+/// apart from plain ones, and from those written before compression.
+pub(crate) const COMPRESSED_MARKER: u8 = 2;
+/// Values shorter than this are stored as they are, the frame header and
+/// dictionary lookups costing more than they would save.
+const COMPRESSION_THRESHOLD: usize = 256;
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// A raw content dictionary of message and thread JSON and common chat
+/// phrasing, which short values reference instead of repeating. Values are
+/// decompressed with it, so it must never change; a new dictionary needs a
+/// new marker.
+const DICTIONARY: &[u8] = include_bytes!("compression_dictionary.txt");
+
+static ENCODER_DICTIONARY: OnceLock<EncoderDictionary<'static>> = OnceLock::new();
+static DECODER_DICTIONARY: OnceLock<DecoderDictionary<'static>> = OnceLock::new();
+
+/// Compresses serialized values past the threshold with zstd, keeping them
+/// as they are when compression doesn't make them shorter.
+pub(crate) fn compress(bytes: Vec<u8>) -> Result<Vec<u8>, BoxedError> {
+    if bytes.len() < COMPRESSION_THRESHOLD {
+        return Ok(bytes);
+    }
+
+    let dictionary =
+        ENCODER_DICTIONARY.get_or_init(|| EncoderDictionary::copy(DICTIONARY, COMPRESSION_LEVEL));
+    let mut encoder = Encoder::with_prepared_dictionary(vec![COMPRESSED_MARKER], dictionary)?;
+    encoder.write_all(&bytes)?;
+    let compressed = encoder.finish()?;
+
+    Ok(if compressed.len() < bytes.len() {
+        compressed
+    } else {
+        bytes
+    })
+}
+
+/// Decompresses values written by [`compress`], returning others as they
+/// are.
+pub(crate) fn decompress(bytes: &[u8]) -> Result<Cow<'_, [u8]>, BoxedError> {
+    if bytes.first() != Some(&COMPRESSED_MARKER) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let dictionary = DECODER_DICTIONARY.get_or_init(|| DecoderDictionary::copy(DICTIONARY));
+    let mut decoder = Decoder::with_prepared_dictionary(&bytes[1..], dictionary)?;
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(Cow::Owned(decompressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_values_round_trip_compressed() {
+        let json = serde_json::to_vec(&serde_json::json!({
+            "role": "user",
+            "content": [{ "type": "text", "text": "Could you please help me with my tomatoes? ".repeat(20) }],
+        }))
+        .unwrap();
+
+        let compressed = compress(json.clone()).unwrap();
+        assert_eq!(compressed[0], COMPRESSED_MARKER);
+        assert!(compressed.len() < json.len());
+        assert_eq!(decompress(&compressed).unwrap(), json);
+    }
+
+    #[test]
+    fn short_values_are_kept_as_they_are() {
+        let json = br#"{"id":"a","title":null}"#.to_vec();
+
+        assert_eq!(compress(json.clone()).unwrap(), json);
+        assert_eq!(decompress(&json).unwrap(), json);
+    }
+}
@@ -0,0 +1,369 @@
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt, io,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use heed::{BoxedError, BytesDecode, BytesEncode};
+use serde::{de::DeserializeOwned, Serialize};
+use synx_database::DatabaseError;
+
+use crate::compression::{compress, decompress};
+
+/// JSON never starts with a NUL byte, so it safely tells encrypted values
+/// apart from plain ones written before encryption was enabled.
+const ENCRYPTED_MARKER: u8 = 0;
+/// Raw bytes may start with anything, so [`EncryptedBytes`] marks plain
+/// values too.
+const PLAIN_MARKER: u8 = 1;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = 1 + 4 + NONCE_LEN;
+
+thread_local! {
+    /// The keyring of the database whose transaction runs on this thread, see
+    /// [`with_keyring`].
+    static KEYRING: RefCell<Option<Arc<Keyring>>> = const { RefCell::new(None) };
+    /// Whether the database whose transaction runs on this thread refuses
+    /// to write plain values, see [`requiring_encryption`].
+    static ENCRYPTION_REQUIRED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// AES-256-GCM keys indexed by id. New values are always encrypted with the
+/// active key, while the remaining keys are kept around to decrypt values
+/// written before a rotation.
+pub struct Keyring {
+    active: u32,
+    ciphers: HashMap<u32, Aes256Gcm>,
+}
+
+impl Keyring {
+    pub fn new(active: u32, keys: Vec<(u32, Vec<u8>)>) -> Result<Self, DatabaseError> {
+        let mut ciphers = HashMap::new();
+        for (id, key) in keys {
+            let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| {
+                DatabaseError::InvalidInput(format!("encryption key {} must be 32 bytes", id))
+            })?;
+            ciphers.insert(id, cipher);
+        }
+
+        if !ciphers.contains_key(&active) {
+            return Err(DatabaseError::InvalidInput(format!(
+                "active encryption key {} is not configured",
+                active
+            )));
+        }
+
+        Ok(Self { active, ciphers })
+    }
+
+    /// Parses a comma-separated list of `<id>:<hex key>` entries, the first
+    /// one being the active key.
+    pub fn parse(spec: &str) -> Result<Self, DatabaseError> {
+        let mut keys = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || {
+                DatabaseError::InvalidInput(
+                    "encryption keys must be formatted as `<id>:<hex key>`".to_string(),
+                )
+            };
+            let (id, key) = entry.split_once(':').ok_or_else(invalid)?;
+            let id = id.parse::<u32>().map_err(|_| invalid())?;
+            let key = decode_hex(key).ok_or_else(invalid)?;
+            keys.push((id, key));
+        }
+
+        let Some(&(active, _)) = keys.first() else {
+            return Err(DatabaseError::InvalidInput(
+                "no encryption keys provided".to_string(),
+            ));
+        };
+
+        Self::new(active, keys)
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, BoxedError> {
+        let cipher = &self.ciphers[&self.active];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| invalid_data("failed to encrypt value"))?;
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        bytes.push(ENCRYPTED_MARKER);
+        bytes.extend_from_slice(&self.active.to_be_bytes());
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        Ok(bytes)
+    }
+
+    fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>, BoxedError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(invalid_data("truncated encrypted value"));
+        }
+
+        let key_id = u32::from_be_bytes(bytes[1..5].try_into()?);
+        let cipher = self
+            .ciphers
+            .get(&key_id)
+            .ok_or_else(|| invalid_data(format!("unknown encryption key {}", key_id)))?;
+
+        cipher
+            .decrypt(
+                Nonce::from_slice(&bytes[5..HEADER_LEN]),
+                &bytes[HEADER_LEN..],
+            )
+            .map_err(|_| invalid_data("failed to decrypt value"))
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ids = self.ciphers.keys().collect::<Vec<_>>();
+        ids.sort();
+        f.debug_struct("Keyring")
+            .field("active", &self.active)
+            .field("ids", &ids)
+            .finish()
+    }
+}
+
+/// Runs `f` with `keyring` used by [`EncryptedJson`] and [`EncryptedBytes`]
+/// on this thread. Heed codecs are stateless, so each database hands its own
+/// keys to them around its transactions, and stores with different keys can
+/// be used side by side.
+pub(crate) fn with_keyring<T>(keyring: Option<&Arc<Keyring>>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<Keyring>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            KEYRING.with(|keyring| *keyring.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(KEYRING.with(|current| current.replace(keyring.cloned())));
+    f()
+}
+
+/// Runs `f` with [`EncryptedJson`] and [`EncryptedBytes`] failing to write
+/// values on this thread without a keyring when `required` is set, rather
+/// than writing them plain.
+pub(crate) fn requiring_encryption<T>(required: bool, f: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            ENCRYPTION_REQUIRED.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(ENCRYPTION_REQUIRED.with(|current| current.replace(required)));
+    f()
+}
+
+fn current_keyring() -> Option<Arc<Keyring>> {
+    KEYRING.with(|keyring| keyring.borrow().clone())
+}
+
+/// The keyring to encrypt new values with, none meaning they are written
+/// plain, which fails when encryption is required.
+fn encrypting_keyring() -> Result<Option<Arc<Keyring>>, BoxedError> {
+    match current_keyring() {
+        None if ENCRYPTION_REQUIRED.with(Cell::get) => Err(invalid_data(
+            "encryption is required but no encryption keys are configured",
+        )),
+        keyring => Ok(keyring),
+    }
+}
+
+/// A `SerdeJson` codec that transparently compresses values, then encrypts
+/// them when the database has a keyring, and still reads plain values
+/// written before either.
+pub struct EncryptedJson<T>(PhantomData<T>);
+
+impl<'a, T: Serialize + 'a> BytesEncode<'a> for EncryptedJson<T> {
+    type EItem = T;
+
+    fn bytes_encode(item: &'a Self::EItem) -> Result<Cow<'a, [u8]>, BoxedError> {
+        let json = compress(serde_json::to_vec(item)?)?;
+        match encrypting_keyring()? {
+            Some(keyring) => Ok(Cow::Owned(keyring.encrypt(&json)?)),
+            None => Ok(Cow::Owned(json)),
+        }
+    }
+}
+
+impl<'a, T: DeserializeOwned + 'a> BytesDecode<'a> for EncryptedJson<T> {
+    type DItem = T;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, BoxedError> {
+        if bytes.first() != Some(&ENCRYPTED_MARKER) {
+            return Ok(serde_json::from_slice(&decompress(bytes)?)?);
+        }
+
+        let keyring = current_keyring().ok_or_else(|| {
+            invalid_data("value is encrypted but no encryption keys are configured")
+        })?;
+        Ok(serde_json::from_slice(&decompress(
+            &keyring.decrypt(bytes)?,
+        )?)?)
+    }
+}
+
+/// Encrypts raw bytes, e.g. blob data, the way [`EncryptedJson`] encrypts
+/// JSON, skipping the serialization.
+pub struct EncryptedBytes;
+
+impl<'a> BytesEncode<'a> for EncryptedBytes {
+    type EItem = [u8];
+
+    fn bytes_encode(item: &'a Self::EItem) -> Result<Cow<'a, [u8]>, BoxedError> {
+        match encrypting_keyring()? {
+            Some(keyring) => Ok(Cow::Owned(keyring.encrypt(item)?)),
+            None => {
+                let mut bytes = Vec::with_capacity(1 + item.len());
+                bytes.push(PLAIN_MARKER);
+                bytes.extend_from_slice(item);
+                Ok(Cow::Owned(bytes))
+            }
+        }
+    }
+}
+
+impl<'a> BytesDecode<'a> for EncryptedBytes {
+    type DItem = Vec<u8>;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, BoxedError> {
+        match bytes.first() {
+            Some(&PLAIN_MARKER) => Ok(bytes[1..].to_vec()),
+            Some(&ENCRYPTED_MARKER) => {
+                let keyring = current_keyring().ok_or_else(|| {
+                    invalid_data("value is encrypted but no encryption keys are configured")
+                })?;
+                keyring.decrypt(bytes)
+            }
+            _ => Err(invalid_data("unknown value marker")),
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> BoxedError {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, message.into()))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(active: u32, ids: &[u32]) -> Arc<Keyring> {
+        let keys = ids.iter().map(|&id| (id, vec![id as u8; 32])).collect();
+        Arc::new(Keyring::new(active, keys).unwrap())
+    }
+
+    fn encode(keyring: Option<&Arc<Keyring>>, value: &str) -> Vec<u8> {
+        with_keyring(keyring, || {
+            EncryptedJson::<String>::bytes_encode(&value.to_string())
+                .unwrap()
+                .into_owned()
+        })
+    }
+
+    fn decode(keyring: Option<&Arc<Keyring>>, bytes: &[u8]) -> Result<String, BoxedError> {
+        with_keyring(keyring, || EncryptedJson::<String>::bytes_decode(bytes))
+    }
+
+    #[test]
+    fn values_round_trip_encrypted() {
+        let keyring = keyring(1, &[1]);
+        let bytes = encode(Some(&keyring), "Hello");
+        assert_eq!(bytes[0], ENCRYPTED_MARKER);
+        assert_eq!(decode(Some(&keyring), &bytes).unwrap(), "Hello");
+
+        let bytes = with_keyring(Some(&keyring), || {
+            EncryptedBytes::bytes_encode(b"Hello").unwrap().into_owned()
+        });
+        assert_eq!(bytes[0], ENCRYPTED_MARKER);
+        let decoded = with_keyring(Some(&keyring), || EncryptedBytes::bytes_decode(&bytes));
+        assert_eq!(decoded.unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn values_encrypted_with_a_retired_key_still_decrypt() {
+        let bytes = encode(Some(&keyring(1, &[1])), "Hello");
+
+        let rotated = keyring(2, &[2, 1]);
+        assert_eq!(decode(Some(&rotated), &bytes).unwrap(), "Hello");
+        let reencrypted = encode(Some(&rotated), "Hello");
+        assert_eq!(u32::from_be_bytes(reencrypted[1..5].try_into().unwrap()), 2);
+    }
+
+    #[test]
+    fn plain_values_written_before_encryption_are_read() {
+        let bytes = encode(None, "Hello");
+        assert_ne!(bytes[0], ENCRYPTED_MARKER);
+        assert_eq!(decode(Some(&keyring(1, &[1])), &bytes).unwrap(), "Hello");
+
+        let bytes = with_keyring(None, || {
+            EncryptedBytes::bytes_encode(b"Hello").unwrap().into_owned()
+        });
+        let decoded = with_keyring(Some(&keyring(1, &[1])), || {
+            EncryptedBytes::bytes_decode(&bytes)
+        });
+        assert_eq!(decoded.unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn plain_values_are_refused_when_encryption_is_required() {
+        requiring_encryption(true, || {
+            assert!(with_keyring(None, || {
+                EncryptedJson::<String>::bytes_encode(&"Hello".to_string())
+            })
+            .is_err());
+            assert!(with_keyring(None, || EncryptedBytes::bytes_encode(b"Hello")).is_err());
+
+            let keyring = keyring(1, &[1]);
+            let bytes = encode(Some(&keyring), "Hello");
+            assert_eq!(decode(Some(&keyring), &bytes).unwrap(), "Hello");
+        });
+        assert!(!ENCRYPTION_REQUIRED.with(Cell::get));
+    }
+
+    #[test]
+    fn unknown_key_ids_fail_to_decrypt() {
+        let bytes = encode(Some(&keyring(1, &[1])), "Hello");
+        assert!(decode(Some(&keyring(2, &[2])), &bytes).is_err());
+        assert!(decode(None, &bytes).is_err());
+    }
+
+    #[test]
+    fn keyrings_are_scoped_to_their_closure() {
+        let outer = keyring(1, &[1]);
+        let bytes = with_keyring(Some(&outer), || {
+            let inner = encode(Some(&keyring(2, &[2])), "Hello");
+            assert_eq!(u32::from_be_bytes(inner[1..5].try_into().unwrap()), 2);
+            EncryptedJson::<String>::bytes_encode(&"Hello".to_string())
+                .unwrap()
+                .into_owned()
+        });
+        assert_eq!(u32::from_be_bytes(bytes[1..5].try_into().unwrap()), 1);
+        assert!(current_keyring().is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use synx_database::{DatabaseError, ExportStream};
+use synx_domain::export::ExportRecord;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot, OwnedSemaphorePermit,
+};
+use uuid::Uuid;
+
+use crate::{heed_ids::HeedMessageCreationTimeId, SynxHeedDatabase};
+
+pub const DEFAULT_MAX_EXPORTS: usize = 4;
+pub const DEFAULT_EXPORT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+/// Records read ahead of the consumer.
+const EXPORT_BUFFER: usize = 64;
+const SEND_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+type Sender = mpsc::Sender<Result<ExportRecord, DatabaseError>>;
+
+/// Why an export stopped before its last record.
+enum Stop {
+    /// The stream was dropped, e.g. the client went away.
+    Closed,
+    Failed(DatabaseError),
+}
+
+impl From<DatabaseError> for Stop {
+    fn from(e: DatabaseError) -> Self {
+        Self::Failed(e)
+    }
+}
+
+impl SynxHeedDatabase {
+    /// Reads the snapshot of an export on a thread of its own, which holds
+    /// the read transaction and sends the records to the stream as it
+    /// consumes them. The transaction is open by the time this returns, so
+    /// later writes aren't exported.
+    ///
+    /// A read transaction takes one of the environment's reader slots, and
+    /// keeps the pages it sees from being reused, so the file grows while
+    /// it is open. Exports are therefore limited in number, see
+    /// [`HeedBuilder::with_max_exports`](crate::HeedBuilder::with_max_exports),
+    /// and in duration, a stalled consumer included.
+    pub(crate) async fn export_snapshot(&self) -> Result<ExportStream, DatabaseError> {
+        let permit = self.export_slots.clone().try_acquire_owned().map_err(|_| {
+            DatabaseError::Unavailable("too many exports are in progress".to_string())
+        })?;
+        // Slots of crashed processes sharing the environment would never be
+        // given back otherwise.
+        self.env
+            .clear_stale_readers()
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+        let (sender, receiver) = mpsc::channel(EXPORT_BUFFER);
+        let (ready_sender, ready) = oneshot::channel();
+        let completed = Arc::new(AtomicBool::new(false));
+        std::thread::Builder::new()
+            .name("synx-heed-export".to_string())
+            .spawn({
+                let db = self.clone();
+                let completed = completed.clone();
+                let deadline = Instant::now() + self.export_timeout;
+                move || {
+                    db.keyed(|| db.run_export(permit, ready_sender, sender, deadline, completed))
+                }
+            })
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        ready
+            .await
+            .map_err(|_| DatabaseError::OperationFailed("the export panicked".to_string()))??;
+
+        Ok(Box::pin(futures::stream::unfold(
+            Some((receiver, completed)),
+            |state| async move {
+                let (mut receiver, completed) = state?;
+                match receiver.recv().await {
+                    Some(record) => Some((record, Some((receiver, completed)))),
+                    None if completed.load(Ordering::Acquire) => None,
+                    None => Some((
+                        Err(DatabaseError::OperationFailed(
+                            "the export stopped before its end".to_string(),
+                        )),
+                        None,
+                    )),
+                }
+            },
+        )))
+    }
+
+    fn run_export(
+        &self,
+        _permit: OwnedSemaphorePermit,
+        ready: oneshot::Sender<Result<(), DatabaseError>>,
+        sender: Sender,
+        deadline: Instant,
+        completed: Arc<AtomicBool>,
+    ) {
+        let rtxn = match self.env.read_txn() {
+            Ok(rtxn) => rtxn,
+            Err(e) => {
+                let _ = ready.send(Err(DatabaseError::OperationFailed(e.to_string())));
+                return;
+            }
+        };
+        let _ = ready.send(Ok(()));
+
+        match self.send_snapshot(&rtxn, &sender, deadline) {
+            Ok(()) => completed.store(true, Ordering::Release),
+            Err(Stop::Closed) => {}
+            Err(Stop::Failed(e)) => {
+                tracing::warn!("Export failed: {}", e);
+                // Without room for it, the stream reports the export as
+                // stopped early all the same.
+                let _ = sender.try_send(Err(e));
+            }
+        }
+    }
+
+    fn send_snapshot(
+        &self,
+        rtxn: &heed::RoTxn,
+        sender: &Sender,
+        deadline: Instant,
+    ) -> Result<(), Stop> {
+        for entry in self
+            .threads_db
+            .iter(rtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (_, thread) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            let thread_id = thread.id;
+            send(sender, Ok(ExportRecord::Thread(thread)), deadline)?;
+
+            let range = HeedMessageCreationTimeId::from((thread_id, 0, Uuid::nil()))
+                ..=HeedMessageCreationTimeId::from((
+                    thread_id,
+                    u64::MAX,
+                    Uuid::from_bytes([0xff; 16]),
+                ));
+            for entry in self
+                .message_creation_time_db
+                .range(rtxn, &range)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                let (HeedMessageCreationTimeId((_, _, message_id)), ()) =
+                    entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+                if let Some(message) = self
+                    .messages_db
+                    .get(rtxn, &(thread_id, message_id).into())
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                {
+                    send(sender, Ok(ExportRecord::Message(message)), deadline)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits for room in the buffer until the deadline, so neither a long
+/// export nor a consumer which stopped reading holds the snapshot forever.
+fn send(
+    sender: &Sender,
+    mut record: Result<ExportRecord, DatabaseError>,
+    deadline: Instant,
+) -> Result<(), Stop> {
+    loop {
+        if Instant::now() >= deadline {
+            return Err(Stop::Failed(DatabaseError::OperationFailed(
+                "the export timed out".to_string(),
+            )));
+        }
+        match sender.try_send(record) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_)) => return Err(Stop::Closed),
+            Err(TrySendError::Full(returned)) => {
+                record = returned;
+                std::thread::sleep(SEND_RETRY_INTERVAL);
+            }
+        }
+    }
+}
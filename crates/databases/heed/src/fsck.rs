@@ -0,0 +1,230 @@
+//! Consistency checks of the databases against one another, for stores
+//! written before bugs that left them inconsistent were fixed, e.g.
+//! deletions leaving their message lists or creation-time keys behind.
+
+use heed::types::DecodeIgnore;
+use synx_database::DatabaseError;
+use uuid::Uuid;
+
+use crate::{
+    heed_ids::{HeedMessageCreationTimeId, HeedUuid},
+    OrphanReport, SynxHeedDatabase,
+};
+
+/// Inconsistencies found by [`SynxHeedDatabase::check_consistency`].
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ConsistencyReport {
+    pub orphans: OrphanReport,
+    /// Message ids listed by a thread without a stored message.
+    pub dangling_thread_messages: usize,
+    /// Messages missing from the creation-time index, which orders their
+    /// thread's listing and export.
+    pub missing_creation_times: usize,
+    /// Embeddings of threads without a summary, which searches rank as if
+    /// the thread had one.
+    pub embeddings_without_summaries: usize,
+    /// Threads with a summary but no embedding, which searches leave out.
+    /// They are only reported, storing their summary again embeds it.
+    pub summaries_without_embeddings: Vec<Uuid>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.orphans.total() == 0
+            && self.dangling_thread_messages == 0
+            && self.missing_creation_times == 0
+            && self.embeddings_without_summaries == 0
+            && self.summaries_without_embeddings.is_empty()
+    }
+}
+
+impl SynxHeedDatabase {
+    /// Checks the databases against one another: the orphans of
+    /// [`scan_orphans`](Self::scan_orphans), then message lists, the
+    /// creation-time index, and embeddings, for the threads that exist.
+    /// Inconsistencies are repaired when `repair` is set, except summaries
+    /// without embeddings. Unlike the orphan scan, messages and threads are
+    /// decoded, which takes the encryption keys.
+    pub fn check_consistency(&self, repair: bool) -> Result<ConsistencyReport, DatabaseError> {
+        self.keyed(|| self.check_values(repair))
+    }
+
+    fn check_values(&self, repair: bool) -> Result<ConsistencyReport, DatabaseError> {
+        let orphans = self.scan_orphans(repair)?;
+
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+        // Threads whose message list names missing messages, along with the
+        // messages it should list.
+        let mut dangling_thread_messages = 0;
+        let mut message_lists = Vec::new();
+        for entry in self
+            .thread_messages_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (HeedUuid(thread_id), message_ids) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, thread_id)? {
+                continue;
+            }
+
+            let mut kept = Vec::with_capacity(message_ids.len());
+            for message_id in &message_ids {
+                if self
+                    .messages_db
+                    .remap_data_type::<DecodeIgnore>()
+                    .get(&wtxn, &(thread_id, *message_id).into())
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                    .is_some()
+                {
+                    kept.push(*message_id);
+                }
+            }
+            if kept.len() < message_ids.len() {
+                dangling_thread_messages += message_ids.len() - kept.len();
+                message_lists.push((thread_id, kept));
+            }
+        }
+
+        let mut creation_times = Vec::new();
+        for entry in self
+            .messages_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (_, message) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, message.thread_id)? {
+                continue;
+            }
+
+            let key = HeedMessageCreationTimeId::from((
+                message.thread_id,
+                message.created_at,
+                message.id,
+            ));
+            if self
+                .message_creation_time_db
+                .get(&wtxn, &key)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_none()
+            {
+                creation_times.push(key);
+            }
+        }
+
+        let mut embeddings = Vec::new();
+        let mut summaries_without_embeddings = Vec::new();
+        for entry in self
+            .threads_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (_, thread) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            let embedded = self
+                .embeddings_db
+                .remap_data_type::<DecodeIgnore>()
+                .get(&wtxn, &thread.id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_some();
+            match (thread.summary.is_some(), embedded) {
+                (true, false) => summaries_without_embeddings.push(thread.id),
+                (false, true) => embeddings.push(thread.id),
+                _ => {}
+            }
+        }
+
+        let report = ConsistencyReport {
+            orphans,
+            dangling_thread_messages,
+            missing_creation_times: creation_times.len(),
+            embeddings_without_summaries: embeddings.len(),
+            summaries_without_embeddings,
+        };
+
+        if !repair {
+            wtxn.abort();
+            return Ok(report);
+        }
+
+        for (thread_id, message_ids) in message_lists {
+            self.thread_messages_db
+                .put(&mut wtxn, &thread_id.into(), &message_ids)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for key in creation_times {
+            self.message_creation_time_db
+                .put(&mut wtxn, &key, &())
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for thread_id in embeddings {
+            self.embeddings_db
+                .delete(&mut wtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+
+        wtxn.commit()
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_database::Db;
+    use synx_domain::{message::CreateMessage, role::Role, thread::CreateThread};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn inconsistencies_are_repaired() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SynxHeedDatabase::builder()
+            .with_map_size(64 * 1024 * 1024)
+            .open(dir.path())
+            .unwrap();
+        let thread = db.create_thread(CreateThread::default()).await.unwrap();
+        let message = db
+            .create_message(
+                thread.id,
+                CreateMessage {
+                    role: Role::User,
+                    content: "Hello".to_string().into(),
+                    parent_message_id: None,
+                    metadata: Default::default(),
+                    status: Default::default(),
+                    memorize: true,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(db.check_consistency(false).unwrap().is_consistent());
+
+        let mut wtxn = db.env.write_txn().unwrap();
+        db.message_creation_time_db
+            .delete(
+                &mut wtxn,
+                &(thread.id, message.created_at, message.id).into(),
+            )
+            .unwrap();
+        db.update_thread_messages(&mut wtxn, thread.id, |message_ids| {
+            message_ids.push(Uuid::new_v4())
+        })
+        .unwrap();
+        db.embeddings_db
+            .put(&mut wtxn, &thread.id.into(), &vec![1.0, 0.0].into())
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        let report = db.check_consistency(true).unwrap();
+        assert_eq!(report.dangling_thread_messages, 1);
+        assert_eq!(report.missing_creation_times, 1);
+        assert_eq!(report.embeddings_without_summaries, 1);
+        assert!(db.check_consistency(false).unwrap().is_consistent());
+    }
+}
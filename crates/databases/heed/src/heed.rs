@@ -1,43 +1,145 @@
+mod compression;
+pub mod encryption;
+mod export;
+mod fsck;
 mod heed_ids;
+mod map_usage;
+mod writer;
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    ops::{Bound, RangeInclusive},
+    path::Path,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
 
+use encryption::{requiring_encryption, with_keyring, EncryptedBytes, EncryptedJson, Keyring};
+pub use export::{DEFAULT_EXPORT_TIMEOUT, DEFAULT_MAX_EXPORTS};
+pub use fsck::ConsistencyReport;
+use futures::StreamExt;
 pub use heed;
 use heed::{
-    types::{SerdeJson, Unit},
-    Database, Env,
+    byteorder::BigEndian,
+    types::{DecodeIgnore, SerdeJson, Str, Unit, U64},
+    Database, Env, EnvFlags, EnvOpenOptions,
 };
 use heed_ids::{HeedMessageCreationTimeId, HeedTimestampUuid, HeedUuid, HeedUuidTuple};
-use synx_database::{DatabaseError, Db};
+pub use map_usage::{MapUsage, DEFAULT_MAP_USAGE_WARNING};
+use synx_database::{BatchOp, DatabaseError, Db, ExportStream, ThreadStream, WriteBatch};
 use synx_domain::{
+    annotation::{Annotation, SearchFeedback},
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
     embedding::Embedding,
-    message::{CreateMessage, Message, ThreadMessagesResponse, UpdateMessage},
-    thread::{Thread, UpdateThread},
+    message::{CreateMessage, Message, PatchMessage, ThreadMessagesResponse, UpdateMessage},
+    read::ReadMarker,
+    sync::{Operation, OperationFilter, OperationKind, SyncCursor},
+    thread::{
+        CreateThread, PatchThread, PendingSummary, ShadowSummary, SummaryProvenance,
+        SummaryRedactions, Thread, ThreadFilter, ThreadVector, UpdateThread,
+    },
+    usage::{DailyUsage, UsageFilter},
 };
+use tokio::sync::Semaphore;
 use uuid::Uuid;
+use writer::{PendingMessage, Writer};
+pub use writer::{WriterStats, DEFAULT_WRITE_QUEUE_CAPACITY};
+
+/// Threads read per transaction by [`Db::stream_threads`].
+const LISTING_BATCH_SIZE: usize = 256;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SynxHeedDatabase {
     env: Arc<heed::Env>,
-    threads_db: Database<HeedUuid, SerdeJson<Thread>>,
-    messages_db: Database<HeedUuidTuple, SerdeJson<Message>>,
+    writer: Arc<Writer>,
+    export_slots: Arc<Semaphore>,
+    export_timeout: Duration,
+    page_size: u64,
+    map_usage_warning: f64,
+    map_usage_warned: Arc<AtomicBool>,
+    /// Encrypts the values written and decrypts the values read, see
+    /// [`HeedBuilder::with_keyring`].
+    keyring: Option<Arc<Keyring>>,
+    /// Fails writes rather than storing plain values without a keyring, see
+    /// [`HeedBuilder::with_encryption_required`].
+    encryption_required: bool,
+    threads_db: Database<HeedUuid, EncryptedJson<Thread>>,
+    messages_db: Database<HeedUuidTuple, EncryptedJson<Message>>,
     thread_messages_db: Database<HeedUuid, SerdeJson<Vec<Uuid>>>,
-    embeddings_db: Database<HeedUuid, SerdeJson<Embedding>>,
+    embeddings_db: Database<HeedUuid, EncryptedJson<Embedding>>,
+    /// Keyed by thread id and vector id, so a thread's vectors are read and
+    /// deleted as a range.
+    vectors_db: Database<HeedUuidTuple, EncryptedJson<Embedding>>,
     thread_creation_time_db: Database<HeedTimestampUuid, Unit>,
     message_creation_time_db: Database<HeedMessageCreationTimeId, Unit>,
+    audit_db: Database<HeedTimestampUuid, EncryptedJson<AuditEntry>>,
+    operations_db: Database<U64<BigEndian>, EncryptedJson<Operation>>,
+    /// Deletion timestamps of the threads and messages deleted by an
+    /// operation of the op-log.
+    tombstones_db: Database<HeedUuid, U64<BigEndian>>,
+    sync_cursors_db: Database<Str, SerdeJson<SyncCursor>>,
+    shadow_summaries_db: Database<HeedUuid, EncryptedJson<ShadowSummary>>,
+    summary_redactions_db: Database<HeedUuid, EncryptedJson<SummaryRedactions>>,
+    usage_db: Database<Str, EncryptedJson<DailyUsage>>,
+    pinned_threads_db: Database<HeedUuid, Unit>,
+    read_markers_db: Database<Str, SerdeJson<ReadMarker>>,
+    annotations_db: Database<Str, EncryptedJson<Annotation>>,
+    search_feedback_db: Database<Str, EncryptedJson<SearchFeedback>>,
+    pending_summaries_db: Database<Str, EncryptedJson<PendingSummary>>,
+    blobs_db: Database<Str, EncryptedJson<Blob>>,
+    blob_data_db: Database<Str, EncryptedBytes>,
 }
 
 impl SynxHeedDatabase {
-    fn apply_pagination<T>(
-        items: Vec<T>,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> (Vec<T>, usize, usize, usize) {
-        let total = items.len();
-        let offset = offset.unwrap_or(0);
-        let limit = limit.unwrap_or(total);
-        let paginated_items = items.into_iter().skip(offset).take(limit).collect();
-        (paginated_items, total, offset, limit)
+    pub fn builder() -> HeedBuilder {
+        HeedBuilder::default()
+    }
+
+    /// Number of named databases the environment must be opened with,
+    /// including the legacy `message_creation_time` index.
+    pub const MAX_DBS: u32 = 22;
+
+    /// Runs LMDB reads on tokio's blocking pool, so transactions don't stall
+    /// the runtime's worker threads. Writes go through [`Self::write`].
+    async fn blocking<T, F>(&self, f: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || db.keyed(|| f(&db)))
+            .await
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+    }
+
+    /// Runs LMDB write work on the writer thread, in submission order. See
+    /// [`writer`].
+    async fn write<T, F>(&self, f: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Self) -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let db = self.clone();
+        self.writer
+            .run(move || {
+                let result = db.keyed(|| f(&db));
+                db.check_map_usage();
+                result
+            })
+            .await
+    }
+
+    /// Runs `f` with the database's keyring encrypting and decrypting the
+    /// values its transactions touch.
+    pub(crate) fn keyed<T>(&self, f: impl FnOnce() -> T) -> T {
+        requiring_encryption(self.encryption_required, || {
+            with_keyring(self.keyring.as_ref(), f)
+        })
+    }
+
+    pub fn writer_stats(&self) -> WriterStats {
+        self.writer.stats()
     }
 
     fn get_thread_with_embedding(
@@ -57,6 +159,15 @@ impl SynxHeedDatabase {
             {
                 thread.embedding = Some(embedding);
             }
+            thread.vectors = self
+                .vectors_db
+                .range(rtxn, &thread_vectors(*id))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .map(|entry| {
+                    entry.map(|(HeedUuidTuple((_, id)), embedding)| ThreadVector { id, embedding })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
         }
         Ok(thread)
     }
@@ -72,10 +183,77 @@ impl SynxHeedDatabase {
         self.thread_messages_db
             .put(wtxn, &thread.id().into(), &Vec::new())
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        let timestamp = chrono::Utc::now().timestamp() as u64;
         self.thread_creation_time_db
-            .put(wtxn, &(timestamp, thread.id()).into(), &())
+            .put(wtxn, &(thread.created_at, thread.id()).into(), &())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        Ok(())
+    }
+
+    fn append_operation_internal(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        operation: Operation,
+    ) -> Result<u64, DatabaseError> {
+        // The write transaction is exclusive, so the next sequence number
+        // can't be taken by anyone else before the commit.
+        let seq = self
+            .operations_db
+            .remap_data_type::<DecodeIgnore>()
+            .last(wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map_or(1, |(last, ())| last + 1);
+        for id in operation.kind.deleted_ids() {
+            let kept = self
+                .tombstones_db
+                .get(wtxn, &id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+            if kept.is_none() {
+                self.tombstones_db
+                    .put(wtxn, &id.into(), &operation.timestamp)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            }
+        }
+        let purge =
+            matches!(operation.kind, OperationKind::Purge { .. }).then(|| operation.kind.clone());
+        self.operations_db
+            .put(wtxn, &seq, &Operation { seq, ..operation })
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+        if let Some(purge) = purge {
+            let thread_id = purge.thread_id();
+            let purged = self
+                .operations_db
+                .range(wtxn, &(..seq))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .filter(|entry| {
+                    entry.as_ref().map_or(true, |(_, operation)| {
+                        operation.kind.thread_id() == thread_id
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            for (purged_seq, operation) in purged {
+                match operation.purged_by(&purge) {
+                    Some(operation) => self.operations_db.put(wtxn, &purged_seq, &operation),
+                    None => self.operations_db.delete(wtxn, &purged_seq).map(|_| ()),
+                }
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            }
+        }
+        Ok(seq)
+    }
+
+    /// Keeps the pinned index in line with a thread being written.
+    fn index_pinned(&self, wtxn: &mut heed::RwTxn, thread: &Thread) -> Result<(), DatabaseError> {
+        if thread.pinned {
+            self.pinned_threads_db
+                .put(wtxn, &thread.id.into(), &())
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        } else {
+            self.pinned_threads_db
+                .delete(wtxn, &thread.id.into())
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
         Ok(())
     }
 
@@ -84,6 +262,21 @@ impl SynxHeedDatabase {
         wtxn: &mut heed::RwTxn,
         thread_id: Uuid,
     ) -> Result<(), DatabaseError> {
+        // The message ids must be read before the thread's entry is removed,
+        // otherwise its messages would be left behind in `messages_db`.
+        let message_ids = self
+            .thread_messages_db
+            .get(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .unwrap_or_default();
+        for message_id in message_ids {
+            self.delete_message_entries(wtxn, thread_id, message_id)?;
+        }
+
+        let thread = self
+            .threads_db
+            .get(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
         self.threads_db
             .delete(wtxn, &thread_id.into())
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
@@ -93,31 +286,309 @@ impl SynxHeedDatabase {
         self.embeddings_db
             .delete(wtxn, &thread_id.into())
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.vectors_db
+            .delete_range(wtxn, &thread_vectors(thread_id))
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.shadow_summaries_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.summary_redactions_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.pinned_threads_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        delete_prefixed(&self.read_markers_db, wtxn, &format!("{}/", thread_id))?;
+        delete_prefixed(&self.search_feedback_db, wtxn, &format!("{}/", thread_id))?;
+        delete_prefixed(&self.pending_summaries_db, wtxn, &format!("{}/", thread_id))?;
 
-        let message_ids = self
-            .thread_messages_db
+        let created_at = match thread.map(|thread| thread.created_at) {
+            Some(created_at) if created_at > 0 => Some(created_at),
+            // Threads stored before `created_at` existed were indexed with a
+            // timestamp they don't carry, so their key has to be looked up.
+            _ => self
+                .thread_creation_time_db
+                .iter(wtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .find(|(HeedTimestampUuid((_, id)), _)| *id == thread_id)
+                .map(|(HeedTimestampUuid((timestamp, _)), _)| timestamp),
+        };
+        if let Some(created_at) = created_at {
+            self.thread_creation_time_db
+                .delete(wtxn, &(created_at, thread_id).into())
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the thread of a new message, and its parent, exist before
+    /// storing it.
+    fn insert_message(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        message: &Message,
+    ) -> Result<(), DatabaseError> {
+        let thread_id = message.thread_id;
+        if self
+            .threads_db
             .get(wtxn, &thread_id.into())
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .unwrap_or_default();
-        for message_id in message_ids {
-            self.delete_message_internal(wtxn, thread_id, message_id)?;
+            .is_none()
+        {
+            return Err(DatabaseError::NotFound);
+        }
+
+        if let Some(parent_id) = message.parent_message_id {
+            if self
+                .messages_db
+                .remap_data_type::<DecodeIgnore>()
+                .get(wtxn, &(thread_id, parent_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_none()
+            {
+                return Err(DatabaseError::InvalidInput(format!(
+                    "message {} is not in thread {}",
+                    parent_id, thread_id
+                )));
+            }
+        }
+
+        self.create_message_internal(wtxn, message)
+    }
+
+    /// Stores new messages in one transaction, reporting each one's result.
+    /// A message failing its checks is left out alone, while any other
+    /// failure aborts the transaction, and the messages are then stored in
+    /// one transaction each.
+    pub(crate) fn create_message_group(&self, group: Vec<PendingMessage>) {
+        let grouped = (|| {
+            let mut wtxn = self
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            let results = group
+                .iter()
+                .map(
+                    |pending| match self.insert_message(&mut wtxn, &pending.message) {
+                        Ok(()) => Ok(Ok(())),
+                        Err(e @ (DatabaseError::NotFound | DatabaseError::InvalidInput(_))) => {
+                            Ok(Err(e))
+                        }
+                        Err(e) => Err(e),
+                    },
+                )
+                .collect::<Result<Vec<_>, DatabaseError>>()?;
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok::<_, DatabaseError>(results)
+        })();
+
+        let results = match grouped {
+            Ok(results) => results,
+            Err(e) => {
+                tracing::warn!(
+                    "Group commit of {} messages failed, storing them one by one: {}",
+                    group.len(),
+                    e
+                );
+                group
+                    .iter()
+                    .map(|pending| {
+                        let mut wtxn = self
+                            .env
+                            .write_txn()
+                            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                        self.insert_message(&mut wtxn, &pending.message)?;
+                        wtxn.commit()
+                            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))
+                    })
+                    .collect()
+            }
+        };
+
+        self.check_map_usage();
+        for (pending, result) in group.into_iter().zip(results) {
+            let _ = pending.result.send(result.map(|()| pending.message));
         }
+    }
 
-        if let Some((HeedTimestampUuid((_, id)), _)) = self
-            .thread_creation_time_db
-            .get_greater_than_or_equal_to(wtxn, &(0, thread_id).into())
+    fn pinned_threads(&self, rtxn: &heed::RoTxn) -> Result<Vec<Thread>, DatabaseError> {
+        let mut threads = Vec::new();
+        for entry in self
+            .pinned_threads_db
+            .iter(rtxn)
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?
         {
-            if id == thread_id {
-                self.thread_creation_time_db
-                    .delete(wtxn, &(0, thread_id).into())
-                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            let (thread_id, ()) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if let Some(thread) = self
+                .threads_db
+                .get(rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                threads.push(thread);
+            }
+        }
+        Ok(threads)
+    }
+
+    /// Up to `limit` threads stored after `after`, in storage order, leaving
+    /// out pinned ones, along with the key to read on from, `None` once
+    /// every thread was read.
+    fn unpinned_threads_after(
+        &self,
+        after: Option<Uuid>,
+        limit: usize,
+    ) -> Result<(Vec<Thread>, Option<Uuid>), DatabaseError> {
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        let start = match after {
+            Some(after) => Bound::Excluded(HeedUuid(after)),
+            None => Bound::Unbounded,
+        };
+
+        let mut threads = Vec::new();
+        let mut scanned = 0;
+        let mut last = None;
+        for entry in self
+            .threads_db
+            .range(&rtxn, &(start, Bound::Unbounded))
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .take(limit)
+        {
+            let (HeedUuid(thread_id), thread) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            scanned += 1;
+            last = Some(thread_id);
+            if !thread.pinned {
+                threads.push(thread);
             }
         }
 
+        Ok((threads, if scanned == limit { last } else { None }))
+    }
+
+    fn clear_thread_summary_internal(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        thread_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        let Some(mut thread) = self
+            .threads_db
+            .get(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        else {
+            return Err(DatabaseError::NotFound);
+        };
+        thread.clear_summary();
+        self.threads_db
+            .put(wtxn, &thread_id.into(), &thread)
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.embeddings_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.vectors_db
+            .delete_range(wtxn, &thread_vectors(thread_id))
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.shadow_summaries_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        self.summary_redactions_db
+            .delete(wtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
         Ok(())
     }
 
+    /// Applies an operation of a [`WriteBatch`] with the same checks as the
+    /// matching single write.
+    fn apply_batch_op(&self, wtxn: &mut heed::RwTxn, op: BatchOp) -> Result<(), DatabaseError> {
+        match op {
+            BatchOp::CreateThread(thread) => {
+                if self.thread_exists(wtxn, thread.id)? {
+                    return Err(DatabaseError::InvalidInput(format!(
+                        "thread {} already exists",
+                        thread.id
+                    )));
+                }
+                self.create_thread_internal(wtxn, &thread)?;
+                self.index_pinned(wtxn, &thread)?;
+                if let Some(embedding) = &thread.embedding {
+                    self.embeddings_db
+                        .put(wtxn, &thread.id.into(), embedding)
+                        .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                }
+                Ok(())
+            }
+            BatchOp::CreateMessage(message) => self.insert_message(wtxn, &message),
+            BatchOp::DeleteMessage {
+                thread_id,
+                message_id,
+            } => {
+                if self
+                    .messages_db
+                    .remap_data_type::<DecodeIgnore>()
+                    .get(wtxn, &(thread_id, message_id).into())
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                    .is_none()
+                {
+                    return Err(DatabaseError::NotFound);
+                }
+                self.delete_message_internal(wtxn, thread_id, message_id)
+            }
+            BatchOp::PruneMessage {
+                thread_id,
+                message_id,
+            } => {
+                self.apply_batch_op(
+                    wtxn,
+                    BatchOp::DeleteMessage {
+                        thread_id,
+                        message_id,
+                    },
+                )?;
+                let Some(mut thread) = self
+                    .threads_db
+                    .get(wtxn, &thread_id.into())
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                else {
+                    return Err(DatabaseError::NotFound);
+                };
+                thread.pruned_message_count += 1;
+                self.threads_db
+                    .put(wtxn, &thread_id.into(), &thread)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))
+            }
+            BatchOp::DeleteThread(thread_id) => {
+                if !self.thread_exists(wtxn, thread_id)? {
+                    return Err(DatabaseError::NotFound);
+                }
+                self.delete_thread_internal(wtxn, thread_id)
+            }
+            BatchOp::ClearSummary(thread_id) => self.clear_thread_summary_internal(wtxn, thread_id),
+            BatchOp::PutThreadVector { thread_id, vector } => {
+                if !self.thread_exists(wtxn, thread_id)? {
+                    return Err(DatabaseError::NotFound);
+                }
+                self.vectors_db
+                    .put(wtxn, &(thread_id, vector.id).into(), &vector.embedding)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))
+            }
+            BatchOp::DeleteThreadVectors(thread_id) => {
+                if !self.thread_exists(wtxn, thread_id)? {
+                    return Err(DatabaseError::NotFound);
+                }
+                self.vectors_db
+                    .delete_range(wtxn, &thread_vectors(thread_id))
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                Ok(())
+            }
+        }
+    }
+
     fn create_message_internal(
         &self,
         wtxn: &mut heed::RwTxn,
@@ -131,9 +602,12 @@ impl SynxHeedDatabase {
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
         self.update_thread_messages(wtxn, thread_id, |ids| ids.push(message_id))?;
 
-        let timestamp = message.created_at().timestamp() as u64;
         self.message_creation_time_db
-            .put(wtxn, &(thread_id, timestamp, message_id).into(), &())
+            .put(
+                wtxn,
+                &(thread_id, message.created_at, message_id).into(),
+                &(),
+            )
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
 
         Ok(())
@@ -145,26 +619,50 @@ impl SynxHeedDatabase {
         thread_id: Uuid,
         message_id: Uuid,
     ) -> Result<(), DatabaseError> {
+        self.delete_message_entries(wtxn, thread_id, message_id)?;
+        self.update_thread_messages(wtxn, thread_id, |ids| ids.retain(|&id| id != message_id))
+    }
+
+    /// Removes a message and its creation-time entry, leaving the thread's
+    /// message list untouched.
+    fn delete_message_entries(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        let Some(message) = self
+            .messages_db
+            .get(wtxn, &(thread_id, message_id).into())
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        else {
+            return Ok(());
+        };
+
         self.messages_db
             .delete(wtxn, &(thread_id, message_id).into())
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        self.update_thread_messages(wtxn, thread_id, |ids| ids.retain(|&id| id != message_id))?;
-
-        if let Some((HeedMessageCreationTimeId((t_id, _, m_id)), _)) = self
-            .message_creation_time_db
-            .get_greater_than_or_equal_to(wtxn, &(thread_id, 0, message_id).into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-        {
-            if t_id == thread_id && m_id == message_id {
-                self.message_creation_time_db
-                    .delete(wtxn, &(thread_id, 0, message_id).into())
-                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            }
-        }
+        self.message_creation_time_db
+            .delete(wtxn, &(thread_id, message.created_at, message_id).into())
+            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        delete_prefixed(
+            &self.annotations_db,
+            wtxn,
+            &format!("{}/{}/", thread_id, message_id),
+        )?;
 
         Ok(())
     }
 
+    fn thread_exists(&self, rtxn: &heed::RoTxn, thread_id: Uuid) -> Result<bool, DatabaseError> {
+        Ok(self
+            .threads_db
+            .remap_data_type::<DecodeIgnore>()
+            .get(rtxn, &thread_id.into())
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .is_some())
+    }
+
     fn update_thread_messages<F>(
         &self,
         wtxn: &mut heed::RwTxn,
@@ -187,6 +685,15 @@ impl SynxHeedDatabase {
     }
 
     pub fn new(env: Arc<Env>, create_databases: bool) -> Result<Self, DatabaseError> {
+        Self::open_env(env, create_databases, DEFAULT_WRITE_QUEUE_CAPACITY, None)
+    }
+
+    fn open_env(
+        env: Arc<Env>,
+        create_databases: bool,
+        write_queue_capacity: usize,
+        keyring: Option<Arc<Keyring>>,
+    ) -> Result<Self, DatabaseError> {
         let mut wtxn = env
             .write_txn()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
@@ -222,6 +729,14 @@ impl SynxHeedDatabase {
                 .map_err(|e| DatabaseError::QueryError(e.to_string()))?
                 .ok_or_else(|| DatabaseError::NotFound)?
         };
+        let vectors_db = if create_databases {
+            env.create_database(&mut wtxn, Some("thread_vectors"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("thread_vectors"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
         let thread_creation_time_db = if create_databases {
             env.create_database(&mut wtxn, Some("thread_creation_time"))
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
@@ -231,354 +746,2108 @@ impl SynxHeedDatabase {
                 .ok_or_else(|| DatabaseError::NotFound)?
         };
         let message_creation_time_db = if create_databases {
-            env.create_database(&mut wtxn, Some("message_creation_time"))
+            env.create_database(&mut wtxn, Some("message_created_at"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("message_created_at"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        // The legacy index was keyed by seconds, so messages created within
+        // the same second couldn't be ordered, and deletions never removed
+        // its entries. It is emptied and rebuilt at millisecond resolution.
+        if let Some(legacy_db) = env
+            .open_database::<HeedMessageCreationTimeId, Unit>(&wtxn, Some("message_creation_time"))
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            legacy_db
+                .clear(&mut wtxn)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        let audit_db = if create_databases {
+            env.create_database(&mut wtxn, Some("audit_log"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("audit_log"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let operations_db = if create_databases {
+            env.create_database(&mut wtxn, Some("operations"))
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
         } else {
-            env.open_database(&wtxn, Some("message_creation_time"))
+            env.open_database(&wtxn, Some("operations"))
                 .map_err(|e| DatabaseError::QueryError(e.to_string()))?
                 .ok_or_else(|| DatabaseError::NotFound)?
         };
+        let tombstones_db = if create_databases {
+            env.create_database(&mut wtxn, Some("tombstones"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("tombstones"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let sync_cursors_db = if create_databases {
+            env.create_database(&mut wtxn, Some("sync_cursors"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("sync_cursors"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let shadow_summaries_db = if create_databases {
+            env.create_database(&mut wtxn, Some("shadow_summaries"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("shadow_summaries"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let summary_redactions_db = if create_databases {
+            env.create_database(&mut wtxn, Some("summary_redactions"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("summary_redactions"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let usage_db = if create_databases {
+            env.create_database(&mut wtxn, Some("usage"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("usage"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let pinned_threads_db = if create_databases {
+            env.create_database(&mut wtxn, Some("pinned_threads"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("pinned_threads"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let read_markers_db = if create_databases {
+            env.create_database(&mut wtxn, Some("read_markers"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("read_markers"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let annotations_db = if create_databases {
+            env.create_database(&mut wtxn, Some("annotations"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("annotations"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let search_feedback_db = if create_databases {
+            env.create_database(&mut wtxn, Some("search_feedback"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("search_feedback"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let pending_summaries_db = if create_databases {
+            env.create_database(&mut wtxn, Some("pending_summaries"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("pending_summaries"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let blobs_db = if create_databases {
+            env.create_database(&mut wtxn, Some("blobs"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("blobs"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let blob_data_db = if create_databases {
+            env.create_database(&mut wtxn, Some("blob_data"))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+        } else {
+            env.open_database(&wtxn, Some("blob_data"))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or_else(|| DatabaseError::NotFound)?
+        };
+        let page_size = threads_db
+            .stat(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .page_size as u64;
         wtxn.commit()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
 
-        Ok(Self {
+        let db = Self {
             env,
+            writer: Arc::new(Writer::spawn(write_queue_capacity)?),
+            export_slots: Arc::new(Semaphore::new(DEFAULT_MAX_EXPORTS)),
+            export_timeout: DEFAULT_EXPORT_TIMEOUT,
+            page_size,
+            map_usage_warning: DEFAULT_MAP_USAGE_WARNING,
+            map_usage_warned: Arc::new(AtomicBool::new(false)),
+            keyring,
+            encryption_required: false,
             threads_db,
             messages_db,
             thread_messages_db,
             embeddings_db,
+            vectors_db,
             thread_creation_time_db,
             message_creation_time_db,
-        })
-    }
-}
-
-#[async_trait::async_trait]
-impl Db for SynxHeedDatabase {
-    async fn get_threads_with_embeddings(
-        &self,
-        thread_ids: &[Uuid],
-    ) -> Result<Vec<Thread>, DatabaseError> {
-        let rtxn = self
-            .env
-            .read_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        let threads = thread_ids
-            .iter()
-            .filter_map(|&id| self.get_thread_with_embedding(&rtxn, &id).transpose())
-            .collect::<Result<Vec<Thread>, DatabaseError>>()?;
-        Ok(threads)
+            audit_db,
+            operations_db,
+            tombstones_db,
+            sync_cursors_db,
+            shadow_summaries_db,
+            summary_redactions_db,
+            usage_db,
+            pinned_threads_db,
+            read_markers_db,
+            annotations_db,
+            search_feedback_db,
+            pending_summaries_db,
+            blobs_db,
+            blob_data_db,
+        };
+        db.keyed(|| db.backfill_message_creation_time())?;
+
+        Ok(db)
     }
 
-    async fn update_thread_summary_and_embedding(
-        &self,
-        thread_id: Uuid,
-        summary: String,
-        embedding: Embedding,
-    ) -> Result<(), DatabaseError> {
+    /// Indexes the messages written before the creation-time index existed.
+    fn backfill_message_creation_time(&self) -> Result<(), DatabaseError> {
         let mut wtxn = self
             .env
             .write_txn()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
 
-        if let Some(mut thread) = self
-            .threads_db
-            .get(&wtxn, &thread_id.into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-        {
-            thread.set_summary(summary);
-            self.threads_db
-                .put(&mut wtxn, &thread_id.into(), &thread)
-                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        } else {
-            return Err(DatabaseError::NotFound);
+        let indexed = self
+            .message_creation_time_db
+            .len(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        let stored = self
+            .messages_db
+            .len(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        if indexed == stored {
+            return Ok(());
         }
 
-        self.embeddings_db
-            .put(&mut wtxn, &thread_id.into(), &embedding)
+        self.message_creation_time_db
+            .clear(&mut wtxn)
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        let messages = self
+            .messages_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(_, message)| message))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        for message in messages {
+            self.message_creation_time_db
+                .put(
+                    &mut wtxn,
+                    &(message.thread_id, message.created_at, message.id).into(),
+                    &(),
+                )
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
 
         wtxn.commit()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
         Ok(())
     }
 
-    async fn create_thread(&self) -> Result<Thread, DatabaseError> {
-        let thread = Thread::new();
+    /// Rewrites every encrypted value with the active key of the database's
+    /// keyring, so retired keys can be dropped after a rotation. Plain values
+    /// written before encryption was enabled get encrypted along the way.
+    pub fn reencrypt(&self) -> Result<usize, DatabaseError> {
+        self.keyed(|| self.reencrypt_values())
+    }
+
+    fn reencrypt_values(&self) -> Result<usize, DatabaseError> {
         let mut wtxn = self
             .env
             .write_txn()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
 
-        self.create_thread_internal(&mut wtxn, &thread)?;
-
-        if self
+        let threads = self
             .threads_db
-            .get(&wtxn, &thread.id().into())
+            .iter(&wtxn)
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .is_none()
-        {
-            return Err(DatabaseError::OperationFailed(
-                "Thread not found after insertion".to_string(),
-            ));
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let messages = self
+            .messages_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let embeddings = self
+            .embeddings_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let vectors = self
+            .vectors_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let operations = self
+            .operations_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let shadow_summaries = self
+            .shadow_summaries_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let annotations = self
+            .annotations_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(key, annotation)| (key.to_string(), annotation)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let search_feedback = self
+            .search_feedback_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(key, feedback)| (key.to_string(), feedback)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let blob_data = self
+            .blob_data_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(hash, data)| (hash.to_string(), data)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let audit_entries = self
+            .audit_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let summary_redactions = self
+            .summary_redactions_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let usage = self
+            .usage_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(key, usage)| (key.to_string(), usage)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let pending_summaries = self
+            .pending_summaries_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(key, pending)| (key.to_string(), pending)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let blobs = self
+            .blobs_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            .map(|entry| entry.map(|(key, blob)| (key.to_string(), blob)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+
+        let count = threads.len()
+            + messages.len()
+            + embeddings.len()
+            + vectors.len()
+            + operations.len()
+            + shadow_summaries.len()
+            + annotations.len()
+            + search_feedback.len()
+            + blob_data.len()
+            + audit_entries.len()
+            + summary_redactions.len()
+            + usage.len()
+            + pending_summaries.len()
+            + blobs.len();
+
+        for (id, thread) in threads {
+            self.threads_db
+                .put(&mut wtxn, &id, &thread)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (id, message) in messages {
+            self.messages_db
+                .put(&mut wtxn, &id, &message)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (id, embedding) in embeddings {
+            self.embeddings_db
+                .put(&mut wtxn, &id, &embedding)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, embedding) in vectors {
+            self.vectors_db
+                .put(&mut wtxn, &key, &embedding)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (seq, operation) in operations {
+            self.operations_db
+                .put(&mut wtxn, &seq, &operation)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (id, shadow) in shadow_summaries {
+            self.shadow_summaries_db
+                .put(&mut wtxn, &id, &shadow)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, annotation) in annotations {
+            self.annotations_db
+                .put(&mut wtxn, &key, &annotation)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, feedback) in search_feedback {
+            self.search_feedback_db
+                .put(&mut wtxn, &key, &feedback)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (hash, data) in blob_data {
+            self.blob_data_db
+                .put(&mut wtxn, &hash, &data)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, entry) in audit_entries {
+            self.audit_db
+                .put(&mut wtxn, &key, &entry)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (id, redactions) in summary_redactions {
+            self.summary_redactions_db
+                .put(&mut wtxn, &id, &redactions)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, daily) in usage {
+            self.usage_db
+                .put(&mut wtxn, &key, &daily)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (key, pending) in pending_summaries {
+            self.pending_summaries_db
+                .put(&mut wtxn, &key, &pending)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        }
+        for (hash, blob) in blobs {
+            self.blobs_db
+                .put(&mut wtxn, &hash, &blob)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
         }
 
         wtxn.commit()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        Ok(thread)
+        Ok(count)
     }
 
-    async fn delete_thread(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+    /// Looks for entries referencing threads that no longer exist, such as
+    /// the messages left behind by deletions before the cascade was fixed,
+    /// and removes them when `remove` is set. Values are never decoded, so
+    /// the scan works without the encryption keys.
+    pub fn scan_orphans(&self, remove: bool) -> Result<OrphanReport, DatabaseError> {
         let mut wtxn = self
             .env
             .write_txn()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
 
-        if self
-            .threads_db
-            .get(&wtxn, &thread_id.into())
+        let mut messages = Vec::new();
+        for entry in self
+            .messages_db
+            .remap_data_type::<DecodeIgnore>()
+            .iter(&wtxn)
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .is_some()
         {
-            self.delete_thread_internal(&mut wtxn, thread_id)?;
-            wtxn.commit()
-                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            Ok(())
-        } else {
-            Err(DatabaseError::NotFound)
+            let (HeedUuidTuple((thread_id, message_id)), ()) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, thread_id)? {
+                messages.push((thread_id, message_id));
+            }
         }
-    }
 
-    async fn create_message(
-        &self,
-        thread_id: Uuid,
-        input: CreateMessage,
-    ) -> Result<Message, DatabaseError> {
-        let mut wtxn = self
-            .env
-            .write_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        let mut message_creation_times = Vec::new();
+        for entry in self
+            .message_creation_time_db
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (HeedMessageCreationTimeId(key), ()) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, key.0)? {
+                message_creation_times.push(key);
+            }
+        }
 
-        if self
-            .threads_db
-            .get(&wtxn, &thread_id.into())
+        let mut thread_messages = Vec::new();
+        for entry in self
+            .thread_messages_db
+            .remap_data_type::<DecodeIgnore>()
+            .iter(&wtxn)
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .is_none()
         {
-            return Err(DatabaseError::NotFound);
+            let (HeedUuid(thread_id), ()) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, thread_id)? {
+                thread_messages.push(thread_id);
+            }
         }
 
-        let message = input.into_message(thread_id);
-        self.create_message_internal(&mut wtxn, &message)?;
+        let mut embeddings = Vec::new();
+        for entry in self
+            .embeddings_db
+            .remap_data_type::<DecodeIgnore>()
+            .iter(&wtxn)
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+        {
+            let (HeedUuid(thread_id), ()) =
+                entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+            if !self.thread_exists(&wtxn, thread_id)? {
+                embeddings.push(thread_id);
+            }
+        }
 
-        wtxn.commit()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        Ok(message)
-    }
+        let report = OrphanReport {
+            messages: messages.len(),
+            message_creation_times: message_creation_times.len(),
+            thread_messages: thread_messages.len(),
+            embeddings: embeddings.len(),
+        };
 
-    async fn update_message(
-        &self,
-        thread_id: Uuid,
-        message_id: Uuid,
-        content: UpdateMessage,
-    ) -> Result<Message, DatabaseError> {
-        let mut wtxn = self
-            .env
-            .write_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+        if !remove {
+            wtxn.abort();
+            return Ok(report);
+        }
 
-        if let Some(mut message) = self
-            .messages_db
-            .get(&wtxn, &(thread_id, message_id).into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-        {
-            message.update_content(content);
+        for key in messages {
             self.messages_db
-                .put(&mut wtxn, &(thread_id, message_id).into(), &message)
+                .delete(&mut wtxn, &key.into())
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            wtxn.commit()
+        }
+        for key in message_creation_times {
+            self.message_creation_time_db
+                .delete(&mut wtxn, &key.into())
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            Ok(message)
-        } else {
-            Err(DatabaseError::NotFound)
         }
-    }
-
-    async fn update_thread(
-        &self,
-        thread_id: Uuid,
-        update: UpdateThread,
-    ) -> Result<Thread, DatabaseError> {
-        let mut wtxn = self
-            .env
-            .write_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-
-        if let Some(mut thread) = self
-            .threads_db
-            .get(&wtxn, &thread_id.into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-        {
-            thread.set_title(update.title);
-            self.threads_db
-                .put(&mut wtxn, &thread_id.into(), &thread)
+        for thread_id in thread_messages {
+            self.thread_messages_db
+                .delete(&mut wtxn, &thread_id.into())
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            wtxn.commit()
+        }
+        for thread_id in embeddings {
+            self.embeddings_db
+                .delete(&mut wtxn, &thread_id.into())
                 .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            Ok(thread)
-        } else {
-            Err(DatabaseError::NotFound)
         }
-    }
 
-    async fn list_threads(&self) -> Result<Vec<Thread>, DatabaseError> {
-        let rtxn = self
-            .env
-            .read_txn()
+        wtxn.commit()
             .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        let threads = self
-            .threads_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
-        let threads: Vec<Thread> = threads.flatten().map(|(_, thread)| thread).collect();
-        Ok(threads)
+        Ok(report)
     }
+}
 
-    async fn get_thread(&self, thread_id: Uuid) -> Result<Thread, DatabaseError> {
-        let rtxn = self
-            .env
-            .read_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-        self.threads_db
-            .get(&rtxn, &thread_id.into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .ok_or(DatabaseError::NotFound)
+/// Opens the LMDB environment of a [`SynxHeedDatabase`], with the flags
+/// operators tune for their storage.
+pub struct HeedBuilder {
+    map_size: usize,
+    read_ahead: bool,
+    write_map: bool,
+    create_databases: bool,
+    write_queue_capacity: usize,
+    max_exports: usize,
+    export_timeout: Duration,
+    map_usage_warning: f64,
+    keyring: Option<Arc<Keyring>>,
+    encryption_required: bool,
+}
+
+impl Default for HeedBuilder {
+    fn default() -> Self {
+        Self {
+            map_size: 10 * 1024 * 1024 * 1024, // 10 GB
+            read_ahead: true,
+            write_map: false,
+            create_databases: true,
+            write_queue_capacity: DEFAULT_WRITE_QUEUE_CAPACITY,
+            max_exports: DEFAULT_MAX_EXPORTS,
+            export_timeout: DEFAULT_EXPORT_TIMEOUT,
+            map_usage_warning: DEFAULT_MAP_USAGE_WARNING,
+            keyring: None,
+            encryption_required: false,
+        }
     }
+}
 
-    async fn get_thread_messages(
-        &self,
-        thread_id: Uuid,
-        limit: Option<usize>,
-        offset: Option<usize>,
-    ) -> Result<ThreadMessagesResponse, DatabaseError> {
-        let rtxn = self
-            .env
-            .read_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+impl HeedBuilder {
+    pub fn with_map_size(mut self, map_size: usize) -> Self {
+        self.map_size = map_size;
+        self
+    }
 
-        if self
-            .threads_db
-            .get(&rtxn, &thread_id.into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .is_none()
-        {
-            return Err(DatabaseError::NotFound);
-        }
+    /// `false` sets `MDB_NORDAHEAD`, so reads of databases larger than
+    /// memory don't fill the page cache with pages read ahead for nothing,
+    /// which mostly helps on SSDs.
+    pub fn with_read_ahead(mut self, read_ahead: bool) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
 
-        let message_ids = self
-            .thread_messages_db
-            .get(&rtxn, &thread_id.into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .unwrap_or_default();
+    /// `true` sets `MDB_WRITEMAP`, writing through a writable memory map:
+    /// faster on local storage, but a stray write of the process can corrupt
+    /// the database, and on network storage the whole map may be written
+    /// back.
+    pub fn with_write_map(mut self, write_map: bool) -> Self {
+        self.write_map = write_map;
+        self
+    }
 
-        let messages: Vec<Message> = message_ids
-            .iter()
-            .filter_map(|&id| {
-                self.messages_db
-                    .get(&rtxn, &(thread_id, id).into())
-                    .ok()
-                    .and_then(|m| m)
-            })
-            .collect();
+    pub fn with_create_databases(mut self, create_databases: bool) -> Self {
+        self.create_databases = create_databases;
+        self
+    }
 
-        let (paginated_messages, total, offset, limit) =
-            Self::apply_pagination(messages, limit, offset);
+    /// Writes waiting for the writer thread before writers wait for room.
+    pub fn with_write_queue_capacity(mut self, write_queue_capacity: usize) -> Self {
+        self.write_queue_capacity = write_queue_capacity;
+        self
+    }
 
-        Ok(ThreadMessagesResponse {
-            messages: paginated_messages,
-            total,
-            offset,
-            limit,
-        })
+    /// Exports running at once, each holding a read transaction, and so a
+    /// reader slot, until it ends. Kept well below LMDB's 126 slots, the
+    /// rest being left to regular reads.
+    pub fn with_max_exports(mut self, max_exports: usize) -> Self {
+        self.max_exports = max_exports;
+        self
     }
 
-    async fn debug_state(&self) -> Result<serde_json::Value, DatabaseError> {
-        let rtxn = self
-            .env
-            .read_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+    /// Longest an export may hold its snapshot, however slowly it is read.
+    /// Pages freed meanwhile can't be reused, so the file grows with writes.
+    pub fn with_export_timeout(mut self, export_timeout: Duration) -> Self {
+        self.export_timeout = export_timeout;
+        self
+    }
 
-        let threads: Vec<(Uuid, Thread)> = self
-            .threads_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, thread)| (k.0, thread))
-            .collect();
-        let messages: Vec<((Uuid, Uuid), Message)> = self
-            .messages_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, message)| ((k.0 .0, k.0 .1), message))
-            .collect();
-        let thread_messages: Vec<(Uuid, Vec<Uuid>)> = self
-            .thread_messages_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, v)| (k.0, v))
-            .collect();
-        let embeddings: Vec<(Uuid, Embedding)> = self
-            .embeddings_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, v)| (k.0, v))
-            .collect();
-        let thread_creation_times: Vec<(u64, Uuid)> = self
-            .thread_creation_time_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, _)| (k.0 .0, k.0 .1))
-            .collect();
-        let message_creation_times: Vec<(Uuid, u64, Uuid)> = self
-            .message_creation_time_db
-            .iter(&rtxn)
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .flatten()
-            .map(|(k, _)| (k.0 .0, k.0 .1, k.0 .2))
-            .collect();
+    /// Fraction of the map used above which writes log a warning, once per
+    /// crossing. `1.0` or more disables it.
+    pub fn with_map_usage_warning(mut self, map_usage_warning: f64) -> Self {
+        self.map_usage_warning = map_usage_warning;
+        self
+    }
 
-        Ok(serde_json::json!({
-            "threads": threads,
-            "messages": messages,
-            "thread_messages": thread_messages,
-            "embeddings": embeddings,
-            "thread_creation_times": thread_creation_times,
-            "message_creation_times": message_creation_times
-        }))
+    /// Encrypts the values written with the keyring's active key. Values
+    /// are read with it too, along with plain ones written before encryption
+    /// was enabled.
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self
     }
 
-    async fn delete_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<(), DatabaseError> {
-        let mut wtxn = self
-            .env
-            .write_txn()
-            .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+    /// Refuses to open the database without a keyring, and to write plain
+    /// values should it lack one anyway, rather than silently storing them
+    /// unencrypted.
+    pub fn with_encryption_required(mut self, encryption_required: bool) -> Self {
+        self.encryption_required = encryption_required;
+        self
+    }
 
-        if self
-            .messages_db
-            .get(&wtxn, &(thread_id, message_id).into())
-            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
-            .is_some()
-        {
-            self.delete_message_internal(&mut wtxn, thread_id, message_id)?;
-            wtxn.commit()
-                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
-            Ok(())
-        } else {
-            Err(DatabaseError::NotFound)
+    pub fn open(self, path: impl AsRef<Path>) -> Result<SynxHeedDatabase, DatabaseError> {
+        if self.encryption_required && self.keyring.is_none() {
+            return Err(DatabaseError::InvalidInput(
+                "encryption is required but no encryption keys are configured".to_string(),
+            ));
         }
-    }
+
+        let mut flags = EnvFlags::empty();
+        if !self.read_ahead {
+            flags |= EnvFlags::NO_READ_AHEAD;
+        }
+        if self.write_map {
+            flags |= EnvFlags::WRITE_MAP;
+        }
+
+        let env = unsafe {
+            let mut options = EnvOpenOptions::new();
+            options
+                .map_size(self.map_size)
+                .max_dbs(SynxHeedDatabase::MAX_DBS)
+                .flags(flags);
+            options
+                .open(path)
+                .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?
+        };
+
+        let mut db = SynxHeedDatabase::open_env(
+            Arc::new(env),
+            self.create_databases,
+            self.write_queue_capacity,
+            self.keyring,
+        )?;
+        db.export_slots = Arc::new(Semaphore::new(self.max_exports));
+        db.export_timeout = self.export_timeout;
+        db.map_usage_warning = self.map_usage_warning;
+        db.encryption_required = self.encryption_required;
+        // Databases opened close to full are reported before the first write.
+        db.check_map_usage();
+        Ok(db)
+    }
+}
+
+/// Number of orphaned entries found by [`SynxHeedDatabase::scan_orphans`],
+/// per database.
+#[derive(Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct OrphanReport {
+    pub messages: usize,
+    pub message_creation_times: usize,
+    pub thread_messages: usize,
+    pub embeddings: usize,
+}
+
+impl OrphanReport {
+    pub fn total(&self) -> usize {
+        self.messages + self.message_creation_times + self.thread_messages + self.embeddings
+    }
+}
+
+#[async_trait::async_trait]
+impl Db for SynxHeedDatabase {
+    async fn get_threads_with_embeddings(
+        &self,
+        thread_ids: &[Uuid],
+    ) -> Result<Vec<Thread>, DatabaseError> {
+        let thread_ids = thread_ids.to_vec();
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            let threads = thread_ids
+                .iter()
+                .filter_map(|&id| db.get_thread_with_embedding(&rtxn, &id).transpose())
+                .collect::<Result<Vec<Thread>, DatabaseError>>()?;
+            Ok(threads)
+        })
+        .await
+    }
+
+    async fn update_thread_summary_and_embedding(
+        &self,
+        thread_id: Uuid,
+        summary: String,
+        provenance: SummaryProvenance,
+        embedding: Embedding,
+        log_origin: Option<Uuid>,
+    ) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let Some(mut thread) = db
+                .threads_db
+                .get(&wtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            else {
+                return Err(DatabaseError::NotFound);
+            };
+            thread.set_summary(summary.clone(), provenance.clone());
+            db.threads_db
+                .put(&mut wtxn, &thread_id.into(), &thread)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.embeddings_db
+                .put(&mut wtxn, &thread_id.into(), &embedding)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if let Some(origin) = log_origin {
+                db.append_operation_internal(
+                    &mut wtxn,
+                    Operation::new(
+                        origin,
+                        thread.summarized_at,
+                        OperationKind::SetSummary {
+                            thread_id,
+                            summary,
+                            provenance,
+                        },
+                    ),
+                )?;
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_thread(&self, input: CreateThread) -> Result<Thread, DatabaseError> {
+        self.write(move |db| {
+            let thread = input.into_thread();
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.create_thread_internal(&mut wtxn, &thread)?;
+
+            if db
+                .threads_db
+                .get(&wtxn, &thread.id().into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_none()
+            {
+                return Err(DatabaseError::OperationFailed(
+                    "Thread not found after insertion".to_string(),
+                ));
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(thread)
+        })
+        .await
+    }
+
+    async fn delete_thread(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if db
+                .threads_db
+                .get(&wtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_some()
+            {
+                db.delete_thread_internal(&mut wtxn, thread_id)?;
+                wtxn.commit()
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                Ok(())
+            } else {
+                Err(DatabaseError::NotFound)
+            }
+        })
+        .await
+    }
+
+    async fn delete_threads(
+        &self,
+        filter: ThreadFilter,
+        dry_run: bool,
+    ) -> Result<Vec<Uuid>, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let thread_ids = db
+                .threads_db
+                .iter(&wtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .filter_map(|entry| match entry {
+                    Ok((_, thread)) => filter.matches(&thread).then_some(Ok(thread.id)),
+                    Err(e) => Some(Err(DatabaseError::SerializationError(e.to_string()))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if dry_run {
+                return Ok(thread_ids);
+            }
+
+            for thread_id in &thread_ids {
+                db.delete_thread_internal(&mut wtxn, *thread_id)?;
+            }
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(thread_ids)
+        })
+        .await
+    }
+
+    async fn create_message(
+        &self,
+        thread_id: Uuid,
+        input: CreateMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.writer
+            .create_message(self.clone(), input.into_message(thread_id))
+            .await
+    }
+
+    async fn update_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        content: UpdateMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if let Some(mut message) = db
+                .messages_db
+                .get(&wtxn, &(thread_id, message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                message.update_content(content);
+                db.messages_db
+                    .put(&mut wtxn, &(thread_id, message_id).into(), &message)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                wtxn.commit()
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                Ok(message)
+            } else {
+                Err(DatabaseError::NotFound)
+            }
+        })
+        .await
+    }
+
+    async fn patch_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        patch: PatchMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let mut message = db
+                .messages_db
+                .get(&wtxn, &(thread_id, message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)?;
+            if !patch.applies_to(&message) {
+                return Err(DatabaseError::Conflict(format!(
+                    "message {} is {:?}",
+                    message_id, message.status
+                )));
+            }
+            message.patch(patch);
+            db.messages_db
+                .put(&mut wtxn, &(thread_id, message_id).into(), &message)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(message)
+        })
+        .await
+    }
+
+    async fn update_thread(
+        &self,
+        thread_id: Uuid,
+        update: UpdateThread,
+    ) -> Result<Thread, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if let Some(mut thread) = db
+                .threads_db
+                .get(&wtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                thread.set_title(update.title);
+                if let Some(language) = update.summary_language {
+                    thread.set_summary_language(language);
+                }
+                if let Some(tags) = update.tags {
+                    thread.set_tags(tags);
+                }
+                db.threads_db
+                    .put(&mut wtxn, &thread_id.into(), &thread)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                wtxn.commit()
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                Ok(thread)
+            } else {
+                Err(DatabaseError::NotFound)
+            }
+        })
+        .await
+    }
+
+    async fn patch_thread(
+        &self,
+        thread_id: Uuid,
+        patch: PatchThread,
+    ) -> Result<Thread, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let mut thread = db
+                .threads_db
+                .get(&wtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)?;
+            if let Some(pinned) = patch.pinned {
+                thread.set_pinned(pinned);
+                db.index_pinned(&mut wtxn, &thread)?;
+            }
+            if let Some(summary_locked) = patch.summary_locked {
+                thread.set_summary_locked(summary_locked);
+            }
+            db.threads_db
+                .put(&mut wtxn, &thread_id.into(), &thread)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(thread)
+        })
+        .await
+    }
+
+    async fn list_threads(&self) -> Result<Vec<Thread>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            // Pinned threads are looked up through their index, then every
+            // other thread follows in storage order.
+            let mut threads = db.pinned_threads(&rtxn)?;
+            threads.extend(
+                db.threads_db
+                    .iter(&rtxn)
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                    .flatten()
+                    .map(|(_, thread)| thread)
+                    .filter(|thread| !thread.pinned),
+            );
+            Ok(threads)
+        })
+        .await
+    }
+
+    async fn stream_threads(&self) -> Result<ThreadStream, DatabaseError> {
+        let pinned = self
+            .blocking(|db| {
+                let rtxn = db
+                    .env
+                    .read_txn()
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                db.pinned_threads(&rtxn)
+            })
+            .await?;
+
+        // Each batch is read in a transaction of its own, so a slow client
+        // doesn't hold a reader slot for the whole listing.
+        let db = self.clone();
+        let unpinned = futures::stream::unfold(Some(None), move |after| {
+            let db = db.clone();
+            async move {
+                let after = after?;
+                match db
+                    .blocking(move |db| db.unpinned_threads_after(after, LISTING_BATCH_SIZE))
+                    .await
+                {
+                    Ok((threads, Some(last))) => Some((Ok(threads), Some(Some(last)))),
+                    Ok((threads, None)) => Some((Ok(threads), None)),
+                    Err(e) => Some((Err(e), None)),
+                }
+            }
+        })
+        .flat_map(|batch| {
+            futures::stream::iter(match batch {
+                Ok(threads) => threads.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            })
+        });
+
+        Ok(Box::pin(
+            futures::stream::iter(pinned.into_iter().map(Ok)).chain(unpinned),
+        ))
+    }
+
+    async fn get_thread(&self, thread_id: Uuid) -> Result<Thread, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            db.threads_db
+                .get(&rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)
+        })
+        .await
+    }
+
+    async fn get_threads(&self, thread_ids: &[Uuid]) -> Result<Vec<Thread>, DatabaseError> {
+        let thread_ids = thread_ids.to_vec();
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            thread_ids
+                .iter()
+                .filter_map(|&id| {
+                    db.threads_db
+                        .get(&rtxn, &id.into())
+                        .map_err(|e| DatabaseError::QueryError(e.to_string()))
+                        .transpose()
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_thread_messages(
+        &self,
+        thread_id: Uuid,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<ThreadMessagesResponse, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if db
+                .threads_db
+                .get(&rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_none()
+            {
+                return Err(DatabaseError::NotFound);
+            }
+
+            let total = db
+                .thread_messages_db
+                .get(&rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .map_or(0, |ids| ids.len());
+            let offset = offset.unwrap_or(0);
+            let limit = limit.unwrap_or(total);
+
+            // Keys are prefixed by the thread id, then ordered by creation time,
+            // so a page is a bounded scan of the thread's slice of the index.
+            let range = HeedMessageCreationTimeId::from((thread_id, 0, Uuid::nil()))
+                ..=HeedMessageCreationTimeId::from((
+                    thread_id,
+                    u64::MAX,
+                    Uuid::from_bytes([0xff; 16]),
+                ));
+            let messages = db
+                .message_creation_time_db
+                .range(&rtxn, &range)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .skip(offset)
+                .take(limit)
+                .map(|entry| {
+                    let (HeedMessageCreationTimeId((_, _, message_id)), ()) =
+                        entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+                    db.messages_db
+                        .get(&rtxn, &(thread_id, message_id).into())
+                        .map_err(|e| DatabaseError::QueryError(e.to_string()))
+                })
+                .filter_map(Result::transpose)
+                .collect::<Result<Vec<Message>, DatabaseError>>()?;
+
+            Ok(ThreadMessagesResponse {
+                messages,
+                total,
+                offset,
+                limit,
+            })
+        })
+        .await
+    }
+
+    async fn storage_stats(&self) -> Result<serde_json::Value, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let info = db.env.info();
+            let disk_size = db
+                .env
+                .real_disk_size()
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+            let databases = serde_json::json!({
+                "threads": database_stats(&db.threads_db, &rtxn)?,
+                "messages": database_stats(&db.messages_db, &rtxn)?,
+                "thread_messages": database_stats(&db.thread_messages_db, &rtxn)?,
+                "embeddings": database_stats(&db.embeddings_db, &rtxn)?,
+                "thread_vectors": database_stats(&db.vectors_db, &rtxn)?,
+                "thread_creation_time": database_stats(&db.thread_creation_time_db, &rtxn)?,
+                "message_created_at": database_stats(&db.message_creation_time_db, &rtxn)?,
+                "audit": database_stats(&db.audit_db, &rtxn)?,
+                "operations": database_stats(&db.operations_db, &rtxn)?,
+                "tombstones": database_stats(&db.tombstones_db, &rtxn)?,
+                "sync_cursors": database_stats(&db.sync_cursors_db, &rtxn)?,
+                "shadow_summaries": database_stats(&db.shadow_summaries_db, &rtxn)?,
+                "summary_redactions": database_stats(&db.summary_redactions_db, &rtxn)?,
+                "usage": database_stats(&db.usage_db, &rtxn)?,
+                "pinned_threads": database_stats(&db.pinned_threads_db, &rtxn)?,
+                "read_markers": database_stats(&db.read_markers_db, &rtxn)?,
+                "annotations": database_stats(&db.annotations_db, &rtxn)?,
+                "search_feedback": database_stats(&db.search_feedback_db, &rtxn)?,
+                "pending_summaries": database_stats(&db.pending_summaries_db, &rtxn)?,
+                "blobs": database_stats(&db.blobs_db, &rtxn)?,
+                "blob_data": database_stats(&db.blob_data_db, &rtxn)?,
+            });
+
+            Ok(serde_json::json!({
+                "backend": "heed",
+                "map_size": info.map_size,
+                "disk_size": disk_size,
+                "last_page_number": info.last_page_number,
+                "last_txn_id": info.last_txn_id,
+                "readers": info.number_of_readers,
+                "max_readers": info.maximum_number_of_readers,
+                "databases": databases,
+                "map_usage": db.map_usage()?,
+                "writer": db.writer.stats(),
+                "exports_available": db.export_slots.available_permits(),
+            }))
+        })
+        .await
+    }
+
+    async fn debug_state(&self) -> Result<serde_json::Value, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let threads: Vec<(Uuid, Thread)> = db
+                .threads_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, thread)| (k.0, thread))
+                .collect();
+            let messages: Vec<((Uuid, Uuid), Message)> = db
+                .messages_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, message)| ((k.0 .0, k.0 .1), message))
+                .collect();
+            let thread_messages: Vec<(Uuid, Vec<Uuid>)> = db
+                .thread_messages_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, v)| (k.0, v))
+                .collect();
+            let embeddings: Vec<(Uuid, Embedding)> = db
+                .embeddings_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, v)| (k.0, v))
+                .collect();
+            let thread_creation_times: Vec<(u64, Uuid)> = db
+                .thread_creation_time_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, _)| (k.0 .0, k.0 .1))
+                .collect();
+            let message_creation_times: Vec<(Uuid, u64, Uuid)> = db
+                .message_creation_time_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(k, _)| (k.0 .0, k.0 .1, k.0 .2))
+                .collect();
+
+            Ok(serde_json::json!({
+                "threads": threads,
+                "messages": messages,
+                "thread_messages": thread_messages,
+                "embeddings": embeddings,
+                "thread_creation_times": thread_creation_times,
+                "message_creation_times": message_creation_times
+            }))
+        })
+        .await
+    }
+
+    async fn delete_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if db
+                .messages_db
+                .get(&wtxn, &(thread_id, message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_some()
+            {
+                db.delete_message_internal(&mut wtxn, thread_id, message_id)?;
+                wtxn.commit()
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+                Ok(())
+            } else {
+                Err(DatabaseError::NotFound)
+            }
+        })
+        .await
+    }
+
+    async fn append_audit_entry(&self, entry: AuditEntry) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.audit_db
+                .put(&mut wtxn, &(entry.timestamp, entry.id).into(), &entry)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_audit_entries(
+        &self,
+        filter: AuditFilter,
+    ) -> Result<Vec<AuditEntry>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let range = HeedTimestampUuid::from((filter.from.unwrap_or(0), Uuid::nil()))
+                ..=HeedTimestampUuid::from((
+                    filter.to.unwrap_or(u64::MAX),
+                    Uuid::from_bytes([0xff; 16]),
+                ));
+
+            let entries = db
+                .audit_db
+                .range(&rtxn, &range)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .flatten()
+                .map(|(_, entry)| entry)
+                .filter(|entry| filter.matches(entry))
+                .take(filter.limit.unwrap_or(usize::MAX))
+                .collect();
+            Ok(entries)
+        })
+        .await
+    }
+
+    async fn get_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Message, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            db.messages_db
+                .get(&rtxn, &(thread_id, message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)
+        })
+        .await
+    }
+
+    async fn get_shadow_summary(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<ShadowSummary>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            db.shadow_summaries_db
+                .get(&rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))
+        })
+        .await
+    }
+
+    async fn put_shadow_summary(&self, shadow: ShadowSummary) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db.thread_exists(&wtxn, shadow.thread_id)? {
+                return Err(DatabaseError::NotFound);
+            }
+            db.shadow_summaries_db
+                .put(&mut wtxn, &shadow.thread_id.into(), &shadow)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_summary_redactions(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<SummaryRedactions>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            db.summary_redactions_db
+                .get(&rtxn, &thread_id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))
+        })
+        .await
+    }
+
+    async fn put_summary_redactions(
+        &self,
+        redactions: SummaryRedactions,
+    ) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db.thread_exists(&wtxn, redactions.thread_id)? {
+                return Err(DatabaseError::NotFound);
+            }
+            db.summary_redactions_db
+                .put(&mut wtxn, &redactions.thread_id.into(), &redactions)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn clear_thread_summary(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.clear_thread_summary_internal(&mut wtxn, thread_id)?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn write_batch(&self, batch: WriteBatch) -> Result<(), DatabaseError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            // Returning early drops the transaction, aborting every earlier
+            // operation of the batch.
+            for op in batch.into_ops() {
+                db.apply_batch_op(&mut wtxn, op)?;
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_thread(&self, thread: Thread) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if db.thread_exists(&wtxn, thread.id)? {
+                db.threads_db
+                    .put(&mut wtxn, &thread.id.into(), &thread)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            } else {
+                db.create_thread_internal(&mut wtxn, &thread)?;
+            }
+            db.index_pinned(&mut wtxn, &thread)?;
+            if let Some(embedding) = &thread.embedding {
+                db.embeddings_db
+                    .put(&mut wtxn, &thread.id.into(), embedding)
+                    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_message(&self, message: Message) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db.thread_exists(&wtxn, message.thread_id)? {
+                return Err(DatabaseError::NotFound);
+            }
+
+            // Replacing goes through the same path as deleting, so the
+            // previous creation-time entry can't be left behind.
+            db.delete_message_internal(&mut wtxn, message.thread_id, message.id)?;
+            db.create_message_internal(&mut wtxn, &message)?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn append_operation(&self, operation: Operation) -> Result<u64, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let seq = db.append_operation_internal(&mut wtxn, operation)?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(seq)
+        })
+        .await
+    }
+
+    async fn get_tombstone(&self, id: Uuid) -> Result<Option<u64>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.tombstones_db
+                .get(&rtxn, &id.into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))
+        })
+        .await
+    }
+
+    async fn get_sync_cursor(&self, remote: String) -> Result<Option<SyncCursor>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.sync_cursors_db
+                .get(&rtxn, &remote)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))
+        })
+        .await
+    }
+
+    async fn put_sync_cursor(
+        &self,
+        remote: String,
+        cursor: SyncCursor,
+    ) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            db.sync_cursors_db
+                .put(&mut wtxn, &remote, &cursor)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_operations(
+        &self,
+        filter: OperationFilter,
+    ) -> Result<Vec<Operation>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let range = filter.since_seq.saturating_add(1)..;
+            let operations = db
+                .operations_db
+                .range(&rtxn, &range)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .filter_map(|entry| match entry {
+                    Ok((_, operation)) => filter.matches(&operation).then_some(Ok(operation)),
+                    Err(e) => Some(Err(DatabaseError::SerializationError(e.to_string()))),
+                })
+                .take(filter.limit.unwrap_or(usize::MAX))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(operations)
+        })
+        .await
+    }
+
+    async fn add_usage(&self, usage: DailyUsage) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let key = usage_key(&usage);
+            let aggregate = match db
+                .usage_db
+                .get(&wtxn, &key)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                Some(mut aggregate) => {
+                    aggregate.usage.add(&usage.usage);
+                    aggregate
+                }
+                None => usage,
+            };
+            db.usage_db
+                .put(&mut wtxn, &key, &aggregate)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_usage(&self, filter: UsageFilter) -> Result<Vec<DailyUsage>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            // Keys start with the ISO day, so they sort by day. The day is
+            // followed by '/' and a thread id in hex, which sort before '~'.
+            let from = filter.from.map(|from| from.to_string()).unwrap_or_default();
+            let to = filter
+                .to
+                .map(|to| format!("{}/~", to))
+                .unwrap_or_else(|| "~".to_string());
+            let usage = db
+                .usage_db
+                .range(
+                    &rtxn,
+                    &(Bound::Included(from.as_str()), Bound::Included(to.as_str())),
+                )
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .filter_map(|entry| match entry {
+                    Ok((_, usage)) => filter.matches(&usage).then_some(Ok(usage)),
+                    Err(e) => Some(Err(DatabaseError::SerializationError(e.to_string()))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(usage)
+        })
+        .await
+    }
+    async fn mark_read(
+        &self,
+        thread_id: Uuid,
+        reader: String,
+        message_id: Uuid,
+    ) -> Result<ReadMarker, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let message = db
+                .messages_db
+                .get(&wtxn, &(thread_id, message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)?;
+            let marker = ReadMarker::new(&message, reader);
+            db.read_markers_db
+                .put(&mut wtxn, &reader_key(thread_id, &marker.reader), &marker)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(marker)
+        })
+        .await
+    }
+
+    async fn unread_counts(&self, reader: String) -> Result<HashMap<Uuid, usize>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let mut counts = HashMap::new();
+            for entry in db
+                .thread_messages_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                let (HeedUuid(thread_id), message_ids) =
+                    entry.map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+                let marker = db
+                    .read_markers_db
+                    .get(&rtxn, &reader_key(thread_id, &reader))
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+                let unread = match marker {
+                    // Messages are indexed by creation time, then id, so the
+                    // unread ones are those after the marker in the index.
+                    Some(marker) => {
+                        let range = (
+                            Bound::Excluded(HeedMessageCreationTimeId::from((
+                                thread_id,
+                                marker.last_read_created_at,
+                                marker.last_read_message_id,
+                            ))),
+                            Bound::Included(HeedMessageCreationTimeId::from((
+                                thread_id,
+                                u64::MAX,
+                                Uuid::from_bytes([0xff; 16]),
+                            ))),
+                        );
+                        db.message_creation_time_db
+                            .range(&rtxn, &range)
+                            .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                            .count()
+                    }
+                    None => message_ids.len(),
+                };
+                counts.insert(thread_id, unread);
+            }
+            Ok(counts)
+        })
+        .await
+    }
+
+    async fn add_annotation(&self, annotation: Annotation) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if db
+                .messages_db
+                .remap_data_type::<DecodeIgnore>()
+                .get(&wtxn, &(annotation.thread_id, annotation.message_id).into())
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .is_none()
+            {
+                return Err(DatabaseError::NotFound);
+            }
+            db.annotations_db
+                .put(
+                    &mut wtxn,
+                    &annotation_key(annotation.thread_id, annotation.message_id, annotation.id),
+                    &annotation,
+                )
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_annotation(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        annotation_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let deleted = db
+                .annotations_db
+                .delete(
+                    &mut wtxn,
+                    &annotation_key(thread_id, message_id, annotation_id),
+                )
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            if !deleted {
+                return Err(DatabaseError::NotFound);
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_annotations(&self, thread_id: Uuid) -> Result<Vec<Annotation>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let mut annotations = db
+                .annotations_db
+                .prefix_iter(&rtxn, &format!("{}/", thread_id))
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .map(|entry| {
+                    entry
+                        .map(|(_, annotation)| annotation)
+                        .map_err(|e| DatabaseError::SerializationError(e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            annotations.sort_by_key(|annotation| annotation.created_at);
+            Ok(annotations)
+        })
+        .await
+    }
+
+    async fn put_search_feedback(&self, feedback: SearchFeedback) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db.thread_exists(&wtxn, feedback.thread_id)? {
+                return Err(DatabaseError::NotFound);
+            }
+            db.search_feedback_db
+                .put(
+                    &mut wtxn,
+                    &reader_key(feedback.thread_id, &feedback.reader),
+                    &feedback,
+                )
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_search_feedback(
+        &self,
+        reader: String,
+    ) -> Result<Vec<SearchFeedback>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let feedback = db
+                .search_feedback_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .filter_map(|entry| match entry {
+                    Ok((_, feedback)) => (feedback.reader == reader).then_some(Ok(feedback)),
+                    Err(e) => Some(Err(DatabaseError::SerializationError(e.to_string()))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(feedback)
+        })
+        .await
+    }
+
+    async fn put_pending_summary(&self, pending: PendingSummary) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db.thread_exists(&wtxn, pending.thread_id)? {
+                return Err(DatabaseError::NotFound);
+            }
+            db.pending_summaries_db
+                .put(
+                    &mut wtxn,
+                    &pending_summary_key(pending.thread_id, pending.message_id),
+                    &pending,
+                )
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_pending_summaries(&self) -> Result<Vec<PendingSummary>, DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let mut pending_summaries = db
+                .pending_summaries_db
+                .iter(&rtxn)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .map(|entry| {
+                    entry
+                        .map(|(_, pending)| pending)
+                        .map_err(|e| DatabaseError::SerializationError(e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            pending_summaries.sort_by_key(|pending| pending.queued_at);
+            Ok(pending_summaries)
+        })
+        .await
+    }
+
+    async fn delete_pending_summary(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let deleted = db
+                .pending_summaries_db
+                .delete(&mut wtxn, &pending_summary_key(thread_id, message_id))
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            if !deleted {
+                return Err(DatabaseError::NotFound);
+            }
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn put_blob(&self, blob: Blob, data: Vec<u8>) -> Result<Blob, DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if let Some(existing) = db
+                .blobs_db
+                .get(&wtxn, &blob.hash)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+            {
+                return Ok(existing);
+            }
+            db.blobs_db
+                .put(&mut wtxn, &blob.hash, &blob)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            db.blob_data_db
+                .put(&mut wtxn, &blob.hash, &data)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(blob)
+        })
+        .await
+    }
+
+    async fn delete_blob(&self, hash: String) -> Result<(), DatabaseError> {
+        self.write(move |db| {
+            let mut wtxn = db
+                .env
+                .write_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            if !db
+                .blobs_db
+                .delete(&mut wtxn, &hash)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?
+            {
+                return Err(DatabaseError::NotFound);
+            }
+            db.blob_data_db
+                .delete(&mut wtxn, &hash)
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            wtxn.commit()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_blob(&self, hash: String) -> Result<(Blob, Vec<u8>), DatabaseError> {
+        self.blocking(move |db| {
+            let rtxn = db
+                .env
+                .read_txn()
+                .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+
+            let blob = db
+                .blobs_db
+                .get(&rtxn, &hash)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)?;
+            let data = db
+                .blob_data_db
+                .get(&rtxn, &hash)
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?
+                .ok_or(DatabaseError::NotFound)?;
+            Ok((blob, data))
+        })
+        .await
+    }
+
+    async fn export(&self) -> Result<ExportStream, DatabaseError> {
+        self.export_snapshot().await
+    }
+}
+
+/// Page counts and B-tree depth of a named database.
+fn database_stats<KC, DC>(
+    db: &Database<KC, DC>,
+    rtxn: &heed::RoTxn,
+) -> Result<serde_json::Value, DatabaseError> {
+    let stat = db
+        .stat(rtxn)
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+    Ok(serde_json::json!({
+        "entries": stat.entries,
+        "depth": stat.depth,
+        "page_size": stat.page_size,
+        "branch_pages": stat.branch_pages,
+        "leaf_pages": stat.leaf_pages,
+        "overflow_pages": stat.overflow_pages,
+    }))
+}
+
+/// The keys of a thread's vectors in `vectors_db`.
+fn thread_vectors(thread_id: Uuid) -> RangeInclusive<HeedUuidTuple> {
+    HeedUuidTuple::from((thread_id, Uuid::nil()))
+        ..=HeedUuidTuple::from((thread_id, Uuid::from_bytes([0xff; 16])))
+}
+
+/// Deletes every entry whose key starts with `prefix`, which ends with '/':
+/// '0' is the character following it.
+fn delete_prefixed<DC>(
+    db: &Database<Str, DC>,
+    wtxn: &mut heed::RwTxn,
+    prefix: &str,
+) -> Result<(), DatabaseError> {
+    let end = format!("{}0", prefix.trim_end_matches('/'));
+    db.delete_range(
+        wtxn,
+        &(Bound::Included(prefix), Bound::Excluded(end.as_str())),
+    )
+    .map_err(|e| DatabaseError::OperationFailed(e.to_string()))?;
+    Ok(())
+}
+
+/// `{thread id}/{message id}/{annotation id}`, so a message's annotations,
+/// and a thread's, are deleted together.
+fn annotation_key(thread_id: Uuid, message_id: Uuid, annotation_id: Uuid) -> String {
+    format!("{}/{}/{}", thread_id, message_id, annotation_id)
+}
+
+/// `{thread id}/{message id}`, so queued summaries are deleted along with
+/// their thread.
+fn pending_summary_key(thread_id: Uuid, message_id: Uuid) -> String {
+    format!("{}/{}", thread_id, message_id)
+}
+
+/// `{thread id}/{reader}`, so what a thread keeps per reader (read markers
+/// and search feedback) is deleted along with it.
+fn reader_key(thread_id: Uuid, reader: &str) -> String {
+    format!("{}/{}", thread_id, reader)
+}
+
+/// `{day}/{thread id}/{tenant}`, the nil id and `-` standing for no thread
+/// and no tenant. Tenants are prefixed so `-` can't be mistaken for one.
+fn usage_key(usage: &DailyUsage) -> String {
+    format!(
+        "{}/{}/{}",
+        usage.day,
+        usage.thread_id.unwrap_or_else(Uuid::nil),
+        usage
+            .tenant
+            .as_ref()
+            .map_or_else(|| "-".to_string(), |tenant| format!("+{}", tenant))
+    )
 }
@@ -0,0 +1,101 @@
+//! How much of the memory map the environment uses. Writes fail with
+//! `MDB_MAP_FULL` once it is used up, so a warning is logged when a write
+//! leaves it fuller than a threshold, giving operators time to raise the
+//! map size.
+
+use std::sync::atomic::Ordering;
+
+use synx_database::DatabaseError;
+
+use crate::SynxHeedDatabase;
+
+/// Fraction of the map used above which writes log a warning.
+pub const DEFAULT_MAP_USAGE_WARNING: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+pub struct MapUsage {
+    pub map_size: u64,
+    /// Bytes up to the last page written, which the file has grown to.
+    pub high_water_bytes: u64,
+    /// Bytes of the pages the databases hold.
+    pub used_bytes: u64,
+    /// Bytes below the high-water mark freed by earlier writes, which later
+    /// writes reuse before growing the file.
+    pub free_bytes: u64,
+    /// `used_bytes` over `map_size`.
+    pub utilization: f64,
+}
+
+impl SynxHeedDatabase {
+    pub fn map_usage(&self) -> Result<MapUsage, DatabaseError> {
+        let info = self.env.info();
+        let map_size = info.map_size as u64;
+        let high_water_bytes = (info.last_page_number as u64 + 1) * self.page_size;
+        let used_bytes = self
+            .env
+            .non_free_pages_size()
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(MapUsage {
+            map_size,
+            high_water_bytes,
+            used_bytes,
+            free_bytes: high_water_bytes.saturating_sub(used_bytes),
+            utilization: used_bytes as f64 / map_size as f64,
+        })
+    }
+
+    /// Logs a warning once the map is used above the threshold, and again
+    /// only after it went back below. Run after writes, on the writer
+    /// thread: the high-water mark is checked first, as it is cheap to read
+    /// and the used pages can't exceed it.
+    pub(crate) fn check_map_usage(&self) {
+        let info = self.env.info();
+        let high_water_bytes = (info.last_page_number as u64 + 1) * self.page_size;
+        if (high_water_bytes as f64) < self.map_usage_warning * info.map_size as f64 {
+            self.map_usage_warned.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        match self.map_usage() {
+            Ok(usage) if usage.utilization >= self.map_usage_warning => {
+                if !self.map_usage_warned.swap(true, Ordering::Relaxed) {
+                    tracing::warn!(
+                        used_bytes = usage.used_bytes,
+                        map_size = usage.map_size,
+                        "The LMDB map is {:.0}% used, writes will fail once it is full: \
+                         raise the map size",
+                        usage.utilization * 100.0
+                    );
+                }
+            }
+            Ok(_) => self.map_usage_warned.store(false, Ordering::Relaxed),
+            Err(e) => tracing::debug!("Could not read the LMDB map usage: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_database::Db;
+    use synx_domain::thread::CreateThread;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn writes_above_the_threshold_warn_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SynxHeedDatabase::builder()
+            .with_map_size(64 * 1024 * 1024)
+            .with_map_usage_warning(0.0)
+            .open(dir.path())
+            .unwrap();
+        db.create_thread(CreateThread::default()).await.unwrap();
+
+        let usage = db.map_usage().unwrap();
+        assert_eq!(usage.map_size, 64 * 1024 * 1024);
+        assert!(usage.used_bytes > 0 && usage.used_bytes <= usage.high_water_bytes);
+        assert!(usage.utilization > 0.0 && usage.utilization < 1.0);
+        assert!(db.map_usage_warned.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use synx_database::DatabaseError;
+use synx_domain::message::Message;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::SynxHeedDatabase;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub const DEFAULT_WRITE_QUEUE_CAPACITY: usize = 1024;
+/// Most new messages stored in one transaction.
+const MAX_GROUP_SIZE: usize = 256;
+
+enum Command {
+    Job(Job),
+    CreateMessage(PendingMessage),
+}
+
+/// A new message waiting to be stored, possibly along with the new messages
+/// queued right behind it.
+pub(crate) struct PendingMessage {
+    pub db: SynxHeedDatabase,
+    pub message: Message,
+    pub result: oneshot::Sender<Result<Message, DatabaseError>>,
+}
+
+/// The single writer of the environment. LMDB allows one write transaction
+/// at a time, so writes are queued to a dedicated thread and run in the
+/// order they were submitted, rather than racing for the write lock on the
+/// blocking pool. Submitting waits while the queue is full.
+///
+/// New messages queued one behind the other are stored in one transaction
+/// (group commit), so bulk imports pay for one commit per group rather than
+/// per message. See [`SynxHeedDatabase::create_message_group`].
+///
+/// The thread stops once every handle, and so the channel, is dropped.
+#[derive(Debug)]
+pub(crate) struct Writer {
+    sender: mpsc::Sender<(Instant, Command)>,
+    capacity: usize,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    queued: AtomicUsize,
+    writes: AtomicU64,
+    group_commits: AtomicU64,
+    wait_micros: AtomicU64,
+    max_wait_micros: AtomicU64,
+    run_micros: AtomicU64,
+    max_run_micros: AtomicU64,
+}
+
+/// Write latencies of [`SynxHeedDatabase`](crate::SynxHeedDatabase), split
+/// between the time spent queued and the time spent writing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+pub struct WriterStats {
+    /// Writes submitted and not yet started, including those waiting for
+    /// room in the queue.
+    pub queued: usize,
+    pub capacity: usize,
+    pub writes: u64,
+    /// Transactions which stored more than one new message.
+    pub group_commits: u64,
+    pub mean_wait_micros: u64,
+    pub max_wait_micros: u64,
+    pub mean_run_micros: u64,
+    pub max_run_micros: u64,
+}
+
+impl Writer {
+    pub(crate) fn spawn(capacity: usize) -> Result<Self, DatabaseError> {
+        let capacity = capacity.max(1);
+        let (sender, mut receiver) = mpsc::channel::<(Instant, Command)>(capacity);
+        let counters = Arc::new(Counters::default());
+
+        std::thread::Builder::new()
+            .name("synx-heed-writer".to_string())
+            .spawn({
+                let counters = counters.clone();
+                move || {
+                    let mut next = None;
+                    while let Some((submitted_at, command)) =
+                        next.take().or_else(|| receiver.blocking_recv())
+                    {
+                        counters.started(submitted_at);
+                        let start = Instant::now();
+                        // A panicking write drops its result senders, failing
+                        // those writes only.
+                        match command {
+                            Command::Job(job) => {
+                                let _ = catch_unwind(AssertUnwindSafe(job));
+                            }
+                            Command::CreateMessage(pending) => {
+                                let mut group = vec![pending];
+                                while group.len() < MAX_GROUP_SIZE {
+                                    match receiver.try_recv() {
+                                        Ok((submitted_at, Command::CreateMessage(pending))) => {
+                                            counters.started(submitted_at);
+                                            group.push(pending);
+                                        }
+                                        Ok(command) => {
+                                            next = Some(command);
+                                            break;
+                                        }
+                                        Err(_) => break,
+                                    }
+                                }
+                                if group.len() > 1 {
+                                    counters.group_commits.fetch_add(1, Ordering::Relaxed);
+                                }
+
+                                let db = group[0].db.clone();
+                                let _ = catch_unwind(AssertUnwindSafe(|| {
+                                    db.keyed(|| db.create_message_group(group))
+                                }));
+                            }
+                        }
+
+                        let run = start.elapsed().as_micros() as u64;
+                        counters.run_micros.fetch_add(run, Ordering::Relaxed);
+                        counters.max_run_micros.fetch_max(run, Ordering::Relaxed);
+                    }
+                }
+            })
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            sender,
+            capacity,
+            counters,
+        })
+    }
+
+    pub(crate) async fn run<T, F>(&self, job: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let (result_sender, result) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = result_sender.send(job());
+        });
+
+        self.submit(Command::Job(job), result).await
+    }
+
+    /// Stores a new message, in the same transaction as the new messages
+    /// queued along with it.
+    pub(crate) async fn create_message(
+        &self,
+        db: SynxHeedDatabase,
+        message: Message,
+    ) -> Result<Message, DatabaseError> {
+        let (result_sender, result) = oneshot::channel();
+        let pending = PendingMessage {
+            db,
+            message,
+            result: result_sender,
+        };
+
+        self.submit(Command::CreateMessage(pending), result).await
+    }
+
+    async fn submit<T>(
+        &self,
+        command: Command,
+        result: oneshot::Receiver<Result<T, DatabaseError>>,
+    ) -> Result<T, DatabaseError> {
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send((Instant::now(), command)).await.is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(DatabaseError::OperationFailed(
+                "the writer thread stopped".to_string(),
+            ));
+        }
+
+        result
+            .await
+            .map_err(|_| DatabaseError::OperationFailed("the write panicked".to_string()))?
+    }
+
+    pub(crate) fn stats(&self) -> WriterStats {
+        let writes = self.counters.writes.load(Ordering::Relaxed);
+        let mean = |total: &AtomicU64| {
+            total
+                .load(Ordering::Relaxed)
+                .checked_div(writes)
+                .unwrap_or(0)
+        };
+
+        WriterStats {
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            capacity: self.capacity,
+            writes,
+            group_commits: self.counters.group_commits.load(Ordering::Relaxed),
+            mean_wait_micros: mean(&self.counters.wait_micros),
+            max_wait_micros: self.counters.max_wait_micros.load(Ordering::Relaxed),
+            mean_run_micros: mean(&self.counters.run_micros),
+            max_run_micros: self.counters.max_run_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Counters {
+    fn started(&self, submitted_at: Instant) {
+        let wait = submitted_at.elapsed().as_micros() as u64;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros.fetch_add(wait, Ordering::Relaxed);
+        self.max_wait_micros.fetch_max(wait, Ordering::Relaxed);
+    }
+}
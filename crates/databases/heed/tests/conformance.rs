@@ -0,0 +1,336 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::StreamExt;
+use synx_database::{DatabaseError, Db};
+use synx_database_tests::DbFixture;
+use synx_domain::{blob::Blob, message::CreateMessage, role::Role, thread::CreateThread};
+use synx_heed_database::{
+    encryption::Keyring, heed::EnvOpenOptions, OrphanReport, SynxHeedDatabase,
+};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+struct HeedFixture {
+    db: SynxHeedDatabase,
+    _dir: TempDir,
+}
+
+impl HeedFixture {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(64 * 1024 * 1024)
+                .max_dbs(SynxHeedDatabase::MAX_DBS)
+                .open(dir.path())
+                .unwrap()
+        };
+
+        Self {
+            db: SynxHeedDatabase::new(Arc::new(env), true).unwrap(),
+            _dir: dir,
+        }
+    }
+}
+
+impl DbFixture for HeedFixture {
+    fn db(&self) -> &dyn Db {
+        &self.db
+    }
+}
+
+synx_database_tests::db_conformance_tests!(HeedFixture::new());
+
+#[tokio::test]
+async fn deleting_thread_leaves_no_orphans() {
+    let fixture = HeedFixture::new();
+    let db = &fixture.db;
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    for text in ["first", "second"] {
+        db.create_message(
+            thread.id,
+            CreateMessage {
+                role: Role::User,
+                content: text.to_string().into(),
+                parent_message_id: None,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            },
+        )
+        .await
+        .unwrap();
+    }
+    db.update_thread_summary_and_embedding(
+        thread.id,
+        "summary".to_string(),
+        Default::default(),
+        vec![1.0, 0.0].into(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    db.delete_thread(thread.id).await.unwrap();
+
+    assert_eq!(db.scan_orphans(false).unwrap(), OrphanReport::default());
+}
+
+#[tokio::test]
+async fn deletions_remove_exact_creation_time_keys() {
+    let fixture = HeedFixture::new();
+    let db = &fixture.db;
+    let mut threads = Vec::new();
+    let mut messages = Vec::new();
+    for _ in 0..2 {
+        let thread = db.create_thread(CreateThread::default()).await.unwrap();
+        for text in ["first", "second"] {
+            messages.push(
+                db.create_message(
+                    thread.id,
+                    CreateMessage {
+                        role: Role::User,
+                        content: text.to_string().into(),
+                        parent_message_id: None,
+                        metadata: Default::default(),
+                        status: Default::default(),
+                        memorize: true,
+                    },
+                )
+                .await
+                .unwrap(),
+            );
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        threads.push(thread);
+    }
+
+    db.delete_message(threads[1].id, messages[3].id)
+        .await
+        .unwrap();
+    db.delete_thread(threads[0].id).await.unwrap();
+
+    let state = db.debug_state().await.unwrap();
+    let thread_ids: Vec<Uuid> =
+        serde_json::from_value::<Vec<(u64, Uuid)>>(state["thread_creation_times"].clone())
+            .unwrap()
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect();
+    let message_keys: Vec<(Uuid, u64, Uuid)> =
+        serde_json::from_value(state["message_creation_times"].clone()).unwrap();
+
+    assert_eq!(thread_ids, vec![threads[1].id]);
+    assert_eq!(
+        message_keys,
+        vec![(threads[1].id, messages[2].created_at, messages[2].id)]
+    );
+}
+
+#[tokio::test]
+async fn long_messages_round_trip_compressed() {
+    let fixture = HeedFixture::new();
+    let db = &fixture.db;
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let text = "Could you please help me plan a trip to Rome? ".repeat(50);
+    let message = db
+        .create_message(
+            thread.id,
+            CreateMessage {
+                role: Role::User,
+                content: text.clone().into(),
+                parent_message_id: None,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            },
+        )
+        .await
+        .unwrap();
+
+    let stored = db.get_message(thread.id, message.id).await.unwrap();
+
+    assert_eq!(stored.content.to_string(), text);
+}
+
+#[tokio::test]
+async fn storage_stats_count_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = SynxHeedDatabase::builder()
+        .with_map_size(64 * 1024 * 1024)
+        .with_read_ahead(false)
+        .open(dir.path())
+        .unwrap();
+    db.create_thread(CreateThread::default()).await.unwrap();
+
+    let stats = db.storage_stats().await.unwrap();
+
+    assert_eq!(stats["databases"]["threads"]["entries"], 1);
+    assert_eq!(stats["databases"]["messages"]["entries"], 0);
+    assert!(stats["max_readers"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn stores_requiring_encryption_need_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let builder = || {
+        SynxHeedDatabase::builder()
+            .with_map_size(64 * 1024 * 1024)
+            .with_encryption_required(true)
+    };
+    assert!(matches!(
+        builder().open(dir.path()),
+        Err(DatabaseError::InvalidInput(_))
+    ));
+
+    let keyring = Keyring::parse(&format!("1:{}", "ab".repeat(32))).unwrap();
+    let db = builder().with_keyring(keyring).open(dir.path()).unwrap();
+    let blob = Blob {
+        hash: "ab".repeat(32),
+        mime_type: Some("image/png".to_string()),
+        size: 5,
+        created_at: 0,
+        tenant: None,
+    };
+    db.put_blob(blob.clone(), b"Hello".to_vec()).await.unwrap();
+
+    let (stored, data) = db.get_blob(blob.hash).await.unwrap();
+    assert_eq!(stored.mime_type, blob.mime_type);
+    assert_eq!(data, b"Hello");
+    // The blob and its data.
+    assert_eq!(db.reencrypt().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn concurrent_writes_are_queued_to_the_writer() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = SynxHeedDatabase::builder()
+        .with_map_size(64 * 1024 * 1024)
+        .with_write_queue_capacity(2)
+        .open(dir.path())
+        .unwrap();
+
+    let threads =
+        futures::future::join_all((0..16).map(|_| db.create_thread(CreateThread::default()))).await;
+
+    assert!(threads.iter().all(Result::is_ok));
+    let stats = db.writer_stats();
+    assert_eq!(stats.writes, 16);
+    assert_eq!(stats.queued, 0);
+    assert_eq!(stats.capacity, 2);
+}
+
+#[tokio::test]
+async fn grouped_messages_report_their_own_results() {
+    let fixture = HeedFixture::new();
+    let db = &fixture.db;
+    let thread = db.create_thread(CreateThread::default()).await.unwrap();
+    let missing = Uuid::new_v4();
+    let message = |text: &str| CreateMessage {
+        role: Role::User,
+        content: text.to_string().into(),
+        parent_message_id: None,
+        metadata: Default::default(),
+        status: Default::default(),
+        memorize: true,
+    };
+
+    let results = futures::future::join_all((0..32).map(|index| {
+        let thread_id = if index == 7 { missing } else { thread.id };
+        db.create_message(thread_id, message(&format!("message {}", index)))
+    }))
+    .await;
+
+    assert!(matches!(
+        results[7],
+        Err(synx_database::DatabaseError::NotFound)
+    ));
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 31);
+    assert_eq!(
+        db.get_thread_messages(thread.id, None, None)
+            .await
+            .unwrap()
+            .total,
+        31
+    );
+}
+
+#[tokio::test]
+async fn exports_are_limited_in_number() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = SynxHeedDatabase::builder()
+        .with_map_size(64 * 1024 * 1024)
+        .with_max_exports(1)
+        .open(dir.path())
+        .unwrap();
+    // More records than an export reads ahead, so the first one can't end
+    // before being read.
+    for _ in 0..100 {
+        db.create_thread(CreateThread::default()).await.unwrap();
+    }
+
+    let export = db.export().await.unwrap();
+    assert!(matches!(
+        db.export().await,
+        Err(DatabaseError::Unavailable(_))
+    ));
+
+    // The slot is given back once the export thread sees the stream gone.
+    drop(export);
+    let mut retries = 0;
+    let export = loop {
+        match db.export().await {
+            Ok(export) => break export,
+            Err(DatabaseError::Unavailable(_)) if retries < 100 => {
+                retries += 1;
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Err(e) => panic!("export failed: {}", e),
+        }
+    };
+    assert_eq!(export.collect::<Vec<_>>().await.len(), 100);
+}
+
+#[tokio::test]
+async fn exports_holding_their_snapshot_too_long_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = SynxHeedDatabase::builder()
+        .with_map_size(64 * 1024 * 1024)
+        .with_export_timeout(Duration::ZERO)
+        .open(dir.path())
+        .unwrap();
+    db.create_thread(CreateThread::default()).await.unwrap();
+
+    let records = db.export().await.unwrap().collect::<Vec<_>>().await;
+
+    assert!(matches!(records.last(), Some(Err(_))));
+}
+
+#[tokio::test]
+async fn streamed_threads_span_batches() {
+    let fixture = HeedFixture::new();
+    let mut created = Vec::new();
+    for _ in 0..600 {
+        created.push(
+            fixture
+                .db
+                .create_thread(CreateThread::default())
+                .await
+                .unwrap()
+                .id,
+        );
+    }
+
+    let mut streamed = fixture
+        .db
+        .stream_threads()
+        .await
+        .unwrap()
+        .map(|thread| thread.unwrap().id)
+        .collect::<Vec<_>>()
+        .await;
+
+    streamed.sort();
+    created.sort();
+    assert_eq!(streamed, created);
+}
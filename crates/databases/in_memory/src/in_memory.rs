@@ -1,13 +1,23 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     sync::Arc,
 };
 
-use synx_database::{DatabaseError, Db};
+use synx_database::{BatchOp, DatabaseError, Db, ExportStream, ThreadStream, WriteBatch};
 use synx_domain::{
+    annotation::{Annotation, SearchFeedback},
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
     embedding::Embedding,
-    message::{CreateMessage, Message, ThreadMessagesResponse, UpdateMessage},
-    thread::{Thread, UpdateThread},
+    export::ExportRecord,
+    message::{CreateMessage, Message, PatchMessage, ThreadMessagesResponse, UpdateMessage},
+    read::ReadMarker,
+    sync::{Operation, OperationFilter, OperationKind, SyncCursor},
+    thread::{
+        CreateThread, PatchThread, PendingSummary, ShadowSummary, SummaryProvenance,
+        SummaryRedactions, Thread, ThreadFilter, UpdateThread,
+    },
+    usage::{DailyUsage, NaiveDate, Usage, UsageFilter},
 };
 use tokio::sync::Mutex;
 use uuid::Uuid;
@@ -15,10 +25,24 @@ use uuid::Uuid;
 #[derive(Clone)]
 pub struct SynxInMemory {
     threads: Arc<Mutex<HashMap<Uuid, Thread>>>,
-    messages: Arc<Mutex<HashMap<Uuid, Message>>>,
+    messages: Arc<Mutex<HashMap<(Uuid, Uuid), Message>>>,
     thread_messages: Arc<Mutex<HashMap<Uuid, HashSet<Uuid>>>>,
+    audit_log: Arc<Mutex<Vec<AuditEntry>>>,
+    operations: Arc<Mutex<Vec<Operation>>>,
+    tombstones: Arc<Mutex<HashMap<Uuid, u64>>>,
+    sync_cursors: Arc<Mutex<HashMap<String, SyncCursor>>>,
+    shadow_summaries: Arc<Mutex<HashMap<Uuid, ShadowSummary>>>,
+    summary_redactions: Arc<Mutex<HashMap<Uuid, SummaryRedactions>>>,
+    usage: Arc<Mutex<BTreeMap<UsageKey, Usage>>>,
+    read_markers: Arc<Mutex<HashMap<(Uuid, String), ReadMarker>>>,
+    annotations: Arc<Mutex<BTreeMap<(Uuid, Uuid, Uuid), Annotation>>>,
+    search_feedback: Arc<Mutex<HashMap<(Uuid, String), SearchFeedback>>>,
+    pending_summaries: Arc<Mutex<HashMap<(Uuid, Uuid), PendingSummary>>>,
+    blobs: Arc<Mutex<HashMap<String, (Blob, Vec<u8>)>>>,
 }
 
+type UsageKey = (NaiveDate, Option<String>, Option<Uuid>);
+
 #[allow(unused)]
 impl SynxInMemory {
     pub fn new() -> Self {
@@ -26,6 +50,18 @@ impl SynxInMemory {
             threads: Arc::new(Mutex::new(HashMap::new())),
             messages: Arc::new(Mutex::new(HashMap::new())),
             thread_messages: Arc::new(Mutex::new(HashMap::new())),
+            audit_log: Arc::new(Mutex::new(Vec::new())),
+            operations: Arc::new(Mutex::new(Vec::new())),
+            tombstones: Arc::new(Mutex::new(HashMap::new())),
+            sync_cursors: Arc::new(Mutex::new(HashMap::new())),
+            shadow_summaries: Arc::new(Mutex::new(HashMap::new())),
+            summary_redactions: Arc::new(Mutex::new(HashMap::new())),
+            usage: Arc::new(Mutex::new(BTreeMap::new())),
+            read_markers: Arc::new(Mutex::new(HashMap::new())),
+            annotations: Arc::new(Mutex::new(BTreeMap::new())),
+            search_feedback: Arc::new(Mutex::new(HashMap::new())),
+            pending_summaries: Arc::new(Mutex::new(HashMap::new())),
+            blobs: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -39,48 +75,73 @@ impl Db for SynxInMemory {
 
         Ok(serde_json::json!({
             "threads": threads.clone(),
-            "messages": messages.clone(),
+            "messages": messages
+                .values()
+                .map(|message| (message.id, message))
+                .collect::<HashMap<_, _>>(),
             "thread_messages": thread_messages.clone(),
         }))
     }
 
+    async fn storage_stats(&self) -> Result<serde_json::Value, DatabaseError> {
+        Ok(serde_json::json!({
+            "backend": "in-memory",
+            "threads": self.threads.lock().await.len(),
+            "messages": self.messages.lock().await.len(),
+        }))
+    }
+
     async fn get_threads_with_embeddings(
         &self,
         thread_ids: &[Uuid],
     ) -> Result<Vec<Thread>, DatabaseError> {
         let threads = self.threads.lock().await;
-        thread_ids
+        Ok(thread_ids
             .iter()
-            .filter_map(|id| threads.get(id).cloned())
-            .collect::<Vec<Thread>>()
-            .into_iter()
-            .map(|mut thread| {
-                if thread.embedding.is_none() {
-                    thread.embedding = Some(Embedding::from(vec![0.0; 1536]));
-                }
-                Ok(thread)
-            })
-            .collect()
+            .filter_map(|id| threads.get(id))
+            .filter(|thread| thread.embedding.is_some())
+            .cloned()
+            .collect())
     }
 
     async fn update_thread_summary_and_embedding(
         &self,
         thread_id: Uuid,
         summary: String,
+        provenance: SummaryProvenance,
         embedding: Embedding,
+        log_origin: Option<Uuid>,
     ) -> Result<(), DatabaseError> {
         let mut threads = self.threads.lock().await;
-        if let Some(thread) = threads.get_mut(&thread_id) {
-            thread.set_summary(summary);
-            thread.set_embedding(embedding);
-            Ok(())
-        } else {
-            Err(DatabaseError::NotFound)
+        let Some(thread) = threads.get_mut(&thread_id) else {
+            return Err(DatabaseError::NotFound);
+        };
+
+        // Both locks are held until the thread is updated, so readers can't
+        // observe the operation without the summary or the other way round.
+        let mut operations = self.operations.lock().await;
+        thread.set_summary(summary.clone(), provenance.clone());
+        thread.set_embedding(embedding);
+        if let Some(origin) = log_origin {
+            let seq = operations.last().map_or(1, |last| last.seq + 1);
+            operations.push(Operation {
+                seq,
+                ..Operation::new(
+                    origin,
+                    thread.summarized_at,
+                    OperationKind::SetSummary {
+                        thread_id,
+                        summary,
+                        provenance,
+                    },
+                )
+            });
         }
+        Ok(())
     }
 
-    async fn create_thread(&self) -> Result<Thread, DatabaseError> {
-        let thread = Thread::new();
+    async fn create_thread(&self, input: CreateThread) -> Result<Thread, DatabaseError> {
+        let thread = input.into_thread();
         let mut threads = self.threads.lock().await;
         threads.insert(thread.id(), thread.clone());
         self.thread_messages
@@ -101,13 +162,70 @@ impl Db for SynxInMemory {
 
         if let Some(message_ids) = thread_messages.remove(&thread_id) {
             for message_id in message_ids {
-                messages.remove(&message_id);
+                messages.remove(&(thread_id, message_id));
             }
         }
+        self.shadow_summaries.lock().await.remove(&thread_id);
+        self.summary_redactions.lock().await.remove(&thread_id);
+        self.read_markers
+            .lock()
+            .await
+            .retain(|(marked_thread_id, _), _| *marked_thread_id != thread_id);
+        self.annotations
+            .lock()
+            .await
+            .retain(|(annotated_thread_id, _, _), _| *annotated_thread_id != thread_id);
+        self.search_feedback
+            .lock()
+            .await
+            .retain(|(rated_thread_id, _), _| *rated_thread_id != thread_id);
+        self.pending_summaries
+            .lock()
+            .await
+            .retain(|(pending_thread_id, _), _| *pending_thread_id != thread_id);
 
         Ok(())
     }
 
+    async fn delete_threads(
+        &self,
+        filter: ThreadFilter,
+        dry_run: bool,
+    ) -> Result<Vec<Uuid>, DatabaseError> {
+        let mut threads = self.threads.lock().await;
+        let thread_ids = threads
+            .values()
+            .filter(|thread| filter.matches(thread))
+            .map(|thread| thread.id)
+            .collect::<Vec<_>>();
+        if dry_run {
+            return Ok(thread_ids);
+        }
+
+        let mut messages = self.messages.lock().await;
+        let mut thread_messages = self.thread_messages.lock().await;
+        let mut shadow_summaries = self.shadow_summaries.lock().await;
+        let mut summary_redactions = self.summary_redactions.lock().await;
+        let mut read_markers = self.read_markers.lock().await;
+        let mut annotations = self.annotations.lock().await;
+        let mut search_feedback = self.search_feedback.lock().await;
+        let mut pending_summaries = self.pending_summaries.lock().await;
+        for thread_id in &thread_ids {
+            threads.remove(thread_id);
+            for message_id in thread_messages.remove(thread_id).unwrap_or_default() {
+                messages.remove(&(*thread_id, message_id));
+            }
+            shadow_summaries.remove(thread_id);
+            summary_redactions.remove(thread_id);
+            read_markers.retain(|(marked_thread_id, _), _| marked_thread_id != thread_id);
+            annotations.retain(|(annotated_thread_id, _, _), _| annotated_thread_id != thread_id);
+            search_feedback.retain(|(rated_thread_id, _), _| rated_thread_id != thread_id);
+            pending_summaries.retain(|(pending_thread_id, _), _| pending_thread_id != thread_id);
+        }
+
+        Ok(thread_ids)
+    }
+
     async fn create_message(
         &self,
         thread_id: Uuid,
@@ -119,10 +237,19 @@ impl Db for SynxInMemory {
             .get(&thread_id)
             .ok_or(DatabaseError::NotFound)?;
 
+        let mut messages = self.messages.lock().await;
+        if let Some(parent_id) = input.parent_message_id {
+            if !messages.contains_key(&(thread_id, parent_id)) {
+                return Err(DatabaseError::InvalidInput(format!(
+                    "message {} is not in thread {}",
+                    parent_id, thread_id
+                )));
+            }
+        }
+
         let message = input.into_message(thread_id);
         let message_id = message.id();
-        let mut messages = self.messages.lock().await;
-        messages.insert(message_id, message.clone());
+        messages.insert((thread_id, message_id), message.clone());
 
         let mut thread_messages = self.thread_messages.lock().await;
         thread_messages
@@ -147,16 +274,61 @@ impl Db for SynxInMemory {
 
         let mut messages = self.messages.lock().await;
         let message = messages
-            .get_mut(&message_id)
+            .get_mut(&(thread_id, message_id))
             .ok_or(DatabaseError::NotFound)?;
 
         message.update_content(content);
         Ok(message.clone())
     }
 
+    async fn patch_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        patch: PatchMessage,
+    ) -> Result<Message, DatabaseError> {
+        let mut messages = self.messages.lock().await;
+        let message = messages
+            .get_mut(&(thread_id, message_id))
+            .ok_or(DatabaseError::NotFound)?;
+        if !patch.applies_to(message) {
+            return Err(DatabaseError::Conflict(format!(
+                "message {} is {:?}",
+                message_id, message.status
+            )));
+        }
+
+        message.patch(patch);
+        Ok(message.clone())
+    }
+
+    async fn patch_thread(
+        &self,
+        thread_id: Uuid,
+        patch: PatchThread,
+    ) -> Result<Thread, DatabaseError> {
+        let mut threads = self.threads.lock().await;
+        let thread = threads.get_mut(&thread_id).ok_or(DatabaseError::NotFound)?;
+        if let Some(pinned) = patch.pinned {
+            thread.set_pinned(pinned);
+        }
+        if let Some(summary_locked) = patch.summary_locked {
+            thread.set_summary_locked(summary_locked);
+        }
+        Ok(thread.clone())
+    }
+
     async fn list_threads(&self) -> Result<Vec<Thread>, DatabaseError> {
         let threads = self.threads.lock().await;
-        Ok(threads.values().cloned().collect())
+        let mut threads = threads.values().cloned().collect::<Vec<_>>();
+        threads.sort_by_key(|thread| !thread.pinned);
+        Ok(threads)
+    }
+
+    async fn stream_threads(&self) -> Result<ThreadStream, DatabaseError> {
+        // Every thread is in memory already.
+        let threads = self.list_threads().await?;
+        Ok(Box::pin(futures::stream::iter(threads.into_iter().map(Ok))))
     }
 
     async fn get_thread(&self, thread_id: Uuid) -> Result<Thread, DatabaseError> {
@@ -167,6 +339,15 @@ impl Db for SynxInMemory {
             .ok_or(DatabaseError::NotFound)
     }
 
+    async fn get_threads(&self, thread_ids: &[Uuid]) -> Result<Vec<Thread>, DatabaseError> {
+        let threads = self.threads.lock().await;
+        Ok(thread_ids
+            .iter()
+            .filter_map(|id| threads.get(id))
+            .cloned()
+            .collect())
+    }
+
     async fn get_thread_messages(
         &self,
         thread_id: Uuid,
@@ -178,13 +359,13 @@ impl Db for SynxInMemory {
             return Err(DatabaseError::NotFound);
         }
 
-        let thread_messages = self.thread_messages.lock().await;
         let messages = self.messages.lock().await;
+        let thread_messages = self.thread_messages.lock().await;
 
         let message_ids = thread_messages.get(&thread_id).cloned().unwrap_or_default();
         let mut thread_messages: Vec<Message> = message_ids
             .iter()
-            .filter_map(|id| messages.get(id).cloned())
+            .filter_map(|&id| messages.get(&(thread_id, id)).cloned())
             .collect();
 
         thread_messages.sort_by(|a, b| a.created_at().cmp(&b.created_at()));
@@ -216,9 +397,17 @@ impl Db for SynxInMemory {
 
         let mut messages = self.messages.lock().await;
         messages
-            .remove(&message_id)
+            .remove(&(thread_id, message_id))
             .ok_or(DatabaseError::NotFound)?;
 
+        if let Some(message_ids) = self.thread_messages.lock().await.get_mut(&thread_id) {
+            message_ids.remove(&message_id);
+        }
+        self.annotations
+            .lock()
+            .await
+            .retain(|(_, annotated_message_id, _), _| *annotated_message_id != message_id);
+
         Ok(())
     }
 
@@ -230,9 +419,689 @@ impl Db for SynxInMemory {
         let mut threads = self.threads.lock().await;
         if let Some(thread) = threads.get_mut(&thread_id) {
             thread.set_title(update.title);
+            if let Some(language) = update.summary_language {
+                thread.set_summary_language(language);
+            }
+            if let Some(tags) = update.tags {
+                thread.set_tags(tags);
+            }
             Ok(thread.clone())
         } else {
             Err(DatabaseError::NotFound)
         }
     }
+
+    async fn append_audit_entry(&self, entry: AuditEntry) -> Result<(), DatabaseError> {
+        self.audit_log.lock().await.push(entry);
+        Ok(())
+    }
+
+    async fn list_audit_entries(
+        &self,
+        filter: AuditFilter,
+    ) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let audit_log = self.audit_log.lock().await;
+        Ok(audit_log
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Message, DatabaseError> {
+        self.messages
+            .lock()
+            .await
+            .get(&(thread_id, message_id))
+            .cloned()
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn get_shadow_summary(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<ShadowSummary>, DatabaseError> {
+        Ok(self.shadow_summaries.lock().await.get(&thread_id).cloned())
+    }
+
+    async fn put_shadow_summary(&self, shadow: ShadowSummary) -> Result<(), DatabaseError> {
+        let threads = self.threads.lock().await;
+        if !threads.contains_key(&shadow.thread_id) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.shadow_summaries
+            .lock()
+            .await
+            .insert(shadow.thread_id, shadow);
+        Ok(())
+    }
+
+    async fn get_summary_redactions(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<SummaryRedactions>, DatabaseError> {
+        Ok(self
+            .summary_redactions
+            .lock()
+            .await
+            .get(&thread_id)
+            .cloned())
+    }
+
+    async fn put_summary_redactions(
+        &self,
+        redactions: SummaryRedactions,
+    ) -> Result<(), DatabaseError> {
+        let threads = self.threads.lock().await;
+        if !threads.contains_key(&redactions.thread_id) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.summary_redactions
+            .lock()
+            .await
+            .insert(redactions.thread_id, redactions);
+        Ok(())
+    }
+
+    async fn clear_thread_summary(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+        let mut threads = self.threads.lock().await;
+        let Some(thread) = threads.get_mut(&thread_id) else {
+            return Err(DatabaseError::NotFound);
+        };
+
+        thread.clear_summary();
+        self.shadow_summaries.lock().await.remove(&thread_id);
+        self.summary_redactions.lock().await.remove(&thread_id);
+        Ok(())
+    }
+
+    async fn write_batch(&self, batch: WriteBatch) -> Result<(), DatabaseError> {
+        // Locked in the order `delete_thread` locks them. The operations are
+        // applied to copies, which replace the tables only once every one
+        // of them succeeded.
+        let mut threads = self.threads.lock().await;
+        let mut messages = self.messages.lock().await;
+        let mut thread_messages = self.thread_messages.lock().await;
+        let mut shadow_summaries = self.shadow_summaries.lock().await;
+        let mut summary_redactions = self.summary_redactions.lock().await;
+        let mut read_markers = self.read_markers.lock().await;
+        let mut annotations = self.annotations.lock().await;
+        let mut search_feedback = self.search_feedback.lock().await;
+        let mut pending_summaries = self.pending_summaries.lock().await;
+
+        let mut tables = BatchTables {
+            threads: threads.clone(),
+            messages: messages.clone(),
+            thread_messages: thread_messages.clone(),
+            shadow_summaries: shadow_summaries.clone(),
+            summary_redactions: summary_redactions.clone(),
+            read_markers: read_markers.clone(),
+            annotations: annotations.clone(),
+            search_feedback: search_feedback.clone(),
+            pending_summaries: pending_summaries.clone(),
+        };
+        for op in batch.into_ops() {
+            tables.apply(op)?;
+        }
+
+        *threads = tables.threads;
+        *messages = tables.messages;
+        *thread_messages = tables.thread_messages;
+        *shadow_summaries = tables.shadow_summaries;
+        *summary_redactions = tables.summary_redactions;
+        *read_markers = tables.read_markers;
+        *annotations = tables.annotations;
+        *search_feedback = tables.search_feedback;
+        *pending_summaries = tables.pending_summaries;
+        Ok(())
+    }
+
+    async fn put_thread(&self, thread: Thread) -> Result<(), DatabaseError> {
+        let mut threads = self.threads.lock().await;
+        let (embedding, vectors) = threads
+            .get(&thread.id)
+            .map(|existing| (existing.embedding.clone(), existing.vectors.clone()))
+            .unwrap_or_default();
+        self.thread_messages
+            .lock()
+            .await
+            .entry(thread.id)
+            .or_insert_with(HashSet::new);
+        threads.insert(
+            thread.id,
+            Thread {
+                embedding: thread.embedding.or(embedding),
+                vectors,
+                ..thread
+            },
+        );
+        Ok(())
+    }
+
+    async fn put_message(&self, message: Message) -> Result<(), DatabaseError> {
+        self.threads
+            .lock()
+            .await
+            .get(&message.thread_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        self.thread_messages
+            .lock()
+            .await
+            .entry(message.thread_id)
+            .or_insert_with(HashSet::new)
+            .insert(message.id);
+        self.messages
+            .lock()
+            .await
+            .insert((message.thread_id, message.id), message);
+        Ok(())
+    }
+
+    async fn append_operation(&self, operation: Operation) -> Result<u64, DatabaseError> {
+        let mut operations = self.operations.lock().await;
+        let seq = operations.last().map_or(1, |last| last.seq + 1);
+        let mut tombstones = self.tombstones.lock().await;
+        for id in operation.kind.deleted_ids() {
+            tombstones.entry(id).or_insert(operation.timestamp);
+        }
+        if let OperationKind::Purge { .. } = &operation.kind {
+            *operations = std::mem::take(&mut *operations)
+                .into_iter()
+                .filter_map(|purged| purged.purged_by(&operation.kind))
+                .collect();
+        }
+        operations.push(Operation { seq, ..operation });
+        Ok(seq)
+    }
+
+    async fn get_tombstone(&self, id: Uuid) -> Result<Option<u64>, DatabaseError> {
+        Ok(self.tombstones.lock().await.get(&id).copied())
+    }
+
+    async fn get_sync_cursor(&self, remote: String) -> Result<Option<SyncCursor>, DatabaseError> {
+        Ok(self.sync_cursors.lock().await.get(&remote).copied())
+    }
+
+    async fn put_sync_cursor(
+        &self,
+        remote: String,
+        cursor: SyncCursor,
+    ) -> Result<(), DatabaseError> {
+        self.sync_cursors.lock().await.insert(remote, cursor);
+        Ok(())
+    }
+
+    async fn list_operations(
+        &self,
+        filter: OperationFilter,
+    ) -> Result<Vec<Operation>, DatabaseError> {
+        let operations = self.operations.lock().await;
+        Ok(operations
+            .iter()
+            .filter(|operation| filter.matches(operation))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn add_usage(&self, usage: DailyUsage) -> Result<(), DatabaseError> {
+        self.usage
+            .lock()
+            .await
+            .entry((usage.day, usage.tenant, usage.thread_id))
+            .or_default()
+            .add(&usage.usage);
+        Ok(())
+    }
+
+    async fn list_usage(&self, filter: UsageFilter) -> Result<Vec<DailyUsage>, DatabaseError> {
+        let usage = self.usage.lock().await;
+        Ok(usage
+            .iter()
+            .map(|((day, tenant, thread_id), usage)| DailyUsage {
+                day: *day,
+                tenant: tenant.clone(),
+                thread_id: *thread_id,
+                usage: usage.clone(),
+            })
+            .filter(|usage| filter.matches(usage))
+            .collect())
+    }
+
+    async fn mark_read(
+        &self,
+        thread_id: Uuid,
+        reader: String,
+        message_id: Uuid,
+    ) -> Result<ReadMarker, DatabaseError> {
+        let marker = {
+            let messages = self.messages.lock().await;
+            let message = messages
+                .get(&(thread_id, message_id))
+                .ok_or(DatabaseError::NotFound)?;
+            ReadMarker::new(message, reader.clone())
+        };
+        self.read_markers
+            .lock()
+            .await
+            .insert((thread_id, reader), marker.clone());
+        Ok(marker)
+    }
+
+    async fn unread_counts(&self, reader: String) -> Result<HashMap<Uuid, usize>, DatabaseError> {
+        let messages = self.messages.lock().await;
+        let thread_messages = self.thread_messages.lock().await;
+        let read_markers = self.read_markers.lock().await;
+
+        Ok(thread_messages
+            .iter()
+            .map(|(thread_id, message_ids)| {
+                let marker = read_markers.get(&(*thread_id, reader.clone()));
+                let unread = message_ids
+                    .iter()
+                    .filter_map(|id| messages.get(&(*thread_id, *id)))
+                    .filter(|message| marker.is_none_or(|marker| !marker.is_read(message)))
+                    .count();
+                (*thread_id, unread)
+            })
+            .collect())
+    }
+
+    async fn add_annotation(&self, annotation: Annotation) -> Result<(), DatabaseError> {
+        let messages = self.messages.lock().await;
+        if !messages.contains_key(&(annotation.thread_id, annotation.message_id)) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.annotations.lock().await.insert(
+            (annotation.thread_id, annotation.message_id, annotation.id),
+            annotation,
+        );
+        Ok(())
+    }
+
+    async fn delete_annotation(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        annotation_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.annotations
+            .lock()
+            .await
+            .remove(&(thread_id, message_id, annotation_id))
+            .map(|_| ())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn list_annotations(&self, thread_id: Uuid) -> Result<Vec<Annotation>, DatabaseError> {
+        let mut annotations = self
+            .annotations
+            .lock()
+            .await
+            .values()
+            .filter(|annotation| annotation.thread_id == thread_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        annotations.sort_by_key(|annotation| annotation.created_at);
+        Ok(annotations)
+    }
+
+    async fn put_search_feedback(&self, feedback: SearchFeedback) -> Result<(), DatabaseError> {
+        let threads = self.threads.lock().await;
+        if !threads.contains_key(&feedback.thread_id) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.search_feedback
+            .lock()
+            .await
+            .insert((feedback.thread_id, feedback.reader.clone()), feedback);
+        Ok(())
+    }
+
+    async fn list_search_feedback(
+        &self,
+        reader: String,
+    ) -> Result<Vec<SearchFeedback>, DatabaseError> {
+        Ok(self
+            .search_feedback
+            .lock()
+            .await
+            .values()
+            .filter(|feedback| feedback.reader == reader)
+            .cloned()
+            .collect())
+    }
+
+    async fn put_pending_summary(&self, pending: PendingSummary) -> Result<(), DatabaseError> {
+        let threads = self.threads.lock().await;
+        if !threads.contains_key(&pending.thread_id) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.pending_summaries
+            .lock()
+            .await
+            .insert((pending.thread_id, pending.message_id), pending);
+        Ok(())
+    }
+
+    async fn list_pending_summaries(&self) -> Result<Vec<PendingSummary>, DatabaseError> {
+        let mut pending_summaries = self
+            .pending_summaries
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        pending_summaries.sort_by_key(|pending| pending.queued_at);
+        Ok(pending_summaries)
+    }
+
+    async fn delete_pending_summary(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.pending_summaries
+            .lock()
+            .await
+            .remove(&(thread_id, message_id))
+            .map(|_| ())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn put_blob(&self, blob: Blob, data: Vec<u8>) -> Result<Blob, DatabaseError> {
+        let mut blobs = self.blobs.lock().await;
+        let (blob, _) = blobs
+            .entry(blob.hash.clone())
+            .or_insert_with(|| (blob, data));
+        Ok(blob.clone())
+    }
+
+    async fn delete_blob(&self, hash: String) -> Result<(), DatabaseError> {
+        self.blobs
+            .lock()
+            .await
+            .remove(&hash)
+            .map(|_| ())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn get_blob(&self, hash: String) -> Result<(Blob, Vec<u8>), DatabaseError> {
+        self.blobs
+            .lock()
+            .await
+            .get(&hash)
+            .cloned()
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn export(&self) -> Result<ExportStream, DatabaseError> {
+        // Copied under both locks, so the snapshot is consistent; the
+        // stream then holds no lock.
+        let threads = self.threads.lock().await;
+        let messages = self.messages.lock().await;
+
+        let mut sorted_threads = threads.values().cloned().collect::<Vec<_>>();
+        sorted_threads.sort_by_key(|thread| (thread.created_at, thread.id));
+        let mut messages_by_thread = HashMap::<Uuid, Vec<Message>>::new();
+        for message in messages.values() {
+            messages_by_thread
+                .entry(message.thread_id)
+                .or_default()
+                .push(message.clone());
+        }
+
+        let mut records = Vec::with_capacity(threads.len() + messages.len());
+        for thread in sorted_threads {
+            let mut thread_messages = messages_by_thread.remove(&thread.id).unwrap_or_default();
+            thread_messages.sort_by_key(|message| (message.created_at, message.id));
+
+            records.push(Ok(ExportRecord::Thread(thread)));
+            records.extend(
+                thread_messages
+                    .into_iter()
+                    .map(ExportRecord::Message)
+                    .map(Ok),
+            );
+        }
+
+        Ok(Box::pin(futures::stream::iter(records)))
+    }
+}
+
+/// Copies of the tables a [`WriteBatch`] writes to.
+struct BatchTables {
+    threads: HashMap<Uuid, Thread>,
+    messages: HashMap<(Uuid, Uuid), Message>,
+    thread_messages: HashMap<Uuid, HashSet<Uuid>>,
+    shadow_summaries: HashMap<Uuid, ShadowSummary>,
+    summary_redactions: HashMap<Uuid, SummaryRedactions>,
+    read_markers: HashMap<(Uuid, String), ReadMarker>,
+    annotations: BTreeMap<(Uuid, Uuid, Uuid), Annotation>,
+    search_feedback: HashMap<(Uuid, String), SearchFeedback>,
+    pending_summaries: HashMap<(Uuid, Uuid), PendingSummary>,
+}
+
+impl BatchTables {
+    fn apply(&mut self, op: BatchOp) -> Result<(), DatabaseError> {
+        match op {
+            BatchOp::CreateThread(thread) => {
+                if self.threads.contains_key(&thread.id) {
+                    return Err(DatabaseError::InvalidInput(format!(
+                        "thread {} already exists",
+                        thread.id
+                    )));
+                }
+                self.thread_messages.insert(thread.id, HashSet::new());
+                self.threads.insert(thread.id, thread);
+            }
+            BatchOp::CreateMessage(message) => {
+                let thread_id = message.thread_id;
+                if !self.threads.contains_key(&thread_id) {
+                    return Err(DatabaseError::NotFound);
+                }
+                if let Some(parent_id) = message.parent_message_id {
+                    if !self.messages.contains_key(&(thread_id, parent_id)) {
+                        return Err(DatabaseError::InvalidInput(format!(
+                            "message {} is not in thread {}",
+                            parent_id, thread_id
+                        )));
+                    }
+                }
+
+                self.thread_messages
+                    .entry(thread_id)
+                    .or_default()
+                    .insert(message.id);
+                self.messages.insert((thread_id, message.id), message);
+            }
+            BatchOp::DeleteMessage {
+                thread_id,
+                message_id,
+            } => {
+                self.messages
+                    .remove(&(thread_id, message_id))
+                    .ok_or(DatabaseError::NotFound)?;
+                if let Some(message_ids) = self.thread_messages.get_mut(&thread_id) {
+                    message_ids.remove(&message_id);
+                }
+                self.annotations
+                    .retain(|(_, annotated_message_id, _), _| *annotated_message_id != message_id);
+            }
+            BatchOp::PruneMessage {
+                thread_id,
+                message_id,
+            } => {
+                self.apply(BatchOp::DeleteMessage {
+                    thread_id,
+                    message_id,
+                })?;
+                self.threads
+                    .get_mut(&thread_id)
+                    .ok_or(DatabaseError::NotFound)?
+                    .pruned_message_count += 1;
+            }
+            BatchOp::DeleteThread(thread_id) => {
+                self.threads
+                    .remove(&thread_id)
+                    .ok_or(DatabaseError::NotFound)?;
+                for message_id in self.thread_messages.remove(&thread_id).unwrap_or_default() {
+                    self.messages.remove(&(thread_id, message_id));
+                }
+                self.shadow_summaries.remove(&thread_id);
+                self.summary_redactions.remove(&thread_id);
+                self.read_markers
+                    .retain(|(marked_thread_id, _), _| *marked_thread_id != thread_id);
+                self.annotations
+                    .retain(|(annotated_thread_id, _, _), _| *annotated_thread_id != thread_id);
+                self.search_feedback
+                    .retain(|(rated_thread_id, _), _| *rated_thread_id != thread_id);
+                self.pending_summaries
+                    .retain(|(pending_thread_id, _), _| *pending_thread_id != thread_id);
+            }
+            BatchOp::ClearSummary(thread_id) => {
+                self.threads
+                    .get_mut(&thread_id)
+                    .ok_or(DatabaseError::NotFound)?
+                    .clear_summary();
+                self.shadow_summaries.remove(&thread_id);
+                self.summary_redactions.remove(&thread_id);
+            }
+            BatchOp::PutThreadVector { thread_id, vector } => {
+                let vectors = &mut self
+                    .threads
+                    .get_mut(&thread_id)
+                    .ok_or(DatabaseError::NotFound)?
+                    .vectors;
+                match vectors.iter_mut().find(|stored| stored.id == vector.id) {
+                    Some(stored) => *stored = vector,
+                    None => vectors.push(vector),
+                }
+            }
+            BatchOp::DeleteThreadVectors(thread_id) => {
+                self.threads
+                    .get_mut(&thread_id)
+                    .ok_or(DatabaseError::NotFound)?
+                    .vectors
+                    .clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_domain::thread::CreateThread;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn debug_state_keys_messages_by_id() {
+        let db = SynxInMemory::new();
+        let thread = db.create_thread(CreateThread::default()).await.unwrap();
+        let message = db
+            .create_message(
+                thread.id,
+                CreateMessage {
+                    role: "user".into(),
+                    content: "Hello".to_string().into(),
+                    parent_message_id: None,
+                    metadata: Default::default(),
+                    status: Default::default(),
+                    memorize: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let state = db.debug_state().await.unwrap();
+
+        assert_eq!(
+            state["messages"][message.id.to_string()]["thread_id"],
+            thread.id.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn threads_without_embeddings_are_skipped() {
+        let db = SynxInMemory::new();
+        let thread = db.create_thread(CreateThread::default()).await.unwrap();
+
+        let threads = db.get_threads_with_embeddings(&[thread.id]).await.unwrap();
+
+        assert!(threads.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_threads_are_skipped() {
+        let db = SynxInMemory::new();
+
+        let threads = db
+            .get_threads_with_embeddings(&[Uuid::new_v4()])
+            .await
+            .unwrap();
+
+        assert!(threads.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stored_embeddings_are_returned() {
+        let db = SynxInMemory::new();
+        let embedded = db.create_thread(CreateThread::default()).await.unwrap();
+        let pending = db.create_thread(CreateThread::default()).await.unwrap();
+        db.update_thread_summary_and_embedding(
+            embedded.id,
+            "I asked about the weather".to_string(),
+            Default::default(),
+            Embedding::from(vec![0.6, 0.8]),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let threads = db
+            .get_threads_with_embeddings(&[embedded.id, pending.id])
+            .await
+            .unwrap();
+
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].id, embedded.id);
+        assert_eq!(
+            threads[0].summary.as_deref(),
+            Some("I asked about the weather")
+        );
+        assert_eq!(
+            threads[0].embedding.as_ref().unwrap().to_vec(),
+            vec![0.6, 0.8]
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_of_unknown_thread_is_not_found() {
+        let db = SynxInMemory::new();
+
+        let result = db
+            .update_thread_summary_and_embedding(
+                Uuid::new_v4(),
+                "summary".to_string(),
+                Default::default(),
+                Embedding::from(vec![1.0, 0.0]),
+                None,
+            )
+            .await;
+
+        assert!(matches!(result, Err(DatabaseError::NotFound)));
+    }
 }
@@ -0,0 +1,3 @@
+use synx_in_memory_database::SynxInMemory;
+
+synx_database_tests::db_conformance_tests!(SynxInMemory::new());
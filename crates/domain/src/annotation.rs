@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::message::Message;
+
+/// Feedback left on a message, e.g. to evaluate summaries or to weigh what
+/// a thread remembers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnotationKind {
+    ThumbsUp,
+    ThumbsDown,
+    Label { label: String },
+    Note { text: String },
+}
+
+impl AnnotationKind {
+    /// Whether the annotation marks the message useful (`Some(true)`) or
+    /// not (`Some(false)`), labels and notes telling neither.
+    pub fn verdict(&self) -> Option<bool> {
+        match self {
+            AnnotationKind::ThumbsUp => Some(true),
+            AnnotationKind::ThumbsDown => Some(false),
+            AnnotationKind::Label { .. } | AnnotationKind::Note { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: Uuid,
+    pub thread_id: Uuid,
+    pub message_id: Uuid,
+    /// The API key or user who left the annotation.
+    pub author: String,
+    #[serde(flatten)]
+    pub kind: AnnotationKind,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateAnnotation {
+    #[serde(flatten)]
+    pub kind: AnnotationKind,
+}
+
+impl CreateAnnotation {
+    pub fn into_annotation(self, thread_id: Uuid, message_id: Uuid, author: String) -> Annotation {
+        Annotation {
+            id: Uuid::new_v4(),
+            thread_id,
+            message_id,
+            author,
+            kind: self.kind,
+            created_at: Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
+
+/// A message along with its annotations, oldest first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnotatedMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// A message along with its replies, recursively.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageNode {
+    #[serde(flatten)]
+    pub message: AnnotatedMessage,
+    #[serde(default)]
+    pub replies: Vec<MessageNode>,
+}
+
+impl MessageNode {
+    /// Arranges the messages into trees, keeping the order of siblings.
+    /// Messages whose parent isn't among them, e.g. deleted, are roots.
+    pub fn tree(messages: Vec<AnnotatedMessage>) -> Vec<MessageNode> {
+        let ids = messages
+            .iter()
+            .map(|annotated| annotated.message.id)
+            .collect::<HashSet<_>>();
+        let mut replies = HashMap::<Uuid, Vec<AnnotatedMessage>>::new();
+        let mut roots = Vec::new();
+        for annotated in messages {
+            match annotated
+                .message
+                .parent_message_id
+                .filter(|parent_id| ids.contains(parent_id))
+            {
+                Some(parent_id) => replies.entry(parent_id).or_default().push(annotated),
+                None => roots.push(annotated),
+            }
+        }
+
+        fn node(
+            message: AnnotatedMessage,
+            replies: &mut HashMap<Uuid, Vec<AnnotatedMessage>>,
+        ) -> MessageNode {
+            let children = replies.remove(&message.message.id).unwrap_or_default();
+            MessageNode {
+                replies: children
+                    .into_iter()
+                    .map(|child| node(child, replies))
+                    .collect(),
+                message,
+            }
+        }
+
+        roots
+            .into_iter()
+            .map(|root| node(root, &mut replies))
+            .collect()
+    }
+}
+
+/// A reader's verdict on a thread returned by a search, the latest one
+/// replacing the earlier ones.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SearchFeedback {
+    pub thread_id: Uuid,
+    pub reader: String,
+    pub relevant: bool,
+    /// The query the thread was returned for, kept for evaluation.
+    #[serde(default)]
+    pub query: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateSearchFeedback {
+    pub thread_id: Uuid,
+    pub relevant: bool,
+    #[serde(default)]
+    pub query: Option<String>,
+}
+
+impl CreateSearchFeedback {
+    pub fn into_feedback(self, reader: String) -> SearchFeedback {
+        SearchFeedback {
+            thread_id: self.thread_id,
+            reader,
+            relevant: self.relevant,
+            query: self.query,
+            created_at: Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{message::CreateMessage, role::Role};
+
+    use super::*;
+
+    fn message(thread_id: Uuid, parent_message_id: Option<Uuid>) -> AnnotatedMessage {
+        AnnotatedMessage {
+            message: CreateMessage {
+                role: Role::User,
+                content: "hello".to_string().into(),
+                parent_message_id,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            }
+            .into_message(thread_id),
+            annotations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn replies_are_nested_under_their_parent() {
+        let thread_id = Uuid::new_v4();
+        let root = message(thread_id, None);
+        let first = message(thread_id, Some(root.message.id));
+        let second = message(thread_id, Some(root.message.id));
+        let nested = message(thread_id, Some(first.message.id));
+        let orphan = message(thread_id, Some(Uuid::new_v4()));
+        let ids = [&root, &first, &second, &nested, &orphan].map(|m| m.message.id);
+
+        let tree = MessageNode::tree(vec![root, first, second, nested, orphan]);
+
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree[0].message.message.id, ids[0]);
+        assert_eq!(tree[1].message.message.id, ids[4]);
+        let replies = &tree[0].replies;
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].message.message.id, ids[1]);
+        assert_eq!(replies[1].message.message.id, ids[2]);
+        assert_eq!(replies[0].replies[0].message.message.id, ids[3]);
+    }
+}
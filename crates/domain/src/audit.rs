@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub timestamp: u64,
+    pub actor: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub entity_ids: Vec<Uuid>,
+    pub status: u16,
+    pub request_hash: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AuditFilter {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub entity_id: Option<Uuid>,
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp <= to)
+            && self
+                .entity_id
+                .is_none_or(|id| entry.entity_ids.contains(&id))
+    }
+}
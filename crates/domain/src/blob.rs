@@ -0,0 +1,43 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the image references pointing at a stored blob, rather than at
+/// a URL.
+pub const BLOB_REF_PREFIX: &str = "blob:";
+
+/// Binary content, e.g. an image, stored once however many messages use it.
+/// Blobs are addressed by the SHA-256 of their data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blob {
+    /// Lowercase hex SHA-256 of the data.
+    pub hash: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    pub size: usize,
+    pub created_at: u64,
+    /// The tenant of the caller who stored it, the only one who can read it.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+impl Blob {
+    pub fn new(hash: String, mime_type: Option<String>, size: usize) -> Self {
+        Self {
+            hash,
+            mime_type,
+            size,
+            created_at: Utc::now().timestamp_millis() as u64,
+            tenant: None,
+        }
+    }
+
+    /// `blob:{hash}`, what image content refers to the blob with.
+    pub fn reference(&self) -> String {
+        format!("{}{}", BLOB_REF_PREFIX, self.hash)
+    }
+}
+
+/// The hash an image refers to, when it is a blob reference.
+pub fn parse_blob_ref(image: &str) -> Option<&str> {
+    image.strip_prefix(BLOB_REF_PREFIX)
+}
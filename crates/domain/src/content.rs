@@ -11,6 +11,13 @@ pub enum ContentKind {
         #[serde(rename = "mimeType")]
         mime_type: Option<String>,
     },
+    /// Structured data, e.g. the result of a tool call.
+    Json {
+        value: serde_json::Value,
+        /// Name or URL of the schema the value follows.
+        #[serde(default)]
+        schema: Option<String>,
+    },
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -40,6 +47,7 @@ impl ToString for Content {
             .map(|content| match content {
                 ContentKind::Text { text } => text.clone(),
                 ContentKind::Image { image, .. } => image.clone(),
+                ContentKind::Json { value, .. } => value.to_string(),
             })
             .collect::<Vec<String>>()
             .join("\n")
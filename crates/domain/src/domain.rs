@@ -1,6 +1,14 @@
+pub mod annotation;
+pub mod audit;
+pub mod blob;
 pub mod content;
 pub mod embedding;
+pub mod export;
 pub mod message;
+pub mod read;
+pub mod role;
+pub mod sync;
 pub mod thread;
+pub mod usage;
 
 pub use uuid::Uuid;
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{message::Message, thread::Thread};
+
+/// An entry of an export: every thread, each followed by its messages in
+/// chronological order. Embeddings aren't exported, they are made again
+/// from the summaries.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Thread(Thread),
+    Message(Message),
+}
@@ -1,16 +1,53 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use uuid::Uuid;
 
-use crate::content::Content;
+use crate::{
+    content::{Content, ContentKind},
+    role::Role,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Message {
     pub id: Uuid,
     pub thread_id: Uuid,
-    pub role: String,
+    pub role: Role,
     pub content: Content,
     pub created_at: u64,
+    /// Milliseconds since the epoch of the last content change, `0` if it
+    /// never changed since this was recorded.
+    #[serde(default)]
+    pub updated_at: u64,
+    /// The message this one replies to, in the same thread.
+    #[serde(default)]
+    pub parent_message_id: Option<Uuid>,
+    /// Free-form data attached by the client, never summarized.
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    #[serde(default)]
+    pub status: MessageStatus,
+    /// Whether the message may flow into the summary of its thread.
+    #[serde(default = "memorize_by_default")]
+    pub memorize: bool,
+}
+
+fn memorize_by_default() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    /// Summarized as soon as it is stored.
+    #[default]
+    Final,
+    /// Written incrementally, e.g. as an assistant reply streams in, and
+    /// summarized once complete.
+    Streaming,
+    /// Stored and served, but left out of summaries until finalized, e.g.
+    /// an assistant generation that may still be abandoned.
+    Draft,
 }
 
 impl Message {
@@ -20,6 +57,30 @@ impl Message {
 
     pub fn update_content(&mut self, new_content: UpdateMessage) {
         self.content = new_content.content;
+        self.updated_at = Utc::now().timestamp_millis() as u64;
+    }
+
+    /// Appends the content parts and merges the metadata, as described by
+    /// [`PatchMessage`].
+    pub fn patch(&mut self, patch: PatchMessage) {
+        self.content.0.extend(patch.append);
+        if let Some(text) = patch.append_text {
+            match self.content.0.last_mut() {
+                Some(ContentKind::Text { text: last }) => last.push_str(&text),
+                _ => self.content.0.push(ContentKind::Text { text }),
+            }
+        }
+        if let Some(status) = patch.status {
+            self.status = status;
+        }
+        for (key, value) in patch.metadata.unwrap_or_default() {
+            if value.is_null() {
+                self.metadata.remove(&key);
+            } else {
+                self.metadata.insert(key, value);
+            }
+        }
+        self.updated_at = Utc::now().timestamp_millis() as u64;
     }
 
     pub fn created_at(&self) -> DateTime<Utc> {
@@ -35,8 +96,21 @@ impl ToString for Message {
 
 #[derive(Serialize, Deserialize)]
 pub struct CreateMessage {
-    pub role: String,
+    #[serde(deserialize_with = "Role::deserialize_strict")]
+    pub role: Role,
     pub content: Content,
+    #[serde(default)]
+    pub parent_message_id: Option<Uuid>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+    /// [`MessageStatus::Streaming`] to write the message with appends, or
+    /// [`MessageStatus::Draft`] to keep it out of summaries until finalized.
+    #[serde(default)]
+    pub status: MessageStatus,
+    /// `false` for sensitive or ephemeral messages, stored and served but
+    /// never summarized nor embedded.
+    #[serde(default = "memorize_by_default")]
+    pub memorize: bool,
 }
 
 impl CreateMessage {
@@ -47,6 +121,11 @@ impl CreateMessage {
             role: self.role,
             content: self.content,
             created_at: Utc::now().timestamp_millis() as u64,
+            updated_at: 0,
+            parent_message_id: self.parent_message_id,
+            metadata: self.metadata,
+            status: self.status,
+            memorize: self.memorize,
         }
     }
 }
@@ -56,6 +135,45 @@ pub struct UpdateMessage {
     pub content: Content,
 }
 
+/// A partial update of a message, e.g. to build an assistant reply as it
+/// streams in without resending what was already stored.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PatchMessage {
+    /// Content parts added after the existing ones.
+    #[serde(default)]
+    pub append: Vec<ContentKind>,
+    /// Merged into the metadata, `null` values removing their key.
+    #[serde(default)]
+    pub metadata: Option<Map<String, Value>>,
+    /// Text added to the last text part, or as a new part when the content
+    /// doesn't end with text.
+    #[serde(skip)]
+    pub append_text: Option<String>,
+    #[serde(skip)]
+    pub status: Option<MessageStatus>,
+    /// Fails the patch, leaving the message as it is, unless the message has
+    /// this status. Checked in the same write as the patch.
+    #[serde(skip)]
+    pub expected_status: Option<MessageStatus>,
+}
+
+impl PatchMessage {
+    /// Whether the message has the status the patch expects, if any.
+    pub fn applies_to(&self, message: &Message) -> bool {
+        self.expected_status
+            .map_or(true, |status| message.status == status)
+    }
+}
+
+/// A chunk of a streaming message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AppendMessage {
+    pub text: String,
+    /// Marks the message complete, which gets it summarized.
+    #[serde(default)]
+    pub done: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ThreadMessagesResponse {
     pub messages: Vec<Message>,
@@ -63,3 +181,33 @@ pub struct ThreadMessagesResponse {
     pub offset: usize,
     pub limit: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_text_extends_the_last_text_part() {
+        let mut message = CreateMessage {
+            role: Role::Assistant,
+            content: "Hel".to_string().into(),
+            parent_message_id: None,
+            metadata: Map::new(),
+            status: MessageStatus::Streaming,
+            memorize: true,
+        }
+        .into_message(Uuid::new_v4());
+
+        for (text, status) in [("lo", None), (", world", Some(MessageStatus::Final))] {
+            message.patch(PatchMessage {
+                append_text: Some(text.to_string()),
+                status,
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(message.content.0.len(), 1);
+        assert_eq!(message.content.to_string(), "Hello, world");
+        assert_eq!(message.status, MessageStatus::Final);
+    }
+}
@@ -0,0 +1,40 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::message::Message;
+
+/// The last message of a thread a reader (an API key or a user) has read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadMarker {
+    pub thread_id: Uuid,
+    pub reader: String,
+    pub last_read_message_id: Uuid,
+    /// Creation time of the last read message, so the messages after it can
+    /// be counted without looking it up, even once it has been deleted.
+    pub last_read_created_at: u64,
+    pub read_at: u64,
+}
+
+impl ReadMarker {
+    pub fn new(message: &Message, reader: String) -> Self {
+        Self {
+            thread_id: message.thread_id,
+            reader,
+            last_read_message_id: message.id,
+            last_read_created_at: message.created_at,
+            read_at: Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    /// Whether the message comes at or before the last read one, messages
+    /// being ordered by creation time, then by id.
+    pub fn is_read(&self, message: &Message) -> bool {
+        (message.created_at, message.id) <= (self.last_read_created_at, self.last_read_message_id)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarkRead {
+    pub last_read_message_id: Uuid,
+}
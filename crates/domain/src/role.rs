@@ -0,0 +1,142 @@
+use std::fmt;
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+const KNOWN_ROLES: [Role; 4] = [Role::User, Role::Assistant, Role::System, Role::Tool];
+
+/// The author of a message. Roles outside the well-known ones are kept as
+/// [`Role::Other`], so stored messages always round-trip.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+    Tool,
+    Other(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidRole {
+    #[error("role must not be empty")]
+    Empty,
+    #[error("unknown role \"{0}\", did you mean \"{1}\"?")]
+    Misspelled(String, Role),
+}
+
+impl Role {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+            Role::Tool => "tool",
+            Role::Other(role) => role,
+        }
+    }
+
+    /// Parses a role coming from a client, rejecting empty roles and custom
+    /// roles that look like a typo of a well-known one (e.g. "asistant"),
+    /// allowing one edit every four characters of the well-known role.
+    pub fn parse_strict(role: &str) -> Result<Self, InvalidRole> {
+        let role = Role::from(role.trim());
+        let Role::Other(name) = &role else {
+            return Ok(role);
+        };
+
+        if name.is_empty() {
+            return Err(InvalidRole::Empty);
+        }
+
+        let lowercase = name.to_lowercase();
+        if let Some(known) = KNOWN_ROLES
+            .into_iter()
+            .find(|known| edit_distance(&lowercase, known.as_str()) <= known.as_str().len() / 4)
+        {
+            return Err(InvalidRole::Misspelled(name.clone(), known));
+        }
+
+        Ok(role)
+    }
+
+    pub fn deserialize_strict<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let role = String::deserialize(deserializer)?;
+        Role::parse_strict(&role).map_err(D::Error::custom)
+    }
+}
+
+impl From<&str> for Role {
+    fn from(role: &str) -> Self {
+        KNOWN_ROLES
+            .into_iter()
+            .find(|known| known.as_str().eq_ignore_ascii_case(role))
+            .unwrap_or_else(|| Role::Other(role.to_string()))
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Role::from(String::deserialize(deserializer)?.as_str()))
+    }
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_round_trip_through_serde() {
+        for role in [Role::User, Role::Tool, Role::Other("narrator".to_string())] {
+            let json = serde_json::to_string(&role).unwrap();
+            assert_eq!(serde_json::from_str::<Role>(&json).unwrap(), role);
+        }
+        assert_eq!(
+            serde_json::to_string(&Role::Assistant).unwrap(),
+            "\"assistant\""
+        );
+    }
+
+    #[test]
+    fn strict_parsing_rejects_typos_of_known_roles() {
+        assert_eq!(Role::parse_strict("Assistant").unwrap(), Role::Assistant);
+        assert!(matches!(
+            Role::parse_strict("asistant"),
+            Err(InvalidRole::Misspelled(_, Role::Assistant))
+        ));
+        assert!(matches!(Role::parse_strict(" "), Err(InvalidRole::Empty)));
+        assert_eq!(
+            Role::parse_strict("narrator").unwrap(),
+            Role::Other("narrator".to_string())
+        );
+    }
+}
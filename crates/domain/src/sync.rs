@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    message::Message,
+    thread::{SummaryProvenance, Thread},
+};
+
+/// A change recorded in an instance's op-log, consumed by external indexers
+/// or backups, and replayed by other replicas to converge on the same state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Operation {
+    pub id: Uuid,
+    /// The replica the change was originally made on.
+    pub origin: Uuid,
+    /// Milliseconds since the epoch at which the change was made on its
+    /// origin, used to resolve conflicts (last writer wins).
+    pub timestamp: u64,
+    /// Position in the local op-log, assigned when the operation is
+    /// appended. Sequence numbers only ever increase, so operations from
+    /// other replicas, appended as they arrive, land after every position
+    /// handed out before them.
+    #[serde(default)]
+    pub seq: u64,
+    #[serde(flatten)]
+    pub kind: OperationKind,
+}
+
+impl Operation {
+    /// An operation for a change made at `timestamp`, which should be the
+    /// time recorded on the entity itself so every replica stores the same.
+    pub fn new(origin: Uuid, timestamp: u64, kind: OperationKind) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            origin,
+            timestamp,
+            seq: 0,
+            kind,
+        }
+    }
+
+    /// Whether the operation wins over a write made at `timestamp`, the
+    /// earlier write being kept on ties.
+    pub fn supersedes(&self, timestamp: u64) -> bool {
+        self.timestamp > timestamp
+    }
+
+    /// The operation as kept in the op-log once `purge`, an
+    /// [`OperationKind::Purge`], was appended: `None` when it holds purged
+    /// content, i.e. it puts a purged message or sets a summary of the
+    /// purged thread, and without the summary when it puts the thread.
+    pub fn purged_by(self, purge: &OperationKind) -> Option<Self> {
+        let OperationKind::Purge {
+            thread_id,
+            message_ids,
+        } = purge
+        else {
+            return Some(self);
+        };
+
+        match self.kind {
+            OperationKind::PutMessage { message }
+                if message.thread_id == *thread_id && message_ids.contains(&message.id) =>
+            {
+                None
+            }
+            OperationKind::SetSummary {
+                thread_id: summarized,
+                ..
+            } if summarized == *thread_id => None,
+            OperationKind::PutThread { thread } if thread.id == *thread_id => Some(Self {
+                kind: OperationKind::PutThread {
+                    thread: Thread {
+                        summary: None,
+                        summary_provenance: None,
+                        embedding: None,
+                        ..thread
+                    },
+                },
+                ..self
+            }),
+            kind => Some(Self { kind, ..self }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OperationKind {
+    /// Creates the thread or replaces its metadata, leaving its summary to
+    /// [`OperationKind::SetSummary`].
+    PutThread {
+        thread: Thread,
+    },
+    DeleteThread {
+        thread_id: Uuid,
+    },
+    /// Creates the message or replaces its content.
+    PutMessage {
+        message: Message,
+    },
+    DeleteMessage {
+        thread_id: Uuid,
+        message_id: Uuid,
+    },
+    SetSummary {
+        thread_id: Uuid,
+        summary: String,
+        #[serde(default)]
+        provenance: SummaryProvenance,
+    },
+    /// Deletes the messages and clears the summary of the thread, dropping
+    /// the earlier operations holding their content from the op-log, see
+    /// [`Operation::purged_by`].
+    Purge {
+        thread_id: Uuid,
+        message_ids: Vec<Uuid>,
+    },
+}
+
+impl OperationKind {
+    /// The threads and messages the operation deletes, for which a
+    /// tombstone is kept once it is appended to the op-log.
+    pub fn deleted_ids(&self) -> Vec<Uuid> {
+        match self {
+            OperationKind::DeleteThread { thread_id } => vec![*thread_id],
+            OperationKind::DeleteMessage { message_id, .. } => vec![*message_id],
+            OperationKind::Purge { message_ids, .. } => message_ids.clone(),
+            OperationKind::PutThread { .. }
+            | OperationKind::PutMessage { .. }
+            | OperationKind::SetSummary { .. } => Vec::new(),
+        }
+    }
+
+    pub fn thread_id(&self) -> Uuid {
+        match self {
+            OperationKind::PutThread { thread } => thread.id,
+            OperationKind::PutMessage { message } => message.thread_id,
+            OperationKind::DeleteThread { thread_id }
+            | OperationKind::DeleteMessage { thread_id, .. }
+            | OperationKind::SetSummary { thread_id, .. }
+            | OperationKind::Purge { thread_id, .. } => *thread_id,
+        }
+    }
+}
+
+/// How far an instance synced with a remote, persisted so it resumes there
+/// after a restart rather than exchanging the whole op-log again.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCursor {
+    /// Last sequence number of the local op-log pushed to the remote.
+    pub pushed_seq: u64,
+    /// Last sequence number of the remote op-log pulled from it.
+    pub pulled_seq: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OperationFilter {
+    /// Only operations appended after this sequence number.
+    #[serde(default)]
+    pub since_seq: u64,
+    /// Only operations made on this replica.
+    #[serde(default)]
+    pub origin: Option<Uuid>,
+    /// Leaves out operations made on this replica, e.g. the caller's own.
+    #[serde(default)]
+    pub exclude_origin: Option<Uuid>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl OperationFilter {
+    pub fn matches(&self, operation: &Operation) -> bool {
+        operation.seq > self.since_seq
+            && self.origin.is_none_or(|origin| operation.origin == origin)
+            && self
+                .exclude_origin
+                .is_none_or(|origin| operation.origin != origin)
+    }
+}
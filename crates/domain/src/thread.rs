@@ -1,15 +1,63 @@
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::embedding::Embedding;
+use crate::{embedding::Embedding, message::Message};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Thread {
     pub id: Uuid,
     pub title: Option<String>,
     pub summary: Option<String>,
+    #[serde(default)]
+    pub summary_language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Milliseconds since the epoch, `0` for threads stored before it was
+    /// recorded.
+    #[serde(default)]
+    pub created_at: u64,
+    /// Milliseconds since the epoch of the last change to the thread itself,
+    /// `0` if it never changed since this was recorded.
+    #[serde(default)]
+    pub updated_at: u64,
+    /// Milliseconds since the epoch of the last summary change, `0` if it
+    /// never changed since this was recorded.
+    #[serde(default)]
+    pub summarized_at: u64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Pinned threads are listed first.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Locked summaries, e.g. curated by hand, aren't updated by new
+    /// messages.
+    #[serde(default)]
+    pub summary_locked: bool,
+    /// How the summary was made, `None` for summaries stored before it was
+    /// recorded.
+    #[serde(default)]
+    pub summary_provenance: Option<SummaryProvenance>,
+    /// Number of messages deleted by pruning, their content living on in
+    /// the summary only.
+    #[serde(default)]
+    pub pruned_message_count: u64,
     #[serde(skip)]
     pub embedding: Option<Embedding>,
+    /// Embeddings of parts of the thread, read along with `embedding`.
+    #[serde(skip)]
+    pub vectors: Vec<ThreadVector>,
+}
+
+/// An embedding of part of a thread, e.g. a window of its messages, searched
+/// along with the summary's so threads going over several topics are found
+/// by each of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThreadVector {
+    /// Unique within the thread, e.g. the id of the first message of the
+    /// window embedded.
+    pub id: Uuid,
+    pub embedding: Embedding,
 }
 
 impl Thread {
@@ -18,7 +66,18 @@ impl Thread {
             id: Uuid::new_v4(),
             title: None,
             summary: None,
+            summary_language: None,
+            tenant: None,
+            created_at: Utc::now().timestamp_millis() as u64,
+            updated_at: 0,
+            summarized_at: 0,
+            tags: Vec::new(),
+            pinned: false,
+            summary_locked: false,
+            summary_provenance: None,
+            pruned_message_count: 0,
             embedding: None,
+            vectors: Vec::new(),
         }
     }
 
@@ -28,10 +87,49 @@ impl Thread {
 
     pub fn set_title(&mut self, title: Option<String>) {
         self.title = title;
+        self.touch();
     }
 
-    pub fn set_summary(&mut self, summary: String) {
+    pub fn set_summary(&mut self, summary: String, provenance: SummaryProvenance) {
         self.summary = Some(summary);
+        self.summary_provenance = Some(provenance);
+        self.touch();
+        self.summarized_at = self.updated_at;
+    }
+
+    /// Forgets the summary along with its embedding and the thread's
+    /// vectors, e.g. once every message it was made from was deleted.
+    pub fn clear_summary(&mut self) {
+        self.summary = None;
+        self.summary_provenance = None;
+        self.embedding = None;
+        self.vectors.clear();
+        self.touch();
+        self.summarized_at = self.updated_at;
+    }
+
+    pub fn set_summary_language(&mut self, summary_language: Option<String>) {
+        self.summary_language = summary_language;
+        self.touch();
+    }
+
+    pub fn set_tags(&mut self, tags: Vec<String>) {
+        self.tags = tags;
+        self.touch();
+    }
+
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+        self.touch();
+    }
+
+    pub fn set_summary_locked(&mut self, summary_locked: bool) {
+        self.summary_locked = summary_locked;
+        self.touch();
+    }
+
+    fn touch(&mut self) {
+        self.updated_at = Utc::now().timestamp_millis() as u64;
     }
 
     pub fn set_embedding(&mut self, embedding: Embedding) {
@@ -39,7 +137,356 @@ impl Thread {
     }
 }
 
+/// A summary computed by the shadow summarization pipeline, stored apart
+/// from the live thread so a new prompt or model can be compared with it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShadowSummary {
+    pub thread_id: Uuid,
+    pub summary: String,
+    pub embedding: Embedding,
+    /// Milliseconds since the epoch.
+    pub updated_at: u64,
+}
+
+/// A message whose summary failed, e.g. while the summarizer was down, kept
+/// until the message is summarized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingSummary {
+    pub thread_id: Uuid,
+    pub message_id: Uuid,
+    /// Milliseconds since the epoch.
+    pub queued_at: u64,
+    /// Failed summaries of the message, the first included.
+    pub attempts: u32,
+}
+
+/// How a summary was made, for clients and evaluations to reason about it.
+/// The summary's [`Thread::summarized_at`] tells when.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummaryProvenance {
+    /// The summarizer's model, when declared.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Identifies the prompt template the summary was generated with.
+    #[serde(default)]
+    pub prompt_version: Option<String>,
+    /// Estimated tokens of the completions which made the summary.
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    /// Written by hand rather than generated.
+    #[serde(default)]
+    pub manual: bool,
+    /// The last message folded into the summary, `None` for summaries
+    /// written by hand or stored before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<SummaryWatermark>,
+}
+
+/// The last message folded into a summary. Messages are folded in as they
+/// become final, so those final before it are in the summary already, but
+/// for the ones whose summary failed, queued to be summarized again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummaryWatermark {
+    pub message_id: Uuid,
+    /// Milliseconds since the epoch the message became final.
+    pub final_at: u64,
+    /// Messages queued to be summarized again when the summary was made,
+    /// left out of it however early they became final.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pending: Vec<Uuid>,
+}
+
+impl SummaryWatermark {
+    pub fn of(message: &Message) -> Self {
+        Self {
+            message_id: message.id,
+            final_at: final_at(message),
+            pending: Vec::new(),
+        }
+    }
+
+    /// The watermark of the summary folding the message into the one of
+    /// `previous`, `pending` being the messages of the thread queued to be
+    /// summarized again. A message retried after the summary moved past it
+    /// leaves the watermark where it was.
+    pub fn folding(previous: Option<&Self>, message: &Message, pending: Vec<Uuid>) -> Self {
+        let folded = match previous {
+            Some(previous) if previous.final_at > final_at(message) => Self {
+                pending: Vec::new(),
+                ..previous.clone()
+            },
+            _ => Self::of(message),
+        };
+        Self {
+            pending: pending.into_iter().filter(|id| *id != message.id).collect(),
+            ..folded
+        }
+    }
+
+    /// Whether the message is in the summary already. Messages final in the
+    /// same millisecond as the watermark's are only known to be if they are
+    /// the watermark's.
+    pub fn covers(&self, message: &Message) -> bool {
+        (message.id == self.message_id || final_at(message) < self.final_at)
+            && !self.pending.contains(&message.id)
+    }
+}
+
+/// Messages are final when created, or when last patched, as they're
+/// finalized, or their stream completed, with a patch.
+fn final_at(message: &Message) -> u64 {
+    message.created_at.max(message.updated_at)
+}
+
+/// Replaces each redacted span of a summary.
+pub const REDACTED_SPAN: &str = "[REDACTED]";
+
+/// A range of a summary, in characters, the end excluded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SummarySpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Spans of a summary a reviewer marked as redacted, stored apart from the
+/// summary so it's kept as generated. They only apply to the summary they
+/// were marked on, the next summary being generated from the redacted one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SummaryRedactions {
+    pub thread_id: Uuid,
+    pub spans: Vec<SummarySpan>,
+    /// The `summarized_at` of the summary the spans were marked on.
+    pub summarized_at: u64,
+    /// Milliseconds since the epoch.
+    pub updated_at: u64,
+}
+
+impl SummaryRedactions {
+    pub fn applies_to(&self, thread: &Thread) -> bool {
+        self.thread_id == thread.id && self.summarized_at == thread.summarized_at
+    }
+
+    /// The summary with each span, overlapping ones merged, replaced by
+    /// [`REDACTED_SPAN`].
+    pub fn redact(&self, summary: &str) -> String {
+        let mut spans = self.spans.clone();
+        spans.sort_by_key(|span| span.start);
+
+        let chars = summary.chars().collect::<Vec<_>>();
+        let mut redacted = String::with_capacity(summary.len());
+        let mut position = 0;
+        for span in spans {
+            let end = span.end.min(chars.len());
+            if end <= position {
+                continue;
+            }
+            let start = span.start.max(position);
+            redacted.extend(&chars[position..start]);
+            if span.start >= position {
+                redacted.push_str(REDACTED_SPAN);
+            }
+            position = end;
+        }
+        redacted.extend(&chars[position.min(chars.len())..]);
+        redacted
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RedactSummary {
+    pub spans: Vec<SummarySpan>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CreateThread {
+    pub tenant: Option<String>,
+}
+
+impl CreateThread {
+    pub fn into_thread(self) -> Thread {
+        Thread {
+            tenant: self.tenant,
+            ..Thread::new()
+        }
+    }
+}
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct UpdateThread {
     pub title: Option<String>,
+    /// Replaces the language of the thread's summaries when provided, `null`
+    /// clearing it, so summaries fall back to the default language, and
+    /// leaving it out keeping it.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_provided",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub summary_language: Option<Option<String>>,
+    /// Replaces the thread's tags when provided.
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+/// Tells a field set to `null`, `Some(None)`, apart from one left out,
+/// `None` by default.
+fn deserialize_provided<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// A partial update of a thread, fields left out being kept as they are.
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct PatchThread {
+    #[serde(default)]
+    pub pinned: Option<bool>,
+    #[serde(default)]
+    pub summary_locked: Option<bool>,
+}
+
+/// A summary written by hand, replacing the generated one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetSummary {
+    pub summary: String,
+    /// Locks or unlocks the summary, keeping its lock as it is when left out.
+    #[serde(default)]
+    pub locked: Option<bool>,
+}
+
+/// Selects threads for bulk operations. Every criterion set must match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ThreadFilter {
+    #[serde(default)]
+    pub ids: Option<Vec<Uuid>>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Threads whose last change is before this time, in milliseconds since
+    /// the epoch.
+    #[serde(default)]
+    pub older_than: Option<u64>,
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+impl ThreadFilter {
+    /// Whether the filter narrows anything down, so an empty filter can be
+    /// told apart from one meant to select every thread.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_none() && self.tag.is_none() && self.older_than.is_none()
+    }
+
+    pub fn matches(&self, thread: &Thread) -> bool {
+        self.ids.as_ref().is_none_or(|ids| ids.contains(&thread.id))
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| thread.tags.contains(tag))
+            && self
+                .older_than
+                .is_none_or(|older_than| thread.updated_at.max(thread.created_at) < older_than)
+            && (self.tenant.is_none() || thread.tenant == self.tenant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::CreateMessage;
+
+    fn redactions(spans: &[(usize, usize)]) -> SummaryRedactions {
+        SummaryRedactions {
+            thread_id: Uuid::nil(),
+            spans: spans
+                .iter()
+                .map(|&(start, end)| SummarySpan { start, end })
+                .collect(),
+            summarized_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn summary_languages_set_to_null_are_told_apart_from_left_out_ones() {
+        let update = |json| serde_json::from_str::<UpdateThread>(json).unwrap();
+
+        assert_eq!(update(r#"{"title":null}"#).summary_language, None);
+        assert_eq!(
+            update(r#"{"title":null,"summary_language":null}"#).summary_language,
+            Some(None)
+        );
+        assert_eq!(
+            update(r#"{"title":null,"summary_language":"French"}"#).summary_language,
+            Some(Some("French".to_string()))
+        );
+    }
+
+    #[test]
+    fn watermarks_cover_messages_final_before_them() {
+        let message = |created_at, updated_at| Message {
+            created_at,
+            updated_at,
+            ..CreateMessage {
+                role: "user".into(),
+                content: String::new().into(),
+                parent_message_id: None,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            }
+            .into_message(Uuid::nil())
+        };
+        let last = message(2_000, 0);
+        let watermark = SummaryWatermark::of(&last);
+
+        assert!(watermark.covers(&last));
+        assert!(watermark.covers(&message(1_000, 0)));
+        assert!(!watermark.covers(&message(2_000, 0)));
+        assert!(!watermark.covers(&message(3_000, 0)));
+        // A draft created before, finalized after.
+        assert!(!watermark.covers(&message(1_000, 3_000)));
+    }
+
+    #[test]
+    fn watermarks_leave_out_pending_messages() {
+        let message = |created_at| Message {
+            created_at,
+            ..CreateMessage {
+                role: "user".into(),
+                content: String::new().into(),
+                parent_message_id: None,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            }
+            .into_message(Uuid::nil())
+        };
+        let (failed, newer) = (message(1_000), message(2_000));
+
+        // The newer message is summarized while the failed one is queued.
+        let watermark = SummaryWatermark::folding(None, &newer, vec![failed.id]);
+        assert_eq!(watermark.message_id, newer.id);
+        assert!(!watermark.covers(&failed));
+
+        // The failed one is retried, off the queue.
+        let watermark = SummaryWatermark::folding(Some(&watermark), &failed, Vec::new());
+        assert_eq!(watermark.message_id, newer.id);
+        assert!(watermark.covers(&failed));
+        assert!(watermark.covers(&newer));
+    }
+
+    #[test]
+    fn overlapping_spans_are_redacted_once() {
+        let summary = "Café owner Ana lives at 12 Rue Blanche";
+
+        assert_eq!(
+            redactions(&[(24, 38), (11, 14), (26, 30)]).redact(summary),
+            "Café owner [REDACTED] lives at [REDACTED]"
+        );
+        assert_eq!(redactions(&[]).redact(summary), summary);
+    }
 }
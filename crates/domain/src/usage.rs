@@ -0,0 +1,59 @@
+pub use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Requests and tokens sent to the summarizer and the embedders.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub completion_requests: u64,
+    pub completion_input_tokens: u64,
+    pub completion_output_tokens: u64,
+    pub embedding_requests: u64,
+    pub embedding_tokens: u64,
+}
+
+impl Usage {
+    pub fn tokens(&self) -> u64 {
+        self.completion_input_tokens + self.completion_output_tokens + self.embedding_tokens
+    }
+
+    pub fn add(&mut self, other: &Usage) {
+        self.completion_requests += other.completion_requests;
+        self.completion_input_tokens += other.completion_input_tokens;
+        self.completion_output_tokens += other.completion_output_tokens;
+        self.embedding_requests += other.embedding_requests;
+        self.embedding_tokens += other.embedding_tokens;
+    }
+}
+
+/// Usage aggregated per day, tenant, and thread. Requests not tied to a
+/// thread (e.g. search queries or digests) have no `thread_id`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub day: NaiveDate,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub thread_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UsageFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub tenant: Option<String>,
+    pub thread_id: Option<Uuid>,
+}
+
+impl UsageFilter {
+    pub fn matches(&self, usage: &DailyUsage) -> bool {
+        self.from.is_none_or(|from| usage.day >= from)
+            && self.to.is_none_or(|to| usage.day <= to)
+            && (self.tenant.is_none() || usage.tenant == self.tenant)
+            && self
+                .thread_id
+                .is_none_or(|thread_id| usage.thread_id == Some(thread_id))
+    }
+}
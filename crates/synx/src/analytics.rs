@@ -0,0 +1,160 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDate};
+use uuid::Uuid;
+
+/// Upper bounds of the messages-per-thread histogram buckets.
+const MESSAGES_PER_THREAD_BUCKETS: [u64; 5] = [1, 5, 20, 100, u64::MAX];
+
+/// Conversation counters maintained as messages and summaries are written,
+/// so reports never scan the database. They cover the lifetime of the
+/// process only.
+#[derive(Default)]
+pub struct Analytics {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    messages_per_day: BTreeMap<NaiveDate, u64>,
+    active_threads_per_day: BTreeMap<NaiveDate, HashSet<Uuid>>,
+    messages_per_thread: HashMap<Uuid, u64>,
+    summaries: u64,
+    summary_latency: Duration,
+    pending_summaries: usize,
+    slow_queries: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct AnalyticsReport {
+    pub messages_per_day: BTreeMap<NaiveDate, u64>,
+    pub active_threads_per_day: BTreeMap<NaiveDate, usize>,
+    /// Number of threads per bucket of message count, e.g. `"6-20"`.
+    pub messages_per_thread: BTreeMap<String, usize>,
+    pub summaries: u64,
+    pub average_summary_latency_ms: Option<f64>,
+    /// Messages queued to be summarized again, see [`outbox`](crate::outbox).
+    pub pending_summaries: usize,
+    /// Database calls and searches slower than the threshold, per
+    /// operation, see [`slow_queries`](crate::slow_queries).
+    #[serde(default)]
+    pub slow_queries: BTreeMap<String, u64>,
+}
+
+impl Analytics {
+    pub fn record_message(&self, thread_id: Uuid, created_at: u64) {
+        let day = day_of(created_at);
+        let mut state = self.state.lock().unwrap();
+        *state.messages_per_day.entry(day).or_default() += 1;
+        state
+            .active_threads_per_day
+            .entry(day)
+            .or_default()
+            .insert(thread_id);
+        *state.messages_per_thread.entry(thread_id).or_default() += 1;
+    }
+
+    pub fn record_message_deleted(&self, thread_id: Uuid) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.messages_per_thread.get_mut(&thread_id) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    pub fn record_thread_deleted(&self, thread_id: Uuid) {
+        self.state
+            .lock()
+            .unwrap()
+            .messages_per_thread
+            .remove(&thread_id);
+    }
+
+    /// Records the time from a message being stored to the thread summary
+    /// including it.
+    pub fn record_summary(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.summaries += 1;
+        state.summary_latency += latency;
+    }
+
+    /// Records a message queued to be summarized again, or taken off the
+    /// queue with `queued` false.
+    pub fn record_pending_summary(&self, queued: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.pending_summaries = match queued {
+            true => state.pending_summaries + 1,
+            false => state.pending_summaries.saturating_sub(1),
+        };
+    }
+
+    /// Sets the size of the queue of summaries, as read from the database.
+    pub fn record_pending_summaries(&self, count: usize) {
+        self.state.lock().unwrap().pending_summaries = count;
+    }
+
+    pub fn record_slow_query(&self, operation: &'static str) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .slow_queries
+            .entry(operation)
+            .or_default() += 1;
+    }
+
+    pub fn report(&self) -> AnalyticsReport {
+        let state = self.state.lock().unwrap();
+
+        let mut messages_per_thread = BTreeMap::new();
+        for &count in state.messages_per_thread.values() {
+            let Some(bucket) = MESSAGES_PER_THREAD_BUCKETS
+                .iter()
+                .position(|&upper| count <= upper)
+            else {
+                continue;
+            };
+            *messages_per_thread.entry(bucket_label(bucket)).or_default() += 1;
+        }
+
+        AnalyticsReport {
+            messages_per_day: state.messages_per_day.clone(),
+            active_threads_per_day: state
+                .active_threads_per_day
+                .iter()
+                .map(|(day, threads)| (*day, threads.len()))
+                .collect(),
+            messages_per_thread,
+            summaries: state.summaries,
+            average_summary_latency_ms: (state.summaries > 0)
+                .then(|| state.summary_latency.as_secs_f64() * 1000.0 / state.summaries as f64),
+            pending_summaries: state.pending_summaries,
+            slow_queries: state
+                .slow_queries
+                .iter()
+                .map(|(operation, count)| (operation.to_string(), *count))
+                .collect(),
+        }
+    }
+}
+
+fn day_of(timestamp_ms: u64) -> NaiveDate {
+    DateTime::from_timestamp_millis(timestamp_ms as i64)
+        .unwrap_or_default()
+        .date_naive()
+}
+
+fn bucket_label(bucket: usize) -> String {
+    let lower = match bucket {
+        0 => 0,
+        _ => MESSAGES_PER_THREAD_BUCKETS[bucket - 1] + 1,
+    };
+    match MESSAGES_PER_THREAD_BUCKETS[bucket] {
+        u64::MAX => format!("{}+", lower),
+        upper if upper == lower => upper.to_string(),
+        upper => format!("{}-{}", lower, upper),
+    }
+}
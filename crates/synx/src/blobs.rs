@@ -0,0 +1,161 @@
+//! Content-addressed storage for binary content, e.g. images.
+//!
+//! Blobs are stored once under the SHA-256 of their data. Images sent inline
+//! in messages, as `data:` URLs or bare base64, are moved to blobs when the
+//! message is stored and replaced by a `blob:{hash}` reference, which keeps
+//! messages small. Images given as URLs are kept as they are.
+//!
+//! Blobs stored for a tenant, whether uploaded by one of its callers or
+//! inlined in one of its threads, belong to it. Their hash covers the tenant
+//! along with the data, so tenants never share a blob, nor learn whether
+//! another one stored the same data.
+//!
+//! Only raster images, see [`IMAGE_MIME_TYPES`], are served with their
+//! media type, anything else, e.g. HTML or SVG which browsers would run
+//! scripts of, being served as opaque bytes.
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use synx_domain::{
+    blob::{parse_blob_ref, Blob},
+    content::{Content, ContentKind},
+};
+use uuid::Uuid;
+
+use crate::Synx;
+
+/// Media types blobs may be uploaded with and are served with.
+pub const IMAGE_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// The media type, without its parameters and lowercased, if it is one of
+/// [`IMAGE_MIME_TYPES`].
+pub fn image_mime_type(mime_type: &str) -> Option<&'static str> {
+    let essence = mime_type.split(';').next().unwrap_or_default().trim();
+    IMAGE_MIME_TYPES
+        .iter()
+        .find(|image| image.eq_ignore_ascii_case(essence))
+        .copied()
+}
+
+impl Synx {
+    /// Stores the data for the tenant, or returns the blob already holding
+    /// it.
+    pub async fn put_blob(
+        &self,
+        data: Vec<u8>,
+        mime_type: Option<String>,
+        tenant: Option<String>,
+    ) -> Result<Blob> {
+        let mut hasher = Sha256::new();
+        if let Some(tenant) = &tenant {
+            hasher.update(tenant.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(&data);
+        let hash = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let blob = Blob {
+            tenant,
+            ..Blob::new(hash, mime_type, data.len())
+        };
+        Ok(self.db.put_blob(blob, data).await?)
+    }
+
+    pub async fn get_blob(&self, hash: &str) -> Result<(Blob, Vec<u8>)> {
+        Ok(self.db.get_blob(hash.to_string()).await?)
+    }
+
+    /// Moves the inline images of the content to blobs of the thread's
+    /// tenant.
+    pub(crate) async fn store_inline_images(
+        &self,
+        thread_id: Uuid,
+        content: Content,
+    ) -> Result<Content> {
+        // Looked up with the first inline image, most content having none.
+        let mut tenant = None;
+        let mut kinds = Vec::with_capacity(content.0.len());
+        for kind in content.0 {
+            kinds.push(match kind {
+                ContentKind::Image { image, mime_type } => match decode_inline(&image) {
+                    Some((data, inline_type)) => {
+                        if tenant.is_none() {
+                            tenant = Some(self.db.get_thread(thread_id).await?.tenant);
+                        }
+                        let blob = self
+                            .put_blob(
+                                data,
+                                mime_type.clone().or(inline_type),
+                                tenant.clone().flatten(),
+                            )
+                            .await?;
+                        ContentKind::Image {
+                            image: blob.reference(),
+                            mime_type: mime_type.or(blob.mime_type),
+                        }
+                    }
+                    None => ContentKind::Image { image, mime_type },
+                },
+                kind => kind,
+            });
+        }
+
+        Ok(Content(kinds))
+    }
+}
+
+/// The hashes of the blobs the content refers to.
+pub(crate) fn blob_hashes(content: &Content) -> impl Iterator<Item = &str> {
+    content.0.iter().filter_map(|kind| match kind {
+        ContentKind::Image { image, .. } => parse_blob_ref(image),
+        _ => None,
+    })
+}
+
+/// The data of an inline image, along with the media type of a `data:` URL.
+fn decode_inline(image: &str) -> Option<(Vec<u8>, Option<String>)> {
+    if let Some(url) = image.strip_prefix("data:") {
+        let (header, payload) = url.split_once(',')?;
+        let mime_type = header.strip_suffix(";base64")?;
+        let data = STANDARD.decode(payload).ok()?;
+        return Some((data, (!mime_type.is_empty()).then(|| mime_type.to_string())));
+    }
+
+    if image.contains("://") || parse_blob_ref(image).is_some() {
+        return None;
+    }
+    STANDARD.decode(image).ok().map(|data| (data, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_raster_images_are_allowed() {
+        assert_eq!(image_mime_type("image/png"), Some("image/png"));
+        assert_eq!(image_mime_type("Image/JPEG; q=1"), Some("image/jpeg"));
+        assert_eq!(image_mime_type("image/svg+xml"), None);
+        assert_eq!(image_mime_type("text/html"), None);
+    }
+
+    #[test]
+    fn inline_images_are_decoded() {
+        assert_eq!(
+            decode_inline("data:image/png;base64,AQID"),
+            Some((vec![1, 2, 3], Some("image/png".to_string())))
+        );
+        assert_eq!(decode_inline("AQID"), Some((vec![1, 2, 3], None)));
+    }
+
+    #[test]
+    fn urls_and_references_are_kept() {
+        assert_eq!(decode_inline("https://example.com/cat.png"), None);
+        assert_eq!(decode_inline(&format!("blob:{}", "ab".repeat(32))), None);
+        assert_eq!(decode_inline("data:text/plain,hello"), None);
+    }
+}
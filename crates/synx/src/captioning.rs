@@ -0,0 +1,114 @@
+//! Captions for the images of new messages, so messages made only of images
+//! still update the thread summary.
+//!
+//! Built with [`SynxBuilder::with_captioner`](crate::SynxBuilder::with_captioner),
+//! an instance asks a vision-capable completion model to describe each image
+//! of a message before summarizing it. Captions are appended to the text of
+//! the message given to the summarizer, and aren't stored. Images stored as
+//! blobs are sent inline, others as they were given. An image that can't be
+//! captioned is skipped, like without a captioner.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ferrochain::completion::Completion;
+use synx_domain::{
+    blob::parse_blob_ref,
+    content::{Content, ContentKind},
+};
+use uuid::Uuid;
+
+use crate::{
+    usage::UsageScope,
+    utils::{completion::CAPTION_PROMPT, content::extract_text_content},
+    Synx,
+};
+
+impl Synx {
+    /// The text of the content, followed by the captions of its images.
+    pub(crate) async fn summary_content(
+        &self,
+        thread_id: Uuid,
+        content: &Content,
+    ) -> Option<String> {
+        let captions = self.caption_images(thread_id, content).await;
+        let parts = extract_text_content(content)
+            .into_iter()
+            .chain(
+                captions
+                    .into_iter()
+                    .map(|caption| format!("[Image: {}]", caption)),
+            )
+            .collect::<Vec<_>>();
+
+        (!parts.is_empty()).then(|| parts.join("\n"))
+    }
+
+    async fn caption_images(&self, thread_id: Uuid, content: &Content) -> Vec<String> {
+        let Some(captioner) = &self.captioner else {
+            return Vec::new();
+        };
+        let images = content
+            .0
+            .iter()
+            .filter_map(|kind| match kind {
+                ContentKind::Image { image, mime_type } => Some((image, mime_type)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if images.is_empty() {
+            return Vec::new();
+        }
+
+        let thread = match self.db.get_thread(thread_id).await {
+            Ok(thread) => thread,
+            Err(e) => {
+                tracing::warn!(%thread_id, "Skipping image captions: {}", e);
+                return Vec::new();
+            }
+        };
+        // The summary itself would be skipped.
+        if let Ok(true) = self.over_budget(thread.tenant.as_deref()).await {
+            return Vec::new();
+        }
+
+        let scope = UsageScope::thread(&thread);
+        let mut captions = Vec::with_capacity(images.len());
+        for (image, mime_type) in images {
+            match self.caption(captioner, image, mime_type, &scope).await {
+                Ok(caption) => captions.push(caption.trim().to_string()),
+                Err(e) => tracing::warn!(%thread_id, "Failed to caption an image: {}", e),
+            }
+        }
+
+        captions
+    }
+
+    async fn caption(
+        &self,
+        captioner: &Arc<dyn Completion>,
+        image: &str,
+        mime_type: &Option<String>,
+        scope: &UsageScope,
+    ) -> Result<String> {
+        let image = match parse_blob_ref(image) {
+            Some(hash) => {
+                let (blob, data) = self.db.get_blob(hash.to_string()).await?;
+                ContentKind::Image {
+                    image: STANDARD.encode(data),
+                    mime_type: mime_type.clone().or(blob.mime_type),
+                }
+            }
+            None => ContentKind::Image {
+                image: image.to_string(),
+                mime_type: mime_type.clone(),
+            },
+        };
+        // Message content shares its wire format with ferrochain's.
+        let image = serde_json::from_value(serde_json::to_value(image)?)?;
+
+        self.complete_image(captioner, image, CAPTION_PROMPT, scope)
+            .await
+    }
+}
@@ -0,0 +1,232 @@
+//! Topics over the whole memory store, found by clustering the embeddings
+//! of thread summaries with k-means.
+//!
+//! Embeddings are normalized first, so threads are grouped by cosine
+//! similarity like in search. Centroids start from the threads farthest
+//! apart, which keeps the clusters the same from one request to the next.
+//! Clusters can be named by the summarizer, from the summaries closest to
+//! their centroid.
+
+use anyhow::Result;
+use synx_domain::thread::Thread;
+use uuid::Uuid;
+
+use crate::{
+    usage::UsageScope,
+    utils::{completion::CLUSTER_NAME_PROMPT, similarity::normalize},
+    Synx,
+};
+
+const MAX_ITERATIONS: usize = 50;
+/// Summaries shown to the summarizer to name a cluster.
+const NAMING_SUMMARIES: usize = 10;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ClusterRequest {
+    #[serde(default = "ClusterRequest::default_k")]
+    pub k: usize,
+    /// Names each cluster with the summarizer, one completion per cluster.
+    #[serde(default)]
+    pub names: bool,
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+impl ClusterRequest {
+    fn default_k() -> usize {
+        10
+    }
+}
+
+/// Clusters are labelled from the largest, 0, to the smallest.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Cluster {
+    pub label: usize,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub thread_ids: Vec<Uuid>,
+}
+
+impl Synx {
+    /// Clusters the threads having a summary embedding. Threads yet to be
+    /// summarized are left out.
+    pub async fn cluster_threads(&self, request: ClusterRequest) -> Result<Vec<Cluster>> {
+        let thread_ids = self
+            .db
+            .list_threads()
+            .await?
+            .into_iter()
+            .filter(|thread| request.tenant.is_none() || thread.tenant == request.tenant)
+            .map(|thread| thread.id)
+            .collect::<Vec<_>>();
+        let (threads, points): (Vec<Thread>, Vec<Vec<f32>>) = self
+            .db
+            .get_threads_with_embeddings(&thread_ids)
+            .await?
+            .into_iter()
+            .filter_map(|thread| {
+                let point = normalize(thread.embedding.as_ref()?.to_vec());
+                Some((thread, point))
+            })
+            .unzip();
+
+        let (labels, centroids) = kmeans(&points, request.k);
+        let mut members = vec![Vec::new(); centroids.len()];
+        for (index, &label) in labels.iter().enumerate() {
+            members[label].push(index);
+        }
+        members.retain(|indices| !indices.is_empty());
+        members.sort_by_key(|indices| std::cmp::Reverse(indices.len()));
+
+        let mut clusters = Vec::with_capacity(members.len());
+        for (label, indices) in members.into_iter().enumerate() {
+            let name = if request.names {
+                let centroid = &centroids[labels[indices[0]]];
+                let mut closest = indices.clone();
+                closest.sort_by(|&a, &b| {
+                    dot(&points[b], centroid).total_cmp(&dot(&points[a], centroid))
+                });
+                closest.truncate(NAMING_SUMMARIES);
+                Some(
+                    self.name_cluster(
+                        closest.into_iter().map(|index| &threads[index]),
+                        &request.tenant,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            clusters.push(Cluster {
+                label,
+                name,
+                thread_ids: indices.into_iter().map(|index| threads[index].id).collect(),
+            });
+        }
+
+        Ok(clusters)
+    }
+
+    async fn name_cluster(
+        &self,
+        threads: impl Iterator<Item = &Thread>,
+        tenant: &Option<String>,
+    ) -> Result<String> {
+        let summaries = threads
+            .map(|thread| {
+                format!(
+                    "<summary>\n{}\n</summary>",
+                    thread.summary.as_deref().unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let name = self
+            .complete(
+                &self.summarizer,
+                self.summarizer_capabilities,
+                CLUSTER_NAME_PROMPT.replace("{{SUMMARIES}}", &summaries),
+                &UsageScope::tenant(tenant.clone()),
+            )
+            .await?;
+
+        Ok(name.trim().to_string())
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Spherical k-means over normalized points, returning the cluster of each
+/// point and the centroids. There are never more clusters than points.
+fn kmeans(points: &[Vec<f32>], k: usize) -> (Vec<usize>, Vec<Vec<f32>>) {
+    let k = k.min(points.len());
+    if k == 0 {
+        return (Vec::new(), Vec::new());
+    }
+
+    // Farthest-point initialization: each centroid is the point least
+    // similar to the centroids picked before it.
+    let mut centroids = vec![points[0].clone()];
+    while centroids.len() < k {
+        let farthest = points
+            .iter()
+            .min_by(|a, b| {
+                let closest = |point: &Vec<f32>| {
+                    centroids
+                        .iter()
+                        .map(|centroid| dot(point, centroid))
+                        .fold(f32::NEG_INFINITY, f32::max)
+                };
+                closest(a).total_cmp(&closest(b))
+            })
+            .unwrap();
+        centroids.push(farthest.clone());
+    }
+
+    let mut labels = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (point, label) in points.iter().zip(labels.iter_mut()) {
+            let closest = (0..k)
+                .max_by(|&a, &b| dot(point, &centroids[a]).total_cmp(&dot(point, &centroids[b])))
+                .unwrap();
+            if *label != closest {
+                *label = closest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let mut sum = vec![0.0; centroid.len()];
+            for (point, _) in points.iter().zip(&labels).filter(|(_, &l)| l == cluster) {
+                sum.iter_mut().zip(point).for_each(|(s, x)| *s += x);
+            }
+            // An emptied cluster keeps its centroid.
+            if sum.iter().any(|x| *x != 0.0) {
+                *centroid = normalize(sum);
+            }
+        }
+    }
+
+    (labels, centroids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kmeans_separates_distinct_topics() {
+        let points = [
+            vec![1.0, 0.0],
+            vec![0.98, 0.2],
+            vec![0.0, 1.0],
+            vec![0.1, 0.99],
+            vec![0.95, 0.1],
+        ]
+        .map(normalize);
+
+        let (labels, centroids) = kmeans(&points, 2);
+
+        assert_eq!(centroids.len(), 2);
+        assert_eq!(labels[0], labels[1]);
+        assert_eq!(labels[0], labels[4]);
+        assert_eq!(labels[2], labels[3]);
+        assert_ne!(labels[0], labels[2]);
+    }
+
+    #[test]
+    fn kmeans_never_makes_more_clusters_than_points() {
+        let points = [vec![1.0, 0.0], vec![0.0, 1.0]];
+
+        let (labels, centroids) = kmeans(&points, 10);
+
+        assert_eq!(centroids.len(), 2);
+        assert_ne!(labels[0], labels[1]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Read-your-writes within a session. Messages are stored right away but
+//! summarized and embedded later, so a search or summary read right after
+//! posting may not reflect them yet.
+//!
+//! Each summary job takes the next sequence number when it is queued, and
+//! its sequence becomes visible once it ends, along with every sequence
+//! before it. A client that keeps the [`Synx::sequence`] read after its
+//! writes, e.g. from the API's `X-Sequence` header, can wait with
+//! [`Synx::wait_for_sequence`] for them to be visible.
+//!
+//! Sequences are per run, so [`SequenceToken`]s carry the epoch of the run
+//! that issued them, the time it started. Tokens of an earlier run are
+//! visible already, its summaries being done or queued to be retried, while
+//! tokens of a later run, or beyond the last sequence issued, can't have
+//! been issued by this one and are refused. Summaries queued while the
+//! summarizer is down are retried later and don't hold the sequence back.
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+
+use crate::Synx;
+
+/// A sequence along with the epoch of the run that issued it, formatted as
+/// `<epoch>.<sequence>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SequenceToken {
+    pub epoch: u64,
+    pub sequence: u64,
+}
+
+impl fmt::Display for SequenceToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.epoch, self.sequence)
+    }
+}
+
+impl FromStr for SequenceToken {
+    type Err = InvalidSequence;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (epoch, sequence) = value.split_once('.').ok_or(InvalidSequence)?;
+        Ok(Self {
+            epoch: epoch.parse().map_err(|_| InvalidSequence)?,
+            sequence: sequence.parse().map_err(|_| InvalidSequence)?,
+        })
+    }
+}
+
+/// Returned for a sequence token this run couldn't have issued.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid sequence token")]
+pub struct InvalidSequence;
+
+pub(crate) struct Sequences {
+    epoch: u64,
+    state: Mutex<State>,
+    visible: watch::Sender<u64>,
+}
+
+struct State {
+    issued: u64,
+    in_flight: BTreeSet<u64>,
+}
+
+/// The sequence of a job in flight, visible once dropped.
+pub(crate) struct SequenceTicket {
+    sequences: Arc<Sequences>,
+    sequence: u64,
+}
+
+impl Default for Sequences {
+    fn default() -> Self {
+        Self {
+            epoch: chrono::Utc::now().timestamp_micros().max(0) as u64,
+            state: Mutex::new(State {
+                issued: 0,
+                in_flight: BTreeSet::new(),
+            }),
+            visible: watch::Sender::new(0),
+        }
+    }
+}
+
+impl Sequences {
+    pub(crate) fn issue(self: &Arc<Self>) -> SequenceTicket {
+        let mut state = self.state.lock().unwrap();
+        state.issued += 1;
+        state.in_flight.insert(state.issued);
+        SequenceTicket {
+            sequences: self.clone(),
+            sequence: state.issued,
+        }
+    }
+
+    fn issued(&self) -> u64 {
+        self.state.lock().unwrap().issued
+    }
+
+    /// The sequence of this run to wait for, none when the token is of an
+    /// earlier run.
+    fn resolve(&self, token: SequenceToken) -> Result<Option<u64>, InvalidSequence> {
+        if token.epoch < self.epoch {
+            return Ok(None);
+        }
+        if token.epoch > self.epoch || token.sequence > self.issued() {
+            return Err(InvalidSequence);
+        }
+        Ok(Some(token.sequence))
+    }
+
+    fn complete(&self, sequence: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight.remove(&sequence);
+        let visible = match state.in_flight.first() {
+            Some(first) => first - 1,
+            None => state.issued,
+        };
+        self.visible.send_if_modified(|current| {
+            let advanced = visible > *current;
+            *current = (*current).max(visible);
+            advanced
+        });
+    }
+}
+
+impl Drop for SequenceTicket {
+    fn drop(&mut self) {
+        self.sequences.complete(self.sequence);
+    }
+}
+
+impl Synx {
+    /// The last sequence issued, covering every write made so far.
+    pub fn sequence(&self) -> SequenceToken {
+        SequenceToken {
+            epoch: self.sequences.epoch,
+            sequence: self.sequences.issued(),
+        }
+    }
+
+    /// The last sequence whose summaries, and those of every sequence
+    /// before it, are done.
+    pub fn visible_sequence(&self) -> u64 {
+        *self.sequences.visible.borrow()
+    }
+
+    /// Waits until the token's sequence is visible, for as long as it
+    /// takes: callers bound the wait themselves. Fails right away for tokens
+    /// this run couldn't have issued.
+    pub async fn wait_for_sequence(&self, token: SequenceToken) -> Result<(), InvalidSequence> {
+        let Some(sequence) = self.sequences.resolve(token)? else {
+            return Ok(());
+        };
+        // The sender lives as long as `self`, so the wait can't fail.
+        let _ = self
+            .sequences
+            .visible
+            .subscribe()
+            .wait_for(|visible| *visible >= sequence)
+            .await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_are_visible_once_every_earlier_one_is() {
+        let sequences = Arc::new(Sequences::default());
+        let start = sequences.issued();
+
+        let first = sequences.issue();
+        let second = sequences.issue();
+        assert_eq!(sequences.issued(), start + 2);
+
+        drop(second);
+        assert_eq!(*sequences.visible.borrow(), start);
+        drop(first);
+        assert_eq!(*sequences.visible.borrow(), start + 2);
+    }
+
+    #[test]
+    fn tokens_are_resolved_against_the_run_that_issued_them() {
+        let sequences = Arc::new(Sequences::default());
+        let _ticket = sequences.issue();
+        let token = |epoch, sequence| SequenceToken { epoch, sequence };
+        let epoch = sequences.epoch;
+
+        assert_eq!(sequences.resolve(token(epoch, 1)).unwrap(), Some(1));
+        assert_eq!(sequences.resolve(token(epoch - 1, 100)).unwrap(), None);
+        assert!(sequences.resolve(token(epoch, 2)).is_err());
+        assert!(sequences.resolve(token(epoch + 1, 1)).is_err());
+
+        let parsed: SequenceToken = token(epoch, 1).to_string().parse().unwrap();
+        assert_eq!(parsed, token(epoch, 1));
+        assert!("1".parse::<SequenceToken>().is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Detection of likely duplicate threads, e.g. the same conversation
+//! started twice.
+//!
+//! Built with [`SynxBuilder::with_duplicate_threshold`](crate::SynxBuilder::with_duplicate_threshold),
+//! an instance compares every new summary embedding to the embeddings of
+//! the other threads of the same tenant, and keeps the pairs at least as
+//! similar as the threshold. [`Synx::scan_duplicates`] rebuilds the report
+//! from scratch, e.g. after a restart, the report being kept in memory.
+//! Duplicates can then be deleted, e.g. with [`Synx::delete_threads`].
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use synx_domain::{embedding::Embedding, thread::Thread};
+use uuid::Uuid;
+
+use crate::{replication::now_millis, utils::similarity::cosine_similarity, Synx};
+
+pub(crate) struct DuplicateDetector {
+    threshold: f32,
+    /// Pairs keyed by their thread ids, the lowest first.
+    pairs: Mutex<BTreeMap<(Uuid, Uuid), DuplicatePair>>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct DuplicatePair {
+    pub thread_ids: [Uuid; 2],
+    pub similarity: f32,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub detected_at: u64,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct DuplicateReport {
+    pub threshold: f32,
+    /// Most similar first.
+    pub pairs: Vec<DuplicatePair>,
+}
+
+/// Returned when duplicate detection wasn't enabled on the instance.
+#[derive(Debug, thiserror::Error)]
+#[error("duplicate detection is disabled, see `SynxBuilder::with_duplicate_threshold`")]
+pub struct DuplicatesDisabled;
+
+impl DuplicateDetector {
+    pub(crate) fn new(threshold: f32) -> Self {
+        Self {
+            threshold,
+            pairs: Mutex::default(),
+        }
+    }
+
+    /// The pairs the thread makes with `others`.
+    fn find<'a>(
+        &self,
+        thread: &Thread,
+        embedding: &Embedding,
+        others: impl IntoIterator<Item = &'a Thread>,
+    ) -> Vec<((Uuid, Uuid), DuplicatePair)> {
+        others
+            .into_iter()
+            .filter(|other| other.id != thread.id)
+            .filter_map(|other| {
+                let similarity = cosine_similarity(embedding, other.embedding.as_ref()?);
+                (similarity >= self.threshold).then(|| {
+                    let key = (thread.id.min(other.id), thread.id.max(other.id));
+                    let pair = DuplicatePair {
+                        thread_ids: [key.0, key.1],
+                        similarity,
+                        tenant: thread.tenant.clone(),
+                        detected_at: now_millis(),
+                    };
+                    (key, pair)
+                })
+            })
+            .collect()
+    }
+
+    /// Replaces the pairs of the thread with those found among `others`.
+    fn update(&self, thread: &Thread, embedding: &Embedding, others: &[Thread]) {
+        let found = self.find(thread, embedding, others);
+        let mut pairs = self.pairs.lock().unwrap();
+        pairs.retain(|&(a, b), _| a != thread.id && b != thread.id);
+        pairs.extend(found);
+    }
+
+    pub(crate) fn forget(&self, thread_id: Uuid) {
+        self.pairs
+            .lock()
+            .unwrap()
+            .retain(|&(a, b), _| a != thread_id && b != thread_id);
+    }
+
+    fn report(&self, tenant: Option<&str>) -> DuplicateReport {
+        let mut pairs = self
+            .pairs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|pair| tenant.is_none() || pair.tenant.as_deref() == tenant)
+            .cloned()
+            .collect::<Vec<_>>();
+        pairs.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+        DuplicateReport {
+            threshold: self.threshold,
+            pairs,
+        }
+    }
+}
+
+impl Synx {
+    /// The pairs found so far, only those of `tenant` when set.
+    pub fn duplicate_report(&self, tenant: Option<&str>) -> Result<DuplicateReport> {
+        let detector = self.duplicates.as_ref().ok_or(DuplicatesDisabled)?;
+        Ok(detector.report(tenant))
+    }
+
+    /// Compares every pair of threads of each tenant, or only of `tenant`
+    /// when set, replacing their part of the report.
+    pub async fn scan_duplicates(&self, tenant: Option<&str>) -> Result<DuplicateReport> {
+        let detector = self.duplicates.as_ref().ok_or(DuplicatesDisabled)?;
+
+        let thread_ids = self
+            .db
+            .list_threads()
+            .await?
+            .into_iter()
+            .filter(|thread| tenant.is_none() || thread.tenant.as_deref() == tenant)
+            .map(|thread| thread.id)
+            .collect::<Vec<_>>();
+        let threads = self.db.get_threads_with_embeddings(&thread_ids).await?;
+
+        let mut found = Vec::new();
+        for (index, thread) in threads.iter().enumerate() {
+            let Some(embedding) = &thread.embedding else {
+                continue;
+            };
+            // Each pair is compared once, from its first thread.
+            let others = threads[index + 1..]
+                .iter()
+                .filter(|other| other.tenant == thread.tenant);
+            found.extend(detector.find(thread, embedding, others));
+        }
+
+        {
+            let mut pairs = detector.pairs.lock().unwrap();
+            pairs.retain(|_, pair| tenant.is_some() && pair.tenant.as_deref() != tenant);
+            pairs.extend(found);
+        }
+
+        Ok(detector.report(tenant))
+    }
+
+    /// Compares a thread's new summary embedding to the other threads of its
+    /// tenant. Failures are logged and leave the report as it was.
+    pub(crate) async fn check_duplicates(&self, thread_id: Uuid, embedding: &Embedding) {
+        let Some(detector) = &self.duplicates else {
+            return;
+        };
+
+        let result = async {
+            let threads = self.db.list_threads().await?;
+            let thread = threads
+                .iter()
+                .find(|thread| thread.id == thread_id)
+                .ok_or_else(|| anyhow!("thread {} not found", thread_id))?;
+            let thread_ids = threads
+                .iter()
+                .filter(|other| other.tenant == thread.tenant)
+                .map(|other| other.id)
+                .collect::<Vec<_>>();
+            // Embeddings stored before a reduction are compared truncated.
+            let others = self
+                .db
+                .get_threads_with_embeddings(&thread_ids)
+                .await?
+                .into_iter()
+                .map(|other| Thread {
+                    embedding: other.embedding.map(|e| self.reduce_embedding(e)),
+                    ..other
+                })
+                .collect::<Vec<_>>();
+            detector.update(thread, embedding, &others);
+            anyhow::Ok(())
+        };
+        if let Err(e) = result.await {
+            tracing::warn!(%thread_id, "Failed to check for duplicate threads: {}", e);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! What is embedded for a thread. Only its summary by default, but titles
+//! and tags often carry what searches look for, e.g. a ticket number or a
+//! customer's name the summary leaves out.
+//!
+//! [`SynxBuilder::with_embedding_template`](crate::SynxBuilder::with_embedding_template)
+//! sets the text embedded, where `{{TITLE}}`, `{{TAGS}}` (separated by
+//! commas), and `{{SUMMARY}}` are replaced by the thread's. Threads are
+//! embedded along with their summary, so a title or tags changed afterwards
+//! are embedded with the next summary.
+
+use anyhow::Result;
+use synx_domain::{embedding::Embedding, thread::Thread};
+
+use crate::{usage::UsageScope, Synx};
+
+pub const DEFAULT_EMBEDDING_TEMPLATE: &str = "{{SUMMARY}}";
+
+/// The template filled in with the thread's details and `summary`, which
+/// may not be stored yet, trimmed of the space left by missing details.
+fn render(template: &str, thread: &Thread, summary: &str) -> String {
+    template
+        .replace("{{TITLE}}", thread.title.as_deref().unwrap_or_default())
+        .replace("{{TAGS}}", &thread.tags.join(", "))
+        .replace("{{SUMMARY}}", summary)
+        .trim()
+        .to_string()
+}
+
+impl Synx {
+    /// Embeds the thread's new summary as the template has it.
+    pub(crate) async fn embed_summary(
+        &self,
+        thread: &Thread,
+        summary: &str,
+        scope: &UsageScope,
+    ) -> Result<Embedding> {
+        let input = render(&self.embedding_template, thread, summary);
+        self.embed(&self.document_embedder, &input, scope).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_are_filled_in_with_the_thread() {
+        let mut thread = Thread::new();
+        thread.tags = vec!["billing".to_string(), "acme".to_string()];
+        let template = "{{TITLE}}\n{{TAGS}}\n{{SUMMARY}}";
+
+        assert_eq!(
+            render(template, &thread, "The user was charged twice."),
+            "billing, acme\nThe user was charged twice."
+        );
+
+        thread.title = Some("Refund #42".to_string());
+        assert_eq!(
+            render(template, &thread, "The user was charged twice."),
+            "Refund #42\nbilling, acme\nThe user was charged twice."
+        );
+        assert_eq!(
+            render(
+                DEFAULT_EMBEDDING_TEMPLATE,
+                &thread,
+                "The user was charged twice."
+            ),
+            "The user was charged twice."
+        );
+    }
+}
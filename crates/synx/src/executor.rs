@@ -1,5 +1,124 @@
+#[cfg(feature = "testing")]
+use std::{collections::VecDeque, sync::Mutex};
 use std::{future::Future, pin::Pin};
 
 pub trait Executor: Send + Sync {
     fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>);
 }
+
+/// Spawns background work on the ambient tokio runtime. Used by
+/// [`SynxBuilder`](crate::SynxBuilder) when no executor is given, with the
+/// `tokio-executor` feature.
+#[cfg(all(feature = "tokio-executor", not(target_arch = "wasm32")))]
+pub struct TokioExecutor;
+
+#[cfg(all(feature = "tokio-executor", not(target_arch = "wasm32")))]
+impl Executor for TokioExecutor {
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) {
+        tokio::spawn(future);
+    }
+}
+
+/// Spawns background work on the JavaScript event loop, for browser and edge
+/// runtimes without threads.
+#[cfg(target_arch = "wasm32")]
+pub struct LocalExecutor;
+
+#[cfg(target_arch = "wasm32")]
+impl Executor for LocalExecutor {
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+}
+
+/// Holds spawned work until the test driving it runs it, so background
+/// summaries happen at known points rather than racing the assertions.
+/// Enabled with the `testing` feature.
+///
+/// Futures are run one at a time to completion, in the order they were
+/// spawned, so one waiting on a future spawned after it never completes.
+#[cfg(feature = "testing")]
+#[derive(Default)]
+pub struct DeferredExecutor {
+    queue: Mutex<VecDeque<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>,
+}
+
+#[cfg(feature = "testing")]
+impl DeferredExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of futures spawned and not run yet.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Runs the oldest spawned future, returning whether there was one.
+    pub async fn run_next(&self) -> bool {
+        let Some(future) = self.queue.lock().unwrap().pop_front() else {
+            return false;
+        };
+        future.await;
+        true
+    }
+
+    /// Runs spawned futures, including those they spawn, until none are
+    /// left, returning how many ran.
+    pub async fn run_until_idle(&self) -> usize {
+        let mut ran = 0;
+        while self.run_next().await {
+            ran += 1;
+        }
+        ran
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Executor for DeferredExecutor {
+    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) {
+        self.queue.lock().unwrap().push_back(future);
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::sync::Arc;
+
+    use ferrochain::futures::executor::block_on;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::worker_pool::{Lane, LaneLimits, WorkerPool};
+
+    const LIMITS: LaneLimits = LaneLimits {
+        concurrency: 1,
+        capacity: 8,
+    };
+
+    #[test]
+    fn deferred_work_runs_when_driven() {
+        let executor = Arc::new(DeferredExecutor::new());
+        let pool = WorkerPool::new(executor.clone(), LIMITS, LIMITS);
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        for (key, job) in [(first, 1), (second, 2), (first, 3)] {
+            let ran = ran.clone();
+            pool.reserve(Lane::Interactive)
+                .unwrap()
+                .submit(key, Box::pin(async move { ran.lock().unwrap().push(job) }));
+        }
+
+        // Jobs sharing a key are drained by the future of the first one.
+        assert_eq!(executor.pending(), 2);
+        assert!(ran.lock().unwrap().is_empty());
+        assert_eq!(pool.pending(Lane::Interactive), 3);
+
+        assert!(block_on(executor.run_next()));
+        assert_eq!(*ran.lock().unwrap(), vec![1, 3]);
+        assert_eq!(block_on(executor.run_until_idle()), 1);
+        assert_eq!(*ran.lock().unwrap(), vec![1, 3, 2]);
+        assert_eq!(pool.pending(Lane::Interactive), 0);
+        assert!(!block_on(executor.run_next()));
+    }
+}
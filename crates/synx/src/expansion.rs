@@ -0,0 +1,100 @@
+//! Query expansion, improving the recall of vague searches.
+//!
+//! With [`SearchRequest::expand`](crate::SearchRequest::expand), the
+//! summarizer first paraphrases the query, and threads are ranked for the
+//! query and for each paraphrase. The rankings are then fused with
+//! Reciprocal Rank Fusion, the fused score of each thread standing for its
+//! similarity. Expansion costs a completion per search, hence is opt-in.
+
+use anyhow::Result;
+use ferrochain::futures::future::try_join_all;
+use synx_domain::embedding::Embedding;
+
+use crate::{
+    query_embeddings::normalize_query, usage::UsageScope, utils::completion::EXPAND_QUERY_PROMPT,
+    Synx,
+};
+
+/// Paraphrases searched along with the query, at most.
+pub const MAX_PARAPHRASES: usize = 3;
+
+impl Synx {
+    /// The embedding of the query, followed by those of its paraphrases
+    /// when expanded. Searches go on with the query alone when it can't be
+    /// paraphrased.
+    pub(crate) async fn embed_queries(
+        &self,
+        query: &str,
+        expand: bool,
+        scope: &UsageScope,
+    ) -> Result<Vec<Embedding>> {
+        let mut queries = vec![query.to_string()];
+        if expand {
+            match self.paraphrase(query, scope).await {
+                Ok(paraphrases) => queries.extend(paraphrases),
+                Err(e) => tracing::warn!("Failed to paraphrase search query: {}", e),
+            }
+        }
+
+        try_join_all(queries.iter().map(|query| self.embed_query(query, scope))).await
+    }
+
+    async fn paraphrase(&self, query: &str, scope: &UsageScope) -> Result<Vec<String>> {
+        let output = self
+            .complete(
+                &self.summarizer,
+                self.summarizer_capabilities,
+                EXPAND_QUERY_PROMPT.replace("{{QUERY}}", query),
+                scope,
+            )
+            .await?;
+
+        Ok(paraphrases(&output, query))
+    }
+}
+
+/// The distinct paraphrases of the completion, one per line, list markers
+/// aside, leaving out the query itself.
+fn paraphrases(output: &str, query: &str) -> Vec<String> {
+    let mut seen = vec![normalize_query(query)];
+    let mut paraphrases = Vec::new();
+    for line in output.lines() {
+        let paraphrase = line
+            .trim()
+            .trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '-' | '*' | '.' | ')'))
+            .trim()
+            .trim_matches('"')
+            .trim();
+        let normalized = normalize_query(paraphrase);
+        if normalized.is_empty() || seen.contains(&normalized) {
+            continue;
+        }
+
+        seen.push(normalized);
+        paraphrases.push(paraphrase.to_string());
+        if paraphrases.len() == MAX_PARAPHRASES {
+            break;
+        }
+    }
+
+    paraphrases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paraphrases_are_distinct_lines_without_markers() {
+        let output = "1. Forecast for Paris\n\n- \"weather in paris\"\n* Paris climate today\n2) forecast for  PARIS\nIs it raining in Paris?\nParis temperature";
+
+        assert_eq!(
+            paraphrases(output, "Weather in Paris"),
+            vec![
+                "Forecast for Paris",
+                "Paris climate today",
+                "Is it raining in Paris?"
+            ]
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Relevance feedback, re-ranking search results for the reader who gave it.
+//!
+//! Readers tell whether a thread returned by a search was relevant with
+//! [`Synx::record_search_feedback`], or give a thumbs up or down to its
+//! messages with annotations. Each thread gets a signal between -1 and 1:
+//! the reader's explicit feedback when there is some, otherwise the balance
+//! of the reader's thumbs up and down. Search scores are moved by the signal
+//! times the feedback weight, see [`SynxBuilder::with_feedback_weight`](crate::SynxBuilder::with_feedback_weight).
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use synx_domain::annotation::{Annotation, CreateSearchFeedback, SearchFeedback};
+use uuid::Uuid;
+
+use crate::Synx;
+
+pub const DEFAULT_FEEDBACK_WEIGHT: f32 = 0.1;
+
+impl Synx {
+    pub async fn record_search_feedback(
+        &self,
+        reader: &str,
+        input: CreateSearchFeedback,
+    ) -> Result<SearchFeedback> {
+        let feedback = input.into_feedback(reader.to_string());
+        self.db.put_search_feedback(feedback.clone()).await?;
+        self.search_index_changed();
+        Ok(feedback)
+    }
+
+    /// The reader's signal for each of the threads having one.
+    pub(crate) async fn feedback_signals(
+        &self,
+        reader: &str,
+        thread_ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, f32>> {
+        let feedback = self
+            .db
+            .list_search_feedback(reader.to_string())
+            .await?
+            .into_iter()
+            .map(|feedback| (feedback.thread_id, feedback))
+            .collect::<HashMap<_, _>>();
+
+        let mut signals = HashMap::new();
+        for &thread_id in thread_ids {
+            let annotations = match feedback.get(&thread_id) {
+                Some(_) => Vec::new(),
+                None => self.db.list_annotations(thread_id).await?,
+            };
+            if let Some(signal) = signal(feedback.get(&thread_id), &annotations, reader) {
+                signals.insert(thread_id, signal);
+            }
+        }
+
+        Ok(signals)
+    }
+}
+
+fn signal(
+    feedback: Option<&SearchFeedback>,
+    annotations: &[Annotation],
+    reader: &str,
+) -> Option<f32> {
+    if let Some(feedback) = feedback {
+        return Some(if feedback.relevant { 1.0 } else { -1.0 });
+    }
+
+    let (up, down) = annotations
+        .iter()
+        .filter(|annotation| annotation.author == reader)
+        .filter_map(|annotation| annotation.kind.verdict())
+        .fold((0, 0), |(up, down), useful| {
+            if useful {
+                (up + 1, down)
+            } else {
+                (up, down + 1)
+            }
+        });
+    (up + down > 0).then(|| (up - down) as f32 / (up + down) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_domain::annotation::{AnnotationKind, CreateAnnotation};
+
+    use super::*;
+
+    fn annotation(author: &str, kind: AnnotationKind) -> Annotation {
+        CreateAnnotation { kind }.into_annotation(Uuid::nil(), Uuid::nil(), author.to_string())
+    }
+
+    #[test]
+    fn explicit_feedback_wins_over_annotations() {
+        let feedback = CreateSearchFeedback {
+            thread_id: Uuid::nil(),
+            relevant: false,
+            query: None,
+        }
+        .into_feedback("alice".to_string());
+        let annotations = [annotation("alice", AnnotationKind::ThumbsUp)];
+
+        assert_eq!(signal(Some(&feedback), &annotations, "alice"), Some(-1.0));
+    }
+
+    #[test]
+    fn annotations_of_the_reader_are_balanced() {
+        let annotations = [
+            annotation("alice", AnnotationKind::ThumbsUp),
+            annotation("alice", AnnotationKind::ThumbsUp),
+            annotation("alice", AnnotationKind::ThumbsDown),
+            annotation(
+                "alice",
+                AnnotationKind::Label {
+                    label: "todo".to_string(),
+                },
+            ),
+            annotation("bob", AnnotationKind::ThumbsDown),
+        ];
+
+        assert_eq!(signal(None, &annotations, "alice"), Some(1.0 / 3.0));
+        assert_eq!(signal(None, &annotations, "bob"), Some(-1.0));
+        assert_eq!(signal(None, &annotations, "carol"), None);
+    }
+}
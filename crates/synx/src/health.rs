@@ -0,0 +1,130 @@
+//! Health of the embedding providers and the summarizer, as seen from the
+//! requests made to them, so an outage shows before users report it.
+//!
+//! A provider is unhealthy from its last failed request until a request
+//! succeeds again. While the summarizer is unhealthy, summaries of new
+//! messages are queued without calling it, see [`outbox`](crate::outbox). While the query embedder is unavailable, searches whose
+//! results were cached, see [`SynxBuilder::with_search_cache`](crate::SynxBuilder::with_search_cache),
+//! are answered with them, however out of date, flagged as degraded.
+//! Others fail with [`EmbedderUnavailable`].
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use ferrochain::{completion::Completion, embedding::Embedder};
+use synx_domain::embedding::Embedding;
+
+use crate::{replication::now_millis, Synx};
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct ProviderHealth {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// In milliseconds since the epoch.
+    #[serde(default)]
+    pub last_success_at: Option<u64>,
+    #[serde(default)]
+    pub last_failure_at: Option<u64>,
+}
+
+impl ProviderHealth {
+    fn record<T>(&mut self, result: &Result<T>) {
+        match result {
+            Ok(_) => {
+                self.healthy = true;
+                self.consecutive_failures = 0;
+                self.last_success_at = Some(now_millis());
+            }
+            Err(e) => {
+                self.healthy = false;
+                self.consecutive_failures += 1;
+                self.last_error = Some(e.to_string());
+                self.last_failure_at = Some(now_millis());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct HealthReport {
+    /// Whether any provider is unhealthy.
+    pub degraded: bool,
+    pub query_embedder: ProviderHealth,
+    pub document_embedder: ProviderHealth,
+    pub summarizer: ProviderHealth,
+}
+
+pub(crate) struct Health {
+    query_embedder: Mutex<ProviderHealth>,
+    document_embedder: Mutex<ProviderHealth>,
+    summarizer: Mutex<ProviderHealth>,
+}
+
+impl Default for Health {
+    /// Providers are deemed healthy until a request fails.
+    fn default() -> Self {
+        let healthy = || {
+            Mutex::new(ProviderHealth {
+                healthy: true,
+                ..Default::default()
+            })
+        };
+        Self {
+            query_embedder: healthy(),
+            document_embedder: healthy(),
+            summarizer: healthy(),
+        }
+    }
+}
+
+/// Returned by searches when the query can't be embedded and no results
+/// were cached for it.
+#[derive(Debug, thiserror::Error)]
+#[error("the query embedder is unavailable: {0}")]
+pub struct EmbedderUnavailable(pub String);
+
+impl Synx {
+    pub fn health(&self) -> HealthReport {
+        let query_embedder = self.health.query_embedder.lock().unwrap().clone();
+        let document_embedder = self.health.document_embedder.lock().unwrap().clone();
+        let summarizer = self.health.summarizer.lock().unwrap().clone();
+
+        HealthReport {
+            degraded: !query_embedder.healthy || !document_embedder.healthy || !summarizer.healthy,
+            query_embedder,
+            document_embedder,
+            summarizer,
+        }
+    }
+
+    /// Records the outcome of a request made to `embedder`.
+    pub(crate) fn record_embedding(
+        &self,
+        embedder: &Arc<dyn Embedder>,
+        result: &Result<Embedding>,
+    ) {
+        if Arc::ptr_eq(embedder, &self.query_embedder) {
+            self.health.query_embedder.lock().unwrap().record(result);
+        }
+        if Arc::ptr_eq(embedder, &self.document_embedder) {
+            self.health.document_embedder.lock().unwrap().record(result);
+        }
+    }
+
+    /// Records the outcome of a request made to `completion`.
+    pub(crate) fn record_completion<T>(
+        &self,
+        completion: &Arc<dyn Completion>,
+        result: &Result<T>,
+    ) {
+        if Arc::ptr_eq(completion, &self.summarizer) {
+            self.health.summarizer.lock().unwrap().record(result);
+        }
+    }
+
+    pub(crate) fn summarizer_healthy(&self) -> bool {
+        self.health.summarizer.lock().unwrap().healthy
+    }
+}
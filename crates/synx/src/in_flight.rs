@@ -0,0 +1,161 @@
+//! Deduplication of summaries in flight. A message may be submitted for
+//! summary more than once at about the same time, e.g. when a streaming
+//! message is finalized while its last chunk is appended, and summarizing
+//! it twice would fold it into the summary twice.
+//!
+//! Jobs already run one after the other per thread, in submission order, see
+//! [`WorkerPool`](crate::worker_pool::WorkerPool). A message submitted again
+//! while its job is queued collapses into that job, which summarizes the
+//! latest input given when it starts, keeping its place in the thread's
+//! order. Submitted while its job runs, the message gets a job again, queued
+//! behind the running one, so the latest input isn't lost when the running
+//! job fails or skips it.
+//!
+//! Jobs are keyed by thread and message, message ids being unique per thread
+//! only.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use synx_domain::message::Message;
+use uuid::Uuid;
+
+#[derive(Default)]
+pub(crate) struct InFlightSummaries {
+    jobs: Mutex<HashMap<(Uuid, Uuid), Job>>,
+}
+
+#[derive(Default)]
+struct Job {
+    /// The latest input of the queued job, taken when it starts.
+    queued: Option<Message>,
+    running: bool,
+}
+
+/// The summary job of a message, out of flight once dropped, however the
+/// job ends.
+pub(crate) struct InFlightTicket {
+    summaries: Arc<InFlightSummaries>,
+    key: (Uuid, Uuid),
+    started: bool,
+}
+
+impl InFlightSummaries {
+    /// A ticket for the message's job, `None` when a job of the message is
+    /// queued already, taking `message` as its input.
+    pub(crate) fn submit(self: &Arc<Self>, message: Message) -> Option<InFlightTicket> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let key = (message.thread_id, message.id);
+        let job = jobs.entry(key).or_default();
+        if let Some(input) = &mut job.queued {
+            *input = message;
+            return None;
+        }
+
+        job.queued = Some(message);
+        Some(InFlightTicket {
+            summaries: self.clone(),
+            key,
+            started: false,
+        })
+    }
+}
+
+impl InFlightTicket {
+    /// Marks the job running, returning the latest input it was given.
+    pub(crate) fn start(&mut self) -> Option<Message> {
+        let mut jobs = self.summaries.jobs.lock().unwrap();
+        let job = jobs.get_mut(&self.key)?;
+        let message = job.queued.take()?;
+        job.running = true;
+        self.started = true;
+        Some(message)
+    }
+}
+
+impl Drop for InFlightTicket {
+    fn drop(&mut self) {
+        let mut jobs = self.summaries.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&self.key) else {
+            return;
+        };
+        if self.started {
+            job.running = false;
+        } else {
+            // Dropped before it started, e.g. along with the worker pool.
+            job.queued = None;
+        }
+        if job.queued.is_none() && !job.running {
+            jobs.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_domain::message::CreateMessage;
+
+    use super::*;
+
+    fn message(text: &str) -> Message {
+        CreateMessage {
+            role: "user".into(),
+            content: text.to_string().into(),
+            parent_message_id: None,
+            metadata: Default::default(),
+            status: Default::default(),
+            memorize: true,
+        }
+        .into_message(Uuid::new_v4())
+    }
+
+    #[test]
+    fn submissions_collapse_into_the_job_in_flight() {
+        let summaries = Arc::new(InFlightSummaries::default());
+        let first = message("Hel");
+        let latest = Message {
+            content: "Hello".to_string().into(),
+            ..first.clone()
+        };
+
+        let mut ticket = summaries.submit(first.clone()).unwrap();
+        assert!(summaries.submit(latest).is_none());
+        assert_eq!(ticket.start().unwrap().content.to_string(), "Hello");
+
+        drop(ticket);
+        assert!(summaries.submit(first).is_some());
+    }
+
+    #[test]
+    fn submissions_while_running_get_a_job_again() {
+        let summaries = Arc::new(InFlightSummaries::default());
+        let first = message("Hel");
+        let latest = Message {
+            content: "Hello".to_string().into(),
+            ..first.clone()
+        };
+
+        let mut running = summaries.submit(first.clone()).unwrap();
+        running.start().unwrap();
+        let mut rerun = summaries.submit(first).unwrap();
+        assert!(summaries.submit(latest).is_none());
+
+        drop(running);
+        assert_eq!(rerun.start().unwrap().content.to_string(), "Hello");
+    }
+
+    #[test]
+    fn jobs_are_kept_apart_per_thread() {
+        let summaries = Arc::new(InFlightSummaries::default());
+        let message = message("Hello");
+        let elsewhere = Message {
+            thread_id: Uuid::new_v4(),
+            ..message.clone()
+        };
+
+        let _ticket = summaries.submit(message).unwrap();
+        assert!(summaries.submit(elsewhere).is_some());
+    }
+}
@@ -0,0 +1,31 @@
+//! Provider calls made for debugging rather than on behalf of a thread, e.g.
+//! from the `synx repl` console: how similar the document embedder finds two
+//! texts, and what the summarizer answers to a prompt. Their usage is
+//! recorded without a tenant.
+
+use anyhow::Result;
+
+use crate::{usage::UsageScope, utils::similarity::cosine_similarity, Synx};
+
+impl Synx {
+    /// The cosine similarity of the document embeddings of both texts, as
+    /// search would compare a summary with another.
+    pub async fn text_similarity(&self, a: &str, b: &str) -> Result<f32> {
+        let scope = UsageScope::default();
+        let a = self.embed(&self.document_embedder, a, &scope).await?;
+        let b = self.embed(&self.document_embedder, b, &scope).await?;
+        Ok(cosine_similarity(&a, &b))
+    }
+
+    /// The summarizer's answer to the prompt, sent as is without the
+    /// summary instructions.
+    pub async fn complete_prompt(&self, prompt: &str) -> Result<String> {
+        self.complete(
+            &self.summarizer,
+            self.summarizer_capabilities,
+            prompt.to_string(),
+            &UsageScope::default(),
+        )
+        .await
+    }
+}
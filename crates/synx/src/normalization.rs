@@ -0,0 +1,118 @@
+//! Normalization of embeddings to unit length as they are made, so searches
+//! comparing them by cosine similarity take their dot product instead,
+//! without computing two magnitudes per thread scored.
+//!
+//! With [`SynxBuilder::with_embedding_normalization`](crate::SynxBuilder::with_embedding_normalization),
+//! every embedding made, of summaries and queries alike, is scaled to unit
+//! length. Searches then rely on the stored embeddings being unit length:
+//! those stored before, or replicated from an instance not normalizing, are
+//! rewritten by [`Synx::normalize_stored_embeddings`]. Dot-product and
+//! Euclidean searches rank normalized embeddings as cosine similarity does.
+
+use anyhow::Result;
+use synx_database::WriteBatch;
+use synx_domain::{
+    embedding::Embedding,
+    thread::{Thread, ThreadVector},
+};
+
+use crate::{utils::similarity::normalize, Synx};
+
+/// How far from 1 the magnitude of an embedding taken as unit length may be,
+/// for rounding errors.
+const UNIT_TOLERANCE: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct NormalizationReport {
+    /// Number of stored embeddings, of summaries and
+    /// [segments](crate::segments), which were scaled to unit length.
+    pub normalized: usize,
+}
+
+/// Returned when normalizing stored embeddings on an instance which doesn't
+/// normalize the embeddings it makes.
+#[derive(Debug, thiserror::Error)]
+#[error("embedding normalization is disabled, see `SynxBuilder::with_embedding_normalization`")]
+pub struct NormalizationDisabled;
+
+/// The embedding scaled to unit length, or `None` if it is already, or a
+/// zero vector.
+fn scale(embedding: &Embedding) -> Option<Embedding> {
+    let vector = embedding.to_vec();
+    let magnitude = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if magnitude == 0.0 || (magnitude - 1.0).abs() <= UNIT_TOLERANCE {
+        return None;
+    }
+
+    Some(Embedding::from(normalize(vector)))
+}
+
+impl Synx {
+    /// The embedding scaled to unit length, if configured.
+    pub(crate) fn normalize_embedding(&self, embedding: Embedding) -> Embedding {
+        if self.normalize_embeddings {
+            scale(&embedding).unwrap_or(embedding)
+        } else {
+            embedding
+        }
+    }
+
+    /// Scales the stored embeddings which aren't unit length. Threads are
+    /// rewritten as read, so a summary stored in the meantime may be
+    /// overwritten; run it while summaries are quiet.
+    pub async fn normalize_stored_embeddings(&self) -> Result<NormalizationReport> {
+        if !self.normalize_embeddings {
+            return Err(NormalizationDisabled.into());
+        }
+
+        let mut normalized = 0;
+        for mut thread in self.stored_embeddings().await? {
+            let mut batch = WriteBatch::new();
+            for vector in std::mem::take(&mut thread.vectors) {
+                if let Some(embedding) = scale(&vector.embedding) {
+                    batch.put_thread_vector(
+                        thread.id,
+                        ThreadVector {
+                            id: vector.id,
+                            embedding,
+                        },
+                    );
+                    normalized += 1;
+                }
+            }
+            if !batch.is_empty() {
+                self.db.write_batch(batch).await?;
+            }
+
+            let Some(embedding) = thread.embedding.as_ref().and_then(scale) else {
+                continue;
+            };
+            self.db
+                .put_thread(Thread {
+                    embedding: Some(embedding),
+                    ..thread
+                })
+                .await?;
+            normalized += 1;
+        }
+
+        if normalized > 0 {
+            self.search_index_changed();
+        }
+        Ok(NormalizationReport { normalized })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_embeddings_off_unit_length_are_scaled() {
+        let scaled = scale(&Embedding::from(vec![3.0, 4.0])).unwrap();
+        assert_eq!(scaled.to_vec(), vec![0.6, 0.8]);
+
+        assert!(scale(&scaled).is_none());
+        assert!(scale(&Embedding::from(vec![0.0, 0.0])).is_none());
+    }
+}
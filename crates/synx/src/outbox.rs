@@ -0,0 +1,130 @@
+//! Persistent queue of the messages whose summary failed, e.g. while the
+//! summarizer was down, so they are summarized once it recovers rather than
+//! left out of their thread's summary for good.
+//!
+//! While the summarizer is unhealthy, see [`health`](crate::health), new
+//! messages are queued without calling it. [`Synx::retry_pending_summaries`],
+//! run periodically, retries only the oldest queued message while the
+//! summarizer is unhealthy, probing it, and every queued message once it has
+//! recovered. Messages are retried in the order they were first queued, on
+//! the bulk lane, and taken off the queue when retried, a failure queueing
+//! them again. The size of the queue is reported by [`Synx::analytics`].
+//!
+//! Summaries made while messages of their thread are queued keep them out
+//! of their [`SummaryWatermark`](synx_domain::thread::SummaryWatermark), so
+//! they are still folded in when retried after newer messages.
+
+use anyhow::Result;
+use synx_database::DatabaseError;
+use synx_domain::thread::PendingSummary;
+use uuid::Uuid;
+
+use crate::{replication::now_millis, worker_pool::Lane, Synx};
+
+/// The entry queueing the message after a failed summary, `retried` being
+/// its entry if the summary was a retry.
+pub(crate) fn failed_summary(
+    thread_id: Uuid,
+    message_id: Uuid,
+    retried: Option<PendingSummary>,
+) -> PendingSummary {
+    match retried {
+        Some(retried) => PendingSummary {
+            attempts: retried.attempts + 1,
+            ..retried
+        },
+        None => PendingSummary {
+            thread_id,
+            message_id,
+            queued_at: now_millis(),
+            attempts: 1,
+        },
+    }
+}
+
+impl Synx {
+    /// Failing to queue a summary is logged, the message then being left
+    /// out of its thread's summary.
+    pub(crate) async fn queue_summary(&self, pending: PendingSummary) {
+        match self.db.put_pending_summary(pending.clone()).await {
+            Ok(()) => self.analytics.record_pending_summary(true),
+            Err(DatabaseError::NotFound) => tracing::debug!(
+                "Thread {} was deleted before its summary was queued",
+                pending.thread_id
+            ),
+            Err(e) => tracing::error!(
+                "Failed to queue summary of message {}: {}",
+                pending.message_id,
+                e
+            ),
+        }
+    }
+
+    /// Every message queued to be summarized again, the oldest first.
+    pub async fn pending_summaries(&self) -> Result<Vec<PendingSummary>> {
+        let pending = self.db.list_pending_summaries().await?;
+        self.analytics.record_pending_summaries(pending.len());
+        Ok(pending)
+    }
+
+    /// The messages of the thread queued to be summarized again, left out of
+    /// the watermark of its summary. Failing to list them is logged, none
+    /// being left out then.
+    pub(crate) async fn pending_messages(&self, thread_id: Uuid) -> Vec<Uuid> {
+        match self.db.list_pending_summaries().await {
+            Ok(pending) => pending
+                .into_iter()
+                .filter(|pending| pending.thread_id == thread_id)
+                .map(|pending| pending.message_id)
+                .collect(),
+            Err(e) => {
+                tracing::error!("Failed to list pending summaries: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Submits queued summaries for another attempt, only the oldest while
+    /// the summarizer is unhealthy, returning how many were submitted. Stops
+    /// early when the bulk lane is full.
+    pub async fn retry_pending_summaries(&self) -> Result<usize> {
+        let mut pending = self.pending_summaries().await?;
+        if !self.summarizer_healthy() {
+            pending.truncate(1);
+        }
+
+        let mut retried = 0;
+        for pending in pending {
+            let Ok(slot) = self.workers.reserve(Lane::Bulk) else {
+                break;
+            };
+            match self
+                .db
+                .delete_pending_summary(pending.thread_id, pending.message_id)
+                .await
+            {
+                Ok(()) => self.analytics.record_pending_summary(false),
+                // Its thread was deleted in the meantime.
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            let message = match self
+                .db
+                .get_message(pending.thread_id, pending.message_id)
+                .await
+            {
+                Ok(message) => message,
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => {
+                    self.queue_summary(pending).await;
+                    return Err(e.into());
+                }
+            };
+            self.process_new_message(slot, pending.thread_id, message, Some(pending));
+            retried += 1;
+        }
+
+        Ok(retried)
+    }
+}
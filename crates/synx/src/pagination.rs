@@ -0,0 +1,156 @@
+//! Pages of search results. Searches rank every thread they are given, then
+//! return the page asked for with [`SearchPage`], ties being broken by
+//! thread id so the same search ranks the same way every time.
+//!
+//! A page is either `offset` results in, or right after the last result of
+//! the previous page, identified by its cursor. Cursors stay on track when
+//! summaries change between pages, resuming after the thread they point at
+//! wherever it ranks now, or at the position it had when it's gone.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ferrochain::vectorstore::Similarity;
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct SearchPage {
+    /// Results returned at most, every one by default.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Results skipped, ignored along with a cursor.
+    #[serde(default)]
+    pub offset: Option<usize>,
+    /// The [`next_cursor`](crate::SearchResults::next_cursor) of the
+    /// previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+/// Returned for a cursor which wasn't given by a previous search.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid search cursor")]
+pub struct InvalidCursor;
+
+/// A page of results, along with the number of results in every page.
+pub(crate) struct Paged {
+    pub results: Vec<Similarity>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+impl SearchPage {
+    pub(crate) fn apply(&self, results: Vec<Similarity>) -> Result<Paged, InvalidCursor> {
+        let total = results.len();
+        let start = match &self.cursor {
+            Some(cursor) => {
+                let (position, last_id) = decode_cursor(cursor).ok_or(InvalidCursor)?;
+                results
+                    .iter()
+                    .position(|result| result.stored.id == last_id)
+                    .map_or(position, |index| index + 1)
+            }
+            None => self.offset.unwrap_or(0),
+        }
+        .min(total);
+        let end = self
+            .limit
+            .map_or(total, |limit| start.saturating_add(limit).min(total));
+
+        let next_cursor =
+            (start < end && end < total).then(|| encode_cursor(end, &results[end - 1].stored.id));
+        Ok(Paged {
+            results: results.into_iter().skip(start).take(end - start).collect(),
+            total,
+            next_cursor,
+        })
+    }
+}
+
+/// `{position of the next result}:{id of the last result}`, base64 encoded.
+fn encode_cursor(position: usize, last_id: &str) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}:{}", position, last_id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<(usize, String)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (position, last_id) = decoded.split_once(':')?;
+    Some((position.parse().ok()?, last_id.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ferrochain::document::{Document, StoredDocument};
+
+    use super::*;
+
+    fn results(ids: &[&str]) -> Vec<Similarity> {
+        ids.iter()
+            .map(|id| Similarity {
+                stored: StoredDocument {
+                    id: id.to_string(),
+                    document: Document {
+                        content: String::new(),
+                        metadata: HashMap::new(),
+                    },
+                },
+                score: 0.5,
+            })
+            .collect()
+    }
+
+    fn ids(paged: &Paged) -> Vec<&str> {
+        paged
+            .results
+            .iter()
+            .map(|result| result.stored.id.as_str())
+            .collect()
+    }
+
+    fn page(limit: usize, cursor: Option<String>) -> SearchPage {
+        SearchPage {
+            limit: Some(limit),
+            offset: None,
+            cursor,
+        }
+    }
+
+    #[test]
+    fn cursors_resume_after_the_last_result() {
+        let first = page(2, None).apply(results(&["a", "b", "c", "d"])).unwrap();
+        assert_eq!(ids(&first), ["a", "b"]);
+        assert_eq!(first.total, 4);
+
+        // A thread ranking first since resumes after "b" all the same.
+        let second = page(2, first.next_cursor)
+            .apply(results(&["e", "a", "b", "c", "d"]))
+            .unwrap();
+        assert_eq!(ids(&second), ["c", "d"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[test]
+    fn cursors_fall_back_to_their_position() {
+        let first = page(2, None).apply(results(&["a", "b", "c", "d"])).unwrap();
+        let second = page(2, first.next_cursor)
+            .apply(results(&["a", "c", "d"]))
+            .unwrap();
+        assert_eq!(ids(&second), ["d"]);
+
+        assert!(page(2, Some("not a cursor".to_string()))
+            .apply(results(&["a"]))
+            .is_err());
+    }
+
+    #[test]
+    fn offsets_skip_results() {
+        let paged = SearchPage {
+            offset: Some(1),
+            ..Default::default()
+        }
+        .apply(results(&["a", "b", "c"]))
+        .unwrap();
+
+        assert_eq!(ids(&paged), ["b", "c"]);
+        assert!(paged.next_cursor.is_none());
+    }
+}
@@ -0,0 +1,15 @@
+//! What the completion providers behind the summarizers support beyond
+//! plain completions, declared when building [`Synx`](crate::Synx) since the
+//! completion trait doesn't expose it.
+
+/// Features of a completion provider requests are shaped for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The provider caches the prefix of a request up to a breakpoint, e.g.
+    /// Anthropic's prompt caching. Summary requests then send the part of
+    /// the prompt shared by every request, the template up to its first
+    /// placeholder, as their first content block for the adapter to mark as
+    /// cacheable. The system prompt, preceding it,
+    /// is cached along with it.
+    pub prompt_caching: bool,
+}
@@ -0,0 +1,158 @@
+//! Pruning of old messages, keeping the memory made of them.
+//!
+//! [`Synx::prune_messages`] deletes the messages older than the retention
+//! of threads whose summary is done with them: locked summaries, and
+//! summaries made after the message was stored with no summary of the
+//! thread still queued. The thread, its summary, and its embedding are
+//! kept, and the thread counts the messages pruned from it in
+//! [`Thread::pruned_message_count`](synx_domain::thread::Thread::pruned_message_count).
+//!
+//! New messages are summarized on top of the summary, so pruning doesn't
+//! lose anything to later summaries. Summarizing a thread from scratch, as
+//! a purge does, only sees the messages left. Pruning isn't replicated:
+//! each instance prunes on its own retention.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Result;
+use synx_database::{DatabaseError, WriteBatch};
+use synx_domain::{message::MessageStatus, thread::Thread};
+use uuid::Uuid;
+
+use crate::{replication::now_millis, Synx};
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct PruneRequest {
+    /// Prunes the messages older than this many days, the instance's
+    /// retention by default.
+    #[serde(default)]
+    pub older_than_days: Option<u64>,
+    /// Prunes the threads of this tenant only.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Reports the messages which would be pruned without pruning them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct PruneReport {
+    #[serde(flatten)]
+    pub request: PruneRequest,
+    /// The ids of the pruned messages, by thread.
+    pub messages: BTreeMap<Uuid, Vec<Uuid>>,
+    pub messages_pruned: usize,
+    pub started_at: u64,
+    pub completed_at: u64,
+}
+
+/// Returned when pruning without an age, on an instance without a
+/// retention.
+#[derive(Debug, thiserror::Error)]
+#[error("no message retention, see `SynxBuilder::with_message_retention`")]
+pub struct RetentionUnset;
+
+impl Synx {
+    pub async fn prune_messages(&self, request: PruneRequest) -> Result<PruneReport> {
+        let retention = match request.older_than_days {
+            Some(days) => Duration::from_secs(days.saturating_mul(24 * 60 * 60)),
+            None => self.message_retention.ok_or(RetentionUnset)?,
+        };
+
+        let started_at = now_millis();
+        let cutoff = started_at.saturating_sub(retention.as_millis() as u64);
+        let queued = self
+            .db
+            .list_pending_summaries()
+            .await?
+            .into_iter()
+            .map(|pending| pending.thread_id)
+            .collect::<HashSet<_>>();
+
+        let mut messages = BTreeMap::new();
+        for thread in self.db.list_threads().await? {
+            if request.tenant.is_some() && thread.tenant != request.tenant {
+                continue;
+            }
+            if thread.summary.is_none() || (queued.contains(&thread.id) && !thread.summary_locked) {
+                continue;
+            }
+            let prunable = self
+                .db
+                .get_thread_messages(thread.id, None, None)
+                .await?
+                .messages
+                .into_iter()
+                .filter(|message| {
+                    message.created_at < cutoff
+                        && message.status == MessageStatus::Final
+                        && summarized(&thread, message.created_at)
+                })
+                .map(|message| message.id)
+                .collect::<Vec<_>>();
+            if !prunable.is_empty() {
+                messages.insert(thread.id, prunable);
+            }
+        }
+
+        let mut report = PruneReport {
+            messages_pruned: 0,
+            started_at,
+            completed_at: 0,
+            messages,
+            request,
+        };
+        if report.request.dry_run {
+            report.completed_at = now_millis();
+            return Ok(report);
+        }
+
+        for (&thread_id, message_ids) in &report.messages {
+            let mut batch = WriteBatch::new();
+            for &message_id in message_ids {
+                batch.prune_message(thread_id, message_id);
+            }
+            // Skipped when a message or the thread was deleted meanwhile,
+            // to be pruned by the next run.
+            match self.db.write_batch(batch).await {
+                Ok(()) => {}
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            report.messages_pruned += message_ids.len();
+            for _ in message_ids {
+                self.analytics.record_message_deleted(thread_id);
+            }
+        }
+
+        report.completed_at = now_millis();
+        Ok(report)
+    }
+}
+
+/// Whether the thread's summary is done with a message stored at
+/// `created_at`.
+fn summarized(thread: &Thread, created_at: u64) -> bool {
+    thread.summary_locked || created_at <= thread.summarized_at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_summarized_once_the_summary_is_newer_or_locked() {
+        let mut thread = Thread::new();
+        thread.summarized_at = 1_000;
+
+        assert!(summarized(&thread, 999));
+        assert!(!summarized(&thread, 1_001));
+
+        thread.summary_locked = true;
+        assert!(summarized(&thread, 1_001));
+    }
+}
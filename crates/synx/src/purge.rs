@@ -0,0 +1,282 @@
+//! Deep purge of messages, e.g. to honour a right-to-be-forgotten request.
+//!
+//! [`Synx::purge`] hard-deletes the messages of a tenant, those whose text
+//! matches a pattern, or both, then summarizes each affected thread again
+//! from scratch so nothing of the purged messages lingers in its summary,
+//! embedding, or shadow summary. Pending summaries of those threads are
+//! cancelled first, as they may have been made from purged messages.
+//!
+//! Blobs referenced by purged messages are deleted once no other message
+//! refers to them. A [`Purge`](OperationKind::Purge) operation is logged for
+//! each thread, which drops the past operations holding purged messages and
+//! summaries from the op-log, so they can't be pulled through `/sync`
+//! anymore, and has replicas applying it purge the same messages.
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use regex::Regex;
+use synx_database::{DatabaseError, WriteBatch};
+use synx_domain::{
+    message::MessageStatus, sync::OperationKind, thread::SummaryWatermark, usage::Usage,
+};
+use uuid::Uuid;
+
+use crate::{
+    blobs::blob_hashes, replication::now_millis, usage::UsageScope,
+    utils::completion::SUMMARY_PROMPT, Synx,
+};
+
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct PurgeRequest {
+    /// Purges the threads of this tenant only.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Purges the messages whose text matches this regular expression only.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// Reports the messages which would be purged without purging them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What a purge did, kept as a record of its completion.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct PurgeReport {
+    #[serde(flatten)]
+    pub request: PurgeRequest,
+    /// The ids of the purged messages, by thread.
+    pub messages: BTreeMap<Uuid, Vec<Uuid>>,
+    pub messages_deleted: usize,
+    /// Blobs referenced by purged messages and by no other message.
+    #[serde(default)]
+    pub blobs_deleted: usize,
+    /// Threads summarized again from their remaining messages.
+    pub resummarized: Vec<Uuid>,
+    /// Threads which couldn't be summarized again. Their summary was cleared
+    /// all the same.
+    pub failed: Vec<Uuid>,
+    pub started_at: u64,
+    pub completed_at: u64,
+}
+
+/// Returned for a purge without criteria, which would purge everything, or
+/// with a pattern that isn't a valid regular expression.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid purge: {0}")]
+pub struct InvalidPurge(pub String);
+
+impl Synx {
+    pub async fn purge(&self, request: PurgeRequest) -> Result<PurgeReport> {
+        if request.tenant.is_none() && request.pattern.is_none() {
+            return Err(InvalidPurge("a tenant or a pattern is required".to_string()).into());
+        }
+        let pattern = request
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| InvalidPurge(e.to_string()))?;
+
+        let started_at = now_millis();
+        let mut messages = BTreeMap::new();
+        let mut blobs = HashSet::new();
+        for thread in self.db.list_threads().await? {
+            if request.tenant.is_some() && thread.tenant != request.tenant {
+                continue;
+            }
+            let matched = self
+                .db
+                .get_thread_messages(thread.id, None, None)
+                .await?
+                .messages
+                .into_iter()
+                .filter(|message| {
+                    pattern.as_ref().map_or(true, |pattern| {
+                        pattern.is_match(&message.content.to_string())
+                    })
+                })
+                .inspect(|message| {
+                    blobs.extend(blob_hashes(&message.content).map(str::to_owned));
+                })
+                .map(|message| message.id)
+                .collect::<Vec<_>>();
+            if !matched.is_empty() {
+                messages.insert(thread.id, matched);
+            }
+        }
+
+        let mut report = PurgeReport {
+            messages_deleted: 0,
+            blobs_deleted: 0,
+            resummarized: Vec::new(),
+            failed: Vec::new(),
+            started_at,
+            completed_at: 0,
+            messages,
+            request,
+        };
+        if report.request.dry_run {
+            report.completed_at = now_millis();
+            return Ok(report);
+        }
+
+        for (&thread_id, message_ids) in &report.messages {
+            self.workers.cancel(thread_id);
+
+            // The messages and the summary made from them go together, so
+            // a failed purge can't leave a summary of deleted messages.
+            // Those deleted since they were matched are left out, as the
+            // batch would fail on them.
+            let remaining = match self.db.get_thread_messages(thread_id, None, None).await {
+                Ok(response) => response
+                    .messages
+                    .into_iter()
+                    .map(|message| message.id)
+                    .collect::<HashSet<_>>(),
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let deleted = message_ids
+                .iter()
+                .copied()
+                .filter(|message_id| remaining.contains(message_id))
+                .collect::<Vec<_>>();
+            let mut batch = WriteBatch::new();
+            for &message_id in &deleted {
+                batch.delete_message(thread_id, message_id);
+            }
+            batch.clear_summary(thread_id);
+            match self.db.write_batch(batch).await {
+                Ok(()) => self.search_index_changed(),
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            for &message_id in &deleted {
+                report.messages_deleted += 1;
+                self.analytics.record_message_deleted(thread_id);
+                self.log_operation(
+                    now_millis(),
+                    OperationKind::DeleteMessage {
+                        thread_id,
+                        message_id,
+                    },
+                )
+                .await;
+            }
+            self.log_operation(
+                now_millis(),
+                OperationKind::Purge {
+                    thread_id,
+                    message_ids: deleted,
+                },
+            )
+            .await;
+            match self.resummarize(thread_id).await {
+                Ok(()) => report.resummarized.push(thread_id),
+                Err(e) => {
+                    tracing::error!(%thread_id, "Failed to summarize purged thread: {}", e);
+                    report.failed.push(thread_id);
+                }
+            }
+        }
+
+        report.blobs_deleted = self.delete_unreferenced_blobs(blobs).await?;
+        report.completed_at = now_millis();
+        Ok(report)
+    }
+
+    /// Deletes the blobs among `hashes` no message refers to, returning how
+    /// many were.
+    pub(crate) async fn delete_unreferenced_blobs(
+        &self,
+        mut hashes: HashSet<String>,
+    ) -> Result<usize> {
+        if hashes.is_empty() {
+            return Ok(0);
+        }
+        for thread in self.db.list_threads().await? {
+            let messages = match self.db.get_thread_messages(thread.id, None, None).await {
+                Ok(response) => response.messages,
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for message in &messages {
+                for hash in blob_hashes(&message.content) {
+                    hashes.remove(hash);
+                }
+            }
+        }
+
+        let mut deleted = 0;
+        for hash in hashes {
+            match self.db.delete_blob(hash).await {
+                Ok(()) => deleted += 1,
+                Err(DatabaseError::NotFound) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Summarizes a thread from scratch, one message after the other as they
+    /// were summarized when stored. Budgets aren't checked, a purge must
+    /// not leave a summary behind, and locked summaries are replaced too as
+    /// they may hold purged content.
+    async fn resummarize(&self, thread_id: Uuid) -> Result<()> {
+        let thread = self.db.get_thread(thread_id).await?;
+        let scope = UsageScope::thread(&thread);
+        let language = thread
+            .summary_language
+            .clone()
+            .or_else(|| self.summary_language.clone());
+
+        let mut summary = String::new();
+        let mut usage = Usage::default();
+        let mut watermark: Option<SummaryWatermark> = None;
+        let messages = self.db.get_thread_messages(thread_id, None, None).await?;
+        for message in messages.messages {
+            if !message.memorize
+                || message.status != MessageStatus::Final
+                || self.unsummarized(&message)
+            {
+                continue;
+            }
+            let Some(content) = self.summary_input(thread_id, &message).await else {
+                continue;
+            };
+            // Listed as created, not as they became final.
+            let folded = SummaryWatermark::of(&message);
+            if watermark
+                .as_ref()
+                .is_none_or(|watermark| watermark.final_at <= folded.final_at)
+            {
+                watermark = Some(folded);
+            }
+            let (next, completion) = self
+                .generate_summary(summary, message.role, content, language.clone(), &scope)
+                .await?;
+            summary = next;
+            usage.add(&completion);
+        }
+        if summary.is_empty() {
+            return Ok(());
+        }
+
+        let embedding = self.embed_summary(&thread, &summary, &scope).await?;
+        self.db
+            .update_thread_summary_and_embedding(
+                thread_id,
+                summary,
+                self.summary_provenance(SUMMARY_PROMPT, &usage, watermark),
+                embedding.clone(),
+                self.log_origin(),
+            )
+            .await?;
+        self.embed_segments(&thread, &scope).await?;
+        self.search_index_changed();
+        self.check_duplicates(thread_id, &embedding).await;
+        Ok(())
+    }
+}
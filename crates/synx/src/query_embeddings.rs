@@ -0,0 +1,108 @@
+//! Caching of query embeddings, as interactive clients send the same query
+//! over and over, e.g. while the user types and again on submit.
+//!
+//! Queries are normalized first, see [`normalize_query`], so those differing
+//! only in case or spacing share an embedding, the normalized query being
+//! the one embedded. Embeddings are kept for a while, see
+//! [`SynxBuilder::with_query_embedding_cache`](crate::SynxBuilder::with_query_embedding_cache),
+//! the oldest making room for new ones.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use synx_domain::embedding::Embedding;
+use web_time::Instant;
+
+pub const DEFAULT_QUERY_EMBEDDING_CACHE_CAPACITY: usize = 1024;
+pub const DEFAULT_QUERY_EMBEDDING_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Trims the query, lowercases it, and collapses its runs of whitespace into
+/// single spaces.
+pub fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub(crate) struct QueryEmbeddingCache {
+    capacity: usize,
+    ttl: Duration,
+    /// Embeddings keyed by normalized query.
+    entries: Mutex<HashMap<String, (Instant, Embedding)>>,
+}
+
+impl QueryEmbeddingCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn get(&self, query: &str) -> Option<Embedding> {
+        let mut entries = self.entries.lock().unwrap();
+        let (stored_at, embedding) = entries.get(query)?;
+        if stored_at.elapsed() >= self.ttl {
+            entries.remove(query);
+            return None;
+        }
+
+        Some(embedding.clone())
+    }
+
+    /// Stores the embedding of the normalized `query`, making room by
+    /// dropping expired embeddings, then the oldest one.
+    pub(crate) fn insert(&self, query: String, embedding: Embedding) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&query) {
+            entries.retain(|_, (stored_at, _)| stored_at.elapsed() < self.ttl);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&query) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(query, _)| query.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(query, (Instant::now(), embedding));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_are_trimmed_lowercased_and_collapsed() {
+        assert_eq!(
+            normalize_query("  Weather \t in\n\nPARIS "),
+            "weather in paris"
+        );
+        assert_eq!(normalize_query(" \n "), "");
+    }
+
+    #[test]
+    fn embeddings_expire_and_make_room() {
+        let cache = QueryEmbeddingCache::new(2, DEFAULT_QUERY_EMBEDDING_CACHE_TTL);
+        for query in ["a", "b", "c"] {
+            cache.insert(query.to_string(), Embedding::from(vec![1.0]));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+
+        let cache = QueryEmbeddingCache::new(2, Duration::ZERO);
+        cache.insert("a".to_string(), Embedding::from(vec![1.0]));
+        assert!(cache.get("a").is_none());
+    }
+}
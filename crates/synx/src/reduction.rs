@@ -0,0 +1,199 @@
+//! Truncation of embeddings to fewer dimensions, e.g. 256 of 1024, trading
+//! a little recall for faster searches and smaller storage. Only embedders
+//! trained for it (Matryoshka representation learning), whose first
+//! dimensions carry most of the meaning, keep their recall when truncated.
+//!
+//! With [`SynxBuilder::with_embedding_reduction`](crate::SynxBuilder::with_embedding_reduction),
+//! every embedding made is truncated and scaled back to unit length.
+//! Embeddings stored before keep their dimensions, and are truncated as they
+//! are searched, until [`Synx::reduce_stored_embeddings`] rewrites them.
+//! [`Synx::calibrate_reduction`] measures, over the stored embeddings, how
+//! many search results truncating them would change, before committing to a
+//! dimension.
+
+use anyhow::Result;
+use synx_domain::{embedding::Embedding, thread::Thread};
+
+use crate::{utils::similarity::normalize, Synx};
+
+/// Number of nearest neighbours compared by [`Synx::calibrate_reduction`].
+const CALIBRATION_NEIGHBOURS: usize = 10;
+/// Number of embeddings whose neighbours are compared at most.
+const CALIBRATION_SAMPLE: usize = 100;
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CalibrationRequest {
+    pub dimensions: usize,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct CalibrationReport {
+    pub dimensions: usize,
+    /// The dimensions of the stored embeddings, the largest if they differ.
+    pub stored_dimensions: Option<usize>,
+    /// Number of stored embeddings whose neighbours were compared.
+    pub sampled: usize,
+    /// Share of the nearest neighbours of the sampled embeddings still found
+    /// among their nearest neighbours once truncated, `None` when fewer than
+    /// two embeddings are stored with more dimensions.
+    pub recall: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ReductionReport {
+    pub dimensions: usize,
+    /// Number of stored embeddings which were truncated.
+    pub reduced: usize,
+}
+
+/// Returned when reducing stored embeddings without a dimension to reduce
+/// them to.
+#[derive(Debug, thiserror::Error)]
+#[error("embedding reduction is disabled, see `SynxBuilder::with_embedding_reduction`")]
+pub struct ReductionDisabled;
+
+/// The first `dimensions` of the embedding, scaled to unit length, or `None`
+/// if it has no more dimensions than that.
+fn truncate(embedding: &Embedding, dimensions: usize) -> Option<Embedding> {
+    let mut vector = embedding.to_vec();
+    if vector.len() <= dimensions {
+        return None;
+    }
+
+    vector.truncate(dimensions);
+    Some(Embedding::from(normalize(vector)))
+}
+
+impl Synx {
+    /// The embedding truncated to the configured dimensions, if any.
+    pub(crate) fn reduce_embedding(&self, embedding: Embedding) -> Embedding {
+        match self.embedding_reduction {
+            Some(dimensions) => truncate(&embedding, dimensions).unwrap_or(embedding),
+            None => embedding,
+        }
+    }
+
+    /// Compares the nearest neighbours of up to 100 stored embeddings with
+    /// and without truncating every embedding to `dimensions`, without
+    /// changing them.
+    pub async fn calibrate_reduction(&self, dimensions: usize) -> Result<CalibrationReport> {
+        let (full, reduced): (Vec<Vec<f32>>, Vec<Vec<f32>>) = self
+            .stored_embeddings()
+            .await?
+            .into_iter()
+            .filter_map(|thread| {
+                let embedding = thread.embedding?;
+                let reduced = truncate(&embedding, dimensions.max(1))?;
+                Some((normalize(embedding.to_vec()), reduced.to_vec()))
+            })
+            .unzip();
+
+        let stride = full.len().div_ceil(CALIBRATION_SAMPLE).max(1);
+        let sample = (0..full.len()).step_by(stride).collect::<Vec<_>>();
+        let neighbours = CALIBRATION_NEIGHBOURS.min(full.len().saturating_sub(1));
+        let found = sample
+            .iter()
+            .map(|&index| {
+                let expected = nearest(&full, index, neighbours);
+                nearest(&reduced, index, neighbours)
+                    .iter()
+                    .filter(|neighbour| expected.contains(neighbour))
+                    .count()
+            })
+            .sum::<usize>();
+
+        Ok(CalibrationReport {
+            dimensions,
+            stored_dimensions: full.iter().map(Vec::len).max(),
+            sampled: sample.len(),
+            recall: (neighbours > 0).then(|| found as f32 / (sample.len() * neighbours) as f32),
+        })
+    }
+
+    /// Truncates the stored embeddings having more dimensions than
+    /// configured. Threads are rewritten as read, so a summary stored in the
+    /// meantime may be overwritten; run it while summaries are quiet.
+    pub async fn reduce_stored_embeddings(&self) -> Result<ReductionReport> {
+        let dimensions = self.embedding_reduction.ok_or(ReductionDisabled)?;
+
+        let mut reduced = 0;
+        for thread in self.stored_embeddings().await? {
+            let Some(embedding) = thread
+                .embedding
+                .as_ref()
+                .and_then(|embedding| truncate(embedding, dimensions))
+            else {
+                continue;
+            };
+            self.db
+                .put_thread(Thread {
+                    embedding: Some(embedding),
+                    ..thread
+                })
+                .await?;
+            reduced += 1;
+        }
+
+        if reduced > 0 {
+            self.search_index_changed();
+        }
+        Ok(ReductionReport {
+            dimensions,
+            reduced,
+        })
+    }
+
+    pub(crate) async fn stored_embeddings(&self) -> Result<Vec<Thread>> {
+        let thread_ids = self
+            .db
+            .list_threads()
+            .await?
+            .into_iter()
+            .filter(|thread| thread.summary.is_some())
+            .map(|thread| thread.id)
+            .collect::<Vec<_>>();
+
+        Ok(self.db.get_threads_with_embeddings(&thread_ids).await?)
+    }
+}
+
+/// The `count` vectors closest to the one at `index`, itself excluded, the
+/// vectors being of unit length.
+fn nearest(vectors: &[Vec<f32>], index: usize, count: usize) -> Vec<usize> {
+    let mut others = (0..vectors.len())
+        .filter(|&other| other != index)
+        .map(|other| {
+            let similarity: f32 = vectors[index]
+                .iter()
+                .zip(&vectors[other])
+                .map(|(x, y)| x * y)
+                .sum();
+            (other, similarity)
+        })
+        .collect::<Vec<_>>();
+    others.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    others.truncate(count);
+
+    others.into_iter().map(|(other, _)| other).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embeddings_are_truncated_to_unit_length() {
+        let embedding = Embedding::from(vec![3.0, 4.0, 12.0]);
+
+        assert_eq!(truncate(&embedding, 2).unwrap().to_vec(), vec![0.6, 0.8]);
+        assert!(truncate(&embedding, 3).is_none());
+    }
+
+    #[test]
+    fn nearest_vectors_exclude_themselves() {
+        let vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![0.8, 0.6]];
+
+        assert_eq!(nearest(&vectors, 0, 2), vec![2, 1]);
+        assert_eq!(nearest(&vectors, 1, 1), vec![2]);
+    }
+}
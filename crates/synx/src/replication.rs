@@ -0,0 +1,281 @@
+//! Replication between instances through an op-log.
+//!
+//! An instance built with [`SynxBuilder::with_replica_id`](crate::SynxBuilder::with_replica_id)
+//! or [`SynxBuilder::with_change_log`](crate::SynxBuilder::with_change_log)
+//! appends an [`Operation`] for every change it makes. Another instance
+//! catches up by fetching the operations appended after the last sequence
+//! number it saw and applying them with [`Synx::apply_operations`]. Conflicts are resolved
+//! per entity with last-writer-wins: messages by their last change, thread
+//! metadata by the thread's last change, and summaries by the time they were
+//! generated. Deletions are final: appending one to the op-log keeps a
+//! tombstone of the deleted thread or message, see
+//! [`Db::get_tombstone`](synx_database::Db::get_tombstone), which wins over
+//! every change to it, so replaying a stale change can't bring it back.
+//! Purges, see [`purge`](crate::purge), delete
+//! the same messages on every replica and drop the operations holding their
+//! content from each op-log.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use chrono::Utc;
+use synx_database::{DatabaseError, WriteBatch};
+use synx_domain::{
+    message::Message,
+    sync::{Operation, OperationFilter, OperationKind, SyncCursor},
+    thread::Thread,
+};
+use uuid::Uuid;
+
+use crate::{blobs::blob_hashes, usage::UsageScope, Synx};
+
+/// Outcome of [`Synx::apply_operations`].
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct ApplyReport {
+    pub applied: usize,
+    /// Operations superseded by a later local change, or targeting an entity
+    /// that no longer exists.
+    pub skipped: usize,
+}
+
+impl Synx {
+    pub fn replica_id(&self) -> Option<Uuid> {
+        self.replica_id
+    }
+
+    pub async fn list_operations(&self, filter: OperationFilter) -> Result<Vec<Operation>> {
+        Ok(self.db.list_operations(filter).await?)
+    }
+
+    /// How far the instance synced with the remote, from the start of both
+    /// op-logs if it never did.
+    pub async fn sync_cursor(&self, remote: &str) -> Result<SyncCursor> {
+        Ok(self
+            .db
+            .get_sync_cursor(remote.to_string())
+            .await?
+            .unwrap_or_default())
+    }
+
+    pub async fn save_sync_cursor(&self, remote: &str, cursor: SyncCursor) -> Result<()> {
+        Ok(self.db.put_sync_cursor(remote.to_string(), cursor).await?)
+    }
+
+    /// Applies operations made on other replicas in order. Applied operations
+    /// are logged again, with their original origin, so they propagate to
+    /// replicas syncing with this one.
+    pub async fn apply_operations(&self, operations: Vec<Operation>) -> Result<ApplyReport> {
+        let mut report = ApplyReport::default();
+        for operation in operations {
+            if self.apply_operation(&operation).await? {
+                report.applied += 1;
+                self.append_operation(operation).await;
+            } else {
+                report.skipped += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn apply_operation(&self, operation: &Operation) -> Result<bool> {
+        match &operation.kind {
+            OperationKind::PutThread { thread } => {
+                let thread = match self.db.get_thread(thread.id).await {
+                    Ok(existing) => {
+                        if !operation.supersedes(existing.updated_at.max(existing.created_at)) {
+                            return Ok(false);
+                        }
+                        Thread {
+                            summary: existing.summary,
+                            summary_provenance: existing.summary_provenance,
+                            summarized_at: existing.summarized_at,
+                            pruned_message_count: existing.pruned_message_count,
+                            updated_at: operation.timestamp,
+                            embedding: None,
+                            ..thread.clone()
+                        }
+                    }
+                    // The summary follows in its own operation, along with
+                    // the embedding computed from it.
+                    Err(DatabaseError::NotFound) if self.tombstoned(&[thread.id]).await? => {
+                        return Ok(false)
+                    }
+                    Err(DatabaseError::NotFound) => Thread {
+                        summary: None,
+                        summary_provenance: None,
+                        summarized_at: 0,
+                        pruned_message_count: 0,
+                        embedding: None,
+                        ..thread.clone()
+                    },
+                    Err(e) => return Err(e.into()),
+                };
+                self.db.put_thread(thread).await?;
+                self.search_index_changed();
+            }
+            OperationKind::DeleteThread { thread_id } => {
+                match self.db.delete_thread(*thread_id).await {
+                    Ok(()) => self.thread_deleted(*thread_id),
+                    // Kept as a tombstone once appended, unless it is one.
+                    Err(DatabaseError::NotFound) => {
+                        return Ok(!self.tombstoned(&[*thread_id]).await?)
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            OperationKind::PutMessage { message } => {
+                match self.db.get_message(message.thread_id, message.id).await {
+                    Ok(existing) => {
+                        if !operation.supersedes(existing.updated_at.max(existing.created_at)) {
+                            return Ok(false);
+                        }
+                    }
+                    Err(DatabaseError::NotFound) => {
+                        if self.tombstoned(&[message.id, message.thread_id]).await? {
+                            return Ok(false);
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                match self.db.put_message(message.clone()).await {
+                    Ok(()) => {}
+                    Err(DatabaseError::NotFound) => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            OperationKind::DeleteMessage {
+                thread_id,
+                message_id,
+            } => match self.db.delete_message(*thread_id, *message_id).await {
+                Ok(()) => self.analytics.record_message_deleted(*thread_id),
+                Err(DatabaseError::NotFound) => {
+                    return Ok(!self.tombstoned(&[*message_id]).await?)
+                }
+                Err(e) => return Err(e.into()),
+            },
+            OperationKind::SetSummary {
+                thread_id,
+                summary,
+                provenance,
+            } => {
+                let mut thread = match self.db.get_thread(*thread_id).await {
+                    Ok(thread) => thread,
+                    Err(DatabaseError::NotFound) => return Ok(false),
+                    Err(e) => return Err(e.into()),
+                };
+                if !operation.supersedes(thread.summarized_at) {
+                    return Ok(false);
+                }
+
+                // Embeddings aren't replicated, each instance may use its own
+                // embedder.
+                let scope = UsageScope::thread(&thread);
+                let embedding = self.embed_summary(&thread, summary, &scope).await?;
+                thread.embedding = Some(embedding.clone());
+                thread.summary = Some(summary.clone());
+                thread.summary_provenance = Some(provenance.clone());
+                thread.summarized_at = operation.timestamp;
+                thread.updated_at = thread.updated_at.max(operation.timestamp);
+                self.db.put_thread(thread).await?;
+                self.search_index_changed();
+                self.check_duplicates(*thread_id, &embedding).await;
+            }
+            // Applied even when nothing is left to delete, so the op-log is
+            // purged once the operation is appended.
+            OperationKind::Purge {
+                thread_id,
+                message_ids,
+            } => {
+                self.workers.cancel(*thread_id);
+                let messages = match self.db.get_thread_messages(*thread_id, None, None).await {
+                    Ok(response) => response.messages,
+                    Err(DatabaseError::NotFound) => return Ok(true),
+                    Err(e) => return Err(e.into()),
+                };
+                let purged = messages
+                    .iter()
+                    .filter(|message| message_ids.contains(&message.id))
+                    .collect::<Vec<_>>();
+                let mut batch = WriteBatch::new();
+                let mut blobs = HashSet::new();
+                for message in &purged {
+                    batch.delete_message(*thread_id, message.id);
+                    blobs.extend(blob_hashes(&message.content).map(str::to_owned));
+                }
+                batch.clear_summary(*thread_id);
+                match self.db.write_batch(batch).await {
+                    Ok(()) => self.search_index_changed(),
+                    Err(DatabaseError::NotFound) => return Ok(true),
+                    Err(e) => return Err(e.into()),
+                }
+                for _ in &purged {
+                    self.analytics.record_message_deleted(*thread_id);
+                }
+                self.delete_unreferenced_blobs(blobs).await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Whether any of the threads or messages was deleted by an operation of
+    /// the op-log.
+    async fn tombstoned(&self, ids: &[Uuid]) -> Result<bool> {
+        for &id in ids {
+            if self.db.get_tombstone(id).await?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub(crate) async fn log_thread(&self, thread: &Thread) {
+        self.log_operation(
+            thread.updated_at.max(thread.created_at),
+            OperationKind::PutThread {
+                thread: thread.clone(),
+            },
+        )
+        .await;
+    }
+
+    pub(crate) async fn log_message(&self, message: &Message) {
+        self.log_operation(
+            message.updated_at.max(message.created_at),
+            OperationKind::PutMessage {
+                message: message.clone(),
+            },
+        )
+        .await;
+    }
+
+    /// Records a local change. Failing to do so doesn't fail the change
+    /// itself, the entity is sent again with its next change.
+    pub(crate) async fn log_operation(&self, timestamp: u64, kind: OperationKind) {
+        if let Some(origin) = self.log_origin() {
+            self.append_operation(Operation::new(origin, timestamp, kind))
+                .await;
+        }
+    }
+
+    async fn append_operation(&self, operation: Operation) {
+        if self.log_origin().is_none() {
+            return;
+        }
+
+        if let Err(e) = self.db.append_operation(operation).await {
+            tracing::error!("Failed to append to the op-log: {}", e);
+        }
+    }
+
+    /// The origin of local operations when the op-log is recorded, the nil
+    /// id standing for an instance that doesn't replicate.
+    pub(crate) fn log_origin(&self) -> Option<Uuid> {
+        self.replica_id.or(self.change_log.then(Uuid::nil))
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
@@ -0,0 +1,115 @@
+//! Re-ranking of search results by a completion model, which reads the
+//! summaries rather than comparing embeddings.
+//!
+//! With [`SearchRequest::rerank`](crate::SearchRequest::rerank), the
+//! summaries of the first results, see
+//! [`SynxBuilder::with_rerank_top_k`](crate::SynxBuilder::with_rerank_top_k),
+//! are sent along with the query to the reranker, the summarizer unless
+//! [`SynxBuilder::with_reranker`](crate::SynxBuilder::with_reranker) is
+//! set, which orders them. Results keep their scores, and the order they
+//! had when the reranker fails.
+
+use anyhow::Result;
+use ferrochain::vectorstore::Similarity;
+
+use crate::{provider::Capabilities, usage::UsageScope, utils::completion::RERANK_PROMPT, Synx};
+
+pub const DEFAULT_RERANK_TOP_K: usize = 10;
+
+impl Synx {
+    /// Reorders the first results as the reranker ranks them.
+    pub(crate) async fn rerank(
+        &self,
+        query: &str,
+        mut results: Vec<Similarity>,
+        scope: &UsageScope,
+    ) -> Vec<Similarity> {
+        let top_k = self.rerank_top_k.min(results.len());
+        if top_k < 2 {
+            return results;
+        }
+
+        let order = match self.rerank_order(query, &results[..top_k], scope).await {
+            Ok(order) => order,
+            Err(e) => {
+                tracing::warn!("Failed to rerank search results: {}", e);
+                return results;
+            }
+        };
+        let rest = results.split_off(top_k);
+        let mut top = results.into_iter().map(Some).collect::<Vec<_>>();
+        order
+            .into_iter()
+            .filter_map(|index| top[index].take())
+            .chain(rest)
+            .collect()
+    }
+
+    async fn rerank_order(
+        &self,
+        query: &str,
+        candidates: &[Similarity],
+        scope: &UsageScope,
+    ) -> Result<Vec<usize>> {
+        let summaries = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, similarity)| {
+                format!(
+                    "<summary number=\"{}\">\n{}\n</summary>",
+                    index + 1,
+                    similarity.stored.document.content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (reranker, capabilities) = match &self.reranker {
+            Some(reranker) => (reranker, Capabilities::default()),
+            None => (&self.summarizer, self.summarizer_capabilities),
+        };
+        let output = self
+            .complete(
+                reranker,
+                capabilities,
+                RERANK_PROMPT
+                    .replace("{{SUMMARIES}}", &summaries)
+                    .replace("{{QUERY}}", query),
+                scope,
+            )
+            .await?;
+
+        Ok(rerank_order(&output, candidates.len()))
+    }
+}
+
+/// The candidate indices in the order the completion numbers them, those it
+/// leaves out following in their original order.
+fn rerank_order(output: &str, candidates: usize) -> Vec<usize> {
+    let mut order = Vec::with_capacity(candidates);
+    for number in output
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse::<usize>().ok())
+    {
+        if (1..=candidates).contains(&number) && !order.contains(&(number - 1)) {
+            order.push(number - 1);
+        }
+    }
+    for index in 0..candidates {
+        if !order.contains(&index) {
+            order.push(index);
+        }
+    }
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_and_repeated_numbers_are_ignored() {
+        assert_eq!(rerank_order("3, 1, 3, 7, 0", 4), vec![2, 0, 1, 3]);
+        assert_eq!(rerank_order("none of them", 3), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,496 @@
+//! Recurring background tasks, e.g. retrying failed summaries or digesting
+//! recent threads, run on schedules given to the builder with
+//! [`SynxBuilder::with_schedule`](crate::SynxBuilder::with_schedule).
+//!
+//! Schedules are cron expressions of five fields (minute, hour, day of the
+//! month, month, day of the week) evaluated in UTC, one of `@hourly`,
+//! `@daily`, `@weekly`, and `@monthly`, or a fixed interval such as
+//! `@every 30s`. The engine keeps no timer of its own:
+//! [`Synx::run_due_tasks`] spawns the tasks whose run is due on the
+//! executor, and is meant to be called every second or so by whatever drives
+//! the instance. A run still going when the next one is due makes it skip.
+//! [`Synx::schedules`] reports when each task last ran and runs next.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Days, NaiveDate, TimeDelta, Timelike, Utc};
+use ferrochain::futures::FutureExt;
+
+use crate::{
+    executor::Executor, pruning::PruneRequest, replication::now_millis, DigestRequest, Synx,
+};
+
+/// How far ahead a cron expression is searched for its next run, beyond
+/// which it's deemed to never run, e.g. on the 31st of February.
+const CRON_HORIZON_DAYS: u64 = 5 * 366;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Task {
+    /// See [`Synx::retry_pending_summaries`].
+    RetrySummaries,
+    /// Digests the threads updated since the previous run, see
+    /// [`Synx::digest`], and logs the digest.
+    Digest,
+    /// See [`Synx::scan_duplicates`], failing unless duplicate detection is
+    /// enabled.
+    ScanDuplicates,
+    /// See [`Synx::prune_messages`], failing unless a message retention is
+    /// configured.
+    PruneMessages,
+}
+
+impl FromStr for Task {
+    type Err = UnknownTask;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "retry-summaries" => Ok(Self::RetrySummaries),
+            "digest" => Ok(Self::Digest),
+            "scan-duplicates" => Ok(Self::ScanDuplicates),
+            "prune-messages" => Ok(Self::PruneMessages),
+            _ => Err(UnknownTask(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown task {0:?}, expected retry-summaries, digest, scan-duplicates, or prune-messages")]
+pub struct UnknownTask(pub String);
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid schedule {0:?}, expected five cron fields or `@every <n>s|m|h|d`")]
+pub struct InvalidSchedule(pub String);
+
+/// When a task runs, kept as written to be reported.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    source: String,
+    rule: Rule,
+}
+
+#[derive(Clone, Debug)]
+enum Rule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Runs every `interval`, which must not be zero.
+    pub fn every(interval: Duration) -> Result<Self, InvalidSchedule> {
+        let source = format!("@every {}s", interval.as_secs());
+        if interval.is_zero() {
+            return Err(InvalidSchedule(source));
+        }
+
+        Ok(Self {
+            source,
+            rule: Rule::Every(interval),
+        })
+    }
+
+    /// The first run strictly after `after`, both in milliseconds since the
+    /// epoch, `None` if there is none.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        match &self.rule {
+            Rule::Every(interval) => after.checked_add(interval.as_millis() as u64),
+            Rule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = InvalidSchedule;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSchedule(s.to_string());
+        let source = s.trim();
+        let cron = match source {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => match source.strip_prefix("@every ") {
+                Some(interval) => {
+                    let interval = parse_interval(interval.trim()).ok_or_else(invalid)?;
+                    return Ok(Self {
+                        source: source.to_string(),
+                        ..Self::every(interval).map_err(|_| invalid())?
+                    });
+                }
+                None => source,
+            },
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            rule: Rule::Cron(Cron::parse(cron).ok_or_else(invalid)?),
+        })
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// `30s`, `5m`, `1h`, or `1d`.
+fn parse_interval(interval: &str) -> Option<Duration> {
+    let unit = match interval.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = interval[..interval.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(count.checked_mul(unit)?))
+}
+
+/// The values each field allows, as bit sets.
+#[derive(Clone, Debug)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether both the day of the month and of the week are restricted,
+    /// a day then matching either, as in cron.
+    either_day: bool,
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Option<Self> {
+        let [minutes, hours, days, months, weekdays] =
+            <[&str; 5]>::try_from(expression.split_whitespace().collect::<Vec<_>>()).ok()?;
+
+        // Sunday is either 0 or 7.
+        let mut weekday_set = parse_field(weekdays, 0, 7)?;
+        if weekday_set & (1 << 7) != 0 {
+            weekday_set = (weekday_set | 1) & !(1 << 7);
+        }
+
+        Some(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_set,
+            either_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+        })
+    }
+
+    fn next_after(&self, after: u64) -> Option<u64> {
+        let after = DateTime::from_timestamp_millis(after.try_into().ok()?)?;
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + TimeDelta::minutes(1);
+        let horizon = time.checked_add_days(Days::new(CRON_HORIZON_DAYS))?;
+
+        while time < horizon {
+            time = if !contains(self.months, time.month()) {
+                let (year, month) = match time.month() {
+                    12 => (time.year() + 1, 1),
+                    month => (time.year(), month + 1),
+                };
+                midnight(NaiveDate::from_ymd_opt(year, month, 1)?)
+            } else if !self.matches_day(&time) {
+                midnight(time.date_naive().checked_add_days(Days::new(1))?)
+            } else if !contains(self.hours, time.hour()) {
+                time.with_minute(0)? + TimeDelta::hours(1)
+            } else if !contains(self.minutes, time.minute()) {
+                time + TimeDelta::minutes(1)
+            } else {
+                return Some(time.timestamp_millis() as u64);
+            };
+        }
+
+        None
+    }
+
+    fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+        let day = contains(self.days, time.day());
+        let weekday = contains(self.weekdays, time.weekday().num_days_from_sunday());
+        match self.either_day {
+            true => day || weekday,
+            false => day && weekday,
+        }
+    }
+}
+
+fn contains(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// A comma separated list of `*`, values, and `a-b` ranges, each optionally
+/// followed by a `/step`.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            // `5/15` runs from 5 onwards.
+            None if step > 1 => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+
+        for value in (start..=end).step_by(step) {
+            set |= 1 << value;
+        }
+    }
+
+    Some(set)
+}
+
+/// What [`Synx::schedules`] reports of a task. Times are in milliseconds
+/// since the epoch.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ScheduleStatus {
+    pub task: Task,
+    pub schedule: String,
+    /// `None` when the schedule never runs again.
+    pub next_run_at: Option<u64>,
+    pub last_run_at: Option<u64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    pub running: bool,
+}
+
+pub(crate) struct Scheduler {
+    executor: Arc<dyn Executor>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    task: Task,
+    schedule: Schedule,
+    state: Arc<Mutex<EntryState>>,
+}
+
+struct EntryState {
+    next_run_at: Option<u64>,
+    last_run_at: Option<u64>,
+    last_error: Option<String>,
+    running: bool,
+    /// Where the window of the next digest starts.
+    since: u64,
+}
+
+impl Scheduler {
+    /// Schedules each task from now on.
+    pub(crate) fn new(executor: Arc<dyn Executor>, schedules: Vec<(Task, Schedule)>) -> Self {
+        let now = now_millis();
+        Self {
+            executor,
+            entries: schedules
+                .into_iter()
+                .map(|(task, schedule)| Entry {
+                    task,
+                    state: Arc::new(Mutex::new(EntryState {
+                        next_run_at: schedule.next_after(now),
+                        last_run_at: None,
+                        last_error: None,
+                        running: false,
+                        since: now,
+                    })),
+                    schedule,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Synx {
+    /// Spawns the scheduled tasks due by now, returning how many were.
+    pub fn run_due_tasks(&self) -> usize {
+        let now = now_millis();
+        let mut spawned = 0;
+        for entry in &self.scheduler.entries {
+            let since = {
+                let mut state = entry.state.lock().unwrap();
+                if state
+                    .next_run_at
+                    .is_none_or(|next_run_at| next_run_at > now)
+                {
+                    continue;
+                }
+                state.next_run_at = entry.schedule.next_after(now);
+                if state.running {
+                    tracing::warn!("Skipping {:?}: its previous run is still going", entry.task);
+                    continue;
+                }
+                state.running = true;
+                std::mem::replace(&mut state.since, now)
+            };
+
+            let this = self.clone();
+            let task = entry.task;
+            let state = entry.state.clone();
+            self.scheduler.executor.spawn(
+                async move {
+                    let result = this.run_task(task, since).await;
+                    if let Err(e) = &result {
+                        tracing::error!("Scheduled {:?} failed: {:?}", task, e);
+                    }
+
+                    let mut state = state.lock().unwrap();
+                    state.running = false;
+                    state.last_run_at = Some(now);
+                    state.last_error = result.err().map(|e| e.to_string());
+                }
+                .boxed(),
+            );
+            spawned += 1;
+        }
+
+        spawned
+    }
+
+    /// Every scheduled task, in the order they were scheduled.
+    pub fn schedules(&self) -> Vec<ScheduleStatus> {
+        self.scheduler
+            .entries
+            .iter()
+            .map(|entry| {
+                let state = entry.state.lock().unwrap();
+                ScheduleStatus {
+                    task: entry.task,
+                    schedule: entry.schedule.to_string(),
+                    next_run_at: state.next_run_at,
+                    last_run_at: state.last_run_at,
+                    last_error: state.last_error.clone(),
+                    running: state.running,
+                }
+            })
+            .collect()
+    }
+
+    async fn run_task(&self, task: Task, since: u64) -> Result<()> {
+        match task {
+            Task::RetrySummaries => {
+                let retried = self.retry_pending_summaries().await?;
+                if retried > 0 {
+                    tracing::info!(retried, "Retrying failed summaries");
+                }
+            }
+            Task::Digest => {
+                let digest = self
+                    .digest(DigestRequest {
+                        since,
+                        until: None,
+                        tenant: None,
+                    })
+                    .await?;
+                if !digest.groups.is_empty() {
+                    tracing::info!(since, groups = digest.groups.len(), "{}", digest.digest);
+                }
+            }
+            Task::ScanDuplicates => {
+                let report = self.scan_duplicates(None).await?;
+                tracing::info!(pairs = report.pairs.len(), "Scanned for duplicates");
+            }
+            Task::PruneMessages => {
+                let report = self.prune_messages(PruneRequest::default()).await?;
+                if report.messages_pruned > 0 {
+                    tracing::info!(
+                        messages = report.messages_pruned,
+                        threads = report.messages.len(),
+                        "Pruned messages"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> u64 {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .timestamp_millis() as u64
+    }
+
+    fn next(schedule: &str, after: &str) -> Option<u64> {
+        schedule.parse::<Schedule>().unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn cron_expressions_run_at_the_next_matching_minute() {
+        assert_eq!(
+            next("*/15 * * * *", "2026-10-15T10:07:30Z"),
+            Some(at("2026-10-15T10:15:00Z"))
+        );
+        assert_eq!(
+            next("0 8 * * *", "2026-10-15T08:00:00Z"),
+            Some(at("2026-10-16T08:00:00Z"))
+        );
+        assert_eq!(
+            next("30 9 1 * *", "2026-12-15T00:00:00Z"),
+            Some(at("2027-01-01T09:30:00Z"))
+        );
+        // 2026-10-18 is a Sunday.
+        assert_eq!(
+            next("0 0 * * 7", "2026-10-15T00:00:00Z"),
+            Some(at("2026-10-18T00:00:00Z"))
+        );
+        // Either the 20th or a Sunday.
+        assert_eq!(
+            next("0 0 20 * 0", "2026-10-18T12:00:00Z"),
+            Some(at("2026-10-20T00:00:00Z"))
+        );
+        assert_eq!(next("0 0 31 2 *", "2026-10-15T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn intervals_and_shorthands_are_parsed() {
+        assert_eq!(
+            next("@every 30s", "2026-10-15T10:00:00Z"),
+            Some(at("2026-10-15T10:00:30Z"))
+        );
+        assert_eq!(
+            next("@daily", "2026-10-15T10:00:00Z"),
+            Some(at("2026-10-16T00:00:00Z"))
+        );
+        assert_eq!(
+            "@every 5m".parse::<Schedule>().unwrap().to_string(),
+            "@every 5m"
+        );
+
+        for invalid in [
+            "@every 0s",
+            "@every 5",
+            "* * * *",
+            "60 * * * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+        ] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{}", invalid);
+        }
+    }
+}
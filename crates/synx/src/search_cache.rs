@@ -0,0 +1,277 @@
+//! Caching of search results, so clients issuing the same search over and
+//! over, e.g. dashboards refreshing every few seconds, don't have the query
+//! embedded and the threads scored each time.
+//!
+//! Built with [`SynxBuilder::with_search_cache`](crate::SynxBuilder::with_search_cache),
+//! results are keyed by the whole search request, the normalized query
+//! (see [`normalize_query`]) standing for its embedding as the query
+//! embedder maps one to the other, and by the index generation. The
+//! generation moves on whenever what searches rank changes: a summary along
+//! with its embedding, a deleted thread, or relevance feedback. Entries of
+//! earlier generations are never returned, and entries expire after a while
+//! regardless, in case a change was made by another instance sharing the
+//! database. Titles and tags don't change rankings, so the `metadata` of
+//! cached results may lag behind them until their entry expires.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use ferrochain::{
+    document::{Document, StoredDocument},
+    vectorstore::Similarity,
+};
+use serde_json::Value;
+use uuid::Uuid;
+use web_time::Instant;
+
+use crate::{query_embeddings::normalize_query, SearchRequest, SimilarityMetric};
+
+pub const DEFAULT_SEARCH_CACHE_TTL: Duration = Duration::from_secs(30);
+
+pub(crate) struct SearchCache {
+    capacity: usize,
+    ttl: Duration,
+    generation: AtomicU64,
+    entries: Mutex<HashMap<SearchKey, Entry>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct SearchKey {
+    query: String,
+    thread_ids: Vec<Uuid>,
+    tenant: Option<String>,
+    reader: Option<String>,
+    /// The lambda's bits and the pool size.
+    diversity: Option<(u32, usize)>,
+    expand: bool,
+    rerank: bool,
+    metric: Option<SimilarityMetric>,
+}
+
+impl From<&SearchRequest> for SearchKey {
+    fn from(request: &SearchRequest) -> Self {
+        Self {
+            query: normalize_query(&request.query),
+            thread_ids: request.thread_ids.clone(),
+            tenant: request.tenant.clone(),
+            reader: request.reader.clone(),
+            diversity: request
+                .diversity
+                .map(|diversity| (diversity.lambda.to_bits(), diversity.pool_size)),
+            expand: request.expand,
+            rerank: request.rerank,
+            metric: request.metric,
+        }
+    }
+}
+
+struct Entry {
+    generation: u64,
+    stored_at: Instant,
+    hits: Vec<Hit>,
+}
+
+impl Entry {
+    fn similarities(&self) -> Vec<Similarity> {
+        self.hits
+            .iter()
+            .map(|hit| Similarity {
+                stored: StoredDocument {
+                    id: hit.id.clone(),
+                    document: Document {
+                        content: hit.content.clone(),
+                        metadata: hit.metadata.clone(),
+                    },
+                },
+                score: hit.score,
+            })
+            .collect()
+    }
+}
+
+/// A search result as cached, [`Similarity`] being rebuilt from it.
+struct Hit {
+    id: String,
+    content: String,
+    metadata: HashMap<String, Value>,
+    score: f32,
+}
+
+impl SearchCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            generation: AtomicU64::new(0),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The current generation, to be read before the database so results
+    /// computed while the index changes are stored as already stale.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Makes every cached result stale.
+    pub(crate) fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub(crate) fn get(&self, key: &SearchKey) -> Option<Vec<Similarity>> {
+        let generation = self.generation();
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        // Stale entries are kept until they make room, see `get_stale`.
+        if entry.generation != generation || entry.stored_at.elapsed() >= self.ttl {
+            return None;
+        }
+
+        Some(entry.similarities())
+    }
+
+    /// The results last cached for the search, however out of date, for
+    /// when they can't be computed again. See [`health`](crate::health).
+    pub(crate) fn get_stale(&self, key: &SearchKey) -> Option<Vec<Similarity>> {
+        Some(self.entries.lock().unwrap().get(key)?.similarities())
+    }
+
+    /// Stores the results of a search which started at `generation`,
+    /// making room by dropping stale entries, then the oldest one.
+    pub(crate) fn insert(&self, key: SearchKey, generation: u64, results: &[Similarity]) {
+        if self.capacity == 0 || generation != self.generation() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| {
+                entry.generation == generation && entry.stored_at.elapsed() < self.ttl
+            });
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key,
+            Entry {
+                generation,
+                stored_at: Instant::now(),
+                hits: results
+                    .iter()
+                    .map(|similarity| Hit {
+                        id: similarity.stored.id.clone(),
+                        content: similarity.stored.document.content.clone(),
+                        metadata: similarity.stored.document.metadata.clone(),
+                        score: similarity.score,
+                    })
+                    .collect(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(query: &str) -> SearchKey {
+        SearchKey::from(&SearchRequest {
+            query: query.to_string(),
+            thread_ids: vec![Uuid::nil()],
+            tenant: None,
+            reader: None,
+            diversity: None,
+            expand: false,
+            rerank: false,
+            metric: None,
+            page: Default::default(),
+            shape: Default::default(),
+        })
+    }
+
+    fn result(id: &str, score: f32) -> Similarity {
+        Similarity {
+            stored: StoredDocument {
+                id: id.to_string(),
+                document: Document {
+                    content: format!("summary of {}", id),
+                    metadata: HashMap::new(),
+                },
+            },
+            score,
+        }
+    }
+
+    fn ids(results: Option<Vec<Similarity>>) -> Option<Vec<String>> {
+        results.map(|results| results.into_iter().map(|r| r.stored.id).collect())
+    }
+
+    #[test]
+    fn results_are_dropped_when_the_index_changes() {
+        let cache = SearchCache::new(8, DEFAULT_SEARCH_CACHE_TTL);
+        let generation = cache.generation();
+        cache.insert(
+            key("rust"),
+            generation,
+            &[result("a", 0.9), result("b", 0.5)],
+        );
+
+        assert_eq!(
+            ids(cache.get(&key("rust"))),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(ids(cache.get(&key("go"))), None);
+
+        cache.invalidate();
+        assert_eq!(ids(cache.get(&key("rust"))), None);
+        assert_eq!(
+            ids(cache.get_stale(&key("rust"))),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+    }
+
+    #[test]
+    fn results_of_searches_overtaken_by_a_change_are_not_stored() {
+        let cache = SearchCache::new(8, DEFAULT_SEARCH_CACHE_TTL);
+        let generation = cache.generation();
+        cache.invalidate();
+        cache.insert(key("rust"), generation, &[result("a", 0.9)]);
+
+        assert_eq!(ids(cache.get(&key("rust"))), None);
+    }
+
+    #[test]
+    fn results_expire() {
+        let cache = SearchCache::new(8, Duration::ZERO);
+        cache.insert(key("rust"), cache.generation(), &[result("a", 0.9)]);
+
+        assert_eq!(ids(cache.get(&key("rust"))), None);
+    }
+
+    #[test]
+    fn the_oldest_results_make_room() {
+        let cache = SearchCache::new(2, DEFAULT_SEARCH_CACHE_TTL);
+        for query in ["a", "b", "c"] {
+            cache.insert(key(query), cache.generation(), &[result(query, 1.0)]);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(ids(cache.get(&key("a"))), None);
+        assert_eq!(ids(cache.get(&key("b"))), Some(vec!["b".to_string()]));
+        assert_eq!(ids(cache.get(&key("c"))), Some(vec!["c".to_string()]));
+    }
+}
@@ -0,0 +1,112 @@
+//! Multi-vector retrieval. A summary blends everything said in its thread,
+//! so in long conversations going over several topics each of them weighs
+//! little in its embedding, and searches for one of them miss the thread.
+//!
+//! With [`SynxBuilder::with_segment_embeddings`](crate::SynxBuilder::with_segment_embeddings),
+//! threads are also embedded per segment of consecutive summarized messages,
+//! each stored as a [`ThreadVector`] keyed by the id of the segment's first
+//! message. Searches score a thread by the best of its summary's embedding
+//! and its segments'. A segment is embedded again as each of its messages is
+//! summarized, and segments are embedded anew when a purge resummarizes
+//! their thread. Vectors aren't replicated, replicas search summaries only.
+
+use anyhow::Result;
+use synx_database::WriteBatch;
+use synx_domain::{
+    message::{Message, MessageStatus},
+    thread::{Thread, ThreadVector},
+};
+use uuid::Uuid;
+
+use crate::{usage::UsageScope, Synx};
+
+impl Synx {
+    /// Embeds the segment holding the message, once it was summarized.
+    pub(crate) async fn embed_segment_of(
+        &self,
+        thread: &Thread,
+        message_id: Uuid,
+        scope: &UsageScope,
+    ) -> Result<()> {
+        let Some(size) = self.segment_size else {
+            return Ok(());
+        };
+
+        let messages = self.summarized_messages(thread.id).await?;
+        let Some(segment) = messages
+            .chunks(size)
+            .find(|segment| segment.iter().any(|message| message.id == message_id))
+        else {
+            return Ok(());
+        };
+
+        if let Some(vector) = self.embed_segment(thread.id, segment, scope).await? {
+            let mut batch = WriteBatch::new();
+            batch.put_thread_vector(thread.id, vector);
+            self.db.write_batch(batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Embeds every segment of the thread, replacing its vectors.
+    pub(crate) async fn embed_segments(&self, thread: &Thread, scope: &UsageScope) -> Result<()> {
+        let Some(size) = self.segment_size else {
+            return Ok(());
+        };
+
+        let mut batch = WriteBatch::new();
+        batch.delete_thread_vectors(thread.id);
+        for segment in self.summarized_messages(thread.id).await?.chunks(size) {
+            if let Some(vector) = self.embed_segment(thread.id, segment, scope).await? {
+                batch.put_thread_vector(thread.id, vector);
+            }
+        }
+        self.db.write_batch(batch).await?;
+        Ok(())
+    }
+
+    /// The messages of the thread the summarizer is given, as created.
+    async fn summarized_messages(&self, thread_id: Uuid) -> Result<Vec<Message>> {
+        let messages = self.db.get_thread_messages(thread_id, None, None).await?;
+        Ok(messages
+            .messages
+            .into_iter()
+            .filter(|message| {
+                message.memorize
+                    && message.status == MessageStatus::Final
+                    && !self.unsummarized(message)
+            })
+            .collect())
+    }
+
+    /// Embeds what the summarizer is given of the segment's messages, `None`
+    /// when redaction lets none of them through.
+    async fn embed_segment(
+        &self,
+        thread_id: Uuid,
+        segment: &[Message],
+        scope: &UsageScope,
+    ) -> Result<Option<ThreadVector>> {
+        let Some(first) = segment.first() else {
+            return Ok(None);
+        };
+
+        let mut inputs = Vec::with_capacity(segment.len());
+        for message in segment {
+            if let Some(input) = self.summary_input(thread_id, message).await {
+                inputs.push(input);
+            }
+        }
+        if inputs.is_empty() {
+            return Ok(None);
+        }
+
+        let embedding = self
+            .embed(&self.document_embedder, &inputs.join("\n\n"), scope)
+            .await?;
+        Ok(Some(ThreadVector {
+            id: first.id,
+            embedding,
+        }))
+    }
+}
@@ -0,0 +1,162 @@
+//! Shadow summarization, to evaluate a new prompt or model against
+//! production traffic before switching to it.
+//!
+//! Every message summarized by the live pipeline is also summarized by the
+//! [`ShadowSummarizer`], whose output never reaches the live thread: it is
+//! either only logged, or stored apart from the thread along with its
+//! embedding, to be compared with the live summary.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use ferrochain::completion::Completion;
+use synx_domain::{role::Role, thread::ShadowSummary};
+use uuid::Uuid;
+
+use crate::{
+    provider::Capabilities, replication::now_millis, summary_prompt, usage::UsageScope,
+    utils::completion::SUMMARY_PROMPT, Synx,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShadowMode {
+    /// Log shadow summaries without storing them.
+    Log,
+    /// Store shadow summaries and their embeddings apart from the thread.
+    Store,
+}
+
+pub struct ShadowSummarizer {
+    summarizer: Arc<dyn Completion>,
+    capabilities: Capabilities,
+    prompt: Option<String>,
+    mode: ShadowMode,
+}
+
+impl ShadowSummarizer {
+    pub fn new(summarizer: Arc<dyn Completion>, mode: ShadowMode) -> Self {
+        Self {
+            summarizer,
+            capabilities: Capabilities::default(),
+            prompt: None,
+            mode,
+        }
+    }
+
+    /// Declares what the shadow summarizer's provider supports. See
+    /// [`SynxBuilder::with_summarizer_capabilities`](crate::SynxBuilder::with_summarizer_capabilities).
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Replaces the live summary prompt. The template is filled in with the
+    /// same `{{CURRENT_SUMMARY}}`, `{{ROLE}}`, `{{ROLE_GUIDANCE}}`, and
+    /// `{{NEW_MESSAGE}}` placeholders.
+    pub fn with_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+}
+
+impl Synx {
+    pub async fn get_shadow_summary(&self, thread_id: Uuid) -> Result<Option<ShadowSummary>> {
+        Ok(self.db.get_shadow_summary(thread_id).await?)
+    }
+
+    /// Summarizes a message with the shadow summarizer, if any. Failures are
+    /// logged and never affect the live pipeline.
+    pub(crate) async fn generate_shadow_summary(
+        &self,
+        scope: &UsageScope,
+        thread_id: Uuid,
+        live_summary: Option<String>,
+        role: Role,
+        content: String,
+        language: Option<String>,
+    ) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+
+        if let Err(e) = self
+            .shadow_summarize(
+                shadow,
+                scope,
+                thread_id,
+                live_summary,
+                role,
+                content,
+                language,
+            )
+            .await
+        {
+            tracing::warn!(
+                target: "synx::shadow",
+                %thread_id,
+                "Failed to generate shadow summary: {}",
+                e
+            );
+        }
+    }
+
+    async fn shadow_summarize(
+        &self,
+        shadow: &ShadowSummarizer,
+        scope: &UsageScope,
+        thread_id: Uuid,
+        live_summary: Option<String>,
+        role: Role,
+        content: String,
+        language: Option<String>,
+    ) -> Result<()> {
+        // Stored shadow summaries evolve on their own, starting from the live
+        // summary of threads that predate the shadow pipeline.
+        let current = match shadow.mode {
+            ShadowMode::Store => self
+                .db
+                .get_shadow_summary(thread_id)
+                .await?
+                .map(|stored| stored.summary)
+                .or(live_summary),
+            ShadowMode::Log => live_summary,
+        };
+
+        let summary = self
+            .complete(
+                &shadow.summarizer,
+                shadow.capabilities,
+                summary_prompt(
+                    shadow.prompt.as_deref().unwrap_or(SUMMARY_PROMPT),
+                    &current.unwrap_or_default(),
+                    &role,
+                    &content,
+                    language.as_deref(),
+                ),
+                scope,
+            )
+            .await?;
+
+        tracing::info!(
+            target: "synx::shadow",
+            %thread_id,
+            summary = %summary,
+            "generated shadow summary"
+        );
+
+        if shadow.mode == ShadowMode::Store {
+            let thread = self.db.get_thread(thread_id).await?;
+            let embedding = self.embed_summary(&thread, &summary, scope).await?;
+            self.db
+                .put_shadow_summary(ShadowSummary {
+                    thread_id,
+                    summary,
+                    embedding,
+                    updated_at: now_millis(),
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! What each search result carries. Results hold the whole summary of their
+//! thread in `content` by default, which adds up on large deployments
+//! searching many threads at once: [`ResultShape`] leaves summaries out or
+//! trims them, and adds the thread's details in `metadata` on request.
+//!
+//! Results are shaped once ranked and paged, so reranking and the search
+//! cache see whole summaries however results are shaped.
+
+use ferrochain::vectorstore::Similarity;
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ResultShape {
+    /// Carries the summary in `content`, left empty otherwise.
+    #[serde(default = "ResultShape::default_include_summary")]
+    pub include_summary: bool,
+    /// Trims summaries to this many characters.
+    #[serde(default)]
+    pub summary_max_chars: Option<usize>,
+    /// Carries the thread's title, tags, and update times in `metadata`,
+    /// left empty otherwise.
+    #[serde(default)]
+    pub include_metadata: bool,
+}
+
+impl Default for ResultShape {
+    fn default() -> Self {
+        Self {
+            include_summary: Self::default_include_summary(),
+            summary_max_chars: None,
+            include_metadata: false,
+        }
+    }
+}
+
+impl ResultShape {
+    fn default_include_summary() -> bool {
+        true
+    }
+
+    pub(crate) fn apply(&self, results: &mut [Similarity]) {
+        for result in results {
+            let document = &mut result.stored.document;
+            if !self.include_summary {
+                document.content.clear();
+            } else if let Some((end, _)) = self
+                .summary_max_chars
+                .and_then(|max_chars| document.content.char_indices().nth(max_chars))
+            {
+                document.content.truncate(end);
+            }
+            if !self.include_metadata {
+                document.metadata.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ferrochain::document::{Document, StoredDocument};
+    use serde_json::Value;
+
+    use super::*;
+
+    fn results() -> Vec<Similarity> {
+        vec![Similarity {
+            stored: StoredDocument {
+                id: "a".to_string(),
+                document: Document {
+                    content: "Crème brûlée recipe".to_string(),
+                    metadata: HashMap::from([("title".to_string(), Value::from("Dessert"))]),
+                },
+            },
+            score: 0.5,
+        }]
+    }
+
+    #[test]
+    fn summaries_are_trimmed_by_characters() {
+        let mut trimmed = results();
+        ResultShape {
+            summary_max_chars: Some(4),
+            ..Default::default()
+        }
+        .apply(&mut trimmed);
+        assert_eq!(trimmed[0].stored.document.content, "Crèm");
+        assert!(trimmed[0].stored.document.metadata.is_empty());
+
+        let mut whole = results();
+        ResultShape {
+            summary_max_chars: Some(100),
+            include_metadata: true,
+            ..Default::default()
+        }
+        .apply(&mut whole);
+        assert_eq!(whole[0].stored.document.content, "Crème brûlée recipe");
+        assert_eq!(whole[0].stored.document.metadata["title"], "Dessert");
+    }
+
+    #[test]
+    fn summaries_can_be_left_out() {
+        let mut results = results();
+        ResultShape {
+            include_summary: false,
+            ..Default::default()
+        }
+        .apply(&mut results);
+
+        assert!(results[0].stored.document.content.is_empty());
+    }
+}
@@ -0,0 +1,634 @@
+//! Logging of slow database calls and searches, to find pathological
+//! threads, e.g. with tens of thousands of messages.
+//!
+//! With [`SynxBuilder::with_slow_query_threshold`](crate::SynxBuilder::with_slow_query_threshold),
+//! the database is wrapped in a [`SlowQueryDb`] timing every call, and
+//! searches are timed as a whole. Calls slower than the threshold are logged
+//! under the `synx::slow_query` target with their thread ids and result
+//! sizes, never their content, and counted per operation in the
+//! [`AnalyticsReport`](crate::analytics::AnalyticsReport). Streams are timed
+//! until they are open, not while they are read.
+
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use synx_database::{async_trait, DatabaseError, Db, ExportStream, ThreadStream, WriteBatch};
+use synx_domain::{
+    annotation::{Annotation, SearchFeedback},
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
+    embedding::Embedding,
+    message::{CreateMessage, Message, PatchMessage, ThreadMessagesResponse, UpdateMessage},
+    read::ReadMarker,
+    sync::{Operation, OperationFilter, SyncCursor},
+    thread::{
+        CreateThread, PatchThread, PendingSummary, ShadowSummary, SummaryProvenance,
+        SummaryRedactions, Thread, ThreadFilter, UpdateThread,
+    },
+    usage::{DailyUsage, UsageFilter},
+};
+use uuid::Uuid;
+use web_time::Instant;
+
+use crate::analytics::Analytics;
+
+pub(crate) struct SlowQueryLog {
+    threshold: Duration,
+    analytics: Arc<Analytics>,
+}
+
+impl SlowQueryLog {
+    pub(crate) fn new(threshold: Duration, analytics: Arc<Analytics>) -> Self {
+        Self {
+            threshold,
+            analytics,
+        }
+    }
+
+    /// Logs and counts the operation if it took longer than the threshold,
+    /// describing it with `details` only then.
+    pub(crate) fn record(
+        &self,
+        operation: &'static str,
+        started: Instant,
+        details: impl FnOnce() -> String,
+    ) {
+        let elapsed = started.elapsed();
+        if elapsed < self.threshold {
+            return;
+        }
+
+        self.analytics.record_slow_query(operation);
+        tracing::warn!(
+            target: "synx::slow_query",
+            operation,
+            elapsed_ms = elapsed.as_millis() as u64,
+            "Slow {}: {}",
+            operation,
+            details()
+        );
+    }
+}
+
+/// A [`Db`] timing every call of the one it wraps.
+pub struct SlowQueryDb {
+    db: Arc<dyn Db>,
+    log: Arc<SlowQueryLog>,
+}
+
+impl SlowQueryDb {
+    pub(crate) fn new(db: Arc<dyn Db>, log: Arc<SlowQueryLog>) -> Self {
+        Self { db, log }
+    }
+
+    async fn timed<T>(
+        &self,
+        operation: &'static str,
+        call: impl Future<Output = Result<T, DatabaseError>>,
+        details: impl FnOnce(&Result<T, DatabaseError>) -> String,
+    ) -> Result<T, DatabaseError> {
+        let started = Instant::now();
+        let result = call.await;
+        self.log.record(operation, started, || details(&result));
+        result
+    }
+}
+
+fn thread<T>(thread_id: Uuid) -> impl FnOnce(&Result<T, DatabaseError>) -> String {
+    move |_| format!("thread {}", thread_id)
+}
+
+fn none<T>(_: &Result<T, DatabaseError>) -> String {
+    String::new()
+}
+
+fn count<T>(result: &Result<Vec<T>, DatabaseError>) -> String {
+    match result {
+        Ok(items) => format!("{} results", items.len()),
+        Err(e) => format!("failed: {}", e),
+    }
+}
+
+#[async_trait]
+impl Db for SlowQueryDb {
+    async fn debug_state(&self) -> Result<serde_json::Value, DatabaseError> {
+        self.timed("debug_state", self.db.debug_state(), none).await
+    }
+
+    async fn storage_stats(&self) -> Result<serde_json::Value, DatabaseError> {
+        self.timed("storage_stats", self.db.storage_stats(), none)
+            .await
+    }
+
+    async fn get_threads_with_embeddings(
+        &self,
+        thread_ids: &[Uuid],
+    ) -> Result<Vec<Thread>, DatabaseError> {
+        self.timed(
+            "get_threads_with_embeddings",
+            self.db.get_threads_with_embeddings(thread_ids),
+            |result| format!("{} threads, {}", thread_ids.len(), count(result)),
+        )
+        .await
+    }
+
+    async fn update_thread_summary_and_embedding(
+        &self,
+        thread_id: Uuid,
+        summary: String,
+        provenance: SummaryProvenance,
+        embedding: Embedding,
+        log_origin: Option<Uuid>,
+    ) -> Result<(), DatabaseError> {
+        self.timed(
+            "update_thread_summary_and_embedding",
+            self.db.update_thread_summary_and_embedding(
+                thread_id, summary, provenance, embedding, log_origin,
+            ),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn create_thread(&self, input: CreateThread) -> Result<Thread, DatabaseError> {
+        self.timed("create_thread", self.db.create_thread(input), none)
+            .await
+    }
+
+    async fn delete_thread(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+        self.timed(
+            "delete_thread",
+            self.db.delete_thread(thread_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn delete_threads(
+        &self,
+        filter: ThreadFilter,
+        dry_run: bool,
+    ) -> Result<Vec<Uuid>, DatabaseError> {
+        self.timed(
+            "delete_threads",
+            self.db.delete_threads(filter, dry_run),
+            count,
+        )
+        .await
+    }
+
+    async fn create_message(
+        &self,
+        thread_id: Uuid,
+        input: CreateMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.timed(
+            "create_message",
+            self.db.create_message(thread_id, input),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn update_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        content: UpdateMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.timed(
+            "update_message",
+            self.db.update_message(thread_id, message_id, content),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn patch_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        patch: PatchMessage,
+    ) -> Result<Message, DatabaseError> {
+        self.timed(
+            "patch_message",
+            self.db.patch_message(thread_id, message_id, patch),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn update_thread(
+        &self,
+        thread_id: Uuid,
+        update: UpdateThread,
+    ) -> Result<Thread, DatabaseError> {
+        self.timed(
+            "update_thread",
+            self.db.update_thread(thread_id, update),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn patch_thread(
+        &self,
+        thread_id: Uuid,
+        patch: PatchThread,
+    ) -> Result<Thread, DatabaseError> {
+        self.timed(
+            "patch_thread",
+            self.db.patch_thread(thread_id, patch),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn list_threads(&self) -> Result<Vec<Thread>, DatabaseError> {
+        self.timed("list_threads", self.db.list_threads(), count)
+            .await
+    }
+
+    async fn stream_threads(&self) -> Result<ThreadStream, DatabaseError> {
+        self.timed("stream_threads", self.db.stream_threads(), none)
+            .await
+    }
+
+    async fn get_thread(&self, thread_id: Uuid) -> Result<Thread, DatabaseError> {
+        self.timed(
+            "get_thread",
+            self.db.get_thread(thread_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn get_threads(&self, thread_ids: &[Uuid]) -> Result<Vec<Thread>, DatabaseError> {
+        self.timed("get_threads", self.db.get_threads(thread_ids), |result| {
+            format!("{} threads, {}", thread_ids.len(), count(result))
+        })
+        .await
+    }
+
+    async fn get_thread_messages(
+        &self,
+        thread_id: Uuid,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    ) -> Result<ThreadMessagesResponse, DatabaseError> {
+        self.timed(
+            "get_thread_messages",
+            self.db.get_thread_messages(thread_id, limit, offset),
+            |result| match result {
+                Ok(response) => format!(
+                    "thread {}, {} of {} messages",
+                    thread_id,
+                    response.messages.len(),
+                    response.total
+                ),
+                Err(e) => format!("thread {}, failed: {}", thread_id, e),
+            },
+        )
+        .await
+    }
+
+    async fn delete_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<(), DatabaseError> {
+        self.timed(
+            "delete_message",
+            self.db.delete_message(thread_id, message_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn append_audit_entry(&self, entry: AuditEntry) -> Result<(), DatabaseError> {
+        self.timed(
+            "append_audit_entry",
+            self.db.append_audit_entry(entry),
+            none,
+        )
+        .await
+    }
+
+    async fn list_audit_entries(
+        &self,
+        filter: AuditFilter,
+    ) -> Result<Vec<AuditEntry>, DatabaseError> {
+        self.timed(
+            "list_audit_entries",
+            self.db.list_audit_entries(filter),
+            count,
+        )
+        .await
+    }
+
+    async fn get_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Message, DatabaseError> {
+        self.timed(
+            "get_message",
+            self.db.get_message(thread_id, message_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn get_shadow_summary(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<ShadowSummary>, DatabaseError> {
+        self.timed(
+            "get_shadow_summary",
+            self.db.get_shadow_summary(thread_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn put_shadow_summary(&self, shadow: ShadowSummary) -> Result<(), DatabaseError> {
+        let thread_id = shadow.thread_id;
+        self.timed(
+            "put_shadow_summary",
+            self.db.put_shadow_summary(shadow),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn get_summary_redactions(
+        &self,
+        thread_id: Uuid,
+    ) -> Result<Option<SummaryRedactions>, DatabaseError> {
+        self.timed(
+            "get_summary_redactions",
+            self.db.get_summary_redactions(thread_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn put_summary_redactions(
+        &self,
+        redactions: SummaryRedactions,
+    ) -> Result<(), DatabaseError> {
+        let thread_id = redactions.thread_id;
+        self.timed(
+            "put_summary_redactions",
+            self.db.put_summary_redactions(redactions),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn clear_thread_summary(&self, thread_id: Uuid) -> Result<(), DatabaseError> {
+        self.timed(
+            "clear_thread_summary",
+            self.db.clear_thread_summary(thread_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn write_batch(&self, batch: WriteBatch) -> Result<(), DatabaseError> {
+        let operations = batch.len();
+        self.timed("write_batch", self.db.write_batch(batch), |_| {
+            format!("{} operations", operations)
+        })
+        .await
+    }
+
+    async fn put_thread(&self, thread_to_put: Thread) -> Result<(), DatabaseError> {
+        let thread_id = thread_to_put.id;
+        self.timed(
+            "put_thread",
+            self.db.put_thread(thread_to_put),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn put_message(&self, message: Message) -> Result<(), DatabaseError> {
+        let thread_id = message.thread_id;
+        self.timed(
+            "put_message",
+            self.db.put_message(message),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn append_operation(&self, operation: Operation) -> Result<u64, DatabaseError> {
+        self.timed(
+            "append_operation",
+            self.db.append_operation(operation),
+            none,
+        )
+        .await
+    }
+
+    async fn list_operations(
+        &self,
+        filter: OperationFilter,
+    ) -> Result<Vec<Operation>, DatabaseError> {
+        self.timed("list_operations", self.db.list_operations(filter), count)
+            .await
+    }
+
+    async fn get_tombstone(&self, id: Uuid) -> Result<Option<u64>, DatabaseError> {
+        self.timed("get_tombstone", self.db.get_tombstone(id), none)
+            .await
+    }
+
+    async fn get_sync_cursor(&self, remote: String) -> Result<Option<SyncCursor>, DatabaseError> {
+        self.timed("get_sync_cursor", self.db.get_sync_cursor(remote), none)
+            .await
+    }
+
+    async fn put_sync_cursor(
+        &self,
+        remote: String,
+        cursor: SyncCursor,
+    ) -> Result<(), DatabaseError> {
+        self.timed(
+            "put_sync_cursor",
+            self.db.put_sync_cursor(remote, cursor),
+            none,
+        )
+        .await
+    }
+
+    async fn add_usage(&self, usage: DailyUsage) -> Result<(), DatabaseError> {
+        self.timed("add_usage", self.db.add_usage(usage), none)
+            .await
+    }
+
+    async fn list_usage(&self, filter: UsageFilter) -> Result<Vec<DailyUsage>, DatabaseError> {
+        self.timed("list_usage", self.db.list_usage(filter), count)
+            .await
+    }
+
+    async fn mark_read(
+        &self,
+        thread_id: Uuid,
+        reader: String,
+        message_id: Uuid,
+    ) -> Result<ReadMarker, DatabaseError> {
+        self.timed(
+            "mark_read",
+            self.db.mark_read(thread_id, reader, message_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn unread_counts(&self, reader: String) -> Result<HashMap<Uuid, usize>, DatabaseError> {
+        self.timed(
+            "unread_counts",
+            self.db.unread_counts(reader),
+            |result| match result {
+                Ok(counts) => format!("{} threads", counts.len()),
+                Err(e) => format!("failed: {}", e),
+            },
+        )
+        .await
+    }
+
+    async fn add_annotation(&self, annotation: Annotation) -> Result<(), DatabaseError> {
+        let thread_id = annotation.thread_id;
+        self.timed(
+            "add_annotation",
+            self.db.add_annotation(annotation),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn delete_annotation(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        annotation_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.timed(
+            "delete_annotation",
+            self.db
+                .delete_annotation(thread_id, message_id, annotation_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn list_annotations(&self, thread_id: Uuid) -> Result<Vec<Annotation>, DatabaseError> {
+        self.timed(
+            "list_annotations",
+            self.db.list_annotations(thread_id),
+            |result| format!("thread {}, {}", thread_id, count(result)),
+        )
+        .await
+    }
+
+    async fn put_search_feedback(&self, feedback: SearchFeedback) -> Result<(), DatabaseError> {
+        let thread_id = feedback.thread_id;
+        self.timed(
+            "put_search_feedback",
+            self.db.put_search_feedback(feedback),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn list_search_feedback(
+        &self,
+        reader: String,
+    ) -> Result<Vec<SearchFeedback>, DatabaseError> {
+        self.timed(
+            "list_search_feedback",
+            self.db.list_search_feedback(reader),
+            count,
+        )
+        .await
+    }
+
+    async fn put_pending_summary(&self, pending: PendingSummary) -> Result<(), DatabaseError> {
+        let thread_id = pending.thread_id;
+        self.timed(
+            "put_pending_summary",
+            self.db.put_pending_summary(pending),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn list_pending_summaries(&self) -> Result<Vec<PendingSummary>, DatabaseError> {
+        self.timed(
+            "list_pending_summaries",
+            self.db.list_pending_summaries(),
+            count,
+        )
+        .await
+    }
+
+    async fn delete_pending_summary(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        self.timed(
+            "delete_pending_summary",
+            self.db.delete_pending_summary(thread_id, message_id),
+            thread(thread_id),
+        )
+        .await
+    }
+
+    async fn put_blob(&self, blob: Blob, data: Vec<u8>) -> Result<Blob, DatabaseError> {
+        let size = data.len();
+        self.timed("put_blob", self.db.put_blob(blob, data), |_| {
+            format!("{} bytes", size)
+        })
+        .await
+    }
+
+    async fn delete_blob(&self, hash: String) -> Result<(), DatabaseError> {
+        self.timed("delete_blob", self.db.delete_blob(hash), none)
+            .await
+    }
+
+    async fn get_blob(&self, hash: String) -> Result<(Blob, Vec<u8>), DatabaseError> {
+        self.timed("get_blob", self.db.get_blob(hash), |result| match result {
+            Ok((_, data)) => format!("{} bytes", data.len()),
+            Err(e) => format!("failed: {}", e),
+        })
+        .await
+    }
+
+    async fn export(&self) -> Result<ExportStream, DatabaseError> {
+        self.timed("export", self.db.export(), none).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operations_slower_than_the_threshold_are_counted() {
+        let analytics = Arc::new(Analytics::default());
+        let started = Instant::now();
+        SlowQueryLog::new(Duration::ZERO, analytics.clone()).record(
+            "get_thread",
+            started,
+            String::new,
+        );
+        SlowQueryLog::new(Duration::from_secs(60), analytics.clone()).record(
+            "list_threads",
+            started,
+            || unreachable!("only slow operations are described"),
+        );
+
+        let slow_queries = analytics.report().slow_queries;
+        assert_eq!(slow_queries.get("get_thread"), Some(&1));
+        assert_eq!(slow_queries.get("list_threads"), None);
+    }
+}
@@ -1,43 +1,336 @@
+pub mod analytics;
+pub mod blobs;
+pub mod captioning;
+pub mod clustering;
+pub mod consistency;
+pub mod duplicates;
+pub mod embedding_input;
 pub mod executor;
+pub mod expansion;
+pub mod feedback;
+pub mod health;
+mod in_flight;
+pub mod inspect;
+pub mod normalization;
+pub mod outbox;
+pub mod pagination;
+pub mod provider;
+pub mod pruning;
+pub mod purge;
+pub mod query_embeddings;
+pub mod redaction;
+pub mod reduction;
+pub mod replication;
+pub mod rerank;
+pub mod scheduler;
+pub mod search_cache;
+pub mod segments;
+pub mod shadow;
+pub mod shaping;
+pub mod slow_queries;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod usage;
 mod utils;
+pub mod warm_up;
+pub mod worker_pool;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use anyhow::Result;
 use ferrochain::{
     completion::Completion,
     document::{Document, StoredDocument},
     embedding::Embedder,
-    futures::FutureExt,
+    futures::{future::join, stream::BoxStream, FutureExt, StreamExt},
     vectorstore::Similarity,
 };
+use regex::RegexSet;
 use serde_json::Value;
-use synx_database::Db;
+use synx_database::{DatabaseError, Db, ExportStream};
 use synx_domain::{
-    message::{CreateMessage, Message, ThreadMessagesResponse, UpdateMessage},
-    thread::{Thread, UpdateThread},
+    annotation::{AnnotatedMessage, Annotation, CreateAnnotation, MessageNode},
+    audit::{AuditEntry, AuditFilter},
+    content::Content,
+    embedding::Embedding,
+    message::{
+        AppendMessage, CreateMessage, Message, MessageStatus, PatchMessage, ThreadMessagesResponse,
+        UpdateMessage,
+    },
+    read::{MarkRead, ReadMarker},
+    role::Role,
+    sync::OperationKind,
+    thread::{
+        CreateThread, PatchThread, PendingSummary, RedactSummary, SetSummary, SummaryProvenance,
+        SummaryRedactions, SummaryWatermark, Thread, ThreadFilter, UpdateThread,
+    },
+    usage::Usage,
+};
+use utils::{
+    completion::{
+        prompt_version, role_guidance, Prompt, DIGEST_PROMPT, SUMMARY_LANGUAGE_PROMPT,
+        SUMMARY_PROMPT, TRANSLATE_PROMPT,
+    },
+    similarity::{
+        cosine_similarity, dot_product, euclidean_similarity, mmr_order, reciprocal_rank_fusion,
+    },
+    task::yield_now,
 };
-use utils::{completion::SUMMARY_PROMPT, similarity::cosine_similarity};
 use uuid::Uuid;
+use web_time::Instant;
 
 use crate::{
+    analytics::{Analytics, AnalyticsReport},
+    consistency::Sequences,
+    duplicates::DuplicateDetector,
+    embedding_input::DEFAULT_EMBEDDING_TEMPLATE,
     executor::Executor,
-    utils::{content::extract_text_content, embedding::generate_embeddings},
+    feedback::DEFAULT_FEEDBACK_WEIGHT,
+    health::{EmbedderUnavailable, Health},
+    in_flight::InFlightSummaries,
+    outbox::failed_summary,
+    pagination::{Paged, SearchPage},
+    provider::Capabilities,
+    query_embeddings::{
+        normalize_query, QueryEmbeddingCache, DEFAULT_QUERY_EMBEDDING_CACHE_CAPACITY,
+        DEFAULT_QUERY_EMBEDDING_CACHE_TTL,
+    },
+    redaction::{PiiBlocked, RedactionStage, Redactor},
+    replication::now_millis,
+    rerank::DEFAULT_RERANK_TOP_K,
+    scheduler::{Schedule, Scheduler, Task},
+    search_cache::{SearchCache, SearchKey},
+    shadow::ShadowSummarizer,
+    shaping::ResultShape,
+    slow_queries::{SlowQueryDb, SlowQueryLog},
+    usage::{Budgets, UsageScope},
+    worker_pool::{Lane, LaneLimits, QueueFull, Slot, WorkerPool},
 };
 
+const DEFAULT_SUMMARY_LIMITS: LaneLimits = LaneLimits {
+    concurrency: 4,
+    capacity: 1024,
+};
+const DEFAULT_BULK_LIMITS: LaneLimits = LaneLimits {
+    concurrency: 1,
+    capacity: 10_000,
+};
+/// Threads scored by a search between two yields to the executor, where
+/// an abandoned search stops.
+const SCORING_BATCH_SIZE: usize = 1024;
+
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct SearchRequest {
     pub query: String,
     pub thread_ids: Vec<Uuid>,
+    #[serde(skip)]
+    pub tenant: Option<String>,
+    /// Re-ranks results with the relevance feedback of this reader. See
+    /// [`feedback`].
+    #[serde(skip)]
+    pub reader: Option<String>,
+    #[serde(default)]
+    pub diversity: Option<Diversity>,
+    /// Also searches with paraphrases of the query, at the cost of a
+    /// completion, scores then being fused ranks. See [`expansion`].
+    #[serde(default)]
+    pub expand: bool,
+    /// Has the completion model reorder the first results. See [`rerank`].
+    #[serde(default)]
+    pub rerank: bool,
+    /// Compares the query with threads this way instead of the instance's
+    /// metric.
+    #[serde(default)]
+    pub metric: Option<SimilarityMetric>,
+    /// The page of results returned. See [`pagination`].
+    #[serde(flatten, default)]
+    pub page: SearchPage,
+    /// What each result carries. See [`shaping`].
+    #[serde(flatten, default)]
+    pub shape: ResultShape,
+}
+
+/// What [`Synx::search_threads`] found.
+pub struct SearchResults {
+    pub results: Vec<Similarity>,
+    /// Number of results in every page.
+    pub total: usize,
+    /// Resumes the search after these results, unless they were the last.
+    pub next_cursor: Option<String>,
+    /// The results were cached, and may be out of date, as the query
+    /// couldn't be embedded. See [`health`].
+    pub degraded: bool,
+}
+
+impl SearchResults {
+    fn page(page: &SearchPage, results: Vec<Similarity>, degraded: bool) -> Result<Self> {
+        let Paged {
+            results,
+            total,
+            next_cursor,
+        } = page.apply(results)?;
+        Ok(Self {
+            results,
+            total,
+            next_cursor,
+            degraded,
+        })
+    }
+}
+
+/// Re-ranks the most relevant threads with Maximal Marginal Relevance, so
+/// the top results aren't near duplicates of one another. Only the
+/// `pool_size` most relevant threads are re-ranked and returned, with their
+/// scores unchanged. Uses the stored embeddings, without provider calls.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct Diversity {
+    /// Trade-off between relevance (1) and diversity (0).
+    #[serde(default = "Diversity::default_lambda")]
+    pub lambda: f32,
+    #[serde(default = "Diversity::default_pool_size")]
+    pub pool_size: usize,
+}
+
+impl Diversity {
+    fn default_lambda() -> f32 {
+        0.5
+    }
+
+    fn default_pool_size() -> usize {
+        20
+    }
+}
+
+/// How searches compare the query's embedding with the threads', as
+/// embedding models are trained for one or the other. Diversity and
+/// duplicates compare embeddings by cosine similarity regardless.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarityMetric {
+    #[default]
+    Cosine,
+    /// For models whose embeddings' magnitudes carry relevance.
+    DotProduct,
+    /// `1 / (1 + distance)`, in `(0, 1]`.
+    Euclidean,
+}
+
+impl SimilarityMetric {
+    pub fn score(self, a: &Embedding, b: &Embedding) -> f32 {
+        match self {
+            Self::Cosine => cosine_similarity(a, b),
+            Self::DotProduct => dot_product(a, b),
+            Self::Euclidean => euclidean_similarity(a, b),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct DigestRequest {
+    /// Start of the window, in milliseconds since the epoch.
+    pub since: u64,
+    /// End of the window, in milliseconds since the epoch, now by default.
+    #[serde(default)]
+    pub until: Option<u64>,
+    #[serde(skip)]
+    pub tenant: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Digest {
+    pub since: u64,
+    pub until: Option<u64>,
+    /// Threads per group, each thread being grouped under every one of its
+    /// tags, or its tenant when untagged.
+    pub groups: BTreeMap<String, Vec<Uuid>>,
+    pub digest: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BulkDeleteRequest {
+    #[serde(flatten)]
+    pub filter: ThreadFilter,
+    /// Reports the matching threads without deleting them.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct BulkDeleteReport {
+    pub count: usize,
+    pub thread_ids: Vec<Uuid>,
+    pub dry_run: bool,
+}
+
+/// A thread as listed for a reader, along with how many of its messages the
+/// reader hasn't read.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ThreadListing {
+    #[serde(flatten)]
+    pub thread: Thread,
+    pub unread_count: usize,
+}
+
+/// A thread along with its latest messages, all a client needs to open the
+/// conversation.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ThreadSnapshot {
+    #[serde(flatten)]
+    pub thread: Thread,
+    /// The latest messages, oldest first.
+    pub messages: Vec<AnnotatedMessage>,
+    /// Number of messages in the thread, those left out included.
+    pub total_messages: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TranslateSummaryRequest {
+    pub language: String,
 }
 
 #[derive(Clone)]
 pub struct Synx {
     db: Arc<dyn Db>,
     summarizer: Arc<dyn Completion>,
+    summarizer_capabilities: Capabilities,
+    summarizer_model: Option<String>,
     document_embedder: Arc<dyn Embedder>,
     query_embedder: Arc<dyn Embedder>,
-    executor: Arc<dyn Executor>,
+    workers: WorkerPool,
+    summary_language: Option<String>,
+    unsummarized_roles: Vec<Role>,
+    unsummarized_patterns: Arc<RegexSet>,
+    redactor: Option<Arc<Redactor>>,
+    analytics: Arc<Analytics>,
+    replica_id: Option<Uuid>,
+    change_log: bool,
+    shadow: Option<Arc<ShadowSummarizer>>,
+    budgets: Arc<Budgets>,
+    feedback_weight: f32,
+    similarity_metric: SimilarityMetric,
+    duplicates: Option<Arc<DuplicateDetector>>,
+    captioner: Option<Arc<dyn Completion>>,
+    embedding_dimensions: Arc<OnceLock<usize>>,
+    search_cache: Option<Arc<SearchCache>>,
+    query_embeddings: Arc<QueryEmbeddingCache>,
+    reranker: Option<Arc<dyn Completion>>,
+    rerank_top_k: usize,
+    health: Arc<Health>,
+    scheduler: Arc<Scheduler>,
+    embedding_reduction: Option<usize>,
+    normalize_embeddings: bool,
+    embedding_template: String,
+    segment_size: Option<usize>,
+    sequences: Arc<Sequences>,
+    in_flight: Arc<InFlightSummaries>,
+    slow_queries: Option<Arc<SlowQueryLog>>,
+    message_retention: Option<Duration>,
 }
 
 impl Synx {
@@ -45,26 +338,152 @@ impl Synx {
         SynxBuilder {
             db: None,
             summarizer: None,
+            summarizer_capabilities: Capabilities::default(),
+            summarizer_model: None,
             document_embedder: None,
             query_embedder: None,
             executor: None,
+            summary_limits: DEFAULT_SUMMARY_LIMITS,
+            bulk_limits: DEFAULT_BULK_LIMITS,
+            summary_language: None,
+            unsummarized_roles: vec![Role::System],
+            unsummarized_patterns: Vec::new(),
+            redactor: None,
+            replica_id: None,
+            change_log: false,
+            shadow: None,
+            monthly_token_budget: None,
+            tenant_token_budgets: HashMap::new(),
+            feedback_weight: DEFAULT_FEEDBACK_WEIGHT,
+            similarity_metric: SimilarityMetric::default(),
+            duplicate_threshold: None,
+            captioner: None,
+            search_cache: None,
+            query_embedding_cache: (
+                DEFAULT_QUERY_EMBEDDING_CACHE_CAPACITY,
+                DEFAULT_QUERY_EMBEDDING_CACHE_TTL,
+            ),
+            reranker: None,
+            rerank_top_k: DEFAULT_RERANK_TOP_K,
+            schedules: Vec::new(),
+            embedding_reduction: None,
+            normalize_embeddings: false,
+            embedding_template: DEFAULT_EMBEDDING_TEMPLATE.to_string(),
+            segment_size: None,
+            slow_query_threshold: None,
+            message_retention: None,
         }
     }
 
-    pub async fn create_thread(&self) -> Result<Thread> {
-        Ok(self.db.create_thread().await?)
+    pub async fn create_thread(&self, input: CreateThread) -> Result<Thread> {
+        let thread = self.db.create_thread(input).await?;
+        self.log_thread(&thread).await;
+        Ok(thread)
     }
 
     pub async fn list_threads(&self) -> Result<Vec<Thread>> {
         Ok(self.db.list_threads().await?)
     }
 
+    /// Lists threads like [`Synx::list_threads`], with their unread count
+    /// for `reader`.
+    pub async fn list_threads_for(&self, reader: &str) -> Result<Vec<ThreadListing>> {
+        let threads = self.db.list_threads().await?;
+        let unread_counts = self.db.unread_counts(reader.to_string()).await?;
+        Ok(threads
+            .into_iter()
+            .map(|thread| ThreadListing {
+                unread_count: unread_counts.get(&thread.id).copied().unwrap_or_default(),
+                thread,
+            })
+            .collect())
+    }
+
+    /// Lists threads like [`Synx::list_threads_for`], streamed from the
+    /// database as they are consumed. See [`Db::stream_threads`].
+    pub async fn stream_threads_for(
+        &self,
+        reader: &str,
+    ) -> Result<BoxStream<'static, Result<ThreadListing>>> {
+        let threads = self.db.stream_threads().await?;
+        let unread_counts = self.db.unread_counts(reader.to_string()).await?;
+        Ok(threads
+            .map(move |thread| {
+                let thread = thread?;
+                Ok(ThreadListing {
+                    unread_count: unread_counts.get(&thread.id).copied().unwrap_or_default(),
+                    thread,
+                })
+            })
+            .boxed())
+    }
+
+    /// Marks every message of the thread up to the given one as read by
+    /// `reader`.
+    pub async fn mark_read(
+        &self,
+        thread_id: Uuid,
+        reader: &str,
+        input: MarkRead,
+    ) -> Result<ReadMarker> {
+        Ok(self
+            .db
+            .mark_read(thread_id, reader.to_string(), input.last_read_message_id)
+            .await?)
+    }
+
     pub async fn get_thread(&self, thread_id: Uuid) -> Result<Thread> {
         Ok(self.db.get_thread(thread_id).await?)
     }
 
+    /// The threads found among `thread_ids`, in the order given.
+    pub async fn get_threads(&self, thread_ids: &[Uuid]) -> Result<Vec<Thread>> {
+        Ok(self.db.get_threads(thread_ids).await?)
+    }
+
+    /// The thread with its last `messages` messages, annotated.
+    pub async fn thread_snapshot(
+        &self,
+        thread_id: Uuid,
+        messages: usize,
+    ) -> Result<ThreadSnapshot> {
+        let thread = self.db.get_thread(thread_id).await?;
+        let total = self
+            .db
+            .get_thread_messages(thread_id, Some(0), None)
+            .await?
+            .total;
+        let latest = self
+            .db
+            .get_thread_messages(
+                thread_id,
+                Some(messages),
+                Some(total.saturating_sub(messages)),
+            )
+            .await?;
+
+        Ok(ThreadSnapshot {
+            thread,
+            messages: self.with_annotations(thread_id, latest.messages).await?,
+            total_messages: latest.total,
+        })
+    }
+
+    /// Number of background jobs reserved, queued, or running in `lane`.
+    pub fn pending_jobs(&self, lane: Lane) -> usize {
+        self.workers.pending(lane)
+    }
+
     pub async fn update_thread(&self, thread_id: Uuid, update: UpdateThread) -> Result<Thread> {
-        Ok(self.db.update_thread(thread_id, update).await?)
+        let thread = self.db.update_thread(thread_id, update).await?;
+        self.log_thread(&thread).await;
+        Ok(thread)
+    }
+
+    pub async fn patch_thread(&self, thread_id: Uuid, patch: PatchThread) -> Result<Thread> {
+        let thread = self.db.patch_thread(thread_id, patch).await?;
+        self.log_thread(&thread).await;
+        Ok(thread)
     }
 
     pub async fn get_messages(
@@ -79,20 +498,196 @@ impl Synx {
             .await?)
     }
 
+    /// The messages replying to the message, oldest first.
+    pub async fn get_replies(&self, thread_id: Uuid, message_id: Uuid) -> Result<Vec<Message>> {
+        self.db.get_message(thread_id, message_id).await?;
+        Ok(self
+            .db
+            .get_thread_messages(thread_id, None, None)
+            .await?
+            .messages
+            .into_iter()
+            .filter(|message| message.parent_message_id == Some(message_id))
+            .collect())
+    }
+
+    /// Every message of the thread, annotated and nested under the message
+    /// it replies to.
+    pub async fn get_message_tree(&self, thread_id: Uuid) -> Result<Vec<MessageNode>> {
+        let messages = self
+            .db
+            .get_thread_messages(thread_id, None, None)
+            .await?
+            .messages;
+        Ok(MessageNode::tree(
+            self.with_annotations(thread_id, messages).await?,
+        ))
+    }
+
     pub async fn create_message(&self, thread_id: Uuid, input: CreateMessage) -> Result<Message> {
-        let message = self.db.create_message(thread_id, input).await?;
+        // Push back before storing anything when summaries can't keep up.
+        let slot = match input.status {
+            MessageStatus::Final => {
+                self.reserve_summary(&input.role, input.memorize, Lane::Interactive)?
+            }
+            // Summarized once complete, see `append_message` and
+            // `finalize_message`.
+            MessageStatus::Streaming | MessageStatus::Draft => None,
+        };
 
-        self.process_new_message(thread_id, message.clone());
+        let message = self.store_message(thread_id, input).await?;
+
+        if let Some(slot) = slot {
+            self.process_new_message(slot, thread_id, message.clone(), None);
+        }
 
         Ok(message)
     }
 
-    fn process_new_message(&self, thread_id: Uuid, message: Message) {
-        self.executor.spawn({
+    /// Imports messages in order, summarizing them on the bulk lane so large
+    /// imports don't delay summaries of live conversations. Messages stored
+    /// before a failure are kept.
+    pub async fn create_messages(
+        &self,
+        thread_id: Uuid,
+        inputs: Vec<CreateMessage>,
+    ) -> Result<Vec<Message>> {
+        let slots = inputs
+            .iter()
+            .map(|input| match input.status {
+                MessageStatus::Final => {
+                    self.reserve_summary(&input.role, input.memorize, Lane::Bulk)
+                }
+                MessageStatus::Streaming | MessageStatus::Draft => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut messages = Vec::with_capacity(inputs.len());
+        for (slot, input) in slots.into_iter().zip(inputs) {
+            let message = self.store_message(thread_id, input).await?;
+            if let Some(slot) = slot {
+                self.process_new_message(slot, thread_id, message.clone(), None);
+            }
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    /// Messages whose role is excluded from summaries (e.g. system prompts
+    /// or tool logs), or which opted out of being memorized, are stored
+    /// without taking room in the queue.
+    fn reserve_summary(
+        &self,
+        role: &Role,
+        memorize: bool,
+        lane: Lane,
+    ) -> Result<Option<Slot>, QueueFull> {
+        if !memorize || self.unsummarized_roles.contains(role) {
+            return Ok(None);
+        }
+
+        self.workers.reserve(lane).map(Some)
+    }
+
+    /// Whether the message's role or text is excluded from summaries.
+    pub(crate) fn unsummarized(&self, message: &Message) -> bool {
+        self.unsummarized_roles.contains(&message.role)
+            || (!self.unsummarized_patterns.is_empty()
+                && self
+                    .unsummarized_patterns
+                    .is_match(&message.content.to_string()))
+    }
+
+    async fn store_message(&self, thread_id: Uuid, input: CreateMessage) -> Result<Message> {
+        let input = match self.redactor_for(RedactionStage::Storage) {
+            Some(redactor) => CreateMessage {
+                content: redactor
+                    .redact_content(input.content, thread_id, RedactionStage::Storage)
+                    .await?,
+                ..input
+            },
+            None => input,
+        };
+        let input = CreateMessage {
+            content: self.store_inline_images(thread_id, input.content).await?,
+            ..input
+        };
+
+        let message = self.db.create_message(thread_id, input).await?;
+        self.analytics.record_message(thread_id, message.created_at);
+        self.log_message(&message).await;
+
+        Ok(message)
+    }
+
+    fn redactor_for(&self, stage: RedactionStage) -> Option<&Redactor> {
+        self.redactor
+            .as_deref()
+            .filter(|redactor| redactor.applies_to(stage))
+    }
+
+    /// What the summarizer is given of a message, `None` when there's nothing
+    /// to summarize or redaction refuses to let it through.
+    pub(crate) async fn summary_input(&self, thread_id: Uuid, message: &Message) -> Option<String> {
+        let content = self.summary_content(thread_id, &message.content).await?;
+        let Some(redactor) = self.redactor_for(RedactionStage::Summarization) else {
+            return Some(content);
+        };
+
+        match redactor
+            .redact_text(&content, thread_id, RedactionStage::Summarization)
+            .await
+        {
+            Ok(redacted) => Some(redacted),
+            Err(e) => {
+                tracing::warn!("Skipping summary of message {}: {}", message.id, e);
+                None
+            }
+        }
+    }
+
+    /// Summarizes the message in the background, `retried` being its entry
+    /// in the queue of failed summaries when it's retried. See [`outbox`].
+    pub(crate) fn process_new_message(
+        &self,
+        slot: Slot,
+        thread_id: Uuid,
+        message: Message,
+        retried: Option<PendingSummary>,
+    ) {
+        // No slot should have been reserved, dropping it frees its room.
+        if !message.memorize {
+            return;
+        }
+        if self.unsummarized(&message) {
+            tracing::debug!(
+                "Skipping summary of message {}: excluded from summaries",
+                message.id
+            );
+            return;
+        }
+        let message_id = message.id;
+        let Some(mut in_flight) = self.in_flight.submit(message) else {
+            tracing::debug!(
+                "Collapsing summary of message {} into the one queued",
+                message_id
+            );
+            return;
+        };
+
+        slot.submit(thread_id, {
             let this = self.clone();
+            let stored_at = Instant::now();
+            let ticket = self.sequences.issue();
 
             async move {
-                if let Some(completion_content) = extract_text_content(&message.content) {
+                // Visible once the job ends, however it does.
+                let _ticket = ticket;
+                let Some(message) = in_flight.start() else {
+                    return;
+                };
+                if let Some(completion_content) = this.summary_input(thread_id, &message).await {
                     let thread = match this.db.get_thread(thread_id).await {
                         Ok(response) => response,
                         Err(e) => {
@@ -101,37 +696,153 @@ impl Synx {
                         }
                     };
 
-                    let summary = match this
-                        .generate_summary(
-                            thread.summary.unwrap_or_default(),
-                            message.role,
-                            completion_content,
-                        )
-                        .await
+                    if thread.summary_locked {
+                        tracing::debug!(
+                            "Skipping summary of message {}: summary of thread {} is locked",
+                            message.id,
+                            thread_id
+                        );
+                        return;
+                    }
+
+                    // Retried or resubmitted after the summary moved past it.
+                    let summarized = thread
+                        .summary_provenance
+                        .as_ref()
+                        .and_then(|provenance| provenance.watermark.clone());
+                    if summarized
+                        .as_ref()
+                        .is_some_and(|summarized| summarized.covers(&message))
                     {
-                        Ok(s) => s,
+                        tracing::info!(
+                            "Skipping summary of message {}: already in the summary of thread {}",
+                            message.id,
+                            thread_id
+                        );
+                        return;
+                    }
+
+                    match this.over_budget(thread.tenant.as_deref()).await {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            tracing::info!(
+                                "Skipping summary of message {}: tenant over its monthly budget",
+                                message.id
+                            );
+                            return;
+                        }
+                        Err(e) => tracing::error!("Failed to check usage budget: {}", e),
+                    }
+
+                    // Only retries probe a summarizer deemed down.
+                    if retried.is_none() && !this.summarizer_healthy() {
+                        tracing::info!(
+                            "Queueing summary of message {}: the summarizer is unavailable",
+                            message.id
+                        );
+                        this.queue_summary(failed_summary(thread_id, message.id, None))
+                            .await;
+                        return;
+                    }
+
+                    let current_summary = match this.reviewed_summary(&thread).await {
+                        Ok(summary) => summary,
                         Err(e) => {
-                            tracing::error!("Failed to generate summary: {}", e);
+                            tracing::error!("Failed to fetch summary redactions: {}", e);
                             return;
                         }
                     };
+                    let scope = UsageScope::thread(&thread);
+                    let language = thread
+                        .summary_language
+                        .clone()
+                        .or_else(|| this.summary_language.clone());
+
+                    let this = &this;
+                    let scope = &scope;
+                    let shadow = this.generate_shadow_summary(
+                        scope,
+                        thread_id,
+                        current_summary.clone(),
+                        message.role.clone(),
+                        completion_content.clone(),
+                        language.clone(),
+                    );
+                    let live = async move {
+                        let (summary, usage) = match this
+                            .generate_summary(
+                                current_summary.unwrap_or_default(),
+                                message.role.clone(),
+                                completion_content,
+                                language,
+                                scope,
+                            )
+                            .await
+                        {
+                            Ok(s) => s,
+                            Err(e) => {
+                                tracing::error!("Failed to generate summary: {}", e);
+                                this.queue_summary(failed_summary(thread_id, message.id, retried))
+                                    .await;
+                                return;
+                            }
+                        };
 
-                    let embedding =
-                        match generate_embeddings(&this.document_embedder, &summary).await {
+                        let embedding = match this.embed_summary(&thread, &summary, scope).await {
                             Ok(e) => e,
                             Err(e) => {
                                 tracing::error!("Failed to create embedding: {}", e);
+                                this.queue_summary(failed_summary(thread_id, message.id, retried))
+                                    .await;
                                 return;
                             }
                         };
 
-                    if let Err(e) = this
-                        .db
-                        .update_thread_summary_and_embedding(thread_id, summary, embedding)
-                        .await
-                    {
-                        tracing::error!("Failed to update thread summary and embedding: {}", e);
-                    }
+                        match this
+                            .db
+                            .update_thread_summary_and_embedding(
+                                thread_id,
+                                summary,
+                                this.summary_provenance(
+                                    SUMMARY_PROMPT,
+                                    &usage,
+                                    Some(SummaryWatermark::folding(
+                                        summarized.as_ref(),
+                                        &message,
+                                        this.pending_messages(thread_id).await,
+                                    )),
+                                ),
+                                embedding.clone(),
+                                this.log_origin(),
+                            )
+                            .await
+                        {
+                            Ok(()) => {
+                                if let Err(e) =
+                                    this.embed_segment_of(&thread, message.id, scope).await
+                                {
+                                    tracing::error!("Failed to embed segment: {}", e);
+                                }
+                                this.search_index_changed();
+                                this.analytics.record_summary(stored_at.elapsed());
+                                this.check_duplicates(thread_id, &embedding).await;
+                            }
+                            Err(DatabaseError::NotFound) => {
+                                tracing::debug!(
+                                    "Thread {} was deleted while being summarized",
+                                    thread_id
+                                )
+                            }
+                            Err(e) => {
+                                tracing::error!(
+                                    "Failed to update thread summary and embedding: {}",
+                                    e
+                                )
+                            }
+                        }
+                    };
+
+                    join(live, shadow).await;
                 }
             }
             .boxed()
@@ -141,41 +852,206 @@ impl Synx {
     async fn generate_summary(
         &self,
         summary: String,
-        role: String,
+        role: Role,
         content: String,
-    ) -> Result<String> {
-        use ferrochain::{
-            completion::StreamEvent,
-            futures::StreamExt,
-            message::{Content, Message},
-        };
+        language: Option<String>,
+        scope: &UsageScope,
+    ) -> Result<(String, Usage)> {
+        self.complete_with_usage(
+            &self.summarizer,
+            self.summarizer_capabilities,
+            summary_prompt(
+                SUMMARY_PROMPT,
+                &summary,
+                &role,
+                &content,
+                language.as_deref(),
+            ),
+            scope,
+        )
+        .await
+    }
+
+    /// The provenance of a summary generated from `template` by the
+    /// summarizer.
+    fn summary_provenance(
+        &self,
+        template: &str,
+        usage: &Usage,
+        watermark: Option<SummaryWatermark>,
+    ) -> SummaryProvenance {
+        SummaryProvenance {
+            model: self.summarizer_model.clone(),
+            prompt_version: Some(prompt_version(template)),
+            input_tokens: usage.completion_input_tokens,
+            output_tokens: usage.completion_output_tokens,
+            manual: false,
+            watermark,
+        }
+    }
+
+    /// Replaces the summary with one written by hand. Pending summaries of
+    /// the thread are dropped, as they would overwrite it, but a summary
+    /// being generated still may unless the summary is locked.
+    pub async fn set_summary(&self, thread_id: Uuid, input: SetSummary) -> Result<Thread> {
+        if input.summary.trim().is_empty() {
+            return Err(
+                DatabaseError::InvalidInput("summary must not be empty".to_string()).into(),
+            );
+        }
+        let thread = self.db.get_thread(thread_id).await?;
+
+        // Locking first keeps new messages from summarizing over it.
+        if let Some(locked) = input
+            .locked
+            .filter(|&locked| locked != thread.summary_locked)
+        {
+            let thread = self
+                .db
+                .patch_thread(
+                    thread_id,
+                    PatchThread {
+                        summary_locked: Some(locked),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            self.log_thread(&thread).await;
+        }
+        self.workers.cancel(thread_id);
 
-        let mut stream = self
-            .summarizer
-            .complete(vec![Message {
-                content: vec![SUMMARY_PROMPT
-                    .replace("{{CURRENT_SUMMARY}}", &summary)
-                    .replace("{{ROLE}}", &role)
-                    .replace("{{NEW_MESSAGE}}", &content)
-                    .into()],
-                ..Default::default()
-            }])
+        let scope = UsageScope::thread(&thread);
+        let embedding = self.embed_summary(&thread, &input.summary, &scope).await?;
+        self.db
+            .update_thread_summary_and_embedding(
+                thread_id,
+                input.summary,
+                SummaryProvenance {
+                    manual: true,
+                    ..Default::default()
+                },
+                embedding.clone(),
+                self.log_origin(),
+            )
             .await?;
+        self.search_index_changed();
+        self.check_duplicates(thread_id, &embedding).await;
 
-        let mut summary = String::new();
-        while let Some(event) = stream.next().await {
-            match event? {
-                StreamEvent::Start { content, .. } | StreamEvent::Delta { content, .. } => {
-                    match content {
-                        Content::Text { text } => summary.push_str(&text),
-                        Content::Image { .. } => continue,
-                    }
-                }
-                _ => continue,
-            }
+        Ok(self.db.get_thread(thread_id).await?)
+    }
+
+    /// Marks spans of the thread's current summary as redacted, replacing
+    /// those marked before. The summary is kept as generated, the next one
+    /// being generated from the redacted summary.
+    pub async fn redact_summary(
+        &self,
+        thread_id: Uuid,
+        input: RedactSummary,
+    ) -> Result<SummaryRedactions> {
+        let thread = self.db.get_thread(thread_id).await?;
+        let length = thread.summary.as_deref().map_or(0, |s| s.chars().count());
+        if input
+            .spans
+            .iter()
+            .any(|span| span.start >= span.end || span.end > length)
+        {
+            return Err(DatabaseError::InvalidInput(format!(
+                "spans must be non-empty ranges within the {} characters of the summary",
+                length
+            ))
+            .into());
         }
 
-        Ok(summary)
+        let redactions = SummaryRedactions {
+            thread_id,
+            spans: input.spans,
+            summarized_at: thread.summarized_at,
+            updated_at: now_millis(),
+        };
+        self.db.put_summary_redactions(redactions.clone()).await?;
+        Ok(redactions)
+    }
+
+    /// The summary of the thread with the spans marked as redacted replaced,
+    /// as given back to the summarizer.
+    pub(crate) async fn reviewed_summary(&self, thread: &Thread) -> Result<Option<String>> {
+        let Some(summary) = &thread.summary else {
+            return Ok(None);
+        };
+
+        Ok(Some(
+            match self.db.get_summary_redactions(thread.id).await? {
+                Some(redactions) if redactions.applies_to(thread) => redactions.redact(summary),
+                _ => summary.clone(),
+            },
+        ))
+    }
+
+    pub async fn translate_summary(
+        &self,
+        thread_id: Uuid,
+        request: TranslateSummaryRequest,
+    ) -> Result<Thread> {
+        let thread = self.db.get_thread(thread_id).await?;
+
+        // Translated before the language is stored, so a failure leaves the
+        // thread as it was.
+        let translation = match self.reviewed_summary(&thread).await? {
+            Some(summary) => {
+                let scope = UsageScope::thread(&thread);
+                let (translated, usage) = self
+                    .complete_with_usage(
+                        &self.summarizer,
+                        self.summarizer_capabilities,
+                        TRANSLATE_PROMPT
+                            .replace("{{SUMMARY}}", &summary)
+                            .replace("{{LANGUAGE}}", &request.language),
+                        &scope,
+                    )
+                    .await?;
+                let embedding = self.embed_summary(&thread, &translated, &scope).await?;
+                Some((translated, usage, embedding))
+            }
+            None => None,
+        };
+
+        let thread = self
+            .db
+            .update_thread(
+                thread_id,
+                UpdateThread {
+                    title: thread.title,
+                    summary_language: Some(Some(request.language)),
+                    tags: None,
+                },
+            )
+            .await?;
+        self.log_thread(&thread).await;
+
+        let Some((translated, usage, embedding)) = translation else {
+            return Ok(thread);
+        };
+
+        self.db
+            .update_thread_summary_and_embedding(
+                thread_id,
+                translated,
+                self.summary_provenance(
+                    TRANSLATE_PROMPT,
+                    &usage,
+                    thread
+                        .summary_provenance
+                        .as_ref()
+                        .and_then(|provenance| provenance.watermark.clone()),
+                ),
+                embedding.clone(),
+                self.log_origin(),
+            )
+            .await?;
+        self.search_index_changed();
+        self.check_duplicates(thread_id, &embedding).await;
+
+        Ok(self.db.get_thread(thread_id).await?)
     }
 
     pub async fn update_message(
@@ -184,64 +1060,637 @@ impl Synx {
         message_id: Uuid,
         content: UpdateMessage,
     ) -> Result<Message> {
-        Ok(self
+        let content = match self.redactor_for(RedactionStage::Storage) {
+            Some(redactor) => UpdateMessage {
+                content: redactor
+                    .redact_content(content.content, thread_id, RedactionStage::Storage)
+                    .await?,
+            },
+            None => content,
+        };
+        let content = UpdateMessage {
+            content: self.store_inline_images(thread_id, content.content).await?,
+        };
+
+        let message = self
             .db
             .update_message(thread_id, message_id, content)
+            .await?;
+        self.log_message(&message).await;
+        Ok(message)
+    }
+
+    /// Appends content parts and merges metadata. Appended parts go through
+    /// the storage redaction and the blob store like new messages do.
+    pub async fn patch_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        patch: PatchMessage,
+    ) -> Result<Message> {
+        let append = match self.redactor_for(RedactionStage::Storage) {
+            Some(redactor) => {
+                redactor
+                    .redact_content(Content(patch.append), thread_id, RedactionStage::Storage)
+                    .await?
+            }
+            None => Content(patch.append),
+        };
+        let patch = PatchMessage {
+            append: self.store_inline_images(thread_id, append).await?.0,
+            ..patch
+        };
+
+        let message = self.db.patch_message(thread_id, message_id, patch).await?;
+        self.log_message(&message).await;
+        Ok(message)
+    }
+
+    /// Appends a chunk to a streaming message, summarizing it once `done`.
+    /// Chunks go through the storage redaction one by one, then the whole
+    /// message once `done`, catching matches split across chunks. A message
+    /// blocked then is deleted, as if it had never been stored.
+    pub async fn append_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        append: AppendMessage,
+    ) -> Result<Message> {
+        let message = self.db.get_message(thread_id, message_id).await?;
+        if message.status != MessageStatus::Streaming {
+            return Err(MessageComplete(message_id).into());
+        }
+        let slot = if append.done {
+            self.reserve_summary(&message.role, message.memorize, Lane::Interactive)?
+        } else {
+            None
+        };
+
+        let redactor = self.redactor_for(RedactionStage::Storage);
+        let text = match redactor {
+            Some(redactor) => {
+                redactor
+                    .redact_text(&append.text, thread_id, RedactionStage::Storage)
+                    .await?
+            }
+            None => append.text,
+        };
+        let patched = self
+            .db
+            .patch_message(
+                thread_id,
+                message_id,
+                PatchMessage {
+                    append_text: Some(text),
+                    status: append.done.then_some(MessageStatus::Final),
+                    // Finalized since it was read otherwise.
+                    expected_status: Some(MessageStatus::Streaming),
+                    ..Default::default()
+                },
+            )
+            .await;
+        let message = match patched {
+            Err(DatabaseError::Conflict(_)) => return Err(MessageComplete(message_id).into()),
+            patched => patched?,
+        };
+        let message = match redactor.filter(|_| append.done) {
+            Some(redactor) => self.redact_streamed(redactor, message).await?,
+            None => message,
+        };
+        self.log_message(&message).await;
+
+        if let Some(slot) = slot {
+            self.process_new_message(slot, thread_id, message.clone(), None);
+        }
+        Ok(message)
+    }
+
+    /// Redacts the whole content of a message done streaming.
+    async fn redact_streamed(&self, redactor: &Redactor, message: Message) -> Result<Message> {
+        let content = match redactor
+            .redact_content(
+                message.content.clone(),
+                message.thread_id,
+                RedactionStage::Storage,
+            )
+            .await
+        {
+            Ok(content) => content,
+            Err(e) if e.is::<PiiBlocked>() => {
+                self.delete_message(message.thread_id, message.id).await?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        if content.to_string() == message.content.to_string() {
+            return Ok(message);
+        }
+
+        Ok(self
+            .db
+            .update_message(message.thread_id, message.id, UpdateMessage { content })
             .await?)
     }
 
+    /// Marks a draft or streaming message final, which gets it summarized.
+    pub async fn finalize_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<Message> {
+        let message = self.db.get_message(thread_id, message_id).await?;
+        if message.status == MessageStatus::Final {
+            return Err(MessageComplete(message_id).into());
+        }
+        let slot = self.reserve_summary(&message.role, message.memorize, Lane::Interactive)?;
+
+        let message = self
+            .db
+            .patch_message(
+                thread_id,
+                message_id,
+                PatchMessage {
+                    status: Some(MessageStatus::Final),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        self.log_message(&message).await;
+
+        if let Some(slot) = slot {
+            self.process_new_message(slot, thread_id, message.clone(), None);
+        }
+        Ok(message)
+    }
+
     pub async fn delete_message(&self, thread_id: Uuid, message_id: Uuid) -> Result<()> {
-        Ok(self.db.delete_message(thread_id, message_id).await?)
+        self.db.delete_message(thread_id, message_id).await?;
+        self.analytics.record_message_deleted(thread_id);
+        self.log_operation(
+            now_millis(),
+            OperationKind::DeleteMessage {
+                thread_id,
+                message_id,
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    pub async fn annotate_message(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        author: &str,
+        input: CreateAnnotation,
+    ) -> Result<Annotation> {
+        let annotation = input.into_annotation(thread_id, message_id, author.to_string());
+        self.db.add_annotation(annotation.clone()).await?;
+        // Thumbs up and down are relevance feedback.
+        self.search_index_changed();
+        Ok(annotation)
+    }
+
+    pub async fn delete_annotation(
+        &self,
+        thread_id: Uuid,
+        message_id: Uuid,
+        annotation_id: Uuid,
+    ) -> Result<()> {
+        self.db
+            .delete_annotation(thread_id, message_id, annotation_id)
+            .await?;
+        self.search_index_changed();
+        Ok(())
+    }
+
+    /// Attaches to each of the thread's messages its annotations.
+    pub async fn with_annotations(
+        &self,
+        thread_id: Uuid,
+        messages: Vec<Message>,
+    ) -> Result<Vec<AnnotatedMessage>> {
+        let mut annotations = HashMap::<Uuid, Vec<Annotation>>::new();
+        for annotation in self.db.list_annotations(thread_id).await? {
+            annotations
+                .entry(annotation.message_id)
+                .or_default()
+                .push(annotation);
+        }
+
+        Ok(messages
+            .into_iter()
+            .map(|message| AnnotatedMessage {
+                annotations: annotations.remove(&message.id).unwrap_or_default(),
+                message,
+            })
+            .collect())
+    }
+
+    /// Drops what is kept in memory about a deleted thread, aborting its
+    /// pending summaries along the way.
+    pub(crate) fn thread_deleted(&self, thread_id: Uuid) {
+        self.workers.cancel(thread_id);
+        self.analytics.record_thread_deleted(thread_id);
+        if let Some(duplicates) = &self.duplicates {
+            duplicates.forget(thread_id);
+        }
+        self.search_index_changed();
+    }
+
+    /// Drops cached search results, after a change to summaries, embeddings,
+    /// or relevance feedback.
+    pub(crate) fn search_index_changed(&self) {
+        if let Some(cache) = &self.search_cache {
+            cache.invalidate();
+        }
     }
 
+    /// Deletes the thread and aborts its pending summaries, whose late
+    /// writes the database rejects.
     pub async fn delete_thread(&self, thread_id: Uuid) -> Result<()> {
-        Ok(self.db.delete_thread(thread_id).await?)
+        self.db.delete_thread(thread_id).await?;
+        self.thread_deleted(thread_id);
+        self.log_operation(now_millis(), OperationKind::DeleteThread { thread_id })
+            .await;
+        Ok(())
+    }
+
+    /// Deletes the matching threads at once, see [`Synx::delete_thread`].
+    pub async fn delete_threads(&self, request: BulkDeleteRequest) -> Result<BulkDeleteReport> {
+        let thread_ids = self
+            .db
+            .delete_threads(request.filter, request.dry_run)
+            .await?;
+
+        if !request.dry_run {
+            let deleted_at = now_millis();
+            for &thread_id in &thread_ids {
+                self.thread_deleted(thread_id);
+                self.log_operation(deleted_at, OperationKind::DeleteThread { thread_id })
+                    .await;
+            }
+        }
+
+        Ok(BulkDeleteReport {
+            count: thread_ids.len(),
+            thread_ids,
+            dry_run: request.dry_run,
+        })
+    }
+
+    pub fn analytics(&self) -> AnalyticsReport {
+        self.analytics.report()
+    }
+
+    pub async fn record_audit_entry(&self, entry: AuditEntry) -> Result<()> {
+        Ok(self.db.append_audit_entry(entry).await?)
+    }
+
+    pub async fn list_audit_entries(&self, filter: AuditFilter) -> Result<Vec<AuditEntry>> {
+        Ok(self.db.list_audit_entries(filter).await?)
     }
 
     pub async fn debug_state(&self) -> Result<Value> {
         Ok(self.db.debug_state().await?)
     }
 
-    pub async fn search_threads(&self, search_request: SearchRequest) -> Result<Vec<Similarity>> {
+    pub async fn storage_stats(&self) -> Result<Value> {
+        Ok(self.db.storage_stats().await?)
+    }
+
+    /// Every thread and message as of the call. See [`Db::export`].
+    pub async fn export(&self) -> Result<ExportStream> {
+        Ok(self.db.export().await?)
+    }
+
+    pub async fn search_threads(&self, search_request: SearchRequest) -> Result<SearchResults> {
+        let started = Instant::now();
+        let thread_count = search_request.thread_ids.len();
+        let shape = search_request.shape;
+        let results = self.score_threads(search_request).await.map(|mut search| {
+            shape.apply(&mut search.results);
+            search
+        });
+        if let Some(log) = &self.slow_queries {
+            log.record("search", started, || match &results {
+                Ok(results) => format!("{} threads, {} results", thread_count, results.total),
+                Err(e) => format!("{} threads, failed: {}", thread_count, e),
+            });
+        }
+        results
+    }
+
+    async fn score_threads(&self, search_request: SearchRequest) -> Result<SearchResults> {
+        let cached = self.search_cache.as_ref().map(|cache| {
+            let key = SearchKey::from(&search_request);
+            (cache, cache.generation(), key)
+        });
+        if let Some((cache, _, key)) = &cached {
+            if let Some(results) = cache.get(key) {
+                return SearchResults::page(&search_request.page, results, false);
+            }
+        }
+
         let threads = self
             .db
             .get_threads_with_embeddings(&search_request.thread_ids)
             .await?;
 
-        let query_embedding =
-            generate_embeddings(&self.query_embedder, &search_request.query).await?;
+        let query_embeddings = match self
+            .embed_queries(
+                &search_request.query,
+                search_request.expand,
+                &UsageScope::tenant(search_request.tenant.clone()),
+            )
+            .await
+        {
+            Ok(query_embeddings) => query_embeddings,
+            Err(e) => {
+                let stale = cached
+                    .as_ref()
+                    .and_then(|(cache, _, key)| cache.get_stale(key));
+                return match stale {
+                    Some(results) => {
+                        tracing::warn!("Serving cached search results: {}", e);
+                        SearchResults::page(&search_request.page, results, true)
+                    }
+                    None => Err(EmbedderUnavailable(e.to_string()).into()),
+                };
+            }
+        };
+
+        let threads = threads
+            .into_iter()
+            .filter(|thread| {
+                search_request.tenant.is_none() || thread.tenant == search_request.tenant
+            })
+            .collect::<Vec<_>>();
+
+        let signals = match search_request
+            .reader
+            .as_deref()
+            .filter(|_| self.feedback_weight != 0.0)
+        {
+            Some(reader) => {
+                let thread_ids = threads.iter().map(|thread| thread.id).collect::<Vec<_>>();
+                self.feedback_signals(reader, &thread_ids).await?
+            }
+            None => HashMap::new(),
+        };
 
-        let mut similarities: Vec<Similarity> = threads
+        let threads = threads
             .into_iter()
-            .filter_map(|thread| {
-                thread.embedding.map(|embedding| {
-                    let score = cosine_similarity(&query_embedding, &embedding);
-                    Similarity {
-                        stored: StoredDocument {
-                            id: thread.id.to_string(),
-                            document: Document {
-                                content: thread.summary.unwrap_or_default(),
-                                metadata: HashMap::new(),
-                            },
+            .filter_map(|mut thread| {
+                // Embeddings stored before a reduction was configured are
+                // truncated as the query was.
+                let embedding = self.reduce_embedding(thread.embedding.clone()?);
+                let vectors = std::mem::take(&mut thread.vectors)
+                    .into_iter()
+                    .map(|vector| self.reduce_embedding(vector.embedding))
+                    .collect::<Vec<_>>();
+                Some((thread, embedding, vectors))
+            })
+            .collect::<Vec<_>>();
+        let metric = match search_request.metric.unwrap_or(self.similarity_metric) {
+            // The cosine similarity of unit vectors is their dot product.
+            SimilarityMetric::Cosine if self.normalize_embeddings => SimilarityMetric::DotProduct,
+            metric => metric,
+        };
+        let mut rankings = vec![Vec::with_capacity(threads.len()); query_embeddings.len()];
+        for (index, batch) in threads.chunks(SCORING_BATCH_SIZE).enumerate() {
+            if index > 0 {
+                yield_now().await;
+            }
+            for (ranking, query_embedding) in rankings.iter_mut().zip(&query_embeddings) {
+                ranking.extend(batch.iter().map(|(thread, embedding, vectors)| {
+                    // Threads are as close as the closest of their vectors.
+                    let score = vectors
+                        .iter()
+                        .map(|vector| metric.score(query_embedding, vector))
+                        .fold(metric.score(query_embedding, embedding), f32::max);
+                    let signal = signals.get(&thread.id).copied().unwrap_or_default();
+                    score + self.feedback_weight * signal
+                }));
+            }
+        }
+        let scores = match rankings.len() {
+            1 => rankings.remove(0),
+            _ => reciprocal_rank_fusion(&rankings),
+        };
+
+        let mut similarities: Vec<(Similarity, Embedding)> = threads
+            .into_iter()
+            .zip(scores)
+            .map(|((thread, embedding, _), score)| {
+                let metadata = HashMap::from([
+                    ("title".to_string(), Value::from(thread.title)),
+                    ("tags".to_string(), Value::from(thread.tags)),
+                    ("updated_at".to_string(), Value::from(thread.updated_at)),
+                    (
+                        "summarized_at".to_string(),
+                        Value::from(thread.summarized_at),
+                    ),
+                ]);
+                let similarity = Similarity {
+                    stored: StoredDocument {
+                        id: thread.id.to_string(),
+                        document: Document {
+                            content: thread.summary.unwrap_or_default(),
+                            metadata,
                         },
-                        score,
-                    }
-                })
+                    },
+                    score,
+                };
+                (similarity, embedding)
             })
             .collect();
 
-        similarities.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        // Ties are broken by id so pages of the same search don't overlap.
+        similarities.sort_by(|(a, _), (b, _)| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.stored.id.cmp(&b.stored.id))
+        });
+
+        if let Some(diversity) = search_request.diversity {
+            similarities.truncate(diversity.pool_size);
+            let candidates = similarities
+                .iter()
+                .map(|(similarity, embedding)| (similarity.score, embedding))
+                .collect::<Vec<_>>();
+            let order = mmr_order(&candidates, diversity.lambda);
+            let mut pool = similarities.into_iter().map(Some).collect::<Vec<_>>();
+            similarities = order
+                .into_iter()
+                .filter_map(|index| pool[index].take())
+                .collect();
+        }
+
+        let mut results = similarities
+            .into_iter()
+            .map(|(similarity, _)| similarity)
+            .collect::<Vec<_>>();
+        if search_request.rerank {
+            results = self
+                .rerank(
+                    &search_request.query,
+                    results,
+                    &UsageScope::tenant(search_request.tenant.clone()),
+                )
+                .await;
+        }
+        if let Some((cache, generation, key)) = cached {
+            cache.insert(key, generation, &results);
+        }
+        SearchResults::page(&search_request.page, results, false)
+    }
+
+    /// Embeds the normalized query, unless its embedding is cached.
+    async fn embed_query(&self, query: &str, scope: &UsageScope) -> Result<Embedding> {
+        let query = normalize_query(query);
+        if let Some(embedding) = self.query_embeddings.get(&query) {
+            return Ok(embedding);
+        }
+
+        let embedding = self.embed(&self.query_embedder, &query, scope).await?;
+        self.query_embeddings.insert(query, embedding.clone());
+        Ok(embedding)
+    }
+
+    /// Digests the summaries of every thread updated within the window in a
+    /// single completion, grouped by tag (or tenant for untagged threads).
+    pub async fn digest(&self, request: DigestRequest) -> Result<Digest> {
+        let threads: Vec<Thread> = self
+            .db
+            .list_threads()
+            .await?
+            .into_iter()
+            .filter(|thread| request.tenant.is_none() || thread.tenant == request.tenant)
+            .filter(|thread| {
+                thread.updated_at >= request.since
+                    && request.until.is_none_or(|until| thread.updated_at <= until)
+            })
+            .filter(|thread| thread.summary.is_some())
+            .collect();
+
+        let mut groups: BTreeMap<String, Vec<&Thread>> = BTreeMap::new();
+        for thread in &threads {
+            if thread.tags.is_empty() {
+                let group = match &thread.tenant {
+                    Some(tenant) => format!("user:{}", tenant),
+                    None => "untagged".to_string(),
+                };
+                groups.entry(group).or_default().push(thread);
+            }
+            for tag in &thread.tags {
+                groups
+                    .entry(format!("tag:{}", tag))
+                    .or_default()
+                    .push(thread);
+            }
+        }
+
+        let digest = if groups.is_empty() {
+            String::new()
+        } else {
+            let conversations = groups
+                .iter()
+                .map(|(group, threads)| {
+                    let threads = threads
+                        .iter()
+                        .map(|thread| {
+                            format!(
+                                "<conversation title=\"{}\">\n{}\n</conversation>",
+                                thread.title.as_deref().unwrap_or_default(),
+                                thread.summary.as_deref().unwrap_or_default()
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    format!("<group name=\"{}\">\n{}\n</group>", group, threads)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let mut prompt = DIGEST_PROMPT.replace("{{CONVERSATIONS}}", &conversations);
+            if let Some(language) = &self.summary_language {
+                prompt.push_str(&SUMMARY_LANGUAGE_PROMPT.replace("{{LANGUAGE}}", language));
+            }
+
+            self.complete(
+                &self.summarizer,
+                self.summarizer_capabilities,
+                prompt,
+                &UsageScope::tenant(request.tenant.clone()),
+            )
+            .await?
+        };
 
-        Ok(similarities)
+        Ok(Digest {
+            since: request.since,
+            until: request.until,
+            groups: groups
+                .into_iter()
+                .map(|(group, threads)| (group, threads.iter().map(|thread| thread.id).collect()))
+                .collect(),
+            digest,
+        })
     }
 }
 
+/// Fills a summary prompt template, such as [`SUMMARY_PROMPT`], with the
+/// current summary and the new message.
+fn summary_prompt(
+    template: &str,
+    summary: &str,
+    role: &Role,
+    content: &str,
+    language: Option<&str>,
+) -> Prompt {
+    Prompt::from_template(template, |body| {
+        let mut body = body
+            .replace("{{CURRENT_SUMMARY}}", summary)
+            .replace("{{ROLE}}", role.as_str())
+            .replace("{{ROLE_GUIDANCE}}", role_guidance(role))
+            .replace("{{NEW_MESSAGE}}", content);
+        if let Some(language) = language {
+            body.push_str(&SUMMARY_LANGUAGE_PROMPT.replace("{{LANGUAGE}}", language));
+        }
+        body
+    })
+}
+
 pub struct SynxBuilder {
     db: Option<Arc<dyn Db>>,
     summarizer: Option<Arc<dyn Completion>>,
+    summarizer_capabilities: Capabilities,
+    summarizer_model: Option<String>,
     document_embedder: Option<Arc<dyn Embedder>>,
     query_embedder: Option<Arc<dyn Embedder>>,
     executor: Option<Arc<dyn Executor>>,
+    summary_limits: LaneLimits,
+    bulk_limits: LaneLimits,
+    summary_language: Option<String>,
+    unsummarized_roles: Vec<Role>,
+    unsummarized_patterns: Vec<String>,
+    redactor: Option<Redactor>,
+    replica_id: Option<Uuid>,
+    change_log: bool,
+    shadow: Option<ShadowSummarizer>,
+    monthly_token_budget: Option<u64>,
+    tenant_token_budgets: HashMap<String, u64>,
+    feedback_weight: f32,
+    similarity_metric: SimilarityMetric,
+    duplicate_threshold: Option<f32>,
+    captioner: Option<Arc<dyn Completion>>,
+    search_cache: Option<(usize, Duration)>,
+    query_embedding_cache: (usize, Duration),
+    reranker: Option<Arc<dyn Completion>>,
+    rerank_top_k: usize,
+    schedules: Vec<(Task, Schedule)>,
+    embedding_reduction: Option<usize>,
+    normalize_embeddings: bool,
+    embedding_template: String,
+    segment_size: Option<usize>,
+    slow_query_threshold: Option<Duration>,
+    message_retention: Option<Duration>,
 }
 
 impl SynxBuilder {
@@ -255,6 +1704,20 @@ impl SynxBuilder {
         self
     }
 
+    /// Declares what the summarizer's provider supports, e.g. prompt
+    /// caching, so requests are shaped to make use of it.
+    pub fn with_summarizer_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.summarizer_capabilities = capabilities;
+        self
+    }
+
+    /// Names the summarizer's model, recorded in the provenance of the
+    /// summaries it generates.
+    pub fn with_summarizer_model(mut self, model: impl Into<String>) -> Self {
+        self.summarizer_model = Some(model.into());
+        self
+    }
+
     pub fn with_document_embedder(mut self, document_embedder: Arc<dyn Embedder>) -> Self {
         self.document_embedder = Some(document_embedder);
         self
@@ -265,20 +1728,336 @@ impl SynxBuilder {
         self
     }
 
+    /// Truncates embeddings to their first `dimensions`, which must be at
+    /// least 1. See [`reduction`].
+    pub fn with_embedding_reduction(mut self, dimensions: usize) -> Self {
+        self.embedding_reduction = Some(dimensions);
+        self
+    }
+
+    /// Scales embeddings to unit length as they are made, for faster cosine
+    /// similarity. See [`normalization`].
+    pub fn with_embedding_normalization(mut self, normalize: bool) -> Self {
+        self.normalize_embeddings = normalize;
+        self
+    }
+
+    /// The text embedded for a thread, where `{{TITLE}}`, `{{TAGS}}`, and
+    /// `{{SUMMARY}}` are replaced by the thread's, `{{SUMMARY}}` by default.
+    /// See [`embedding_input`].
+    pub fn with_embedding_template(mut self, template: impl Into<String>) -> Self {
+        self.embedding_template = template.into();
+        self
+    }
+
+    /// Also embeds threads per segment of `size` consecutive summarized
+    /// messages, `size` being at least 1. See [`segments`].
+    pub fn with_segment_embeddings(mut self, size: usize) -> Self {
+        self.segment_size = Some(size);
+        self
+    }
+
+    /// Logs and counts database calls and searches taking longer than
+    /// `threshold`. See [`slow_queries`].
+    pub fn with_slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_query_threshold = Some(threshold);
+        self
+    }
+
+    /// Prunes messages older than `retention` when pruning isn't given an
+    /// age, e.g. on schedule. See [`pruning`].
+    pub fn with_message_retention(mut self, retention: Duration) -> Self {
+        self.message_retention = Some(retention);
+        self
+    }
+
+    /// Runs `task` on `schedule`, replacing its previous schedule. See
+    /// [`scheduler`].
+    pub fn with_schedule(mut self, task: Task, schedule: Schedule) -> Self {
+        self.schedules.retain(|(scheduled, _)| *scheduled != task);
+        self.schedules.push((task, schedule));
+        self
+    }
+
+    /// Runs background work, e.g. summaries, on `executor`. Defaults to the
+    /// [`TokioExecutor`](executor::TokioExecutor) with the `tokio-executor`
+    /// feature.
     pub fn with_executor(mut self, executor: Arc<dyn Executor>) -> Self {
         self.executor = Some(executor);
         self
     }
 
-    pub fn build(self) -> Synx {
-        Synx {
-            db: self.db.expect("db is required"),
-            summarizer: self.summarizer.expect("completion is required"),
+    /// Maximum number of summaries generated at once.
+    pub fn with_summary_concurrency(mut self, summary_concurrency: usize) -> Self {
+        self.summary_limits.concurrency = summary_concurrency;
+        self
+    }
+
+    /// Maximum number of summaries waiting to be generated, beyond which
+    /// new messages are rejected with [`worker_pool::QueueFull`].
+    pub fn with_summary_queue_capacity(mut self, summary_queue_capacity: usize) -> Self {
+        self.summary_limits.capacity = summary_queue_capacity;
+        self
+    }
+
+    /// Maximum number of bulk jobs (e.g. summaries of imported messages)
+    /// running at once, on top of the summary concurrency.
+    pub fn with_bulk_concurrency(mut self, bulk_concurrency: usize) -> Self {
+        self.bulk_limits.concurrency = bulk_concurrency;
+        self
+    }
+
+    /// Maximum number of bulk jobs waiting to run, beyond which imports are
+    /// rejected with [`worker_pool::QueueFull`].
+    pub fn with_bulk_queue_capacity(mut self, bulk_queue_capacity: usize) -> Self {
+        self.bulk_limits.capacity = bulk_queue_capacity;
+        self
+    }
+
+    pub fn with_summary_language(mut self, summary_language: impl Into<String>) -> Self {
+        self.summary_language = Some(summary_language.into());
+        self
+    }
+
+    /// Roles whose messages are stored but left out of summaries, only
+    /// system messages by default.
+    pub fn with_unsummarized_roles(mut self, roles: impl IntoIterator<Item = Role>) -> Self {
+        self.unsummarized_roles = roles.into_iter().collect();
+        self
+    }
+
+    /// Regular expressions matching the text of messages stored but left
+    /// out of summaries, e.g. heartbeats or tool noise.
+    pub fn with_unsummarized_patterns(
+        mut self,
+        patterns: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.unsummarized_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Redacts PII at the redactor's stages, recording each redaction in
+    /// the audit log.
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Identifies this instance in the op-log, which is recorded when set.
+    /// See [`replication`].
+    pub fn with_replica_id(mut self, replica_id: Uuid) -> Self {
+        self.replica_id = Some(replica_id);
+        self
+    }
+
+    /// Runs a second summarization pipeline alongside the live one, whose
+    /// summaries never reach the live thread. See [`shadow`].
+    pub fn with_shadow_summarizer(mut self, shadow: ShadowSummarizer) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    /// Records the op-log without replicating, e.g. for change-data capture
+    /// consumers. Implied by [`SynxBuilder::with_replica_id`].
+    pub fn with_change_log(mut self, change_log: bool) -> Self {
+        self.change_log = change_log;
+        self
+    }
+
+    /// Tokens each tenant may use per month before the summaries of its
+    /// threads are paused. See [`usage`].
+    pub fn with_monthly_token_budget(mut self, tokens: u64) -> Self {
+        self.monthly_token_budget = Some(tokens);
+        self
+    }
+
+    /// Overrides the monthly token budget of a tenant.
+    pub fn with_tenant_monthly_token_budget(
+        mut self,
+        tenant: impl Into<String>,
+        tokens: u64,
+    ) -> Self {
+        self.tenant_token_budgets.insert(tenant.into(), tokens);
+        self
+    }
+
+    /// How much relevance feedback moves search scores, zero turning
+    /// re-ranking off. See [`feedback`].
+    pub fn with_feedback_weight(mut self, weight: f32) -> Self {
+        self.feedback_weight = weight;
+        self
+    }
+
+    /// How searches compare queries with threads, unless they ask for
+    /// another metric. Cosine similarity by default.
+    pub fn with_similarity_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.similarity_metric = metric;
+        self
+    }
+
+    /// Reports pairs of threads whose summaries are at least this similar
+    /// as likely duplicates. See [`duplicates`].
+    pub fn with_duplicate_threshold(mut self, threshold: f32) -> Self {
+        self.duplicate_threshold = Some(threshold);
+        self
+    }
+
+    /// Captions the images of new messages with a vision-capable model, so
+    /// they are summarized too. See [`captioning`].
+    pub fn with_captioner(mut self, captioner: Arc<dyn Completion>) -> Self {
+        self.captioner = Some(captioner);
+        self
+    }
+
+    /// Caches the results of up to `capacity` searches for `ttl`, or until
+    /// what searches rank changes. See [`search_cache`].
+    pub fn with_search_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.search_cache = Some((capacity, ttl));
+        self
+    }
+
+    /// Keeps the embeddings of up to `capacity` normalized queries for
+    /// `ttl`, zero turning the cache off. See [`query_embeddings`].
+    pub fn with_query_embedding_cache(mut self, capacity: usize, ttl: Duration) -> Self {
+        self.query_embedding_cache = (capacity, ttl);
+        self
+    }
+
+    /// Reranks search results with this model instead of the summarizer,
+    /// e.g. a smaller and faster one. See [`rerank`].
+    pub fn with_reranker(mut self, reranker: Arc<dyn Completion>) -> Self {
+        self.reranker = Some(reranker);
+        self
+    }
+
+    /// Number of search results reranked, the first ones.
+    pub fn with_rerank_top_k(mut self, top_k: usize) -> Self {
+        self.rerank_top_k = top_k;
+        self
+    }
+
+    pub fn build(self) -> Result<Synx, BuildError> {
+        if self.summary_limits.concurrency == 0 || self.bulk_limits.concurrency == 0 {
+            return Err(BuildError::ZeroConcurrency);
+        }
+        if self.embedding_reduction == Some(0) {
+            return Err(BuildError::ZeroEmbeddingReduction);
+        }
+        if !self.embedding_template.contains("{{SUMMARY}}") {
+            return Err(BuildError::EmbeddingTemplateWithoutSummary);
+        }
+        if self.segment_size == Some(0) {
+            return Err(BuildError::ZeroSegmentSize);
+        }
+        let executor = self
+            .executor
+            .or_else(default_executor)
+            .ok_or(BuildError::MissingExecutor)?;
+        let unsummarized_patterns = RegexSet::new(&self.unsummarized_patterns)
+            .map_err(|e| BuildError::InvalidUnsummarizedPattern(e.to_string()))?;
+        let analytics = Arc::new(Analytics::default());
+        let slow_queries = self
+            .slow_query_threshold
+            .map(|threshold| Arc::new(SlowQueryLog::new(threshold, analytics.clone())));
+        let mut db = self.db.ok_or(BuildError::MissingDb)?;
+        if let Some(log) = &slow_queries {
+            db = Arc::new(SlowQueryDb::new(db, log.clone()));
+        }
+        let redactor = self
+            .redactor
+            .map(|redactor| Arc::new(redactor.audited_by(db.clone())));
+
+        Ok(Synx {
+            db,
+            summarizer: self.summarizer.ok_or(BuildError::MissingSummarizer)?,
+            summarizer_capabilities: self.summarizer_capabilities,
+            summarizer_model: self.summarizer_model,
             document_embedder: self
                 .document_embedder
-                .expect("document_embedder is required"),
-            query_embedder: self.query_embedder.expect("query_embedder is required"),
-            executor: self.executor.expect("executor is required"),
-        }
+                .ok_or(BuildError::MissingDocumentEmbedder)?,
+            query_embedder: self
+                .query_embedder
+                .ok_or(BuildError::MissingQueryEmbedder)?,
+            workers: WorkerPool::new(executor.clone(), self.summary_limits, self.bulk_limits),
+            summary_language: self.summary_language,
+            unsummarized_roles: self.unsummarized_roles,
+            unsummarized_patterns: Arc::new(unsummarized_patterns),
+            redactor,
+            analytics,
+            replica_id: self.replica_id,
+            change_log: self.change_log,
+            shadow: self.shadow.map(Arc::new),
+            budgets: Arc::new(Budgets::new(
+                self.monthly_token_budget,
+                self.tenant_token_budgets,
+            )),
+            feedback_weight: self.feedback_weight,
+            similarity_metric: self.similarity_metric,
+            duplicates: self
+                .duplicate_threshold
+                .map(|threshold| Arc::new(DuplicateDetector::new(threshold))),
+            captioner: self.captioner,
+            embedding_dimensions: Arc::default(),
+            search_cache: self
+                .search_cache
+                .map(|(capacity, ttl)| Arc::new(SearchCache::new(capacity, ttl))),
+            query_embeddings: Arc::new(QueryEmbeddingCache::new(
+                self.query_embedding_cache.0,
+                self.query_embedding_cache.1,
+            )),
+            reranker: self.reranker,
+            rerank_top_k: self.rerank_top_k,
+            health: Arc::default(),
+            scheduler: Arc::new(Scheduler::new(executor, self.schedules)),
+            embedding_reduction: self.embedding_reduction,
+            normalize_embeddings: self.normalize_embeddings,
+            embedding_template: self.embedding_template,
+            segment_size: self.segment_size,
+            sequences: Arc::default(),
+            in_flight: Arc::default(),
+            slow_queries,
+            message_retention: self.message_retention,
+        })
     }
 }
+
+/// Returned when appending to a message that isn't streaming, or when
+/// finalizing one that is already final.
+#[derive(Debug, thiserror::Error)]
+#[error("message {0} is already final")]
+pub struct MessageComplete(pub Uuid);
+
+#[cfg(all(feature = "tokio-executor", not(target_arch = "wasm32")))]
+fn default_executor() -> Option<Arc<dyn Executor>> {
+    Some(Arc::new(executor::TokioExecutor))
+}
+
+#[cfg(not(all(feature = "tokio-executor", not(target_arch = "wasm32"))))]
+fn default_executor() -> Option<Arc<dyn Executor>> {
+    None
+}
+
+/// A required component was not provided to the [`SynxBuilder`].
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    #[error("a database is required, see `SynxBuilder::with_db`")]
+    MissingDb,
+    #[error("a summarizer is required, see `SynxBuilder::with_summarizer`")]
+    MissingSummarizer,
+    #[error("a document embedder is required, see `SynxBuilder::with_document_embedder`")]
+    MissingDocumentEmbedder,
+    #[error("a query embedder is required, see `SynxBuilder::with_query_embedder`")]
+    MissingQueryEmbedder,
+    #[error("an executor is required, see `SynxBuilder::with_executor`")]
+    MissingExecutor,
+    #[error("summary and bulk concurrency must be at least 1")]
+    ZeroConcurrency,
+    #[error("embeddings must be reduced to at least 1 dimension")]
+    ZeroEmbeddingReduction,
+    #[error("invalid unsummarized pattern: {0}")]
+    InvalidUnsummarizedPattern(String),
+    #[error("the embedding template must include {{{{SUMMARY}}}}")]
+    EmbeddingTemplateWithoutSummary,
+    #[error("segments must hold at least 1 message")]
+    ZeroSegmentSize,
+}
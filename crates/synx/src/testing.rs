@@ -0,0 +1,160 @@
+//! Deterministic providers, so the whole stack runs in tests, in CI, and
+//! offline without API keys. Enabled with the `testing` feature.
+//!
+//! [`FakeEmbedder`] hashes the words of a text into a fixed number of
+//! dimensions, so texts sharing words are similar, and [`FakeSummarizer`]
+//! appends each new message to the current summary.
+
+use std::pin::Pin;
+
+use anyhow::Result;
+use ferrochain::{
+    completion::{Completion, StreamEvent},
+    embedding::{Embedder, Embedding},
+    futures::{stream, Stream},
+    message::{Content, Message},
+};
+use sha2::{Digest, Sha256};
+
+pub const DEFAULT_DIMENSIONS: usize = 256;
+
+/// The model recorded in the provenance of fake summaries.
+pub const FAKE_MODEL: &str = "fake";
+
+pub struct FakeEmbedder {
+    dimensions: usize,
+}
+
+impl FakeEmbedder {
+    pub fn new() -> Self {
+        Self::with_dimensions(DEFAULT_DIMENSIONS)
+    }
+
+    pub fn with_dimensions(dimensions: usize) -> Self {
+        Self {
+            dimensions: dimensions.max(1),
+        }
+    }
+}
+
+impl Default for FakeEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedder for FakeEmbedder {
+    async fn embed(&self, input: Vec<String>) -> Result<Vec<Embedding>> {
+        Ok(input
+            .iter()
+            .map(|text| Embedding::from(hash_embedding(text, self.dimensions)))
+            .collect())
+    }
+}
+
+/// Answers summary prompts with the current summary followed by the new
+/// message, and any other prompt with its own text.
+#[derive(Default)]
+pub struct FakeSummarizer;
+
+#[async_trait::async_trait]
+impl Completion for FakeSummarizer {
+    async fn complete(
+        &self,
+        messages: Vec<Message>,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
+        let prompt = messages
+            .iter()
+            .flat_map(|message| &message.content)
+            .filter_map(|content| match content {
+                Content::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect::<String>();
+        let event = StreamEvent::Delta {
+            index: 0,
+            content: Content::Text {
+                text: fake_completion(&prompt),
+            },
+        };
+
+        Ok(Box::pin(stream::iter([Ok(event)])))
+    }
+}
+
+/// Hashes each lowercased word to a dimension and a sign, normalizing the
+/// sum to a unit vector.
+fn hash_embedding(text: &str, dimensions: usize) -> Vec<f32> {
+    let mut vector = vec![0.0; dimensions];
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let hash = Sha256::digest(word.to_lowercase().as_bytes());
+        let index = u64::from_be_bytes(hash[..8].try_into().unwrap()) as usize % dimensions;
+        vector[index] += if hash[8] & 1 == 0 { 1.0 } else { -1.0 };
+    }
+
+    let magnitude = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if magnitude > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= magnitude);
+    }
+    vector
+}
+
+fn fake_completion(prompt: &str) -> String {
+    let (Some(current), Some(new)) = (
+        between(prompt, "<current_summary>", "</current_summary>"),
+        between(prompt, "<new_message", "</new_message>")
+            .and_then(|tag| tag.split_once('>').map(|(_, message)| message)),
+    ) else {
+        return prompt.trim().to_string();
+    };
+
+    [current.trim(), new.trim()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The text between the closing tag and the last opening tag before it,
+/// instructions mentioning the tags coming first.
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let end = text.find(end)?;
+    let start = text[..end].rfind(start)? + start.len();
+    Some(&text[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn similarity(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    #[test]
+    fn shared_words_make_similar_embeddings() {
+        let weather = hash_embedding("What is the weather in Paris?", 64);
+        let forecast = hash_embedding("the weather forecast for Paris", 64);
+        let recipe = hash_embedding("a recipe for pancakes", 64);
+
+        assert_eq!(weather, hash_embedding("What is the weather in Paris?", 64));
+        assert!(similarity(&weather, &forecast) > similarity(&weather, &recipe));
+    }
+
+    #[test]
+    fn summaries_append_the_new_message() {
+        let prompt = "Summarize the <new_message> tags.\n\
+                      <current_summary>\nI asked about Paris.\n</current_summary>\n\
+                      <new_message role=\"user\">\nAnd about Rome.\n</new_message>";
+
+        assert_eq!(
+            fake_completion(prompt),
+            "I asked about Paris.\nAnd about Rome."
+        );
+        assert_eq!(fake_completion("Name this cluster."), "Name this cluster.");
+    }
+}
@@ -0,0 +1,263 @@
+//! Usage of the summarizer and the embedders, and monthly token budgets.
+//!
+//! Completions and embeddings made on behalf of a thread or a tenant are
+//! recorded in daily aggregates. The completion and embedding traits don't
+//! report what providers bill, so tokens are estimated from the text sent
+//! and received, at about four characters per token. Completions made by
+//! the [`crate::redaction`] detector aren't recorded. Images sent to the
+//! [`crate::captioning`] model are counted as a fixed number of tokens.
+//!
+//! A tenant whose usage this month reaches its budget has the summaries of
+//! its threads paused until the next month. Requests made on its behalf,
+//! such as searches and digests, are still served.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate, Utc};
+use ferrochain::{completion::Completion, embedding::Embedder, message::Content};
+use synx_domain::{
+    embedding::Embedding,
+    thread::Thread,
+    usage::{DailyUsage, Usage, UsageFilter},
+};
+use uuid::Uuid;
+
+use crate::{
+    provider::Capabilities,
+    utils::{
+        completion::{complete_content, complete_text, Prompt},
+        embedding::generate_embeddings,
+    },
+    Synx,
+};
+
+const CHARS_PER_TOKEN: u64 = 4;
+/// About what a one megapixel image costs with Anthropic models.
+const IMAGE_TOKENS: u64 = 1_600;
+
+/// Who a completion or an embedding is made for.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct UsageScope {
+    tenant: Option<String>,
+    thread_id: Option<Uuid>,
+}
+
+impl UsageScope {
+    pub(crate) fn thread(thread: &Thread) -> Self {
+        Self {
+            tenant: thread.tenant.clone(),
+            thread_id: Some(thread.id),
+        }
+    }
+
+    pub(crate) fn tenant(tenant: Option<String>) -> Self {
+        Self {
+            tenant,
+            thread_id: None,
+        }
+    }
+}
+
+/// Monthly token budgets, along with the tokens used this month by the
+/// tenants having one, loaded from the database on first use.
+pub(crate) struct Budgets {
+    default: Option<u64>,
+    tenants: HashMap<String, u64>,
+    used: Mutex<HashMap<String, (NaiveDate, u64)>>,
+}
+
+impl Budgets {
+    pub(crate) fn new(default: Option<u64>, tenants: HashMap<String, u64>) -> Self {
+        Self {
+            default,
+            tenants,
+            used: Mutex::default(),
+        }
+    }
+
+    fn budget(&self, tenant: &str) -> Option<u64> {
+        self.tenants.get(tenant).copied().or(self.default)
+    }
+
+    fn used(&self, tenant: &str, month: NaiveDate) -> Option<u64> {
+        self.used
+            .lock()
+            .unwrap()
+            .get(tenant)
+            .filter(|(used_month, _)| *used_month == month)
+            .map(|(_, tokens)| *tokens)
+    }
+
+    fn load(&self, tenant: &str, month: NaiveDate, tokens: u64) {
+        self.used
+            .lock()
+            .unwrap()
+            .insert(tenant.to_string(), (month, tokens));
+    }
+
+    /// Counts tokens towards the tenant's month once it has been loaded.
+    fn add(&self, tenant: &str, month: NaiveDate, tokens: u64) {
+        if let Some((used_month, used)) = self.used.lock().unwrap().get_mut(tenant) {
+            if *used_month == month {
+                *used += tokens;
+            }
+        }
+    }
+}
+
+impl Synx {
+    pub async fn list_usage(&self, filter: UsageFilter) -> Result<Vec<DailyUsage>> {
+        Ok(self.db.list_usage(filter).await?)
+    }
+
+    /// Whether the tenant used up its token budget this month. Threads
+    /// without a tenant have no budget.
+    pub async fn over_budget(&self, tenant: Option<&str>) -> Result<bool> {
+        let Some(tenant) = tenant else {
+            return Ok(false);
+        };
+        let Some(budget) = self.budgets.budget(tenant) else {
+            return Ok(false);
+        };
+
+        let month = month_of(Utc::now().date_naive());
+        let used = match self.budgets.used(tenant, month) {
+            Some(used) => used,
+            None => {
+                let used = self
+                    .db
+                    .list_usage(UsageFilter {
+                        from: Some(month),
+                        tenant: Some(tenant.to_string()),
+                        ..Default::default()
+                    })
+                    .await?
+                    .iter()
+                    .map(|usage| usage.usage.tokens())
+                    .sum();
+                self.budgets.load(tenant, month, used);
+                used
+            }
+        };
+
+        Ok(used >= budget)
+    }
+
+    /// Completes the prompt, recording its usage.
+    pub(crate) async fn complete(
+        &self,
+        completion: &Arc<dyn Completion>,
+        capabilities: Capabilities,
+        prompt: impl Into<Prompt>,
+        scope: &UsageScope,
+    ) -> Result<String> {
+        let (output, _) = self
+            .complete_with_usage(completion, capabilities, prompt, scope)
+            .await?;
+        Ok(output)
+    }
+
+    /// Completes the prompt, recording its usage, which is returned along
+    /// with the output.
+    pub(crate) async fn complete_with_usage(
+        &self,
+        completion: &Arc<dyn Completion>,
+        capabilities: Capabilities,
+        prompt: impl Into<Prompt>,
+        scope: &UsageScope,
+    ) -> Result<(String, Usage)> {
+        let prompt = prompt.into();
+        let input_tokens = estimate_tokens(&prompt.prefix) + estimate_tokens(&prompt.body);
+        let output = complete_text(completion, prompt, capabilities).await;
+        self.record_completion(completion, &output);
+        let output = output?;
+        let usage = Usage {
+            completion_requests: 1,
+            completion_input_tokens: input_tokens,
+            completion_output_tokens: estimate_tokens(&output),
+            ..Default::default()
+        };
+        self.record_usage(scope, usage.clone()).await;
+
+        Ok((output, usage))
+    }
+
+    /// Completes the image followed by the prompt, recording its usage.
+    pub(crate) async fn complete_image(
+        &self,
+        completion: &Arc<dyn Completion>,
+        image: Content,
+        prompt: &str,
+        scope: &UsageScope,
+    ) -> Result<String> {
+        let output = complete_content(completion, vec![image, prompt.to_string().into()]).await?;
+        self.record_usage(
+            scope,
+            Usage {
+                completion_requests: 1,
+                completion_input_tokens: IMAGE_TOKENS + estimate_tokens(prompt),
+                completion_output_tokens: estimate_tokens(&output),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        Ok(output)
+    }
+
+    /// Embeds the content, recording its usage.
+    pub(crate) async fn embed(
+        &self,
+        embedder: &Arc<dyn Embedder>,
+        content: &str,
+        scope: &UsageScope,
+    ) -> Result<Embedding> {
+        let embedding = generate_embeddings(embedder, content).await;
+        self.record_embedding(embedder, &embedding);
+        let embedding = self.normalize_embedding(self.reduce_embedding(embedding?));
+        self.record_usage(
+            scope,
+            Usage {
+                embedding_requests: 1,
+                embedding_tokens: estimate_tokens(content),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        Ok(embedding)
+    }
+
+    /// Failing to record usage doesn't fail the request it was made for.
+    async fn record_usage(&self, scope: &UsageScope, usage: Usage) {
+        let day = Utc::now().date_naive();
+        if let Some(tenant) = &scope.tenant {
+            self.budgets.add(tenant, month_of(day), usage.tokens());
+        }
+
+        if let Err(e) = self
+            .db
+            .add_usage(DailyUsage {
+                day,
+                tenant: scope.tenant.clone(),
+                thread_id: scope.thread_id,
+                usage,
+            })
+            .await
+        {
+            tracing::error!("Failed to record usage: {}", e);
+        }
+    }
+}
+
+fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(CHARS_PER_TOKEN)
+}
+
+fn month_of(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap()
+}
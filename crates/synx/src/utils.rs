@@ -3,3 +3,4 @@ pub mod content;
 pub mod embedding;
 
 pub mod similarity;
+pub mod task;
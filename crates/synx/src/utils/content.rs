@@ -1,15 +1,16 @@
 use synx_domain::content::{Content, ContentKind};
 
+/// JSON longer than this is cut when given to the summarizer.
+const MAX_JSON_CHARS: usize = 4_000;
+
 pub fn extract_text_content(content: &Content) -> Option<String> {
     let text_contents: Vec<String> = content
         .0
         .iter()
-        .filter_map(|c| {
-            if let ContentKind::Text { text } = c {
-                Some(text.clone())
-            } else {
-                None
-            }
+        .filter_map(|c| match c {
+            ContentKind::Text { text } => Some(text.clone()),
+            ContentKind::Json { value, schema } => Some(json_text(value, schema.as_deref())),
+            ContentKind::Image { .. } => None,
         })
         .collect();
 
@@ -19,3 +20,38 @@ pub fn extract_text_content(content: &Content) -> Option<String> {
         Some(text_contents.join("\n"))
     }
 }
+
+/// The value pretty-printed in a fenced block, labelled with its schema.
+fn json_text(value: &serde_json::Value, schema: Option<&str>) -> String {
+    let mut json = serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string());
+    if let Some((cut, _)) = json.char_indices().nth(MAX_JSON_CHARS) {
+        json.truncate(cut);
+        json.push_str("\n…");
+    }
+
+    match schema {
+        Some(schema) => format!("JSON ({}):\n```json\n{}\n```", schema, json),
+        None => format!("JSON:\n```json\n{}\n```", json),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_is_pretty_printed_along_with_text() {
+        let content: Content = serde_json::from_str(
+            r#"[
+                {"type": "text", "text": "Weather lookup"},
+                {"type": "json", "value": {"city": "Rome", "temp": 21}, "schema": "weather"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            extract_text_content(&content).unwrap(),
+            "Weather lookup\nJSON (weather):\n```json\n{\n  \"city\": \"Rome\",\n  \"temp\": 21\n}\n```"
+        );
+    }
+}
@@ -8,5 +8,154 @@ pub fn cosine_similarity(a: &Embedding, b: &Embedding) -> f32 {
     let magnitude_a: f32 = a_vec.iter().map(|x| x * x).sum::<f32>().sqrt();
     let magnitude_b: f32 = b_vec.iter().map(|x| x * x).sum::<f32>().sqrt();
 
+    if magnitude_a == 0.0 || magnitude_b == 0.0 {
+        return 0.0;
+    }
+
     dot_product / (magnitude_a * magnitude_b)
 }
+
+pub fn dot_product(a: &Embedding, b: &Embedding) -> f32 {
+    a.to_vec()
+        .iter()
+        .zip(b.to_vec().iter())
+        .map(|(x, y)| x * y)
+        .sum()
+}
+
+/// `1 / (1 + d)`, `d` being the Euclidean distance, so closer vectors score
+/// higher, identical ones scoring 1.
+pub fn euclidean_similarity(a: &Embedding, b: &Embedding) -> f32 {
+    let distance = a
+        .to_vec()
+        .iter()
+        .zip(b.to_vec().iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt();
+
+    1.0 / (1.0 + distance)
+}
+
+/// Scales the vector to unit length, leaving zero vectors as they are.
+pub fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let magnitude = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if magnitude > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= magnitude);
+    }
+    vector
+}
+
+/// Orders candidates by Maximal Marginal Relevance: each pick maximises
+/// `lambda * relevance - (1 - lambda) * similarity`, the similarity being to
+/// the closest candidate picked before it. Returns candidate indices.
+pub fn mmr_order(candidates: &[(f32, &Embedding)], lambda: f32) -> Vec<usize> {
+    let mut remaining = (0..candidates.len()).collect::<Vec<_>>();
+    let mut picked: Vec<usize> = Vec::with_capacity(candidates.len());
+
+    while !remaining.is_empty() {
+        let marginal = |&index: &usize| {
+            let (relevance, embedding) = candidates[index];
+            let redundancy = picked
+                .iter()
+                .map(|&other| cosine_similarity(embedding, candidates[other].1))
+                .reduce(f32::max)
+                .unwrap_or(0.0);
+            lambda * relevance - (1.0 - lambda) * redundancy
+        };
+        let (position, _) = remaining
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| marginal(a).total_cmp(&marginal(b)))
+            .unwrap();
+        picked.push(remaining.remove(position));
+    }
+
+    picked
+}
+
+/// Constant of Reciprocal Rank Fusion, damping the weight of the first
+/// ranks.
+const RRF_K: f32 = 60.0;
+
+/// Fuses the scores each ranking gives to the same candidates with
+/// Reciprocal Rank Fusion: a candidate scores `1 / (k + rank)` for each
+/// ranking, ranks starting at 1.
+pub fn reciprocal_rank_fusion(rankings: &[Vec<f32>]) -> Vec<f32> {
+    let mut fused = vec![0.0; rankings.first().map_or(0, Vec::len)];
+    for scores in rankings {
+        let mut order = (0..scores.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        for (rank, index) in order.into_iter().enumerate() {
+            fused[index] += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_vectors_are_fully_similar() {
+        let a = Embedding::from(vec![0.6, 0.8]);
+
+        assert!((cosine_similarity(&a, &a) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_vectors_score_zero_instead_of_nan() {
+        let a = Embedding::from(vec![0.0, 0.0]);
+        let b = Embedding::from(vec![0.6, 0.8]);
+
+        assert_eq!(cosine_similarity(&a, &b), 0.0);
+    }
+
+    #[test]
+    fn dot_products_weigh_magnitudes() {
+        let a = Embedding::from(vec![0.6, 0.8]);
+        let b = Embedding::from(vec![1.2, 1.6]);
+
+        assert!((dot_product(&a, &b) - 2.0).abs() < 1e-6);
+        assert!(dot_product(&a, &b) > dot_product(&a, &a));
+        assert!((cosine_similarity(&a, &b) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn closer_vectors_are_more_similar_by_euclidean_distance() {
+        let a = Embedding::from(vec![0.0, 0.0]);
+        let near = Embedding::from(vec![0.3, 0.4]);
+        let far = Embedding::from(vec![3.0, 4.0]);
+
+        assert_eq!(euclidean_similarity(&a, &a), 1.0);
+        assert!((euclidean_similarity(&a, &near) - 1.0 / 1.5).abs() < 1e-6);
+        assert!((euclidean_similarity(&a, &far) - 1.0 / 6.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn mmr_puts_near_duplicates_after_distinct_results() {
+        let incident = Embedding::from(vec![1.0, 0.0]);
+        let same_incident = Embedding::from(vec![0.99, 0.01]);
+        let other_topic = Embedding::from(vec![0.0, 1.0]);
+        let candidates = [
+            (0.9, &incident),
+            (0.89, &same_incident),
+            (0.5, &other_topic),
+        ];
+
+        assert_eq!(mmr_order(&candidates, 0.5), vec![0, 2, 1]);
+        // Relevance alone keeps the original order.
+        assert_eq!(mmr_order(&candidates, 1.0), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn rrf_favours_candidates_ranked_well_by_every_ranking() {
+        let fused = reciprocal_rank_fusion(&[vec![0.9, 0.8, 0.1], vec![0.2, 0.7, 0.6]]);
+
+        assert!(fused[1] > fused[0]);
+        assert!(fused[0] > fused[2]);
+        assert!((fused[1] - (1.0 / 62.0 + 1.0 / 61.0)).abs() < 1e-6);
+    }
+}
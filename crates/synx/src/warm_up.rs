@@ -0,0 +1,129 @@
+//! Validation of the embedders and completions at startup, so a
+//! misconfiguration, e.g. invalid credentials, shows when the instance
+//! boots rather than in the background summary of the first message.
+//!
+//! [`Synx::warm_up`] makes a tiny request to each provider and records the
+//! dimensions of embeddings, checking them against those already stored
+//! since embeddings of another size can't be compared.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use ferrochain::{completion::Completion, embedding::Embedder};
+
+use crate::{provider::Capabilities, usage::UsageScope, Synx};
+
+const WARM_UP_TEXT: &str = "Warming up.";
+const WARM_UP_PROMPT: &str = "Answer with OK.";
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+pub struct WarmUpReport {
+    pub embedding_dimensions: usize,
+    /// The dimensions of the embeddings already stored, if any.
+    pub stored_dimensions: Option<usize>,
+}
+
+/// Returned when the embedders don't make embeddings of the same size, or
+/// of the size of those already stored, e.g. after changing models.
+#[derive(Debug, thiserror::Error)]
+#[error("embeddings have {found} dimensions, {expected} were expected")]
+pub struct DimensionMismatch {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl Synx {
+    /// Embeds and completes a few tokens with every provider, failing with
+    /// the first error, and records the dimensions of embeddings.
+    pub async fn warm_up(&self) -> Result<WarmUpReport> {
+        let scope = UsageScope::default();
+        let embedding_dimensions = self
+            .warm_up_embedder(&self.document_embedder, &scope)
+            .await
+            .context("the document embedder failed to warm up")?;
+        let query_dimensions = self
+            .warm_up_embedder(&self.query_embedder, &scope)
+            .await
+            .context("the query embedder failed to warm up")?;
+        if query_dimensions != embedding_dimensions {
+            return Err(DimensionMismatch {
+                expected: embedding_dimensions,
+                found: query_dimensions,
+            })
+            .context("the query embedder doesn't match the document embedder");
+        }
+
+        self.warm_up_completion(&self.summarizer, self.summarizer_capabilities, &scope)
+            .await
+            .context("the summarizer failed to warm up")?;
+        if let Some(captioner) = &self.captioner {
+            self.warm_up_completion(captioner, Capabilities::default(), &scope)
+                .await
+                .context("the captioner failed to warm up")?;
+        }
+
+        let stored_dimensions = self.stored_dimensions().await?;
+        if let Some(stored) = stored_dimensions.filter(|&stored| stored != embedding_dimensions) {
+            return Err(DimensionMismatch {
+                expected: stored,
+                found: embedding_dimensions,
+            })
+            .context("the document embedder doesn't match the stored embeddings");
+        }
+
+        let _ = self.embedding_dimensions.set(embedding_dimensions);
+        Ok(WarmUpReport {
+            embedding_dimensions,
+            stored_dimensions,
+        })
+    }
+
+    /// The dimensions of embeddings, once recorded by [`Synx::warm_up`].
+    pub fn embedding_dimensions(&self) -> Option<usize> {
+        self.embedding_dimensions.get().copied()
+    }
+
+    async fn warm_up_embedder(
+        &self,
+        embedder: &Arc<dyn Embedder>,
+        scope: &UsageScope,
+    ) -> Result<usize> {
+        Ok(self
+            .embed(embedder, WARM_UP_TEXT, scope)
+            .await?
+            .to_vec()
+            .len())
+    }
+
+    async fn warm_up_completion(
+        &self,
+        completion: &Arc<dyn Completion>,
+        capabilities: Capabilities,
+        scope: &UsageScope,
+    ) -> Result<()> {
+        self.complete(completion, capabilities, WARM_UP_PROMPT.to_string(), scope)
+            .await?;
+        Ok(())
+    }
+
+    /// The dimensions of the embedding of the first summarized thread.
+    async fn stored_dimensions(&self) -> Result<Option<usize>> {
+        let Some(thread) = self
+            .db
+            .list_threads()
+            .await?
+            .into_iter()
+            .find(|thread| thread.summary.is_some())
+        else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .db
+            .get_threads_with_embeddings(&[thread.id])
+            .await?
+            .into_iter()
+            .find_map(|thread| thread.embedding)
+            .map(|embedding| embedding.to_vec().len()))
+    }
+}
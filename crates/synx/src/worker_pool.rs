@@ -0,0 +1,257 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ferrochain::futures::future::select;
+use tokio::sync::{Notify, Semaphore};
+use uuid::Uuid;
+
+use crate::executor::Executor;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+#[derive(Debug, thiserror::Error)]
+#[error("the background queue is full, retry later")]
+pub struct QueueFull;
+
+/// Background work is split in lanes with their own limits, so bulk work
+/// (imports, reindexing) can't starve interactive work of workers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// Work triggered by live conversations, e.g. summarizing a new message.
+    Interactive,
+    /// Work triggered by imports and maintenance.
+    Bulk,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LaneLimits {
+    /// Maximum number of jobs running at once.
+    pub concurrency: usize,
+    /// Maximum number of jobs reserved, queued, or running.
+    pub capacity: usize,
+}
+
+/// Runs background jobs on an [`Executor`] with bounded concurrency per
+/// [`Lane`]. Jobs sharing a key (e.g. a thread id) run one after the other in
+/// submission order, whatever their lane.
+#[derive(Clone)]
+pub struct WorkerPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    executor: Arc<dyn Executor>,
+    interactive: LaneState,
+    bulk: LaneState,
+    queues: Mutex<HashMap<Uuid, Queue>>,
+}
+
+/// Jobs waiting behind the running one for a key, and the token cancelling
+/// it along with them.
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<(Lane, Job)>,
+    cancellation: CancellationToken,
+}
+
+struct LaneState {
+    permits: Semaphore,
+    capacity: usize,
+    pending: Mutex<usize>,
+}
+
+impl LaneState {
+    fn new(limits: LaneLimits) -> Self {
+        Self {
+            permits: Semaphore::new(limits.concurrency),
+            capacity: limits.capacity,
+            pending: Mutex::new(0),
+        }
+    }
+}
+
+impl WorkerPool {
+    pub fn new(executor: Arc<dyn Executor>, interactive: LaneLimits, bulk: LaneLimits) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                executor,
+                interactive: LaneState::new(interactive),
+                bulk: LaneState::new(bulk),
+                queues: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Reserves room for one job in `lane`, so callers can push back before
+    /// doing any work the job would depend on.
+    pub fn reserve(&self, lane: Lane) -> Result<Slot, QueueFull> {
+        let state = self.lane(lane);
+        let mut pending = state.pending.lock().unwrap();
+        if *pending >= state.capacity {
+            return Err(QueueFull);
+        }
+        *pending += 1;
+
+        Ok(Slot {
+            pool: self.clone(),
+            lane,
+            submitted: false,
+        })
+    }
+
+    /// Number of jobs reserved, queued, or running in `lane`.
+    pub fn pending(&self, lane: Lane) -> usize {
+        *self.lane(lane).pending.lock().unwrap()
+    }
+
+    fn lane(&self, lane: Lane) -> &LaneState {
+        match lane {
+            Lane::Interactive => &self.inner.interactive,
+            Lane::Bulk => &self.inner.bulk,
+        }
+    }
+
+    fn release(&self, lane: Lane) {
+        *self.lane(lane).pending.lock().unwrap() -= 1;
+    }
+
+    /// Aborts the job running for `key`, at its next await point, and drops
+    /// the jobs queued behind it, e.g. once the thread they work on is
+    /// deleted. Jobs submitted afterwards run as usual.
+    pub fn cancel(&self, key: Uuid) {
+        let cancelled = {
+            let mut queues = self.inner.queues.lock().unwrap();
+            let Some(queue) = queues.get_mut(&key) else {
+                return;
+            };
+            std::mem::take(queue).cancel()
+        };
+
+        for (lane, _) in cancelled {
+            self.release(lane);
+        }
+    }
+
+    fn enqueue(&self, key: Uuid, lane: Lane, job: Job) {
+        let cancellation = {
+            let mut queues = self.inner.queues.lock().unwrap();
+            if let Some(queue) = queues.get_mut(&key) {
+                queue.jobs.push_back((lane, job));
+                return;
+            }
+            queues.entry(key).or_default().cancellation.clone()
+        };
+
+        let pool = self.clone();
+        self.inner
+            .executor
+            .spawn(Box::pin(pool.drain(key, lane, job, cancellation)));
+    }
+
+    /// Runs `job`, then every job queued behind it for the same key.
+    async fn drain(
+        self,
+        key: Uuid,
+        mut lane: Lane,
+        mut job: Job,
+        mut cancellation: CancellationToken,
+    ) {
+        loop {
+            let run = {
+                let pool = self.clone();
+                async move {
+                    let _permit = pool.lane(lane).permits.acquire().await;
+                    job.await;
+                }
+            };
+            select(Box::pin(run), Box::pin(cancellation.cancelled())).await;
+            self.release(lane);
+
+            let next = {
+                let mut queues = self.inner.queues.lock().unwrap();
+                let next = queues.get_mut(&key).and_then(|queue| {
+                    let (lane, job) = queue.jobs.pop_front()?;
+                    Some((lane, job, queue.cancellation.clone()))
+                });
+                if next.is_none() {
+                    queues.remove(&key);
+                }
+                next
+            };
+
+            match next {
+                Some((next_lane, next_job, next_cancellation)) => {
+                    lane = next_lane;
+                    job = next_job;
+                    cancellation = next_cancellation;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+impl Queue {
+    /// Cancels the running job, returning the queued ones.
+    fn cancel(self) -> VecDeque<(Lane, Job)> {
+        self.cancellation.cancel();
+        self.jobs
+    }
+}
+
+#[derive(Clone, Default)]
+struct CancellationToken {
+    inner: Arc<CancellationState>,
+}
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        self.inner.notify.notify_waiters();
+    }
+
+    async fn cancelled(&self) {
+        // Waiters registered before `notify_waiters` are woken even if they
+        // weren't polled yet, so the flag can be checked after registering.
+        let notified = self.inner.notify.notified();
+        if self.inner.cancelled.load(Ordering::Acquire) {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Room for one job in a [`WorkerPool`] lane, released if dropped unused.
+pub struct Slot {
+    pool: WorkerPool,
+    lane: Lane,
+    submitted: bool,
+}
+
+impl Slot {
+    pub fn submit(mut self, key: Uuid, job: Job) {
+        self.submitted = true;
+        self.pool.enqueue(key, self.lane, job);
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if !self.submitted {
+            self.pool.release(self.lane);
+        }
+    }
+}
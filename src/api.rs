@@ -1,2 +1,11 @@
+pub mod audit;
+pub mod auth;
+pub mod compression;
+pub mod consistency;
+pub mod etag;
 pub mod handlers;
+pub mod logging;
+pub mod pagination;
 pub mod routes;
+pub mod thread_tokens;
+pub mod timeout;
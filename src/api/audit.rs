@@ -0,0 +1,107 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use synx::Synx;
+use synx_domain::audit::AuditEntry;
+use uuid::Uuid;
+
+use crate::api::auth::{hex_digest, Identity};
+
+/// Same as axum's default body limit, which the JSON extractors enforce anyway.
+const MAX_AUDITED_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// Routes using a mutating method without mutating anything.
+const READ_ONLY_ROUTES: &[&str] = &["/search"];
+
+/// Routes whose bodies may be larger than [`MAX_AUDITED_BODY_SIZE`], audited
+/// without buffering them, and so without a request hash.
+const UNBUFFERED_ROUTES: &[&str] = &["/blobs"];
+
+pub async fn audit_middleware(State(synx): State<Synx>, request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| request.uri().path().to_owned());
+    if READ_ONLY_ROUTES.contains(&route.as_str()) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().to_string();
+    let identity = request
+        .extensions()
+        .get::<Identity>()
+        .cloned()
+        .unwrap_or_else(Identity::anonymous);
+    let mut entity_ids: Vec<Uuid> = request
+        .uri()
+        .path()
+        .split('/')
+        .filter_map(|segment| Uuid::parse_str(segment).ok())
+        .collect();
+
+    let (request, request_hash) = if UNBUFFERED_ROUTES.contains(&route.as_str()) {
+        (request, String::new())
+    } else {
+        let (parts, body) = request.into_parts();
+        let body = match to_bytes(body, MAX_AUDITED_BODY_SIZE).await {
+            Ok(body) => body,
+            Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+        };
+        let request_hash = hex_digest(&body);
+        (Request::from_parts(parts, Body::from(body)), request_hash)
+    };
+
+    let response = next.run(request).await;
+    let status = response.status();
+
+    // Created entities only get their id in the response body.
+    let response = if status == StatusCode::CREATED {
+        let (parts, body) = response.into_parts();
+        let body = match to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to buffer response for audit: {:?}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        if let Some(id) = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|value| value.get("id")?.as_str().map(Uuid::parse_str))
+            .and_then(Result::ok)
+        {
+            entity_ids.push(id);
+        }
+        Response::from_parts(parts, Body::from(body))
+    } else {
+        response
+    };
+
+    let entry = AuditEntry {
+        id: Uuid::new_v4(),
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        actor: identity.subject,
+        tenant: identity.tenant,
+        method,
+        route,
+        entity_ids,
+        status: status.as_u16(),
+        request_hash,
+    };
+    if let Err(e) = synx.record_audit_entry(entry).await {
+        tracing::error!("Failed to record audit entry: {:?}", e);
+    }
+
+    response
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use sha2::{Digest, Sha256};
+use synx_domain::thread::Thread;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Minimum delay between two JWKS refreshes triggered by unknown key ids, so
+/// forged tokens can't make us hammer the issuer.
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The authenticated caller, inserted in the request extensions by the
+/// authentication middlewares.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub subject: String,
+    pub tenant: Option<String>,
+    /// The threads a thread token is restricted to, see
+    /// [`thread_tokens`](crate::api::thread_tokens).
+    pub threads: Option<Vec<Uuid>>,
+}
+
+impl Identity {
+    pub fn anonymous() -> Self {
+        Self {
+            subject: "anonymous".to_string(),
+            tenant: None,
+            threads: None,
+        }
+    }
+
+    /// Callers without a tenant (only the static API key) can access every
+    /// thread, otherwise only threads created by the same tenant. Thread
+    /// tokens are further restricted to their threads.
+    pub fn can_access(&self, thread: &Thread) -> bool {
+        (self.tenant.is_none() || thread.tenant == self.tenant)
+            && self
+                .threads
+                .as_ref()
+                .map_or(true, |threads| threads.contains(&thread.id))
+    }
+
+    /// Whether every thread is accessible without looking it up.
+    pub fn is_unrestricted(&self) -> bool {
+        self.tenant.is_none() && self.threads.is_none()
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Identity {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .unwrap_or_else(Identity::anonymous))
+    }
+}
+
+/// Checks the API key, passed as a bearer token or in `X-API-Key`, and
+/// identifies the caller by a fingerprint of it, never the key itself.
+/// Requests authenticated by a thread token already are let through.
+pub async fn api_key_middleware(
+    State(api_key): State<Arc<str>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<Identity>().is_some() {
+        return next.run(request).await;
+    }
+
+    let key = request
+        .headers()
+        .get(AUTHORIZATION)
+        .or_else(|| request.headers().get("x-api-key"))
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value));
+    // Digests are compared so the comparison takes the same time wherever
+    // the keys differ.
+    let Some(digest) = key
+        .map(|key| hex_digest(key.as_bytes()))
+        .filter(|digest| *digest == hex_digest(api_key.as_bytes()))
+    else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let identity = Identity {
+        subject: format!("key:{}", &digest[..16]),
+        tenant: None,
+        threads: None,
+    };
+    request.extensions_mut().insert(identity.clone());
+    let mut response = next.run(request).await;
+    // Outer layers (e.g. request logging) only get to see the response.
+    response.extensions_mut().insert(identity);
+    response
+}
+
+pub async fn oidc_middleware(
+    State(validator): State<Arc<OidcValidator>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<Identity>().is_some() {
+        return next.run(request).await;
+    }
+
+    let Some(token) = bearer_token(request.headers()) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    match validator.validate(token).await {
+        Ok(identity) => {
+            request.extensions_mut().insert(identity.clone());
+            let mut response = next.run(request).await;
+            response.extensions_mut().insert(identity);
+            response
+        }
+        Err(e) => {
+            tracing::warn!("Rejected bearer token: {:?}", e);
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+pub struct OidcValidator {
+    issuer: String,
+    audience: String,
+    tenant_claim: String,
+    jwks_uri: String,
+    client: reqwest::Client,
+    jwks: RwLock<(JwkSet, Instant)>,
+}
+
+impl OidcValidator {
+    /// Discovers the issuer's JWKS endpoint and fetches its signing keys.
+    pub async fn discover(issuer: String, audience: String, tenant_claim: String) -> Result<Self> {
+        #[derive(serde::Deserialize)]
+        struct Discovery {
+            jwks_uri: String,
+        }
+
+        let client = reqwest::Client::new();
+        let discovery: Discovery = client
+            .get(format!(
+                "{}/.well-known/openid-configuration",
+                issuer.trim_end_matches('/')
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("invalid OpenID configuration")?;
+        let jwks = fetch_jwks(&client, &discovery.jwks_uri).await?;
+
+        Ok(Self {
+            issuer,
+            audience,
+            tenant_claim,
+            jwks_uri: discovery.jwks_uri,
+            client,
+            jwks: RwLock::new((jwks, Instant::now())),
+        })
+    }
+
+    pub async fn validate(&self, token: &str) -> Result<Identity> {
+        #[derive(serde::Deserialize)]
+        struct Claims {
+            sub: String,
+            #[serde(flatten)]
+            extra: HashMap<String, serde_json::Value>,
+        }
+
+        let header = jsonwebtoken::decode_header(token)?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(anyhow!("symmetric algorithms are not accepted"));
+        }
+        let kid = header.kid.ok_or_else(|| anyhow!("token has no key id"))?;
+
+        let key = match self.decoding_key(&kid).await? {
+            Some(key) => key,
+            None => {
+                self.refresh_jwks().await?;
+                self.decoding_key(&kid)
+                    .await?
+                    .ok_or_else(|| anyhow!("unknown key id {}", kid))?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+
+        let claims = jsonwebtoken::decode::<Claims>(token, &key, &validation)?.claims;
+        // Only the static API key may span tenants, tokens without a tenant
+        // would otherwise reach every thread and the admin routes.
+        let tenant = claims
+            .extra
+            .get(&self.tenant_claim)
+            .and_then(|value| value.as_str())
+            .filter(|tenant| !tenant.is_empty())
+            .ok_or_else(|| anyhow!("token has no `{}` claim", self.tenant_claim))?;
+
+        Ok(Identity {
+            subject: claims.sub,
+            tenant: Some(tenant.to_owned()),
+            threads: None,
+        })
+    }
+
+    async fn decoding_key(&self, kid: &str) -> Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().await;
+        jwks.0
+            .find(kid)
+            .map(DecodingKey::from_jwk)
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn refresh_jwks(&self) -> Result<()> {
+        let mut jwks = self.jwks.write().await;
+        if jwks.1.elapsed() < JWKS_REFRESH_INTERVAL {
+            return Ok(());
+        }
+
+        *jwks = (
+            fetch_jwks(&self.client, &self.jwks_uri).await?,
+            Instant::now(),
+        );
+        Ok(())
+    }
+}
+
+async fn fetch_jwks(client: &reqwest::Client, jwks_uri: &str) -> Result<JwkSet> {
+    client
+        .get(jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .context("invalid JWKS document")
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+pub fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
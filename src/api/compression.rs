@@ -0,0 +1,49 @@
+//! Compression of response bodies, negotiated with `Accept-Encoding`.
+//! Thread listings, summaries, and search results are mostly text and
+//! shrink several times over, which matters to clients on mobile networks.
+
+use tower_http::compression::{
+    predicate::{NotForContentType, SizeAbove},
+    CompressionLayer, Predicate,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionOptions {
+    pub gzip: bool,
+    pub brotli: bool,
+    /// Bodies shorter than this many bytes are sent as they are, compressing
+    /// them costing more than it saves. Streamed bodies, whose size isn't
+    /// known upfront, are always compressed.
+    pub min_size: u16,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            brotli: true,
+            min_size: 1024,
+        }
+    }
+}
+
+impl CompressionOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.gzip || self.brotli
+    }
+
+    /// Compresses with the enabled algorithm the client prefers. Images are
+    /// sent as they are, being compressed already, and so are server-sent
+    /// events, which must reach the client as they are written.
+    pub fn layer(&self) -> CompressionLayer<impl Predicate> {
+        CompressionLayer::new()
+            .gzip(self.gzip)
+            .br(self.brotli)
+            .compress_when(
+                SizeAbove::new(self.min_size)
+                    .and(NotForContentType::GRPC)
+                    .and(NotForContentType::IMAGES)
+                    .and(NotForContentType::SSE),
+            )
+    }
+}
@@ -0,0 +1,65 @@
+//! Session tokens for read-your-writes: responses to writes carry the
+//! sequence covering them in `X-Sequence`, and requests passing it back in
+//! `X-Min-Sequence` are served once the summaries of those writes are done,
+//! see [`synx::consistency`]. Tokens this run couldn't have issued are
+//! refused with `400 Bad Request` rather than waited for.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use synx::{consistency::SequenceToken, Synx};
+
+pub static SEQUENCE_HEADER: HeaderName = HeaderName::from_static("x-sequence");
+pub static MIN_SEQUENCE_HEADER: HeaderName = HeaderName::from_static("x-min-sequence");
+
+/// Longest a request waits for its `X-Min-Sequence`, after which it fails
+/// with `503 Service Unavailable` rather than serve stale data.
+const MIN_SEQUENCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn consistency_middleware(
+    State(synx): State<Synx>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(value) = request.headers().get(&MIN_SEQUENCE_HEADER) {
+        let Some(min_sequence) = value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<SequenceToken>().ok())
+        else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        match tokio::time::timeout(MIN_SEQUENCE_TIMEOUT, synx.wait_for_sequence(min_sequence)).await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+            Err(_) => {
+                tracing::warn!(
+                    %min_sequence,
+                    visible_sequence = synx.visible_sequence(),
+                    "Timed out waiting for a sequence to be visible"
+                );
+                return StatusCode::SERVICE_UNAVAILABLE.into_response();
+            }
+        }
+    }
+
+    let write = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let mut response = next.run(request).await;
+    if write && response.status().is_success() {
+        let sequence = HeaderValue::try_from(synx.sequence().to_string())
+            .expect("sequence tokens are ASCII digits");
+        response
+            .headers_mut()
+            .insert(SEQUENCE_HEADER.clone(), sequence);
+    }
+    response
+}
@@ -0,0 +1,85 @@
+//! Weak ETags and `If-None-Match`, so clients polling a thread or its
+//! messages get an empty `304 Not Modified` until something changes.
+//!
+//! Tags hash the JSON representation rather than the thread's
+//! `updated_at`: editing a message doesn't touch its thread, and changes in
+//! the same millisecond would share a timestamp.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::api::auth::hex_digest;
+
+/// The weak ETag of `value`'s JSON representation.
+pub fn weak_etag(value: &impl Serialize) -> HeaderValue {
+    let json = serde_json::to_vec(value).unwrap_or_default();
+    HeaderValue::from_str(&format!("W/\"{}\"", hex_digest(&json)))
+        .expect("a hex digest is a valid header value")
+}
+
+/// Whether `If-None-Match` lists `etag`, or is `*`. Tags are compared
+/// weakly, ignoring their `W/` prefix.
+pub fn matches_if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// `304 Not Modified` when the client has the representation tagged `etag`
+/// already, `response` otherwise, either way with the tag.
+pub fn conditional(
+    headers: &HeaderMap,
+    etag: HeaderValue,
+    response: impl IntoResponse,
+) -> Response {
+    if matches_if_none_match(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+
+    ([(header::ETAG, etag)], response).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn if_none_match(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn tags_are_compared_weakly_within_lists() {
+        let etag = weak_etag(&serde_json::json!({ "id": 1 }));
+        let opaque = etag.to_str().unwrap().trim_start_matches("W/").to_string();
+
+        assert!(matches_if_none_match(&if_none_match(&opaque), &etag));
+        assert!(matches_if_none_match(
+            &if_none_match(&format!("W/\"other\", {}", etag.to_str().unwrap())),
+            &etag
+        ));
+        assert!(matches_if_none_match(&if_none_match("*"), &etag));
+        assert!(!matches_if_none_match(&if_none_match("W/\"other\""), &etag));
+        assert!(!matches_if_none_match(&HeaderMap::new(), &etag));
+    }
+
+    #[test]
+    fn tags_change_with_the_representation() {
+        assert_ne!(
+            weak_etag(&serde_json::json!({ "title": "a" })),
+            weak_etag(&serde_json::json!({ "title": "b" }))
+        );
+    }
+}
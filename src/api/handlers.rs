@@ -1,26 +1,100 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
+    body::{Body, Bytes},
+    extract::{OriginalUri, Path, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
+};
+use ferrochain::futures::{Stream, StreamExt};
+use serde::Serialize;
+use synx::{
+    analytics::AnalyticsReport,
+    blobs::image_mime_type,
+    clustering::{Cluster, ClusterRequest},
+    duplicates::{DuplicateReport, DuplicatesDisabled},
+    health::{EmbedderUnavailable, HealthReport},
+    normalization::{NormalizationDisabled, NormalizationReport},
+    pagination::InvalidCursor,
+    pruning::{PruneReport, PruneRequest, RetentionUnset},
+    purge::{InvalidPurge, PurgeReport, PurgeRequest},
+    redaction::PiiBlocked,
+    reduction::{CalibrationReport, CalibrationRequest, ReductionDisabled, ReductionReport},
+    replication::ApplyReport,
+    scheduler::ScheduleStatus,
+    worker_pool::QueueFull,
+    BulkDeleteReport, BulkDeleteRequest, Digest, DigestRequest, MessageComplete, SearchRequest,
+    Synx, TranslateSummaryRequest,
 };
-use ferrochain::vectorstore::Similarity;
-use synx::{SearchRequest, Synx};
+use synx_database::DatabaseError;
 use synx_domain::{
-    message::{CreateMessage, UpdateMessage},
-    thread::{Thread, UpdateThread},
+    annotation::{
+        AnnotatedMessage, Annotation, CreateAnnotation, CreateSearchFeedback, SearchFeedback,
+    },
+    audit::{AuditEntry, AuditFilter},
+    blob::Blob,
+    message::{AppendMessage, CreateMessage, PatchMessage, UpdateMessage},
+    read::{MarkRead, ReadMarker},
+    sync::{Operation, OperationFilter},
+    thread::{
+        CreateThread, PatchThread, RedactSummary, SetSummary, ShadowSummary, SummaryRedactions,
+        Thread, UpdateThread,
+    },
+    usage::{DailyUsage, UsageFilter},
 };
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
-pub struct PaginationParams {
-    limit: Option<usize>,
-    offset: Option<usize>,
+use crate::api::{
+    auth::Identity,
+    etag,
+    pagination::{Page, Paginated, PaginationParams},
+    thread_tokens::{
+        IssueThreadToken, ThreadTokens, DEFAULT_THREAD_TOKEN_TTL, MAX_THREAD_TOKEN_TTL,
+    },
+};
+
+/// Hides threads owned by other tenants as if they didn't exist.
+async fn authorize(synx: &Synx, identity: &Identity, thread_id: Uuid) -> Result<(), StatusCode> {
+    if identity.is_unrestricted() {
+        return Ok(());
+    }
+
+    match synx.get_thread(thread_id).await {
+        Ok(thread) if identity.can_access(&thread) => Ok(()),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(
+                "Failed to authorize access to thread {}: {:?}",
+                thread_id,
+                e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Admin and debug routes, like the op-log, span every tenant, so only
+/// callers without a tenant can use them.
+fn authorize_admin(identity: &Identity) -> Result<(), StatusCode> {
+    if identity.tenant.is_some() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(())
 }
 
-pub async fn create_thread(State(synx): State<Synx>) -> Result<impl IntoResponse, StatusCode> {
+pub async fn create_thread(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<impl IntoResponse, StatusCode> {
     tracing::info!("Attempting to create a new thread");
-    match synx.create_thread().await {
+    match synx
+        .create_thread(CreateThread {
+            tenant: identity.tenant,
+        })
+        .await
+    {
         Ok(thread) => {
             tracing::info!("Thread created successfully: {:?}", thread);
             Ok((StatusCode::CREATED, Json(thread)))
@@ -32,12 +106,38 @@ pub async fn create_thread(State(synx): State<Synx>) -> Result<impl IntoResponse
     }
 }
 
-pub async fn list_threads(State(synx): State<Synx>) -> Result<Json<Vec<Thread>>, StatusCode> {
+/// Lists threads a page at a time, or, when the client accepts
+/// `application/x-ndjson`, every thread streamed one per line, which
+/// neither the server nor the client has to hold in memory at once.
+pub async fn list_threads(
+    State(synx): State<Synx>,
+    identity: Identity,
+    headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    Query(params): Query<PaginationParams>,
+) -> Result<Response, StatusCode> {
+    if accepts_ndjson(&headers) {
+        return match synx.stream_threads_for(&identity.subject).await {
+            Ok(threads) => Ok(ndjson(threads.filter(move |listing| {
+                std::future::ready(
+                    listing
+                        .as_ref()
+                        .map_or(true, |listing| identity.can_access(&listing.thread)),
+                )
+            }))),
+            Err(e) => {
+                tracing::error!("Failed to stream threads: {:?}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+
     tracing::info!("Attempting to list threads");
-    match synx.list_threads().await {
-        Ok(threads) => {
+    match synx.list_threads_for(&identity.subject).await {
+        Ok(mut threads) => {
+            threads.retain(|listing| identity.can_access(&listing.thread));
             tracing::info!("Successfully retrieved {} threads", threads.len());
-            Ok(Json(threads))
+            Ok(params.paginate(&uri, threads).into_response())
         }
         Err(e) => {
             tracing::error!("Failed to list threads: {:?}", e);
@@ -46,12 +146,70 @@ pub async fn list_threads(State(synx): State<Synx>) -> Result<Json<Vec<Thread>>,
     }
 }
 
+/// Groups the caller's threads by topic. See [`synx::clustering`].
+pub async fn cluster_threads(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(mut request): Query<ClusterRequest>,
+) -> Result<Json<Vec<Cluster>>, StatusCode> {
+    if request.k == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    request.tenant = identity.tenant;
+
+    match synx.cluster_threads(request).await {
+        Ok(clusters) => Ok(Json(clusters)),
+        Err(e) => {
+            tracing::error!("Failed to cluster threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Deletes every thread matching the filters at once. At least one filter
+/// is required, so an empty body can't wipe every thread.
+pub async fn bulk_delete_threads(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(mut request): Json<BulkDeleteRequest>,
+) -> Result<Json<BulkDeleteReport>, StatusCode> {
+    if request.filter.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    request.filter.tenant = identity.tenant;
+
+    match synx.delete_threads(request).await {
+        Ok(report) => {
+            tracing::info!(
+                "Bulk deleted {} threads (dry run: {})",
+                report.count,
+                report.dry_run
+            );
+            Ok(Json(report))
+        }
+        Err(e) => {
+            tracing::error!("Failed to bulk delete threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 pub async fn get_thread(
     State(synx): State<Synx>,
+    identity: Identity,
+    headers: HeaderMap,
     Path(thread_id): Path<Uuid>,
-) -> Result<Json<Thread>, StatusCode> {
+) -> Result<Response, StatusCode> {
     match synx.get_thread(thread_id).await {
-        Ok(thread) => Ok(Json(thread)),
+        Ok(thread) if identity.can_access(&thread) => Ok(etag::conditional(
+            &headers,
+            etag::weak_etag(&thread),
+            Json(thread),
+        )),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
         Err(e) => {
             tracing::error!("Failed to get thread {}: {:?}", thread_id, e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -59,11 +217,92 @@ pub async fn get_thread(
     }
 }
 
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct SnapshotParams {
+    /// Number of latest messages, 20 by default.
+    #[serde(default = "default_snapshot_messages")]
+    pub messages: usize,
+}
+
+fn default_snapshot_messages() -> usize {
+    20
+}
+
+/// The thread, its summary, and its latest messages in one response, for
+/// clients opening a conversation.
+pub async fn get_thread_snapshot(
+    State(synx): State<Synx>,
+    identity: Identity,
+    headers: HeaderMap,
+    Path(thread_id): Path<Uuid>,
+    Query(params): Query<SnapshotParams>,
+) -> Result<Response, StatusCode> {
+    match synx.thread_snapshot(thread_id, params.messages).await {
+        Ok(snapshot) if identity.can_access(&snapshot.thread) => Ok(etag::conditional(
+            &headers,
+            etag::weak_etag(&snapshot),
+            Json(snapshot),
+        )),
+        Ok(_) => Err(StatusCode::NOT_FOUND),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!("Failed to get a snapshot of thread {}: {:?}", thread_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Most threads fetched by a single `POST /threads/batch-get`.
+pub const MAX_BATCH_GET_THREADS: usize = 100;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BatchGetThreads {
+    pub thread_ids: Vec<Uuid>,
+    /// Includes the summaries of the threads, left out by default.
+    #[serde(default)]
+    pub include_summaries: bool,
+}
+
+/// The threads among the given ids, in the order given, leaving out those
+/// which don't exist or the caller can't access, e.g. to open the results
+/// of a search without a request per thread.
+pub async fn batch_get_threads(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(request): Json<BatchGetThreads>,
+) -> Result<Json<Vec<Thread>>, StatusCode> {
+    if request.thread_ids.len() > MAX_BATCH_GET_THREADS {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match synx.get_threads(&request.thread_ids).await {
+        Ok(mut threads) => {
+            threads.retain(|thread| identity.can_access(thread));
+            if !request.include_summaries {
+                for thread in &mut threads {
+                    thread.summary = None;
+                    thread.summary_provenance = None;
+                }
+            }
+            Ok(Json(threads))
+        }
+        Err(e) => {
+            tracing::error!("Failed to batch get threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 pub async fn update_thread(
     State(synx): State<Synx>,
+    identity: Identity,
     Path(thread_id): Path<Uuid>,
     Json(update_thread): Json<UpdateThread>,
 ) -> Result<Json<Thread>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
     match synx.update_thread(thread_id, update_thread).await {
         Ok(thread) => Ok(Json(thread)),
         Err(e) => {
@@ -73,22 +312,213 @@ pub async fn update_thread(
     }
 }
 
+pub async fn patch_thread(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(patch): Json<PatchThread>,
+) -> Result<Json<Thread>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.patch_thread(thread_id, patch).await {
+        Ok(thread) => Ok(Json(thread)),
+        Err(e) => {
+            tracing::error!("Failed to patch thread {}: {:?}", thread_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn set_summary(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(input): Json<SetSummary>,
+) -> Result<Json<Thread>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.set_summary(thread_id, input).await {
+        Ok(thread) => Ok(Json(thread)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::InvalidInput(_))) => {
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => {
+            tracing::error!("Failed to set summary of thread {}: {:?}", thread_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn redact_summary(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(input): Json<RedactSummary>,
+) -> Result<Json<SummaryRedactions>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.redact_summary(thread_id, input).await {
+        Ok(redactions) => Ok(Json(redactions)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::InvalidInput(_))) => {
+            Err(StatusCode::BAD_REQUEST)
+        }
+        Err(e) => {
+            tracing::error!("Failed to redact summary of thread {}: {:?}", thread_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Marks the thread as read by the caller up to the given message.
+pub async fn mark_read(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(input): Json<MarkRead>,
+) -> Result<Json<ReadMarker>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.mark_read(thread_id, &identity.subject, input).await {
+        Ok(marker) => Ok(Json(marker)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!("Failed to mark thread {} as read: {:?}", thread_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Issues a token restricted to the thread, and to any other threads listed
+/// which the caller can access. See [`thread_tokens`](crate::api::thread_tokens).
+pub async fn create_thread_token(
+    State(synx): State<Synx>,
+    Extension(tokens): Extension<Arc<ThreadTokens>>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(request): Json<IssueThreadToken>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let ttl = request
+        .ttl_secs
+        .map_or(DEFAULT_THREAD_TOKEN_TTL, Duration::from_secs);
+    if ttl.is_zero() || ttl > MAX_THREAD_TOKEN_TTL {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut thread_ids = vec![thread_id];
+    for thread_id in request.thread_ids {
+        if !thread_ids.contains(&thread_id) {
+            thread_ids.push(thread_id);
+        }
+    }
+    for &thread_id in &thread_ids {
+        match synx.get_thread(thread_id).await {
+            Ok(thread) if identity.can_access(&thread) => {}
+            Ok(_) => return Err(StatusCode::NOT_FOUND),
+            Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+                return Err(StatusCode::NOT_FOUND)
+            }
+            Err(e) => {
+                tracing::error!("Failed to get thread {}: {:?}", thread_id, e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    match tokens.issue(&identity, thread_ids, ttl) {
+        Ok(token) => Ok((StatusCode::CREATED, Json(token))),
+        Err(e) => {
+            tracing::error!("Failed to issue a thread token: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn translate_summary(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(request): Json<TranslateSummaryRequest>,
+) -> Result<Json<Thread>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.translate_summary(thread_id, request).await {
+        Ok(thread) => Ok(Json(thread)),
+        Err(e) => {
+            tracing::error!(
+                "Failed to translate summary of thread {}: {:?}",
+                thread_id,
+                e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+pub struct MessagesView {
+    /// Nests replies under the message they reply to, the page being one of
+    /// root messages.
+    #[serde(default)]
+    pub tree: bool,
+}
+
 pub async fn get_messages(
     State(synx): State<Synx>,
+    identity: Identity,
+    headers: HeaderMap,
     Path(thread_id): Path<Uuid>,
+    OriginalUri(uri): OriginalUri,
     Query(params): Query<PaginationParams>,
-) -> Result<impl IntoResponse, StatusCode> {
-    match synx
-        .get_messages(thread_id, params.limit, params.offset)
-        .await
-    {
-        Ok(response) => {
-            let headers = [
-                ("X-Total-Count", response.total.to_string()),
-                ("X-Offset", response.offset.to_string()),
-                ("X-Limit", response.limit.to_string()),
-            ];
-            Ok((headers, Json(response.messages)))
+    Query(view): Query<MessagesView>,
+) -> Result<Response, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    if view.tree {
+        return match synx.get_message_tree(thread_id).await {
+            Ok(roots) => {
+                let paginated = params.paginate(&uri, roots);
+                let etag = etag::weak_etag(&(&paginated.items, paginated.page.total));
+                Ok(etag::conditional(&headers, etag, paginated))
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to get the message tree of thread {}: {:?}",
+                    thread_id,
+                    e
+                );
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+
+    let annotated = async {
+        let response = synx
+            .get_messages(thread_id, params.limit, params.offset)
+            .await?;
+        let page = Page {
+            total: response.total,
+            offset: response.offset,
+            limit: response.limit,
+        };
+        let messages = synx.with_annotations(thread_id, response.messages).await?;
+        anyhow::Ok((messages, page))
+    };
+    match annotated.await {
+        Ok((items, page)) => {
+            let etag = etag::weak_etag(&(&items, page.total));
+            Ok(etag::conditional(
+                &headers,
+                etag,
+                Paginated { items, page, uri },
+            ))
         }
         Err(e) => {
             tracing::error!("Failed to get messages for thread {}: {:?}", thread_id, e);
@@ -97,13 +527,61 @@ pub async fn get_messages(
     }
 }
 
+pub async fn get_replies(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Vec<AnnotatedMessage>>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    let replies = async {
+        let replies = synx.get_replies(thread_id, message_id).await?;
+        synx.with_annotations(thread_id, replies).await
+    };
+    match replies.await {
+        Ok(replies) => Ok(Json(replies)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to get the replies to message {} in thread {}: {:?}",
+                message_id,
+                thread_id,
+                e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 pub async fn create_message(
     State(synx): State<Synx>,
+    identity: Identity,
     Path(thread_id): Path<Uuid>,
     Json(create_message): Json<CreateMessage>,
 ) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
     match synx.create_message(thread_id, create_message).await {
         Ok(message) => (StatusCode::CREATED, Json(message)).into_response(),
+        Err(e) if e.is::<PiiBlocked>() => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if e.is::<QueueFull>() => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::InvalidInput(_))) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
         Err(e) => {
             tracing::error!("Failed to create message in thread {}: {:?}", thread_id, e);
             (
@@ -115,16 +593,64 @@ pub async fn create_message(
     }
 }
 
+pub async fn create_messages(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+    Json(create_messages): Json<Vec<CreateMessage>>,
+) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
+    match synx.create_messages(thread_id, create_messages).await {
+        Ok(messages) => (StatusCode::CREATED, Json(messages)).into_response(),
+        Err(e) if e.is::<PiiBlocked>() => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if e.is::<QueueFull>() => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::InvalidInput(_))) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to import messages in thread {}: {:?}", thread_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "internal server error" })),
+            )
+                .into_response()
+        }
+    }
+}
+
 pub async fn update_message(
     State(synx): State<Synx>,
+    identity: Identity,
     Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
     Json(update_message): Json<UpdateMessage>,
 ) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
     match synx
         .update_message(thread_id, message_id, update_message)
         .await
     {
         Ok(message) => (StatusCode::OK, Json(message)).into_response(),
+        Err(e) if e.is::<PiiBlocked>() => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
         Err(e) => {
             tracing::error!(
                 "Failed to update message {} in thread {}: {:?}",
@@ -141,10 +667,125 @@ pub async fn update_message(
     }
 }
 
+pub async fn patch_message(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(patch): Json<PatchMessage>,
+) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
+    match synx.patch_message(thread_id, message_id, patch).await {
+        Ok(message) => (StatusCode::OK, Json(message)).into_response(),
+        Err(e) if e.is::<PiiBlocked>() => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to patch message {} in thread {}: {:?}",
+                message_id,
+                thread_id,
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn append_message(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(append): Json<AppendMessage>,
+) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
+    match synx.append_message(thread_id, message_id, append).await {
+        Ok(message) => (StatusCode::OK, Json(message)).into_response(),
+        Err(e) if e.is::<MessageComplete>() => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if e.is::<PiiBlocked>() => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if e.is::<QueueFull>() => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to append to message {} in thread {}: {:?}",
+                message_id,
+                thread_id,
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn finalize_message(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
+) -> Response {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status.into_response();
+    }
+
+    match synx.finalize_message(thread_id, message_id).await {
+        Ok(message) => (StatusCode::OK, Json(message)).into_response(),
+        Err(e) if e.is::<MessageComplete>() => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if e.is::<QueueFull>() => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            StatusCode::NOT_FOUND.into_response()
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to finalize message {} in thread {}: {:?}",
+                message_id,
+                thread_id,
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 pub async fn delete_message(
     State(synx): State<Synx>,
+    identity: Identity,
     Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
 ) -> StatusCode {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status;
+    }
+
     match synx.delete_message(thread_id, message_id).await {
         Ok(_) => StatusCode::NO_CONTENT,
         Err(e) => {
@@ -159,7 +800,72 @@ pub async fn delete_message(
     }
 }
 
-pub async fn delete_thread(State(synx): State<Synx>, Path(thread_id): Path<Uuid>) -> StatusCode {
+pub async fn annotate_message(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id)): Path<(Uuid, Uuid)>,
+    Json(input): Json<CreateAnnotation>,
+) -> Result<Json<Annotation>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx
+        .annotate_message(thread_id, message_id, &identity.subject, input)
+        .await
+    {
+        Ok(annotation) => Ok(Json(annotation)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to annotate message {} in thread {}: {:?}",
+                message_id,
+                thread_id,
+                e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn delete_annotation(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path((thread_id, message_id, annotation_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> StatusCode {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status;
+    }
+
+    match synx
+        .delete_annotation(thread_id, message_id, annotation_id)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            StatusCode::NOT_FOUND
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to delete annotation {} of message {}: {:?}",
+                annotation_id,
+                message_id,
+                e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn delete_thread(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+) -> StatusCode {
+    if let Err(status) = authorize(&synx, &identity, thread_id).await {
+        return status;
+    }
+
     match synx.delete_thread(thread_id).await {
         Ok(_) => StatusCode::NO_CONTENT,
         Err(e) => {
@@ -171,7 +877,10 @@ pub async fn delete_thread(State(synx): State<Synx>, Path(thread_id): Path<Uuid>
 
 pub async fn debug_database_state(
     State(synx): State<Synx>,
+    identity: Identity,
 ) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_admin(&identity)?;
+
     tracing::info!("Debugging database state");
     match synx.debug_state().await {
         Ok(state) => {
@@ -185,12 +894,291 @@ pub async fn debug_database_state(
     }
 }
 
+pub async fn storage_stats(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.storage_stats().await {
+        Ok(stats) => Ok(Json(stats)),
+        Err(e) => {
+            tracing::error!("Failed to get storage statistics: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+const NDJSON: &str = "application/x-ndjson";
+
+fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(NDJSON))
+}
+
+/// A response streaming each item as a line of JSON. An error past the
+/// first line cuts the response short, the status being sent already.
+fn ndjson<T, E>(items: impl Stream<Item = Result<T, E>> + Send + 'static) -> Response
+where
+    T: Serialize,
+    E: Into<anyhow::Error>,
+{
+    let lines = items.map(|item| {
+        let mut line = serde_json::to_vec(&item.map_err(Into::into)?)?;
+        line.push(b'\n');
+        Ok::<_, anyhow::Error>(line)
+    });
+    ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(lines)).into_response()
+}
+
+/// Streams every thread and message as newline-delimited JSON, as of the
+/// request. A failure past the first record cuts the response short.
+pub async fn export(State(synx): State<Synx>, identity: Identity) -> Result<Response, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.export().await {
+        Ok(records) => Ok(ndjson(records)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::Unavailable(_))) => {
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(e) => {
+            tracing::error!("Failed to export: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn analytics(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<AnalyticsReport>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    Ok(Json(synx.analytics()))
+}
+
+pub async fn list_schedules(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<Vec<ScheduleStatus>>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    Ok(Json(synx.schedules()))
+}
+
+pub async fn list_audit_entries(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(filter): Query<AuditFilter>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.list_audit_entries(filter).await {
+        Ok(entries) => Ok(Json(entries)),
+        Err(e) => {
+            tracing::error!("Failed to list audit entries: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Daily usage of the summarizer and embedders, limited to their own for
+/// tenant callers.
+pub async fn list_usage(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(mut filter): Query<UsageFilter>,
+) -> Result<Json<Vec<DailyUsage>>, StatusCode> {
+    if identity.tenant.is_some() {
+        filter.tenant = identity.tenant;
+    }
+
+    match synx.list_usage(filter).await {
+        Ok(usage) => Ok(Json(usage)),
+        Err(e) => {
+            tracing::error!("Failed to list usage: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn duplicate_report(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<DuplicateReport>, StatusCode> {
+    match synx.duplicate_report(identity.tenant.as_deref()) {
+        Ok(report) => Ok(Json(report)),
+        Err(e) if e.is::<DuplicatesDisabled>() => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to report duplicate threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Rebuilds the duplicate report, comparing every pair of threads.
+pub async fn scan_duplicates(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<DuplicateReport>, StatusCode> {
+    match synx.scan_duplicates(identity.tenant.as_deref()).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) if e.is::<DuplicatesDisabled>() => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to scan for duplicate threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn calibrate_reduction(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(request): Query<CalibrationRequest>,
+) -> Result<Json<CalibrationReport>, StatusCode> {
+    authorize_admin(&identity)?;
+    if request.dimensions == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match synx.calibrate_reduction(request.dimensions).await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) => {
+            tracing::error!("Failed to calibrate embedding reduction: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn reduce_embeddings(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<ReductionReport>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.reduce_stored_embeddings().await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) if e.is::<ReductionDisabled>() => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to reduce stored embeddings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn normalize_embeddings(
+    State(synx): State<Synx>,
+    identity: Identity,
+) -> Result<Json<NormalizationReport>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.normalize_stored_embeddings().await {
+        Ok(report) => Ok(Json(report)),
+        Err(e) if e.is::<NormalizationDisabled>() => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!("Failed to normalize stored embeddings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn purge(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(mut request): Json<PurgeRequest>,
+) -> Result<Json<PurgeReport>, StatusCode> {
+    // A tenant can only purge its own threads.
+    if identity.tenant.is_some() {
+        request.tenant = identity.tenant;
+    }
+
+    match synx.purge(request).await {
+        Ok(report) => {
+            tracing::info!(
+                "Purged {} messages from {} threads (dry run: {})",
+                report.messages_deleted,
+                report.messages.len(),
+                report.request.dry_run
+            );
+            Ok(Json(report))
+        }
+        Err(e) if e.is::<InvalidPurge>() => Err(StatusCode::BAD_REQUEST),
+        Err(e) => {
+            tracing::error!("Failed to purge messages: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn prune_messages(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(mut request): Json<PruneRequest>,
+) -> Result<Json<PruneReport>, StatusCode> {
+    // A tenant can only prune its own threads.
+    if identity.tenant.is_some() {
+        request.tenant = identity.tenant;
+    }
+
+    match synx.prune_messages(request).await {
+        Ok(report) => {
+            tracing::info!(
+                "Pruned {} messages from {} threads (dry run: {})",
+                report.messages_pruned,
+                report.messages.len(),
+                report.request.dry_run
+            );
+            Ok(Json(report))
+        }
+        Err(e) if e.is::<RetentionUnset>() => Err(StatusCode::BAD_REQUEST),
+        Err(e) => {
+            tracing::error!("Failed to prune messages: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Set on search responses served from the cache while the query embedder
+/// is unavailable.
+static SEARCH_DEGRADED_HEADER: HeaderName = HeaderName::from_static("x-search-degraded");
+static TOTAL_COUNT_HEADER: HeaderName = HeaderName::from_static("x-total-count");
+static NEXT_CURSOR_HEADER: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// Responds with the requested page of results, `X-Total-Count` being the
+/// number of results in every page and `X-Next-Cursor` resuming the search
+/// after them.
 pub async fn search_threads(
     State(synx): State<Synx>,
-    Json(search_request): Json<SearchRequest>,
-) -> Result<Json<Vec<Similarity>>, StatusCode> {
+    identity: Identity,
+    Json(mut search_request): Json<SearchRequest>,
+) -> Result<Response, StatusCode> {
+    search_request.tenant = identity.tenant;
+    search_request.reader = Some(identity.subject);
     match synx.search_threads(search_request).await {
-        Ok(similarities) => Ok(Json(similarities)),
+        Ok(search) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(TOTAL_COUNT_HEADER.clone(), HeaderValue::from(search.total));
+            if let Some(cursor) = search
+                .next_cursor
+                .and_then(|cursor| HeaderValue::from_str(&cursor).ok())
+            {
+                headers.insert(NEXT_CURSOR_HEADER.clone(), cursor);
+            }
+            if search.degraded {
+                headers.insert(
+                    SEARCH_DEGRADED_HEADER.clone(),
+                    HeaderValue::from_static("true"),
+                );
+            }
+            Ok((headers, Json(search.results)).into_response())
+        }
+        Err(e) if e.is::<InvalidCursor>() => Err(StatusCode::BAD_REQUEST),
+        Err(e) if e.is::<EmbedderUnavailable>() => {
+            tracing::warn!("Failed to search threads: {}", e);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
         Err(e) => {
             tracing::error!("Failed to search threads: {:?}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
@@ -198,6 +1186,182 @@ pub async fn search_threads(
     }
 }
 
+pub async fn search_feedback(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(input): Json<CreateSearchFeedback>,
+) -> Result<Json<SearchFeedback>, StatusCode> {
+    authorize(&synx, &identity, input.thread_id).await?;
+
+    match synx.record_search_feedback(&identity.subject, input).await {
+        Ok(feedback) => Ok(Json(feedback)),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!("Failed to record search feedback: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Stores the request body as a blob, typed with its `Content-Type`.
+/// Largest blob uploaded, well above axum's default body limit of 2MB.
+pub const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+pub async fn upload_blob(
+    State(synx): State<Synx>,
+    identity: Identity,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Blob>, StatusCode> {
+    if body.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(image_mime_type)
+        .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+
+    match synx
+        .put_blob(body.to_vec(), Some(mime_type.to_string()), identity.tenant)
+        .await
+    {
+        Ok(blob) => Ok(Json(blob)),
+        Err(e) => {
+            tracing::error!("Failed to store blob: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Serves a blob's data. Blobs never change, so they can be cached forever,
+/// though only by the caller's browser as they are only served to their
+/// tenant. Blobs of other tenants are hidden as if they didn't exist. They
+/// are served as attachments, and as opaque bytes unless they are images,
+/// so browsers never render them on the API's origin.
+pub async fn get_blob(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(hash): Path<String>,
+) -> Result<Response, StatusCode> {
+    match synx.get_blob(&hash).await {
+        Ok((blob, _)) if identity.tenant.is_some() && blob.tenant != identity.tenant => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Ok((blob, data)) => Ok((
+            [
+                (
+                    header::CONTENT_TYPE,
+                    blob.mime_type
+                        .as_deref()
+                        .and_then(image_mime_type)
+                        .unwrap_or("application/octet-stream"),
+                ),
+                (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (header::CONTENT_DISPOSITION, "attachment"),
+                (
+                    header::CACHE_CONTROL,
+                    "private, max-age=31536000, immutable",
+                ),
+            ],
+            data,
+        )
+            .into_response()),
+        Err(e) if matches!(e.downcast_ref(), Some(DatabaseError::NotFound)) => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            tracing::error!("Failed to get blob {}: {:?}", hash, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn digest(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(mut digest_request): Query<DigestRequest>,
+) -> Result<Json<Digest>, StatusCode> {
+    digest_request.tenant = identity.tenant;
+    match synx.digest(digest_request).await {
+        Ok(digest) => Ok(Json(digest)),
+        Err(e) => {
+            tracing::error!("Failed to digest threads: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn get_shadow_summary(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Path(thread_id): Path<Uuid>,
+) -> Result<Json<ShadowSummary>, StatusCode> {
+    authorize(&synx, &identity, thread_id).await?;
+
+    match synx.get_shadow_summary(thread_id).await {
+        Ok(Some(shadow)) => Ok(Json(shadow)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(
+                "Failed to get shadow summary of thread {}: {:?}",
+                thread_id,
+                e
+            );
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Operations appended after `since_seq`, in order. Consumers resume from
+/// the `seq` of the last operation they processed.
+pub async fn list_changes(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Query(filter): Query<OperationFilter>,
+) -> Result<Json<Vec<Operation>>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.list_operations(filter).await {
+        Ok(operations) => Ok(Json(operations)),
+        Err(e) => {
+            tracing::error!("Failed to list changes: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn apply_operations(
+    State(synx): State<Synx>,
+    identity: Identity,
+    Json(operations): Json<Vec<Operation>>,
+) -> Result<Json<ApplyReport>, StatusCode> {
+    authorize_admin(&identity)?;
+
+    match synx.apply_operations(operations).await {
+        Ok(report) => {
+            tracing::info!(
+                "Applied {} operations, skipped {}",
+                report.applied,
+                report.skipped
+            );
+            Ok(Json(report))
+        }
+        Err(e) => {
+            tracing::error!("Failed to apply operations: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 pub async fn healthz() -> StatusCode {
     StatusCode::OK
 }
+
+/// The health of the providers. Degraded instances still serve requests, so
+/// they are reported rather than failing the probe.
+pub async fn readyz(State(synx): State<Synx>) -> Json<HealthReport> {
+    Json(synx.health())
+}
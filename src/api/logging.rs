@@ -0,0 +1,243 @@
+use std::time::Instant;
+
+use axum::{
+    body::{self, Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::{Map, Value};
+use tracing::Span;
+
+use crate::api::auth::Identity;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request id accepted from clients.
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+/// Error bodies larger than this are passed through without a request id.
+const MAX_ERROR_BODY_SIZE: usize = 64 * 1024;
+
+/// Whether a client-supplied request id is logged and echoed as is: ids are
+/// at most [`MAX_REQUEST_ID_LEN`] ASCII letters, digits, `.`, `_`, or `-`.
+pub fn is_valid_request_id(request_id: &HeaderValue) -> bool {
+    let bytes = request_id.as_bytes();
+    !bytes.is_empty()
+        && bytes.len() <= MAX_REQUEST_ID_LEN
+        && bytes
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/// Drops invalid client-supplied request ids, see [`is_valid_request_id`],
+/// so that one is generated in their place. Goes outside the layer setting
+/// request ids.
+pub async fn request_id_validation_middleware(mut request: Request, next: Next) -> Response {
+    let invalid = request
+        .headers()
+        .get_all(&REQUEST_ID_HEADER)
+        .iter()
+        .enumerate()
+        .any(|(i, request_id)| i > 0 || !is_valid_request_id(request_id));
+    if invalid {
+        tracing::debug!("Replacing an invalid request id");
+        request.headers_mut().remove(&REQUEST_ID_HEADER);
+    }
+
+    next.run(request).await
+}
+
+/// Adds the request id to error responses, as `request_id` in JSON bodies,
+/// and in a `{ "error", "request_id" }` body for empty ones, so clients can
+/// report it along with the error. Goes inside the layer setting request ids
+/// and outside compression.
+pub async fn error_request_id_middleware(request: Request, next: Next) -> Response {
+    let request_id = request.headers().get(&REQUEST_ID_HEADER).cloned();
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let Some(request_id) = request_id.and_then(|id| id.to_str().map(str::to_owned).ok()) else {
+        return response;
+    };
+    if !(status.is_client_error() || status.is_server_error())
+        || response.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return response;
+    }
+
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/json"));
+    let is_empty = response.body().size_hint().exact() == Some(0);
+    if !is_json && !is_empty {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match body::to_bytes(body, MAX_ERROR_BODY_SIZE).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Failed to read error response body: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let mut error = if is_empty {
+        let mut error = Map::new();
+        error.insert(
+            "error".to_string(),
+            status.canonical_reason().unwrap_or_default().into(),
+        );
+        error
+    } else {
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(Value::Object(error)) => error,
+            _ => return Response::from_parts(parts, Body::from(bytes)),
+        }
+    };
+    error.insert("request_id".to_string(), request_id.into());
+
+    let body = serde_json::to_vec(&error).expect("JSON objects serialize");
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Opens the per-request span, tagged with the request id so every event
+/// logged while serving the request can be correlated with it.
+pub fn make_request_span(request: &Request) -> Span {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
+}
+
+pub async fn request_log_middleware(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    let (identity, tenant) = response
+        .extensions()
+        .get::<Identity>()
+        .map(|identity| (identity.subject.clone(), identity.tenant.clone()))
+        .unwrap_or_else(|| ("anonymous".to_string(), None));
+
+    tracing::info!(
+        target: "synx::request",
+        %method,
+        %path,
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        %identity,
+        tenant = tenant.as_deref(),
+        "request completed"
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::StatusCode, middleware, response::IntoResponse, routing::get, Json, Router};
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+    use super::*;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }))
+            .route(
+                "/invalid",
+                get(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({ "error": "invalid limit" })),
+                    )
+                        .into_response()
+                }),
+            )
+            .route("/ok", get(|| async { Json(serde_json::json!({})) }))
+            .layer(middleware::from_fn(error_request_id_middleware))
+            .layer(PropagateRequestIdLayer::new(REQUEST_ID_HEADER.clone()))
+            .layer(SetRequestIdLayer::new(
+                REQUEST_ID_HEADER.clone(),
+                MakeRequestUuid,
+            ))
+            .layer(middleware::from_fn(request_id_validation_middleware))
+    }
+
+    async fn get_with(uri: &str, request_id: &str) -> (StatusCode, String, Value) {
+        let request = Request::get(uri)
+            .header(&REQUEST_ID_HEADER, request_id)
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let request_id = response.headers()[&REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, request_id, body)
+    }
+
+    #[tokio::test]
+    async fn error_bodies_carry_the_request_id() {
+        let (status, request_id, body) = get_with("/missing", "req-1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(request_id, "req-1");
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "Not Found", "request_id": "req-1" })
+        );
+
+        let (_, _, body) = get_with("/invalid", "req-2").await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "invalid limit", "request_id": "req-2" })
+        );
+
+        let (status, _, body) = get_with("/ok", "req-3").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({}));
+    }
+
+    #[tokio::test]
+    async fn invalid_request_ids_are_replaced() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for invalid in [
+            "with spaces",
+            "new\tline",
+            "\u{e9}t\u{e9}",
+            too_long.as_str(),
+        ] {
+            let (_, request_id, body) = get_with("/missing", invalid).await;
+            assert_ne!(request_id, invalid);
+            assert!(uuid::Uuid::parse_str(&request_id).is_ok());
+            assert_eq!(body["request_id"], request_id.as_str());
+        }
+
+        let (_, request_id, _) = get_with("/missing", "trace.01_A-b").await;
+        assert_eq!(request_id, "trace.01_A-b");
+    }
+}
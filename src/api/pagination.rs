@@ -0,0 +1,186 @@
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+pub struct PaginationParams {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl PaginationParams {
+    /// Slices a list fetched in full, e.g. threads, into the requested page.
+    pub fn paginate<T: Serialize>(self, uri: &Uri, items: Vec<T>) -> Paginated<T> {
+        let total = items.len();
+        let offset = self.offset.unwrap_or(0).min(total);
+        let limit = self.limit.unwrap_or(total);
+
+        Paginated {
+            items: items.into_iter().skip(offset).take(limit).collect(),
+            page: Page {
+                total,
+                offset,
+                limit,
+            },
+            uri: uri.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Page {
+    pub fn total_pages(&self) -> usize {
+        if self.limit == 0 {
+            return 1;
+        }
+        self.total.div_ceil(self.limit).max(1)
+    }
+
+    /// Offsets of the `first`, `prev`, `next`, and `last` pages, those past
+    /// either end being left out.
+    fn relations(&self) -> Vec<(&'static str, usize)> {
+        let mut relations = vec![("first", 0)];
+        if self.limit == 0 {
+            return relations;
+        }
+        if self.offset > 0 {
+            relations.push(("prev", self.offset.saturating_sub(self.limit)));
+        }
+        if self.offset + self.limit < self.total {
+            relations.push(("next", self.offset + self.limit));
+        }
+        relations.push(("last", (self.total_pages() - 1) * self.limit));
+        relations
+    }
+
+    /// `X-Total-Count`, `X-Total-Pages`, `X-Offset`, and `X-Limit`, along with
+    /// an RFC 5988 `Link` header pointing at the other pages of `uri`.
+    pub fn headers(&self, uri: &Uri) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-total-count", self.total),
+            ("x-total-pages", self.total_pages()),
+            ("x-offset", self.offset),
+            ("x-limit", self.limit),
+        ] {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+        }
+
+        let links = self
+            .relations()
+            .into_iter()
+            .map(|(rel, offset)| {
+                format!("<{}>; rel=\"{}\"", page_uri(uri, offset, self.limit), rel)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(links) = HeaderValue::from_str(&links) {
+            headers.insert(axum::http::header::LINK, links);
+        }
+
+        headers
+    }
+}
+
+/// The path and query of `uri`, with its `offset` and `limit` replaced.
+fn page_uri(uri: &Uri, offset: usize, limit: usize) -> String {
+    let mut query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            !pair.is_empty() && key != "offset" && key != "limit"
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    query.push(format!("offset={}", offset));
+    query.push(format!("limit={}", limit));
+
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+/// A page of a list endpoint, served with its pagination headers.
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub page: Page,
+    /// The requested URI, which the `Link` header points at other pages of.
+    pub uri: Uri,
+}
+
+impl<T: Serialize> IntoResponse for Paginated<T> {
+    fn into_response(self) -> Response {
+        (self.page.headers(&self.uri), Json(self.items)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(page: Page, uri: &str) -> String {
+        page.headers(&uri.parse().unwrap())
+            .get(axum::http::header::LINK)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn middle_page_links_every_relation() {
+        let page = Page {
+            total: 25,
+            offset: 10,
+            limit: 10,
+        };
+
+        assert_eq!(page.total_pages(), 3);
+        assert_eq!(
+            links(page, "/threads?limit=10&offset=10&tag=a"),
+            "</threads?tag=a&offset=0&limit=10>; rel=\"first\", \
+             </threads?tag=a&offset=0&limit=10>; rel=\"prev\", \
+             </threads?tag=a&offset=20&limit=10>; rel=\"next\", \
+             </threads?tag=a&offset=20&limit=10>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn single_page_has_no_prev_or_next() {
+        let page = Page {
+            total: 3,
+            offset: 0,
+            limit: 3,
+        };
+
+        assert_eq!(
+            links(page, "/threads"),
+            "</threads?offset=0&limit=3>; rel=\"first\", \
+             </threads?offset=0&limit=3>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn empty_list_has_one_page() {
+        let page = Page {
+            total: 0,
+            offset: 0,
+            limit: 0,
+        };
+
+        assert_eq!(page.total_pages(), 1);
+        assert_eq!(
+            links(page, "/threads"),
+            "</threads?offset=0&limit=0>; rel=\"first\""
+        );
+    }
+}
@@ -1,29 +1,115 @@
 use axum::{
-    routing::{delete, get, post, put},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use synx::Synx;
 
-use crate::api::handlers;
+use crate::api::{audit, consistency, handlers};
 
 pub fn router(synx: Synx) -> Router {
     Router::new()
         .route("/threads", post(handlers::create_thread))
         .route("/threads", get(handlers::list_threads))
+        .route("/threads/bulk-delete", post(handlers::bulk_delete_threads))
+        .route("/threads/batch-get", post(handlers::batch_get_threads))
+        .route("/threads/clusters", get(handlers::cluster_threads))
         .route("/threads/:id", get(handlers::get_thread))
         .route("/threads/:id", delete(handlers::delete_thread))
         .route("/threads/:id", put(handlers::update_thread))
+        .route("/threads/:id", patch(handlers::patch_thread))
+        .route("/threads/:id/snapshot", get(handlers::get_thread_snapshot))
+        .route("/threads/:id/read", post(handlers::mark_read))
+        .route("/threads/:id/tokens", post(handlers::create_thread_token))
+        .route("/threads/:id/summary", put(handlers::set_summary))
+        .route("/threads/:id/summary", patch(handlers::redact_summary))
+        .route(
+            "/threads/:id/summary/translate",
+            post(handlers::translate_summary),
+        )
         .route("/threads/:id/messages", post(handlers::create_message))
         .route("/threads/:id/messages", get(handlers::get_messages))
+        .route(
+            "/threads/:id/messages/batch",
+            post(handlers::create_messages),
+        )
         .route(
             "/threads/:thread_id/messages/:message_id",
             put(handlers::update_message),
         )
+        .route(
+            "/threads/:thread_id/messages/:message_id",
+            patch(handlers::patch_message),
+        )
         .route(
             "/threads/:thread_id/messages/:message_id",
             delete(handlers::delete_message),
         )
+        .route(
+            "/threads/:thread_id/messages/:message_id/append",
+            post(handlers::append_message),
+        )
+        .route(
+            "/threads/:thread_id/messages/:message_id/finalize",
+            post(handlers::finalize_message),
+        )
+        .route(
+            "/threads/:thread_id/messages/:message_id/replies",
+            get(handlers::get_replies),
+        )
+        .route(
+            "/threads/:thread_id/messages/:message_id/annotations",
+            post(handlers::annotate_message),
+        )
+        .route(
+            "/threads/:thread_id/messages/:message_id/annotations/:annotation_id",
+            delete(handlers::delete_annotation),
+        )
         .route("/search", post(handlers::search_threads))
+        .route("/search/feedback", post(handlers::search_feedback))
+        .route(
+            "/blobs",
+            post(handlers::upload_blob).layer(DefaultBodyLimit::max(handlers::MAX_BLOB_SIZE)),
+        )
+        .route("/blobs/:hash", get(handlers::get_blob))
+        .route("/digest", get(handlers::digest))
         .route("/debug/database", get(handlers::debug_database_state))
+        .route("/admin/audit", get(handlers::list_audit_entries))
+        .route("/admin/analytics", get(handlers::analytics))
+        .route("/admin/stats", get(handlers::storage_stats))
+        .route("/admin/export", get(handlers::export))
+        .route("/admin/usage", get(handlers::list_usage))
+        .route("/admin/duplicates", get(handlers::duplicate_report))
+        .route("/admin/duplicates/scan", post(handlers::scan_duplicates))
+        .route("/admin/purge", post(handlers::purge))
+        .route("/admin/prune", post(handlers::prune_messages))
+        .route(
+            "/admin/embeddings/calibration",
+            get(handlers::calibrate_reduction),
+        )
+        .route(
+            "/admin/embeddings/reduce",
+            post(handlers::reduce_embeddings),
+        )
+        .route(
+            "/admin/embeddings/normalize",
+            post(handlers::normalize_embeddings),
+        )
+        .route("/admin/schedules", get(handlers::list_schedules))
+        .route(
+            "/admin/threads/:id/shadow-summary",
+            get(handlers::get_shadow_summary),
+        )
+        .route("/changes", get(handlers::list_changes))
+        .route("/sync/operations", post(handlers::apply_operations))
+        .route_layer(middleware::from_fn_with_state(
+            synx.clone(),
+            audit::audit_middleware,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            synx.clone(),
+            consistency::consistency_middleware,
+        ))
         .with_state(synx)
 }
@@ -0,0 +1,220 @@
+//! Short-lived tokens restricted to some threads, issued with
+//! `POST /threads/:id/tokens`, so an agent run can be given credentials that
+//! can't read other conversations.
+//!
+//! Tokens are JWTs signed with the server's secret, prefixed to tell them
+//! apart from API keys and OIDC tokens. They carry the tenant of the caller
+//! who issued them and only reach routes under `/threads/:id` for the
+//! threads they name, except issuing more tokens. Tokens can't be revoked:
+//! they expire instead.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use uuid::Uuid;
+
+use crate::api::auth::Identity;
+
+const TOKEN_PREFIX: &str = "synx_thread.";
+pub const DEFAULT_THREAD_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+pub const MAX_THREAD_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct ThreadTokens {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Claims {
+    sub: String,
+    tenant: Option<String>,
+    threads: Vec<Uuid>,
+    exp: i64,
+}
+
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct IssueThreadToken {
+    /// Threads the token reaches besides the one it is issued on.
+    #[serde(default)]
+    pub thread_ids: Vec<Uuid>,
+    /// Seconds until the token expires, an hour by default and a day at
+    /// most.
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ThreadToken {
+    pub token: String,
+    pub thread_ids: Vec<Uuid>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ThreadTokens {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// Signs tokens with a random secret, so they don't outlive the process
+    /// and aren't accepted by other instances.
+    pub fn ephemeral() -> Self {
+        let secret = [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat();
+        Self::new(&secret)
+    }
+
+    /// A token acting as `identity` on `thread_ids` only, which the caller
+    /// is expected to have checked it can access.
+    pub fn issue(
+        &self,
+        identity: &Identity,
+        thread_ids: Vec<Uuid>,
+        ttl: Duration,
+    ) -> Result<ThreadToken> {
+        let expires_at = Utc::now() + chrono::Duration::from_std(ttl)?;
+        let claims = Claims {
+            sub: identity.subject.clone(),
+            tenant: identity.tenant.clone(),
+            threads: thread_ids.clone(),
+            exp: expires_at.timestamp(),
+        };
+        let token =
+            jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)?;
+
+        Ok(ThreadToken {
+            token: format!("{}{}", TOKEN_PREFIX, token),
+            thread_ids,
+            expires_at,
+        })
+    }
+
+    pub fn validate(&self, token: &str) -> Result<Identity> {
+        let token = token
+            .strip_prefix(TOKEN_PREFIX)
+            .ok_or_else(|| anyhow!("not a thread token"))?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding_key, &validation)?.claims;
+
+        Ok(Identity {
+            subject: format!("thread-token:{}", claims.sub),
+            tenant: claims.tenant,
+            threads: Some(claims.threads),
+        })
+    }
+}
+
+/// Runs before the API key or OIDC middleware, which let requests it
+/// authenticated through. Requests with a thread token are confined to the
+/// routes of its threads. Every request gets the signer, for
+/// `POST /threads/:id/tokens`.
+pub async fn thread_token_middleware(
+    State(tokens): State<Arc<ThreadTokens>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .filter(|token| token.starts_with(TOKEN_PREFIX));
+
+    let identity = match token.map(|token| tokens.validate(token)) {
+        Some(Ok(identity)) => Some(identity),
+        Some(Err(e)) => {
+            tracing::warn!("Rejected thread token: {:?}", e);
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        None => None,
+    };
+
+    if let Some(identity) = &identity {
+        if let Err(status) = check_scope(identity, request.uri().path()) {
+            return status.into_response();
+        }
+        request.extensions_mut().insert(identity.clone());
+    }
+    request.extensions_mut().insert(tokens);
+
+    let mut response = next.run(request).await;
+    if let Some(identity) = identity {
+        response.extensions_mut().insert(identity);
+    }
+    response
+}
+
+/// Threads other than the token's are hidden as if they didn't exist.
+fn check_scope(identity: &Identity, path: &str) -> Result<(), StatusCode> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let (Some("threads"), Some(thread_id), next) =
+        (segments.next(), segments.next(), segments.next())
+    else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    let Ok(thread_id) = thread_id.parse::<Uuid>() else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+    if next == Some("tokens") {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    match &identity.threads {
+        Some(threads) if !threads.contains(&thread_id) => Err(StatusCode::NOT_FOUND),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_reach_their_threads_only() {
+        let tokens = ThreadTokens::new(b"secret");
+        let thread_id = Uuid::new_v4();
+        let issued = tokens
+            .issue(
+                &Identity {
+                    subject: "agent".to_string(),
+                    tenant: Some("acme".to_string()),
+                    threads: None,
+                },
+                vec![thread_id],
+                DEFAULT_THREAD_TOKEN_TTL,
+            )
+            .unwrap();
+
+        let identity = tokens.validate(&issued.token).unwrap();
+        assert_eq!(identity.tenant.as_deref(), Some("acme"));
+        assert!(check_scope(&identity, &format!("/threads/{}/messages", thread_id)).is_ok());
+        assert_eq!(
+            check_scope(&identity, &format!("/threads/{}", Uuid::new_v4())),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            check_scope(&identity, &format!("/threads/{}/tokens", thread_id)),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check_scope(&identity, "/threads/bulk-delete"),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            check_scope(&identity, "/search"),
+            Err(StatusCode::FORBIDDEN)
+        );
+
+        assert!(ThreadTokens::new(b"other").validate(&issued.token).is_err());
+        assert!(tokens.validate("synx_thread.garbage").is_err());
+    }
+}
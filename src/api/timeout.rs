@@ -0,0 +1,101 @@
+//! Per-route request timeouts. A request taking longer is answered with
+//! `408 Request Timeout` and its handler dropped, along with the `Synx` call
+//! it was awaiting, as when the client disconnects: searches stop scoring at
+//! their next batch of threads, and exports stop reading their snapshot.
+//!
+//! Only the time until the response starts is bounded. Streamed bodies,
+//! e.g. exports and NDJSON listings, go on until the client stops reading.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub default: Duration,
+    /// Searches embed the query and score every thread listed, so they are
+    /// given up on sooner.
+    pub search: Duration,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        Self {
+            default: Duration::from_secs(30),
+            search: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RequestTimeouts {
+    pub fn for_route(&self, route: &str) -> Duration {
+        match route {
+            "/search" => self.search,
+            _ => self.default,
+        }
+    }
+}
+
+pub async fn timeout_middleware(
+    State(timeouts): State<RequestTimeouts>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| request.uri().path().to_owned());
+    let timeout = timeouts.for_route(&route);
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("Request to {} timed out after {:?}", route, timeout);
+            StatusCode::REQUEST_TIMEOUT.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_routes_time_out() {
+        let app = Router::new()
+            .route(
+                "/search",
+                get(|| async { tokio::time::sleep(Duration::from_secs(5)).await }),
+            )
+            .route(
+                "/threads",
+                get(|| async { tokio::time::sleep(Duration::from_millis(50)).await }),
+            )
+            .route_layer(middleware::from_fn_with_state(
+                RequestTimeouts {
+                    default: Duration::from_secs(5),
+                    search: Duration::from_millis(10),
+                },
+                timeout_middleware,
+            ));
+        let get = |uri: &str| {
+            app.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        };
+
+        assert_eq!(
+            get("/search").await.unwrap().status(),
+            StatusCode::REQUEST_TIMEOUT
+        );
+        assert_eq!(get("/threads").await.unwrap().status(), StatusCode::OK);
+    }
+}
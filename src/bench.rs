@@ -0,0 +1,256 @@
+//! Seeding and timing of database and search operations, shared by the
+//! criterion benchmarks and `synx bench`, so performance can be
+//! compared across backends.
+//!
+//! Summaries are made with the fake providers of [`synx::testing`], only
+//! the database and the search itself are measured.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use ferrochain::embedding::Embedder;
+use synx::{
+    testing::{FakeEmbedder, FakeSummarizer},
+    SearchRequest, Synx,
+};
+use synx_database::Db;
+use synx_domain::{message::CreateMessage, role::Role, thread::CreateThread};
+use uuid::Uuid;
+
+const VOCABULARY: &[&str] = &[
+    "weather",
+    "forecast",
+    "paris",
+    "rome",
+    "recipe",
+    "pancakes",
+    "invoice",
+    "refund",
+    "travel",
+    "flight",
+    "hotel",
+    "budget",
+    "meeting",
+    "deadline",
+    "report",
+    "database",
+    "migration",
+    "release",
+    "bug",
+    "deploy",
+    "garden",
+    "tomatoes",
+    "running",
+    "marathon",
+    "piano",
+    "lesson",
+    "mortgage",
+    "rate",
+    "holiday",
+    "museum",
+    "train",
+    "ticket",
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct BenchOptions {
+    pub threads: usize,
+    pub messages_per_thread: usize,
+    /// Reads and searches timed, each on another thread.
+    pub samples: usize,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            threads: 100,
+            messages_per_thread: 10,
+            samples: 100,
+        }
+    }
+}
+
+/// Percentiles of the latencies of an operation, in milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct Latency {
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Latency {
+    pub fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+
+        let percentile = |p: f64| {
+            let index = ((samples.len() as f64 * p).ceil() as usize).clamp(1, samples.len()) - 1;
+            samples[index].as_secs_f64() * 1000.0
+        };
+        Self {
+            samples: samples.len(),
+            p50_ms: percentile(0.5),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms: percentile(1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct BenchReport {
+    pub threads: usize,
+    pub messages: usize,
+    pub create_messages_per_sec: f64,
+    pub create_message: Latency,
+    pub get_thread_messages: Latency,
+    pub search: Latency,
+}
+
+/// A [`Synx`] over `db` with the fake providers, which only costs the time
+/// of the database and of the search itself.
+pub fn bench_synx(db: Arc<dyn Db>) -> Result<Synx> {
+    Ok(Synx::builder()
+        .with_db(db)
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(Arc::new(FakeSummarizer))
+        .build()?)
+}
+
+/// A few words of the vocabulary, the same for the same seed.
+pub fn text(seed: usize, words: usize) -> String {
+    (0..words)
+        .map(|index| VOCABULARY[(seed * 31 + index * 7 + index * index) % VOCABULARY.len()])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn message(seed: usize) -> CreateMessage {
+    CreateMessage {
+        role: if seed % 2 == 0 {
+            Role::User
+        } else {
+            Role::Assistant
+        },
+        content: text(seed, 24).into(),
+        parent_message_id: None,
+        metadata: Default::default(),
+        status: Default::default(),
+        memorize: true,
+    }
+}
+
+/// Creates `options.threads` threads of `options.messages_per_thread`
+/// messages, each with a summary and its embedding, returning their ids and
+/// the time each message took to create.
+pub async fn seed(db: &dyn Db, options: BenchOptions) -> Result<(Vec<Uuid>, Vec<Duration>)> {
+    let embedder = FakeEmbedder::new();
+    let mut thread_ids = Vec::with_capacity(options.threads);
+    let mut timings = Vec::with_capacity(options.threads * options.messages_per_thread);
+    for thread_index in 0..options.threads {
+        let thread = db.create_thread(CreateThread::default()).await?;
+        for message_index in 0..options.messages_per_thread {
+            let start = Instant::now();
+            db.create_message(
+                thread.id,
+                message(thread_index * options.messages_per_thread + message_index),
+            )
+            .await?;
+            timings.push(start.elapsed());
+        }
+
+        let summary = text(thread_index, 48);
+        let embedding = embedder
+            .embed(vec![summary.clone()])
+            .await?
+            .pop()
+            .context("the embedder returned no embedding")?;
+        db.update_thread_summary_and_embedding(
+            thread.id,
+            summary,
+            Default::default(),
+            embedding,
+            None,
+        )
+        .await?;
+        thread_ids.push(thread.id);
+    }
+
+    Ok((thread_ids, timings))
+}
+
+/// Seeds `db` and times message creation, message listing, and search.
+pub async fn run(db: Arc<dyn Db>, options: BenchOptions) -> Result<BenchReport> {
+    let synx = bench_synx(db.clone())?;
+
+    let start = Instant::now();
+    let (thread_ids, create_timings) = seed(db.as_ref(), options).await?;
+    let seeding = start.elapsed();
+    let messages = create_timings.len();
+    let creating = create_timings.iter().sum::<Duration>();
+
+    let mut read_timings = Vec::with_capacity(options.samples);
+    let mut search_timings = Vec::with_capacity(options.samples);
+    if !thread_ids.is_empty() {
+        for sample in 0..options.samples {
+            let thread_id = thread_ids[sample % thread_ids.len()];
+            let start = Instant::now();
+            db.get_thread_messages(thread_id, None, None).await?;
+            read_timings.push(start.elapsed());
+
+            let start = Instant::now();
+            synx.search_threads(SearchRequest {
+                query: text(sample, 4),
+                thread_ids: thread_ids.clone(),
+                tenant: None,
+                reader: None,
+                diversity: None,
+                expand: false,
+                rerank: false,
+                metric: None,
+                page: Default::default(),
+                shape: Default::default(),
+            })
+            .await?;
+            search_timings.push(start.elapsed());
+        }
+    }
+
+    tracing::debug!(?seeding, "Seeded {} threads", thread_ids.len());
+    Ok(BenchReport {
+        threads: thread_ids.len(),
+        messages,
+        create_messages_per_sec: if creating.is_zero() {
+            0.0
+        } else {
+            messages as f64 / creating.as_secs_f64()
+        },
+        create_message: Latency::from_samples(create_timings),
+        get_thread_messages: Latency::from_samples(read_timings),
+        search: Latency::from_samples(search_timings),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_round_up_to_a_sample() {
+        let samples = (1..=10).map(Duration::from_millis).collect::<Vec<_>>();
+        let latency = Latency::from_samples(samples);
+
+        assert_eq!(latency.samples, 10);
+        assert_eq!(latency.p50_ms, 5.0);
+        assert_eq!(latency.p95_ms, 10.0);
+        assert_eq!(latency.max_ms, 10.0);
+    }
+}
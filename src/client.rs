@@ -0,0 +1,235 @@
+//! Operates a store from the command line, through the HTTP API of a running
+//! server, or directly on its database when offline.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use ferrochain::vectorstore::Similarity;
+use synx::{pagination::SearchPage, worker_pool::Lane, SearchRequest, Synx, ThreadListing};
+use synx_domain::{
+    message::{CreateMessage, Message},
+    thread::Thread,
+};
+use uuid::Uuid;
+
+pub enum Client {
+    Remote(Remote),
+    Local(Synx),
+}
+
+/// Talks to the server at `server`.
+pub struct Remote {
+    client: reqwest::Client,
+    server: String,
+    /// API key or bearer token.
+    token: Option<String>,
+}
+
+/// A thread along with its messages, oldest first.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ThreadDetails {
+    #[serde(flatten)]
+    pub thread: Thread,
+    pub messages: Vec<Message>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SearchHit {
+    pub thread_id: String,
+    pub score: f32,
+    pub summary: String,
+}
+
+impl From<Similarity> for SearchHit {
+    fn from(similarity: Similarity) -> Self {
+        Self {
+            thread_id: similarity.stored.id,
+            score: similarity.score,
+            summary: similarity.stored.document.content,
+        }
+    }
+}
+
+/// A search result as the API serializes it.
+#[derive(serde::Deserialize)]
+struct RemoteSimilarity {
+    stored: RemoteDocument,
+    score: f32,
+}
+
+#[derive(serde::Deserialize)]
+struct RemoteDocument {
+    id: String,
+    document: RemoteContent,
+}
+
+#[derive(serde::Deserialize)]
+struct RemoteContent {
+    content: String,
+}
+
+impl From<RemoteSimilarity> for SearchHit {
+    fn from(similarity: RemoteSimilarity) -> Self {
+        Self {
+            thread_id: similarity.stored.id,
+            score: similarity.score,
+            summary: similarity.stored.document.content,
+        }
+    }
+}
+
+impl Client {
+    pub fn remote(server: impl Into<String>, token: Option<String>) -> Self {
+        Self::Remote(Remote {
+            client: reqwest::Client::new(),
+            server: server.into().trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    pub async fn list_threads(&self) -> Result<Vec<Thread>> {
+        match self {
+            Self::Remote(remote) => {
+                let listings: Vec<ThreadListing> = remote
+                    .request(reqwest::Method::GET, "/threads")
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("invalid threads")?;
+                Ok(listings.into_iter().map(|listing| listing.thread).collect())
+            }
+            Self::Local(synx) => synx.list_threads().await,
+        }
+    }
+
+    pub async fn show_thread(&self, thread_id: Uuid) -> Result<ThreadDetails> {
+        match self {
+            Self::Remote(remote) => {
+                let thread = remote
+                    .request(reqwest::Method::GET, &format!("/threads/{}", thread_id))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("invalid thread")?;
+                let messages = remote
+                    .request(
+                        reqwest::Method::GET,
+                        &format!("/threads/{}/messages", thread_id),
+                    )
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("invalid messages")?;
+                Ok(ThreadDetails { thread, messages })
+            }
+            Self::Local(synx) => Ok(ThreadDetails {
+                thread: synx.get_thread(thread_id).await?,
+                messages: synx.get_messages(thread_id, None, None).await?.messages,
+            }),
+        }
+    }
+
+    pub async fn delete_thread(&self, thread_id: Uuid) -> Result<()> {
+        match self {
+            Self::Remote(remote) => {
+                remote
+                    .request(reqwest::Method::DELETE, &format!("/threads/{}", thread_id))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(())
+            }
+            Self::Local(synx) => synx.delete_thread(thread_id).await,
+        }
+    }
+
+    /// Adds the message, waiting for the thread's summary to be updated when
+    /// offline, as it would be lost on exit otherwise.
+    pub async fn add_message(&self, thread_id: Uuid, input: CreateMessage) -> Result<Message> {
+        match self {
+            Self::Remote(remote) => Ok(remote
+                .request(
+                    reqwest::Method::POST,
+                    &format!("/threads/{}/messages", thread_id),
+                )
+                .json(&input)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("invalid message")?),
+            Self::Local(synx) => {
+                let message = synx.create_message(thread_id, input).await?;
+                while synx.pending_jobs(Lane::Interactive) > 0 {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Ok(message)
+            }
+        }
+    }
+
+    /// Searches every thread, returning the `limit` most relevant.
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let search_request = SearchRequest {
+            query: query.to_string(),
+            thread_ids: self
+                .list_threads()
+                .await?
+                .into_iter()
+                .map(|thread| thread.id)
+                .collect(),
+            tenant: None,
+            reader: None,
+            diversity: None,
+            expand: false,
+            rerank: false,
+            metric: None,
+            page: SearchPage {
+                limit: Some(limit),
+                ..Default::default()
+            },
+            shape: Default::default(),
+        };
+
+        match self {
+            Self::Remote(remote) => {
+                let results: Vec<RemoteSimilarity> = remote
+                    .request(reqwest::Method::POST, "/search")
+                    .json(&search_request)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("invalid search results")?;
+                Ok(results.into_iter().map(SearchHit::from).collect())
+            }
+            Self::Local(synx) => Ok(synx
+                .search_threads(search_request)
+                .await?
+                .results
+                .into_iter()
+                .map(SearchHit::from)
+                .collect()),
+        }
+    }
+}
+
+impl Remote {
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}{}", self.server, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
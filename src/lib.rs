@@ -0,0 +1,34 @@
+//! The memory engine as a library. Build a [`Synx`] with a database backend,
+//! an embedder, and a summarizer to use it in-process; the HTTP API is only
+//! compiled with the `server` feature.
+
+#[cfg(feature = "server")]
+pub mod api;
+#[cfg(feature = "server")]
+pub mod bench;
+#[cfg(feature = "server")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod loadgen;
+#[cfg(feature = "server")]
+pub mod migrate;
+#[cfg(feature = "server")]
+pub mod remote_sync;
+#[cfg(feature = "server")]
+pub mod repl;
+
+#[cfg(feature = "testing")]
+pub use synx::testing;
+pub use synx::{
+    analytics, blobs, clustering, duplicates, embedding_input, executor, feedback, normalization,
+    pagination, provider, purge, redaction, replication, segments, shadow, shaping, usage, warm_up,
+    worker_pool, BuildError, BulkDeleteReport, BulkDeleteRequest, Digest, DigestRequest, Diversity,
+    MessageComplete, SearchRequest, SimilarityMetric, Synx, SynxBuilder, ThreadListing,
+    ThreadSnapshot, TranslateSummaryRequest,
+};
+pub use synx_database::{DatabaseError, Db};
+pub use synx_domain as domain;
+#[cfg(feature = "heed")]
+pub use synx_heed_database as heed;
+#[cfg(feature = "in-memory")]
+pub use synx_in_memory_database as in_memory;
@@ -0,0 +1,218 @@
+//! Load generation for capacity planning, run with `synx loadgen`.
+//!
+//! Concurrent clients create threads and messages on the configured
+//! [`Synx`], searching their own threads every few messages, for a given
+//! duration. The report gives latency percentiles of each operation and the
+//! lag of background summaries: how deep their queue got, and how long it
+//! took to drain once the clients stopped.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use synx::{
+    worker_pool::{Lane, QueueFull},
+    SearchRequest, Synx,
+};
+use synx_domain::thread::CreateThread;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::bench::{self, Latency};
+
+const QUEUE_SAMPLING_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoadOptions {
+    pub clients: usize,
+    pub duration: Duration,
+    /// Messages each client creates in a thread before starting another.
+    pub messages_per_thread: usize,
+    /// Each client searches its threads once every this many messages, 0
+    /// never searching.
+    pub search_every: usize,
+    /// How long to wait for background summaries once the clients stopped.
+    pub drain_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct LoadReport {
+    pub clients: usize,
+    pub duration_secs: f64,
+    pub operations: usize,
+    /// Operations refused because the background queue was full.
+    pub rejected: usize,
+    pub errors: usize,
+    pub create_thread: Latency,
+    pub create_message: Latency,
+    pub search: Latency,
+    pub max_queue_depth: usize,
+    pub mean_queue_depth: f64,
+    /// Time the background queue took to drain after the clients stopped,
+    /// `None` when it didn't within the drain timeout.
+    pub drain_secs: Option<f64>,
+}
+
+#[derive(Default)]
+struct Timings {
+    create_thread: Vec<Duration>,
+    create_message: Vec<Duration>,
+    search: Vec<Duration>,
+    rejected: usize,
+    errors: usize,
+}
+
+impl Timings {
+    fn record<T>(&mut self, result: &Result<T>) {
+        match result {
+            Ok(_) => {}
+            Err(e) if e.is::<QueueFull>() => self.rejected += 1,
+            Err(e) => {
+                tracing::warn!("Load generation operation failed: {:?}", e);
+                self.errors += 1;
+            }
+        }
+    }
+}
+
+pub async fn run(synx: Synx, options: LoadOptions) -> Result<LoadReport> {
+    let start = Instant::now();
+    let deadline = start + options.duration;
+
+    let sampler = tokio::spawn({
+        let synx = synx.clone();
+        async move {
+            let mut samples = Vec::new();
+            while Instant::now() < deadline {
+                samples.push(synx.pending_jobs(Lane::Interactive));
+                tokio::time::sleep(QUEUE_SAMPLING_INTERVAL).await;
+            }
+            samples
+        }
+    });
+
+    let timings = Arc::new(Mutex::new(Timings::default()));
+    let clients = (0..options.clients)
+        .map(|client| {
+            tokio::spawn(run_client(
+                synx.clone(),
+                client,
+                options,
+                deadline,
+                timings.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    for client in clients {
+        client.await?;
+    }
+    let duration = start.elapsed();
+    let samples = sampler.await?;
+
+    let drain_start = Instant::now();
+    let mut drain_secs = None;
+    while drain_start.elapsed() < options.drain_timeout {
+        if synx.pending_jobs(Lane::Interactive) == 0 {
+            drain_secs = Some(drain_start.elapsed().as_secs_f64());
+            break;
+        }
+        tokio::time::sleep(QUEUE_SAMPLING_INTERVAL).await;
+    }
+
+    let timings = std::mem::take(&mut *timings.lock().await);
+    Ok(LoadReport {
+        clients: options.clients,
+        duration_secs: duration.as_secs_f64(),
+        operations: timings.create_thread.len()
+            + timings.create_message.len()
+            + timings.search.len()
+            + timings.rejected
+            + timings.errors,
+        rejected: timings.rejected,
+        errors: timings.errors,
+        create_thread: Latency::from_samples(timings.create_thread),
+        create_message: Latency::from_samples(timings.create_message),
+        search: Latency::from_samples(timings.search),
+        max_queue_depth: samples.iter().copied().max().unwrap_or_default(),
+        mean_queue_depth: if samples.is_empty() {
+            0.0
+        } else {
+            samples.iter().sum::<usize>() as f64 / samples.len() as f64
+        },
+        drain_secs,
+    })
+}
+
+async fn run_client(
+    synx: Synx,
+    client: usize,
+    options: LoadOptions,
+    deadline: Instant,
+    timings: Arc<Mutex<Timings>>,
+) {
+    let mut thread_ids: Vec<Uuid> = Vec::new();
+    let mut messages = 0;
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        let thread = synx.create_thread(CreateThread::default()).await;
+        let elapsed = start.elapsed();
+        let thread = {
+            let mut timings = timings.lock().await;
+            timings.record(&thread);
+            match thread {
+                Ok(thread) => {
+                    timings.create_thread.push(elapsed);
+                    thread
+                }
+                Err(_) => continue,
+            }
+        };
+        thread_ids.push(thread.id);
+
+        for _ in 0..options.messages_per_thread {
+            if Instant::now() >= deadline {
+                return;
+            }
+
+            let start = Instant::now();
+            let result = synx
+                .create_message(thread.id, bench::message(client * 1_000_000 + messages))
+                .await;
+            let elapsed = start.elapsed();
+            {
+                let mut timings = timings.lock().await;
+                timings.record(&result);
+                if result.is_ok() {
+                    timings.create_message.push(elapsed);
+                }
+            }
+            messages += 1;
+
+            if options.search_every > 0 && messages % options.search_every == 0 {
+                let start = Instant::now();
+                let result = synx
+                    .search_threads(SearchRequest {
+                        query: bench::text(messages, 4),
+                        thread_ids: thread_ids.clone(),
+                        tenant: None,
+                        reader: None,
+                        diversity: None,
+                        expand: false,
+                        rerank: false,
+                        metric: None,
+                        page: Default::default(),
+                        shape: Default::default(),
+                    })
+                    .await;
+                let elapsed = start.elapsed();
+                let mut timings = timings.lock().await;
+                timings.record(&result);
+                if result.is_ok() {
+                    timings.search.push(elapsed);
+                }
+            }
+        }
+    }
+}
@@ -1,27 +1,47 @@
-mod api;
+use std::{path::PathBuf, sync::Arc, time::Duration};
 
-use std::{future::Future, path::PathBuf, pin::Pin, sync::Arc};
-
-use anyhow::Result;
-use axum::{middleware, routing::get};
-use axum_auth_api_key::auth_middleware;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use axum::{middleware, routing::get, Router};
+use clap::{Parser, Subcommand, ValueEnum};
+use ferrochain::{completion::Completion, embedding::Embedder};
 use ferrochain_anthropic_completion::{AnthropicCompletion, Model};
 use ferrochain_voyageai_embedder::{EmbeddingInputType, EmbeddingModel, VoyageAiEmbedder};
-use synx::{executor::Executor, Synx};
-use synx_heed_database::{heed::EnvOpenOptions, SynxHeedDatabase};
+use memory::{
+    api::{
+        self,
+        auth::{api_key_middleware, oidc_middleware, OidcValidator},
+        compression::CompressionOptions,
+        thread_tokens::{thread_token_middleware, ThreadTokens},
+        timeout::{timeout_middleware, RequestTimeouts},
+    },
+    bench::{self, BenchOptions},
+    client::Client,
+    loadgen::{self, LoadOptions},
+    migrate::{self, Backend, Migration},
+    remote_sync::RemoteSync,
+    repl::Repl,
+    Db,
+};
+use synx::{
+    provider::Capabilities,
+    redaction::{
+        CompletionDetector, PiiDetector, RedactionPolicy, RedactionStage, Redactor, RegexDetector,
+    },
+    scheduler::{Schedule, Task},
+    shadow::{ShadowMode, ShadowSummarizer},
+    testing::{FakeEmbedder, FakeSummarizer, FAKE_MODEL},
+    SimilarityMetric, Synx, SynxBuilder,
+};
+use synx_domain::{message::CreateMessage, role::Role};
+use synx_heed_database::{encryption::Keyring, SynxHeedDatabase};
 use synx_in_memory_database::SynxInMemory;
 use tokio::net::TcpListener;
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-struct TokioExecutor;
-
-impl Executor for TokioExecutor {
-    fn spawn(&self, future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>) {
-        tokio::spawn(future);
-    }
-}
+use uuid::Uuid;
 
 #[derive(Parser)]
 struct Cli {
@@ -29,10 +49,455 @@ struct Cli {
     host: String,
     #[clap(long, default_value = "3000")]
     port: u16,
+    #[clap(long, value_enum, default_value = "api-key", env = "SYNX_AUTH")]
+    auth: AuthMode,
     #[clap(long, env = "SYNX_API_KEY")]
-    api_key: String,
+    api_key: Option<String>,
+    #[clap(long, env = "SYNX_OIDC_ISSUER")]
+    oidc_issuer: Option<String>,
+    #[clap(long, env = "SYNX_OIDC_AUDIENCE")]
+    oidc_audience: Option<String>,
+    #[clap(long, default_value = "tenant", env = "SYNX_OIDC_TENANT_CLAIM")]
+    oidc_tenant_claim: String,
+    /// Secret signing the tokens issued with `POST /threads/:id/tokens`.
+    /// Without it, tokens are signed with a random secret and only accepted
+    /// until the server stops.
+    #[clap(long, env = "SYNX_THREAD_TOKEN_SECRET", hide_env_values = true)]
+    thread_token_secret: Option<String>,
+    /// Seconds a request may take before its response starts, after which
+    /// it is answered with `408 Request Timeout` and abandoned.
+    #[clap(long, default_value = "30", env = "SYNX_REQUEST_TIMEOUT_SECS")]
+    request_timeout_secs: u64,
+    /// Same as `--request-timeout-secs`, for searches.
+    #[clap(long, default_value = "10", env = "SYNX_SEARCH_TIMEOUT_SECS")]
+    search_timeout_secs: u64,
+    #[clap(long, env = "SYNX_SUMMARY_LANGUAGE")]
+    summary_language: Option<String>,
+    #[clap(long, default_value = "4", env = "SYNX_SUMMARY_CONCURRENCY")]
+    summary_concurrency: usize,
+    #[clap(long, default_value = "1024", env = "SYNX_SUMMARY_QUEUE_CAPACITY")]
+    summary_queue_capacity: usize,
+    /// Roles whose messages are stored but left out of summaries.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        default_value = "system",
+        env = "SYNX_UNSUMMARIZED_ROLES"
+    )]
+    unsummarized_roles: Vec<String>,
+    /// Regular expression matching the text of messages stored but left
+    /// out of summaries, e.g. `^(ping|heartbeat)$`. Repeat to give several.
+    #[clap(long = "unsummarized-pattern", env = "SYNX_UNSUMMARIZED_PATTERN")]
+    unsummarized_patterns: Vec<String>,
+    #[clap(long, default_value = "1", env = "SYNX_BULK_CONCURRENCY")]
+    bulk_concurrency: usize,
+    #[clap(long, default_value = "10000", env = "SYNX_BULK_QUEUE_CAPACITY")]
+    bulk_queue_capacity: usize,
+    #[clap(long, value_enum, value_delimiter = ',', env = "SYNX_REDACT_PII")]
+    redact_pii: Vec<PiiStage>,
+    #[clap(long, value_enum, default_value = "mask", env = "SYNX_PII_POLICY")]
+    pii_policy: PiiPolicy,
+    /// Secret keying the digests of the `hash` PII policy, which requires
+    /// it.
+    #[clap(long, env = "SYNX_PII_HASH_KEY", hide_env_values = true)]
+    pii_hash_key: Option<String>,
+    /// How PII is detected: `regex` matches emails, phone numbers, and card
+    /// numbers, `completion` asks the summarizer's model for those it can't,
+    /// such as names and addresses.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "regex",
+        env = "SYNX_PII_DETECTORS"
+    )]
+    pii_detectors: Vec<PiiDetection>,
+    /// Identifies this instance in the op-log, which is recorded when set.
+    /// Required to sync, either as a client or as a server.
+    #[clap(long, env = "SYNX_REPLICA_ID")]
+    replica_id: Option<Uuid>,
+    /// Records the op-log served under `/changes` without replicating.
+    #[clap(long, default_value = "false", env = "SYNX_CHANGE_LOG")]
+    change_log: bool,
+    /// URL of the server to sync with.
+    #[clap(long, env = "SYNX_SYNC_REMOTE")]
+    sync_remote: Option<String>,
+    /// API key or bearer token used to authenticate with the sync remote.
+    #[clap(long, env = "SYNX_SYNC_TOKEN", hide_env_values = true)]
+    sync_token: Option<String>,
+    #[clap(
+        long,
+        default_value = "30",
+        value_parser = clap::value_parser!(u64).range(1..),
+        env = "SYNX_SYNC_INTERVAL_SECS"
+    )]
+    sync_interval_secs: u64,
+    /// How often summaries that failed, e.g. while the summarizer was down,
+    /// are retried.
+    #[clap(
+        long,
+        default_value = "30",
+        value_parser = clap::value_parser!(u64).range(1..),
+        env = "SYNX_SUMMARY_RETRY_INTERVAL_SECS"
+    )]
+    summary_retry_interval_secs: u64,
+    /// Recurring tasks, as `task=schedule` separated by `;`, e.g.
+    /// `digest=0 8 * * *` or `scan-duplicates=@daily`. Schedules are cron
+    /// expressions in UTC or intervals such as `@every 10m`.
+    #[clap(
+        long,
+        value_delimiter = ';',
+        value_parser = parse_schedule,
+        env = "SYNX_SCHEDULES"
+    )]
+    schedule: Vec<(Task, Schedule)>,
+    /// Runs a shadow summarization pipeline whose summaries are logged or
+    /// stored apart from the live threads.
+    #[clap(long, value_enum, env = "SYNX_SHADOW_MODE")]
+    shadow_mode: Option<ShadowModeArg>,
+    /// Summary prompt evaluated by the shadow pipeline, the live one by
+    /// default.
+    #[clap(long, env = "SYNX_SHADOW_PROMPT_FILE")]
+    shadow_prompt_file: Option<PathBuf>,
+    /// Marks the part of summary requests shared by every request, along
+    /// with the system prompt, as cacheable by Anthropic.
+    #[clap(long, default_value = "false", env = "SYNX_PROMPT_CACHING")]
+    prompt_caching: bool,
+    /// Estimated tokens each tenant may use per month before the summaries
+    /// of its threads are paused.
+    #[clap(long, env = "SYNX_MONTHLY_TOKEN_BUDGET")]
+    monthly_token_budget: Option<u64>,
+    /// Per-tenant monthly token budgets, as `tenant=tokens`.
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_tenant_budget,
+        env = "SYNX_TENANT_TOKEN_BUDGETS"
+    )]
+    tenant_token_budgets: Vec<(String, u64)>,
+    /// How much relevance feedback moves search scores, 0 turning re-ranking
+    /// off.
+    #[clap(long, default_value = "0.1", env = "SYNX_FEEDBACK_WEIGHT")]
+    feedback_weight: f32,
+    /// How searches compare queries with threads, per the embedding model's
+    /// training, unless a search asks for another metric.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "cosine",
+        env = "SYNX_SIMILARITY_METRIC"
+    )]
+    similarity_metric: SimilarityMetricArg,
+    /// Reports pairs of threads whose summaries are at least this similar
+    /// (cosine similarity) as likely duplicates.
+    #[clap(long, env = "SYNX_DUPLICATE_THRESHOLD")]
+    duplicate_threshold: Option<f32>,
+    /// Truncates embeddings to this many dimensions, for embedders trained
+    /// to keep their meaning in the first ones (Matryoshka embeddings).
+    #[clap(long, env = "SYNX_EMBEDDING_REDUCTION")]
+    embedding_reduction: Option<usize>,
+    /// Scales embeddings to unit length as they are made, so cosine
+    /// similarity is a plain dot product. Embeddings stored before are
+    /// rewritten with `POST /admin/embeddings/normalize`.
+    #[clap(long, default_value = "false", env = "SYNX_NORMALIZE_EMBEDDINGS")]
+    normalize_embeddings: bool,
+    /// The text embedded for a thread, where `{{TITLE}}`, `{{TAGS}}`, and
+    /// `{{SUMMARY}}` are replaced by the thread's, the summary alone by
+    /// default.
+    #[clap(long, env = "SYNX_EMBEDDING_TEMPLATE")]
+    embedding_template: Option<String>,
+    /// Also embeds threads per segment of this many consecutive summarized
+    /// messages, searches scoring a thread by its closest segment or its
+    /// summary, whichever is closer.
+    #[clap(long, env = "SYNX_SEGMENT_SIZE")]
+    segment_size: Option<usize>,
+    /// Prunes messages older than this many days from threads whose summary
+    /// is done with them, e.g. with `--schedule prune-messages=@daily`.
+    #[clap(long, env = "SYNX_MESSAGE_RETENTION_DAYS")]
+    message_retention_days: Option<u64>,
+    /// Logs database calls and searches taking at least this many
+    /// milliseconds, with the threads and result sizes involved.
+    #[clap(long, env = "SYNX_SLOW_QUERY_MS")]
+    slow_query_ms: Option<u64>,
+    /// Caches the results of this many distinct searches until summaries,
+    /// embeddings, or relevance feedback change.
+    #[clap(long, env = "SYNX_SEARCH_CACHE_SIZE")]
+    search_cache_size: Option<usize>,
+    /// Seconds a cached search result is served at most, bounding how stale
+    /// results get when other instances share the database.
+    #[clap(long, default_value = "30", env = "SYNX_SEARCH_CACHE_TTL_SECS")]
+    search_cache_ttl_secs: u64,
+    /// Keeps the embeddings of this many normalized queries, 0 turning the
+    /// cache off.
+    #[clap(long, default_value = "1024", env = "SYNX_QUERY_EMBEDDING_CACHE_SIZE")]
+    query_embedding_cache_size: usize,
+    #[clap(
+        long,
+        default_value = "600",
+        env = "SYNX_QUERY_EMBEDDING_CACHE_TTL_SECS"
+    )]
+    query_embedding_cache_ttl_secs: u64,
+    /// Number of search results the completion model reorders when a
+    /// search asks for reranking.
+    #[clap(long, default_value = "10", env = "SYNX_RERANK_TOP_K")]
+    rerank_top_k: usize,
+    /// Captions the images of new messages with a vision-capable model, so
+    /// messages made only of images still update the thread summary.
+    #[clap(long, default_value = "false", env = "SYNX_CAPTION_IMAGES")]
+    caption_images: bool,
+    /// Makes a tiny request to every provider at startup, failing to start
+    /// or only warning when one is misconfigured.
+    #[clap(long, value_enum, env = "SYNX_WARM_UP")]
+    warm_up: Option<WarmUpMode>,
+    /// Replaces the embedders and summarizers with deterministic fakes, to
+    /// run without provider API keys or network access.
+    #[clap(long, default_value = "false", env = "SYNX_OFFLINE")]
+    offline: bool,
+    /// Response compression algorithms offered to clients, `none` turning
+    /// compression off.
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "br,gzip",
+        env = "SYNX_COMPRESSION"
+    )]
+    compression: Vec<CompressionAlgorithm>,
+    /// Responses shorter than this many bytes are sent uncompressed.
+    #[clap(long, default_value = "1024", env = "SYNX_COMPRESSION_MIN_SIZE")]
+    compression_min_size: u16,
+    /// Server the client commands talk to.
+    #[clap(
+        long,
+        global = true,
+        default_value = "http://localhost:3000",
+        env = "SYNX_SERVER"
+    )]
+    server: String,
+    /// API key or bearer token the client commands authenticate with.
+    #[clap(long, global = true, env = "SYNX_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Has the client commands operate on the heed database at this path
+    /// rather than through a server, with the configured providers.
+    #[clap(long, global = true, env = "SYNX_HEED_PATH")]
+    heed_path: Option<PathBuf>,
     #[clap(subcommand)]
-    database: Database,
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(flatten)]
+    Database(Database),
+    #[clap(flatten)]
+    Client(ClientCommand),
+    /// Seeds the database with `--threads` threads, prints the timings of
+    /// database and search operations as JSON, and exits without serving.
+    Bench {
+        #[clap(long, env = "SYNX_BENCH_THREADS")]
+        threads: usize,
+        #[clap(long, default_value = "10", env = "SYNX_BENCH_MESSAGES")]
+        messages: usize,
+        #[clap(long, default_value = "100", env = "SYNX_BENCH_SAMPLES")]
+        samples: usize,
+        /// The database benchmarked, in memory by default.
+        #[clap(subcommand)]
+        database: Option<Database>,
+    },
+    /// Runs `--clients` concurrent clients against the configured providers
+    /// and database, prints latency percentiles and background queue lag as
+    /// JSON, and exits without serving.
+    Loadgen {
+        #[clap(long, env = "SYNX_LOADGEN_CLIENTS")]
+        clients: usize,
+        #[clap(long, default_value = "60", env = "SYNX_LOADGEN_DURATION_SECS")]
+        duration_secs: u64,
+        #[clap(long, default_value = "10", env = "SYNX_LOADGEN_MESSAGES")]
+        messages: usize,
+        /// Each client searches its threads once every this many messages.
+        #[clap(long, default_value = "5", env = "SYNX_LOADGEN_SEARCH_EVERY")]
+        search_every: usize,
+        #[clap(long, default_value = "300", env = "SYNX_LOADGEN_DRAIN_TIMEOUT_SECS")]
+        drain_timeout_secs: u64,
+        /// The database loaded, in memory by default.
+        #[clap(subcommand)]
+        database: Option<Database>,
+    },
+    /// Opens a debugging console over the heed database at `--heed-path`,
+    /// with the configured providers.
+    Repl {
+        /// Allows commands changing the database, e.g. `delete`.
+        #[clap(long, default_value = "false")]
+        write: bool,
+    },
+    /// Checks the heed database at `--heed-path` for entries left
+    /// inconsistent, e.g. by deletions, printing a report as JSON.
+    Fsck {
+        /// Repairs what was found, except summaries without embeddings.
+        #[clap(long, default_value = "false")]
+        repair: bool,
+        #[clap(long, env = "SYNX_ENCRYPTION_KEYS", hide_env_values = true)]
+        encryption_keys: Option<String>,
+    },
+    /// Copies every thread from one database to another, printing a report
+    /// as JSON. Databases are given as `heed:<path>`.
+    MigrateDb {
+        #[clap(long)]
+        from: Backend,
+        #[clap(long)]
+        to: Backend,
+        /// Records the threads copied, which are skipped when run again.
+        #[clap(long)]
+        checkpoint: Option<PathBuf>,
+        /// Skips comparing both databases once copied.
+        #[clap(long, default_value = "false")]
+        no_verify: bool,
+        /// Keys the source's values are encrypted with, if any.
+        #[clap(long)]
+        from_encryption_keys: Option<String>,
+        /// Keys to encrypt the target's values with, leaving them plain when
+        /// unset.
+        #[clap(long)]
+        to_encryption_keys: Option<String>,
+    },
+}
+
+/// Commands printing what they read or write as JSON, see `--server` and
+/// `--heed-path`.
+#[derive(Subcommand)]
+enum ClientCommand {
+    Threads {
+        #[clap(subcommand)]
+        command: ThreadsCommand,
+    },
+    Messages {
+        #[clap(subcommand)]
+        command: MessagesCommand,
+    },
+    /// Searches every thread by summary.
+    Search {
+        query: String,
+        #[clap(long, default_value = "10")]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ThreadsCommand {
+    List,
+    /// Prints the thread along with its messages.
+    Show {
+        thread_id: Uuid,
+    },
+    Delete {
+        thread_id: Uuid,
+    },
+}
+
+#[derive(Subcommand)]
+enum MessagesCommand {
+    Add {
+        thread_id: Uuid,
+        text: String,
+        #[clap(long, default_value = "user")]
+        role: String,
+    },
+}
+
+/// What the database is opened for.
+enum Mode {
+    Serve,
+    Bench(BenchOptions),
+    Loadgen(LoadOptions),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CompressionAlgorithm {
+    Br,
+    Gzip,
+    None,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AuthMode {
+    ApiKey,
+    Oidc,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PiiStage {
+    Storage,
+    Summarization,
+}
+
+impl From<PiiStage> for RedactionStage {
+    fn from(stage: PiiStage) -> Self {
+        match stage {
+            PiiStage::Storage => RedactionStage::Storage,
+            PiiStage::Summarization => RedactionStage::Summarization,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PiiDetection {
+    Regex,
+    Completion,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PiiPolicy {
+    Block,
+    Mask,
+    Hash,
+}
+
+impl From<PiiPolicy> for RedactionPolicy {
+    fn from(policy: PiiPolicy) -> Self {
+        match policy {
+            PiiPolicy::Block => RedactionPolicy::Block,
+            PiiPolicy::Mask => RedactionPolicy::Mask,
+            PiiPolicy::Hash => RedactionPolicy::Hash,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ShadowModeArg {
+    Log,
+    Store,
+}
+
+impl From<ShadowModeArg> for ShadowMode {
+    fn from(mode: ShadowModeArg) -> Self {
+        match mode {
+            ShadowModeArg::Log => ShadowMode::Log,
+            ShadowModeArg::Store => ShadowMode::Store,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SimilarityMetricArg {
+    Cosine,
+    DotProduct,
+    Euclidean,
+}
+
+impl From<SimilarityMetricArg> for SimilarityMetric {
+    fn from(metric: SimilarityMetricArg) -> Self {
+        match metric {
+            SimilarityMetricArg::Cosine => SimilarityMetric::Cosine,
+            SimilarityMetricArg::DotProduct => SimilarityMetric::DotProduct,
+            SimilarityMetricArg::Euclidean => SimilarityMetric::Euclidean,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum WarmUpMode {
+    Warn,
+    Fail,
 }
 
 #[derive(Default, Subcommand)]
@@ -42,11 +507,170 @@ enum Database {
         path: PathBuf,
         #[clap(long, default_value = "false")]
         regenerate: bool,
+        #[clap(long, env = "SYNX_ENCRYPTION_KEYS", hide_env_values = true)]
+        encryption_keys: Option<String>,
+        /// Refuses to start without `--encryption-keys`, rather than storing
+        /// values unencrypted.
+        #[clap(long, default_value = "false", env = "SYNX_REQUIRE_ENCRYPTION")]
+        require_encryption: bool,
+        #[clap(long, default_value = "false")]
+        reencrypt: bool,
+        /// Report entries left behind by deleted threads.
+        #[clap(long, default_value = "false")]
+        scan_orphans: bool,
+        /// Remove entries left behind by deleted threads.
+        #[clap(long, default_value = "false")]
+        remove_orphans: bool,
+        #[clap(long, default_value = "10", env = "SYNX_HEED_MAP_SIZE_GB")]
+        map_size_gb: usize,
+        /// Disables the OS read-ahead, which mostly fills the page cache with
+        /// unused pages on SSDs once the database outgrows memory.
+        #[clap(long, default_value = "false", env = "SYNX_HEED_NO_READ_AHEAD")]
+        no_read_ahead: bool,
+        /// Writes through a writable memory map, faster on local storage but
+        /// not recommended on network storage.
+        #[clap(long, default_value = "false", env = "SYNX_HEED_WRITE_MAP")]
+        write_map: bool,
+        /// Writes waiting for the writer thread before requests wait for
+        /// room.
+        #[clap(long, default_value = "1024", env = "SYNX_HEED_WRITE_QUEUE_CAPACITY")]
+        write_queue_capacity: usize,
+        /// Exports running at once, each holding one of LMDB's reader slots.
+        #[clap(long, default_value = "4", env = "SYNX_HEED_MAX_EXPORTS")]
+        max_exports: usize,
+        /// Longest an export may hold its snapshot, in seconds.
+        #[clap(long, default_value = "900", env = "SYNX_HEED_EXPORT_TIMEOUT_SECS")]
+        export_timeout_secs: u64,
+        /// Fraction of the LMDB map used above which writes log a warning.
+        #[clap(long, default_value = "0.8", env = "SYNX_HEED_MAP_USAGE_WARNING")]
+        map_usage_warning: f64,
     },
     #[default]
     InMemory,
 }
 
+fn parse_tenant_budget(value: &str) -> Result<(String, u64), String> {
+    let (tenant, tokens) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `tenant=tokens`, got `{}`", value))?;
+    let tokens = tokens.parse().map_err(|e| format!("{}: {}", tokens, e))?;
+    Ok((tenant.to_string(), tokens))
+}
+
+fn parse_schedule(value: &str) -> Result<(Task, Schedule), String> {
+    let (task, schedule) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `task=schedule`, got `{}`", value))?;
+    let task = task.trim().parse().map_err(|e| format!("{}", e))?;
+    let schedule = schedule.parse().map_err(|e| format!("{}", e))?;
+    Ok((task, schedule))
+}
+
+/// The id of the summarizer's model, recorded in the provenance of its
+/// summaries.
+const SUMMARIZER_MODEL: &str = "claude-3-haiku-20240307";
+
+fn summarizer(offline: bool) -> Result<Arc<dyn Completion>> {
+    if offline {
+        return Ok(Arc::new(FakeSummarizer));
+    }
+
+    Ok(Arc::new(AnthropicCompletion::builder()
+        .with_model(Model::ClaudeThreeHaiku)
+        .with_temperature(0.0)
+        .with_max_tokens(1024)
+        .with_system(vec![
+            indoc::indoc! {"
+                You are an AI assistant tasked with summarizing conversations from the user perspective.
+
+                The summaries you provide will be used to NLP-search, so they should always include comprehensive information regarding the conversation and using an adequate style, easy to search.
+            "}.into()
+        ])
+        .build()?))
+}
+
+fn captioner(offline: bool) -> Result<Arc<dyn Completion>> {
+    if offline {
+        return Ok(Arc::new(FakeSummarizer));
+    }
+
+    Ok(Arc::new(AnthropicCompletion::builder()
+        .with_model(Model::ClaudeThreeHaiku)
+        .with_temperature(0.0)
+        .with_max_tokens(256)
+        .with_system(vec![
+            "You are an AI assistant describing images shared in conversations, so they can be summarized and searched.".into()
+        ])
+        .build()?))
+}
+
+fn build(builder: SynxBuilder, db: Arc<dyn Db>, offline: bool) -> Result<Synx> {
+    Ok(builder
+        .with_db(db)
+        .with_document_embedder(embedder(offline, EmbeddingInputType::Document)?)
+        .with_query_embedder(embedder(offline, EmbeddingInputType::Query)?)
+        .with_summarizer(summarizer(offline)?)
+        .with_summarizer_model(if offline {
+            FAKE_MODEL
+        } else {
+            SUMMARIZER_MODEL
+        })
+        .build()?)
+}
+
+async fn run_client(client: Client, command: ClientCommand) -> Result<()> {
+    let output = match command {
+        ClientCommand::Threads {
+            command: ThreadsCommand::List,
+        } => serde_json::to_value(client.list_threads().await?)?,
+        ClientCommand::Threads {
+            command: ThreadsCommand::Show { thread_id },
+        } => serde_json::to_value(client.show_thread(thread_id).await?)?,
+        ClientCommand::Threads {
+            command: ThreadsCommand::Delete { thread_id },
+        } => {
+            client.delete_thread(thread_id).await?;
+            serde_json::json!({ "deleted": thread_id })
+        }
+        ClientCommand::Messages {
+            command:
+                MessagesCommand::Add {
+                    thread_id,
+                    text,
+                    role,
+                },
+        } => {
+            let input = CreateMessage {
+                role: Role::from(role.as_str()),
+                content: text.into(),
+                parent_message_id: None,
+                metadata: Default::default(),
+                status: Default::default(),
+                memorize: true,
+            };
+            serde_json::to_value(client.add_message(thread_id, input).await?)?
+        }
+        ClientCommand::Search { query, limit } => {
+            serde_json::to_value(client.search(&query, limit).await?)?
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+fn embedder(offline: bool, input_type: EmbeddingInputType) -> Result<Arc<dyn Embedder>> {
+    if offline {
+        return Ok(Arc::new(FakeEmbedder::new()));
+    }
+
+    Ok(Arc::new(
+        VoyageAiEmbedder::builder()
+            .model(EmbeddingModel::Voyage3)
+            .input_type(input_type)
+            .build()?,
+    ))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::registry()
@@ -64,68 +688,393 @@ async fn main() -> Result<()> {
 
     let cli = Cli::parse();
 
-    let synx = Synx::builder()
-        .with_db({
-            match cli.database {
-                Database::Heed { path, regenerate } => {
-                    tokio::fs::create_dir_all(&path).await?;
-                    if regenerate {
-                        tokio::fs::remove_dir_all(&path).await?;
-                        tokio::fs::create_dir_all(&path).await?;
-                    }
-
-                    let env = unsafe {
-                        EnvOpenOptions::new()
-                            .map_size(10 * 1024 * 1024 * 1024) // 10 GB
-                            .max_dbs(6)
-                            .open(path)?
-                    };
-
-                    Arc::new(SynxHeedDatabase::new(Arc::new(env), true)?)
+    let capabilities = Capabilities {
+        prompt_caching: cli.prompt_caching,
+    };
+    let mut builder = Synx::builder()
+        .with_summarizer_capabilities(capabilities)
+        .with_summary_concurrency(cli.summary_concurrency)
+        .with_summary_queue_capacity(cli.summary_queue_capacity)
+        .with_bulk_concurrency(cli.bulk_concurrency)
+        .with_bulk_queue_capacity(cli.bulk_queue_capacity)
+        .with_change_log(cli.change_log)
+        .with_unsummarized_roles(
+            cli.unsummarized_roles
+                .iter()
+                .map(|role| Role::from(role.as_str())),
+        )
+        .with_unsummarized_patterns(cli.unsummarized_patterns);
+    if let Some(replica_id) = cli.replica_id {
+        builder = builder.with_replica_id(replica_id);
+    }
+    if let Some(shadow_mode) = cli.shadow_mode {
+        let mut shadow = ShadowSummarizer::new(summarizer(cli.offline)?, shadow_mode.into())
+            .with_capabilities(capabilities);
+        if let Some(path) = cli.shadow_prompt_file {
+            shadow = shadow.with_prompt(
+                tokio::fs::read_to_string(&path)
+                    .await
+                    .with_context(|| format!("failed to read {}", path.display()))?,
+            );
+        }
+        builder = builder.with_shadow_summarizer(shadow);
+    }
+    if let Some(tokens) = cli.monthly_token_budget {
+        builder = builder.with_monthly_token_budget(tokens);
+    }
+    for (tenant, tokens) in cli.tenant_token_budgets {
+        builder = builder.with_tenant_monthly_token_budget(tenant, tokens);
+    }
+    builder = builder.with_feedback_weight(cli.feedback_weight);
+    builder = builder.with_similarity_metric(cli.similarity_metric.into());
+    if let Some(threshold) = cli.duplicate_threshold {
+        builder = builder.with_duplicate_threshold(threshold);
+    }
+    if let Some(dimensions) = cli.embedding_reduction {
+        builder = builder.with_embedding_reduction(dimensions);
+    }
+    builder = builder.with_embedding_normalization(cli.normalize_embeddings);
+    if let Some(template) = cli.embedding_template {
+        builder = builder.with_embedding_template(template);
+    }
+    if let Some(size) = cli.segment_size {
+        builder = builder.with_segment_embeddings(size);
+    }
+    if let Some(days) = cli.message_retention_days {
+        builder = builder.with_message_retention(Duration::from_secs(days * 24 * 60 * 60));
+    }
+    if let Some(ms) = cli.slow_query_ms {
+        builder = builder.with_slow_query_threshold(Duration::from_millis(ms));
+    }
+    builder = builder.with_query_embedding_cache(
+        cli.query_embedding_cache_size,
+        Duration::from_secs(cli.query_embedding_cache_ttl_secs),
+    );
+    builder = builder.with_rerank_top_k(cli.rerank_top_k);
+    builder = builder.with_schedule(
+        Task::RetrySummaries,
+        Schedule::every(Duration::from_secs(cli.summary_retry_interval_secs))?,
+    );
+    for (task, schedule) in cli.schedule {
+        builder = builder.with_schedule(task, schedule);
+    }
+    if let Some(capacity) = cli.search_cache_size {
+        builder =
+            builder.with_search_cache(capacity, Duration::from_secs(cli.search_cache_ttl_secs));
+    }
+    if cli.caption_images {
+        builder = builder.with_captioner(captioner(cli.offline)?);
+    }
+    if let Some(summary_language) = cli.summary_language {
+        builder = builder.with_summary_language(summary_language);
+    }
+    if !cli.redact_pii.is_empty() {
+        let mut redactor = Redactor::builder().with_default_policy(cli.pii_policy.into());
+        if let Some(key) = cli.pii_hash_key {
+            redactor = redactor.with_hash_key(key);
+        }
+        for stage in cli.redact_pii {
+            redactor = redactor.with_stage(stage.into());
+        }
+        for detection in cli.pii_detectors {
+            let detector: Arc<dyn PiiDetector> = match detection {
+                PiiDetection::Regex => Arc::new(RegexDetector::default()),
+                PiiDetection::Completion => {
+                    Arc::new(CompletionDetector::new(summarizer(cli.offline)?))
                 }
-                Database::InMemory => Arc::new(SynxInMemory::new()),
+            };
+            redactor = redactor.with_detector(detector);
+        }
+        builder = builder.with_redactor(redactor.build()?);
+    }
+
+    let (database, mode) = match cli.command {
+        Command::Database(database) => (database, Mode::Serve),
+        Command::Bench {
+            threads,
+            messages,
+            samples,
+            database,
+        } => (
+            database.unwrap_or_default(),
+            Mode::Bench(BenchOptions {
+                threads,
+                messages_per_thread: messages,
+                samples,
+            }),
+        ),
+        Command::Loadgen {
+            clients,
+            duration_secs,
+            messages,
+            search_every,
+            drain_timeout_secs,
+            database,
+        } => (
+            database.unwrap_or_default(),
+            Mode::Loadgen(LoadOptions {
+                clients,
+                duration: Duration::from_secs(duration_secs),
+                messages_per_thread: messages,
+                search_every,
+                drain_timeout: Duration::from_secs(drain_timeout_secs),
+            }),
+        ),
+        Command::Client(command) => {
+            let client = match cli.heed_path {
+                Some(path) => Client::Local(build(
+                    builder,
+                    Arc::new(SynxHeedDatabase::builder().open(path)?),
+                    cli.offline,
+                )?),
+                None => Client::remote(cli.server, cli.token),
+            };
+            return run_client(client, command).await;
+        }
+        Command::Repl { write } => {
+            let path = cli
+                .heed_path
+                .context("--heed-path is required by the repl")?;
+            let synx = build(
+                builder,
+                Arc::new(SynxHeedDatabase::builder().open(path)?),
+                cli.offline,
+            )?;
+            return Repl::new(synx, write)
+                .run(
+                    tokio::io::BufReader::new(tokio::io::stdin()),
+                    tokio::io::stdout(),
+                )
+                .await;
+        }
+        Command::Fsck {
+            repair,
+            encryption_keys,
+        } => {
+            let path = cli.heed_path.context("--heed-path is required by fsck")?;
+            let mut builder = SynxHeedDatabase::builder();
+            if let Some(encryption_keys) = encryption_keys {
+                builder = builder.with_keyring(Keyring::parse(&encryption_keys)?);
             }
-        })
-        .with_document_embedder(Arc::new(
-            VoyageAiEmbedder::builder()
-                .model(EmbeddingModel::Voyage3)
-                .input_type(EmbeddingInputType::Document)
-                .build()?,
-        ))
-        .with_query_embedder(Arc::new(
-            VoyageAiEmbedder::builder()
-                .model(EmbeddingModel::Voyage3)
-                .input_type(EmbeddingInputType::Query)
-                .build()?,
-        ))
-        .with_summarizer(Arc::new(
-            AnthropicCompletion::builder()
-                .with_model(Model::ClaudeThreeHaiku)
-                .with_temperature(0.0)
-                .with_max_tokens(1024)
-                .with_system(vec![
-                    indoc::indoc! {"
-                        You are an AI assistant tasked with summarizing conversations from the user perspective.
-
-                        The summaries you provide will be used to NLP-search, so they should always include comprehensive information regarding the conversation and using an adequate style, easy to search.
-                    "}.into()
-                ])
-                .build()?,
-        ))
-        .with_executor(Arc::new(TokioExecutor))
-        .build();
+            let report = builder.open(path)?.check_consistency(repair)?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !repair && !report.is_consistent() {
+                anyhow::bail!("inconsistencies found, run again with --repair to repair them");
+            }
+            return Ok(());
+        }
+        Command::MigrateDb {
+            from,
+            to,
+            checkpoint,
+            no_verify,
+            from_encryption_keys,
+            to_encryption_keys,
+        } => {
+            migrate::check_backends(&from, &to)?;
+            let keyring = |keys: Option<String>| keys.as_deref().map(Keyring::parse).transpose();
+            let source = from.open(false, keyring(from_encryption_keys)?)?;
+            let target = to.open(true, keyring(to_encryption_keys)?)?;
+            let report = Migration::new(source, target, checkpoint)
+                .await?
+                .run(!no_verify)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report
+                .verification
+                .is_some_and(|verification| !verification.matches)
+            {
+                anyhow::bail!("the databases differ once migrated");
+            }
+            return Ok(());
+        }
+    };
+
+    let db: Arc<dyn Db> = match database {
+        Database::Heed {
+            path,
+            regenerate,
+            encryption_keys,
+            require_encryption,
+            reencrypt,
+            scan_orphans,
+            remove_orphans,
+            map_size_gb,
+            no_read_ahead,
+            write_map,
+            write_queue_capacity,
+            max_exports,
+            export_timeout_secs,
+            map_usage_warning,
+        } => {
+            tokio::fs::create_dir_all(&path).await?;
+            if regenerate {
+                tokio::fs::remove_dir_all(&path).await?;
+                tokio::fs::create_dir_all(&path).await?;
+            }
+
+            let mut builder = SynxHeedDatabase::builder()
+                .with_map_size(map_size_gb * 1024 * 1024 * 1024)
+                .with_read_ahead(!no_read_ahead)
+                .with_write_map(write_map)
+                .with_write_queue_capacity(write_queue_capacity)
+                .with_max_exports(max_exports)
+                .with_export_timeout(Duration::from_secs(export_timeout_secs))
+                .with_map_usage_warning(map_usage_warning)
+                .with_encryption_required(require_encryption);
+            if let Some(encryption_keys) = encryption_keys {
+                builder = builder.with_keyring(Keyring::parse(&encryption_keys)?);
+            }
+            let db = builder.open(path)?;
+            if reencrypt {
+                let count = db.reencrypt()?;
+                tracing::info!("Re-encrypted {} values with the active key", count);
+            }
+            if scan_orphans || remove_orphans {
+                let report = db.scan_orphans(remove_orphans)?;
+                tracing::info!(
+                    messages = report.messages,
+                    message_creation_times = report.message_creation_times,
+                    thread_messages = report.thread_messages,
+                    embeddings = report.embeddings,
+                    "{} {} orphaned entries",
+                    if remove_orphans { "Removed" } else { "Found" },
+                    report.total()
+                );
+            }
+
+            Arc::new(db)
+        }
+        Database::InMemory => Arc::new(SynxInMemory::new()),
+    };
+
+    if let Mode::Bench(options) = mode {
+        let report = bench::run(db, options).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let synx = build(builder, db, cli.offline)?;
+
+    if let Some(mode) = cli.warm_up {
+        match synx.warm_up().await {
+            Ok(report) => tracing::info!(
+                embedding_dimensions = report.embedding_dimensions,
+                "Providers warmed up"
+            ),
+            Err(e) => match mode {
+                WarmUpMode::Warn => tracing::warn!(
+                    "Providers failed to warm up, summaries and searches will fail: {:?}",
+                    e
+                ),
+                WarmUpMode::Fail => return Err(e.context("providers failed to warm up")),
+            },
+        }
+    }
+
+    if let Mode::Loadgen(options) = mode {
+        let report = loadgen::run(synx, options).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if let Some(sync_remote) = cli.sync_remote {
+        let remote_sync = RemoteSync::new(synx.clone(), sync_remote, cli.sync_token)
+            .context("--replica-id is required with --sync-remote")?;
+        tokio::spawn(remote_sync.run(Duration::from_secs(cli.sync_interval_secs)));
+    }
+
+    tokio::spawn({
+        let synx = synx.clone();
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        async move {
+            loop {
+                ticker.tick().await;
+                synx.run_due_tasks();
+            }
+        }
+    });
+
+    // Probes are left out of authentication.
+    let probes = Router::new()
+        .route("/healthz", get(api::handlers::healthz))
+        .route("/readyz", get(api::handlers::readyz))
+        .with_state(synx.clone());
+    let thread_tokens = Arc::new(match &cli.thread_token_secret {
+        Some(secret) => ThreadTokens::new(secret.as_bytes()),
+        None => ThreadTokens::ephemeral(),
+    });
+    let router = match cli.auth {
+        AuthMode::ApiKey => {
+            let api_key = cli
+                .api_key
+                .context("--api-key is required with api-key authentication")?;
+
+            api::routes::router(synx).route_layer(middleware::from_fn_with_state(
+                Arc::<str>::from(api_key),
+                api_key_middleware,
+            ))
+        }
+        AuthMode::Oidc => {
+            let validator = OidcValidator::discover(
+                cli.oidc_issuer
+                    .context("--oidc-issuer is required with oidc authentication")?,
+                cli.oidc_audience
+                    .context("--oidc-audience is required with oidc authentication")?,
+                cli.oidc_tenant_claim,
+            )
+            .await?;
+
+            api::routes::router(synx).route_layer(middleware::from_fn_with_state(
+                Arc::new(validator),
+                oidc_middleware,
+            ))
+        }
+    }
+    .route_layer(middleware::from_fn_with_state(
+        thread_tokens,
+        thread_token_middleware,
+    ))
+    .route_layer(middleware::from_fn_with_state(
+        RequestTimeouts {
+            default: Duration::from_secs(cli.request_timeout_secs),
+            search: Duration::from_secs(cli.search_timeout_secs),
+        },
+        timeout_middleware,
+    ));
+
+    let compression = CompressionOptions {
+        gzip: cli.compression.contains(&CompressionAlgorithm::Gzip),
+        brotli: cli.compression.contains(&CompressionAlgorithm::Br),
+        min_size: cli.compression_min_size,
+    };
+    let router = router.layer(middleware::from_fn(
+        api::logging::error_request_id_middleware,
+    ));
+    let router = if compression.is_enabled() {
+        router.layer(compression.layer())
+    } else {
+        router
+    };
 
     let listener = TcpListener::bind((cli.host, cli.port)).await?;
     tracing::debug!("listening on {}", listener.local_addr()?);
     axum::serve(
         listener,
-        api::routes::router(synx)
-            .route_layer(middleware::from_fn_with_state(
-                cli.api_key.into(),
-                auth_middleware,
+        router
+            .merge(probes)
+            .layer(middleware::from_fn(api::logging::request_log_middleware))
+            .layer(TraceLayer::new_for_http().make_span_with(api::logging::make_request_span))
+            .layer(PropagateRequestIdLayer::new(
+                api::logging::REQUEST_ID_HEADER.clone(),
+            ))
+            .layer(SetRequestIdLayer::new(
+                api::logging::REQUEST_ID_HEADER.clone(),
+                MakeRequestUuid,
             ))
-            .route("/healthz", get(api::handlers::healthz))
-            .layer(TraceLayer::new_for_http()),
+            .layer(middleware::from_fn(
+                api::logging::request_id_validation_middleware,
+            )),
     )
     .await?;
 
@@ -0,0 +1,419 @@
+//! Copies a store from one backend to another, e.g. to move a heed database
+//! to a fresh file or, as backends are added, to another kind of database.
+//!
+//! Threads are read from an export of the source, so they are copied as of
+//! a single snapshot, and written as they are along with their embeddings,
+//! messages, annotations, shadow summaries, redacted spans, and the blobs
+//! their messages refer to. The audit log, usage, op-log, read markers,
+//! relevance feedback, and queued summaries are left behind.
+//!
+//! Writes are idempotent, so a migration can be run again over a partial
+//! copy. With a checkpoint file, the id of each copied thread is appended to
+//! it, and threads listed there are skipped rather than copied again.
+//!
+//! The verification pass exports both stores and compares their number of
+//! threads and messages, and a checksum of them, independent of the order
+//! backends export in. It only matches when the source isn't written to
+//! during the migration.
+
+use std::{collections::HashSet, path::PathBuf, str::FromStr, sync::Arc};
+
+use anyhow::{bail, Result};
+use ferrochain::futures::StreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use synx_database::{DatabaseError, Db};
+use synx_domain::{
+    blob::parse_blob_ref, content::ContentKind, export::ExportRecord, message::Message,
+    thread::Thread,
+};
+use synx_heed_database::{encryption::Keyring, SynxHeedDatabase};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+use uuid::Uuid;
+
+/// Threads copied between progress logs.
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// A store to migrate from or to, as `heed:<path>`.
+#[derive(Clone, Debug)]
+pub enum Backend {
+    Heed(PathBuf),
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some(("heed", path)) if !path.is_empty() => Ok(Self::Heed(path.into())),
+            Some((backend, _)) if backend != "heed" => Err(format!(
+                "unknown backend `{}`, expected `heed:<path>`",
+                backend
+            )),
+            _ => Err(format!("expected `heed:<path>`, got `{}`", value)),
+        }
+    }
+}
+
+impl Backend {
+    /// Opens the store, creating it when `create` is set, with the keys its
+    /// values are encrypted with, if any.
+    pub fn open(&self, create: bool, keyring: Option<Keyring>) -> Result<Arc<dyn Db>> {
+        match self {
+            Self::Heed(path) => {
+                if create {
+                    std::fs::create_dir_all(path)?;
+                } else if !path.is_dir() {
+                    bail!("{} is not a heed database", path.display());
+                }
+                let mut builder = SynxHeedDatabase::builder();
+                if let Some(keyring) = keyring {
+                    builder = builder.with_keyring(keyring);
+                }
+                Ok(Arc::new(builder.open(path)?))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub threads: usize,
+    pub embeddings: usize,
+    pub messages: usize,
+    pub annotations: usize,
+    pub blobs: usize,
+    /// Threads listed in the checkpoint file, copied by a previous run.
+    pub skipped_threads: usize,
+    pub verification: Option<Verification>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub source: Tally,
+    pub target: Tally,
+    pub matches: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Tally {
+    pub threads: usize,
+    pub messages: usize,
+    /// The sum of the SHA-256 prefixes of every exported record.
+    pub checksum: String,
+}
+
+pub struct Migration {
+    source: Arc<dyn Db>,
+    target: Arc<dyn Db>,
+    checkpoint: Option<File>,
+    /// Threads listed in the checkpoint file.
+    copied: HashSet<Uuid>,
+    blobs: HashSet<String>,
+    report: MigrationReport,
+}
+
+impl Migration {
+    /// Reads the threads already copied from the checkpoint file, if any,
+    /// creating it if needed.
+    pub async fn new(
+        source: Arc<dyn Db>,
+        target: Arc<dyn Db>,
+        checkpoint: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut copied = HashSet::new();
+        let checkpoint = match checkpoint {
+            Some(path) => {
+                if tokio::fs::try_exists(&path).await? {
+                    for line in tokio::fs::read_to_string(&path).await?.lines() {
+                        copied.insert(line.trim().parse()?);
+                    }
+                }
+                Some(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .await?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            source,
+            target,
+            checkpoint,
+            copied,
+            blobs: HashSet::new(),
+            report: MigrationReport::default(),
+        })
+    }
+
+    /// Copies every thread, then verifies the copy when `verify` is set.
+    pub async fn run(mut self, verify: bool) -> Result<MigrationReport> {
+        let mut records = self.source.export().await?;
+        // The thread whose messages are being copied, `None` while skipping
+        // one copied by a previous run.
+        let mut current = None;
+        while let Some(record) = records.next().await {
+            match record? {
+                ExportRecord::Thread(thread) => {
+                    if let Some(thread_id) = current.take() {
+                        self.finish_thread(thread_id).await?;
+                    }
+                    if self.copied.contains(&thread.id) {
+                        self.report.skipped_threads += 1;
+                        continue;
+                    }
+                    current = Some(thread.id);
+                    self.copy_thread(thread).await?;
+                }
+                ExportRecord::Message(message) if current == Some(message.thread_id) => {
+                    self.copy_message(message).await?;
+                }
+                ExportRecord::Message(_) => {}
+            }
+        }
+        if let Some(thread_id) = current {
+            self.finish_thread(thread_id).await?;
+        }
+        drop(records);
+
+        if verify {
+            let source = tally(self.source.as_ref()).await?;
+            let target = tally(self.target.as_ref()).await?;
+            self.report.verification = Some(Verification {
+                matches: source == target,
+                source,
+                target,
+            });
+        }
+        Ok(self.report)
+    }
+
+    async fn copy_thread(&mut self, thread: Thread) -> Result<()> {
+        let embedding = self
+            .source
+            .get_threads_with_embeddings(&[thread.id])
+            .await?
+            .pop()
+            .and_then(|stored| stored.embedding);
+        if embedding.is_some() {
+            self.report.embeddings += 1;
+        }
+        let thread_id = thread.id;
+        self.target
+            .put_thread(Thread {
+                embedding,
+                ..thread
+            })
+            .await?;
+
+        if let Some(shadow) = self.source.get_shadow_summary(thread_id).await? {
+            self.target.put_shadow_summary(shadow).await?;
+        }
+        if let Some(redactions) = self.source.get_summary_redactions(thread_id).await? {
+            self.target.put_summary_redactions(redactions).await?;
+        }
+
+        self.report.threads += 1;
+        Ok(())
+    }
+
+    async fn copy_message(&mut self, message: Message) -> Result<()> {
+        for kind in &message.content.0 {
+            let ContentKind::Image { image, .. } = kind else {
+                continue;
+            };
+            let Some(hash) = parse_blob_ref(image) else {
+                continue;
+            };
+            if self.blobs.contains(hash) {
+                continue;
+            }
+            match self.source.get_blob(hash.to_string()).await {
+                Ok((blob, data)) => {
+                    self.target.put_blob(blob, data).await?;
+                    self.report.blobs += 1;
+                }
+                Err(DatabaseError::NotFound) => {
+                    tracing::warn!(
+                        "Blob {} of message {} is missing from the source",
+                        hash,
+                        message.id
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+            self.blobs.insert(hash.to_string());
+        }
+
+        self.target.put_message(message).await?;
+        self.report.messages += 1;
+        Ok(())
+    }
+
+    /// Copies the annotations of the thread, which need its messages, then
+    /// records it as copied.
+    async fn finish_thread(&mut self, thread_id: Uuid) -> Result<()> {
+        for annotation in self.source.list_annotations(thread_id).await? {
+            match self.target.add_annotation(annotation).await {
+                Ok(()) => self.report.annotations += 1,
+                // The message was deleted after the export's snapshot.
+                Err(DatabaseError::NotFound) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint
+                .write_all(format!("{}\n", thread_id).as_bytes())
+                .await?;
+            checkpoint.flush().await?;
+        }
+        if self.report.threads % PROGRESS_INTERVAL == 0 {
+            tracing::info!(
+                threads = self.report.threads,
+                messages = self.report.messages,
+                "Migrating"
+            );
+        }
+        Ok(())
+    }
+}
+
+async fn tally(db: &dyn Db) -> Result<Tally> {
+    let mut records = db.export().await?;
+    let (mut threads, mut messages, mut checksum) = (0, 0, 0u64);
+    while let Some(record) = records.next().await {
+        let record = record?;
+        match &record {
+            ExportRecord::Thread(_) => threads += 1,
+            ExportRecord::Message(_) => messages += 1,
+        }
+        let hash = Sha256::digest(serde_json::to_vec(&record)?);
+        checksum = checksum.wrapping_add(u64::from_be_bytes(hash[..8].try_into()?));
+    }
+
+    Ok(Tally {
+        threads,
+        messages,
+        checksum: format!("{:016x}", checksum),
+    })
+}
+
+/// Fails unless both backends are different stores.
+pub fn check_backends(from: &Backend, to: &Backend) -> Result<()> {
+    match (from, to) {
+        (Backend::Heed(from), Backend::Heed(to)) if from == to => {
+            bail!("cannot migrate {} to itself", from.display())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use synx_domain::{message::CreateMessage, role::Role, thread::CreateThread};
+    use synx_in_memory_database::SynxInMemory;
+
+    use super::*;
+
+    #[test]
+    fn backends_are_parsed() {
+        assert!(matches!(
+            "heed:/var/lib/synx".parse::<Backend>(),
+            Ok(Backend::Heed(path)) if path == PathBuf::from("/var/lib/synx")
+        ));
+        assert!("postgres://localhost/synx".parse::<Backend>().is_err());
+        assert!("heed:".parse::<Backend>().is_err());
+    }
+
+    #[tokio::test]
+    async fn copied_threads_are_verified_and_skipped_on_resume() {
+        let source: Arc<dyn Db> = Arc::new(SynxInMemory::new());
+        let target: Arc<dyn Db> = Arc::new(SynxInMemory::new());
+        for text in ["Hello", "Hi"] {
+            let thread = source.create_thread(CreateThread::default()).await.unwrap();
+            source
+                .create_message(
+                    thread.id,
+                    CreateMessage {
+                        role: Role::from("user"),
+                        content: text.to_string().into(),
+                        parent_message_id: None,
+                        metadata: Default::default(),
+                        status: Default::default(),
+                        memorize: true,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        let checkpoint = tempfile::NamedTempFile::new().unwrap();
+        let migration =
+            |source, target| Migration::new(source, target, Some(checkpoint.path().to_path_buf()));
+
+        let report = migration(source.clone(), target.clone())
+            .await
+            .unwrap()
+            .run(true)
+            .await
+            .unwrap();
+        assert_eq!((report.threads, report.messages), (2, 2));
+        assert!(report.verification.unwrap().matches);
+
+        let report = migration(source, target)
+            .await
+            .unwrap()
+            .run(false)
+            .await
+            .unwrap();
+        assert_eq!((report.threads, report.skipped_threads), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn encrypted_stores_are_migrated_to_plain_ones() {
+        let (from, to) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let keyring = || Keyring::parse(&format!("1:{}", "ab".repeat(32))).unwrap();
+        let source = Backend::Heed(from.path().to_path_buf())
+            .open(true, Some(keyring()))
+            .unwrap();
+        let target = Backend::Heed(to.path().to_path_buf())
+            .open(true, None)
+            .unwrap();
+
+        let thread = source.create_thread(CreateThread::default()).await.unwrap();
+        source
+            .create_message(
+                thread.id,
+                CreateMessage {
+                    role: Role::from("user"),
+                    content: "Hello".to_string().into(),
+                    parent_message_id: None,
+                    metadata: Default::default(),
+                    status: Default::default(),
+                    memorize: true,
+                },
+            )
+            .await
+            .unwrap();
+
+        let report = Migration::new(source, target.clone(), None)
+            .await
+            .unwrap()
+            .run(true)
+            .await
+            .unwrap();
+        assert!(report.verification.unwrap().matches);
+        let messages = target
+            .get_thread_messages(thread.id, None, None)
+            .await
+            .unwrap();
+        assert_eq!(messages.messages.len(), 1);
+    }
+}
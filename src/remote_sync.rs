@@ -0,0 +1,164 @@
+//! Keeps a local instance in sync with a remote server, pushing the changes
+//! made locally and pulling the ones made elsewhere. See
+//! [`synx::replication`] for how conflicts are resolved.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use synx::{replication::ApplyReport, Synx};
+use synx_domain::sync::{Operation, OperationFilter, SyncCursor};
+
+/// Maximum number of operations exchanged per request.
+const BATCH_SIZE: usize = 500;
+
+pub struct RemoteSync {
+    synx: Synx,
+    client: reqwest::Client,
+    remote: String,
+    token: Option<String>,
+    /// Last sequence numbers seen in the local and remote op-logs, read from
+    /// the database on the first round and saved as they advance, so a
+    /// restart resumes where the last run stopped.
+    cursor: Option<SyncCursor>,
+}
+
+impl RemoteSync {
+    pub fn new(synx: Synx, remote: impl Into<String>, token: Option<String>) -> Result<Self> {
+        if synx.replica_id().is_none() {
+            return Err(anyhow!("syncing requires a replica id"));
+        }
+
+        Ok(Self {
+            synx,
+            client: reqwest::Client::new(),
+            remote: remote.into().trim_end_matches('/').to_string(),
+            token,
+            cursor: None,
+        })
+    }
+
+    /// Syncs every `interval` until the task is dropped, retrying failed
+    /// rounds on the next tick.
+    pub async fn run(mut self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.sync().await {
+                tracing::warn!("Failed to sync with {}: {:?}", self.remote, e);
+            }
+        }
+    }
+
+    /// Pushes local changes, then pulls remote ones.
+    pub async fn sync(&mut self) -> Result<()> {
+        if self.cursor.is_none() {
+            self.cursor = Some(self.synx.sync_cursor(&self.remote).await?);
+        }
+        let pushed = self.push().await?;
+        let pulled = self.pull().await?;
+        if pushed > 0 || pulled.applied > 0 {
+            tracing::info!(
+                pushed,
+                pulled = pulled.applied,
+                skipped = pulled.skipped,
+                "Synced with {}",
+                self.remote
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn push(&mut self) -> Result<usize> {
+        let mut pushed = 0;
+        loop {
+            let operations = self
+                .synx
+                .list_operations(OperationFilter {
+                    since_seq: self.cursor().pushed_seq,
+                    origin: self.synx.replica_id(),
+                    exclude_origin: None,
+                    limit: Some(BATCH_SIZE),
+                })
+                .await?;
+            let Some(last_seq) = operations.last().map(|operation| operation.seq) else {
+                return Ok(pushed);
+            };
+
+            self.request(
+                self.client
+                    .post(format!("{}/sync/operations", self.remote))
+                    .json(&operations),
+            )
+            .send()
+            .await?
+            .error_for_status()
+            .context("remote rejected operations")?;
+
+            pushed += operations.len();
+            self.advance(SyncCursor {
+                pushed_seq: last_seq,
+                ..self.cursor()
+            })
+            .await?;
+            if operations.len() < BATCH_SIZE {
+                return Ok(pushed);
+            }
+        }
+    }
+
+    async fn pull(&mut self) -> Result<ApplyReport> {
+        let mut report = ApplyReport::default();
+        loop {
+            let operations: Vec<Operation> = self
+                .request(self.client.get(format!("{}/changes", self.remote)).query(
+                    &OperationFilter {
+                        since_seq: self.cursor().pulled_seq,
+                        origin: None,
+                        exclude_origin: self.synx.replica_id(),
+                        limit: Some(BATCH_SIZE),
+                    },
+                ))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .context("invalid operations")?;
+            let Some(last_seq) = operations.last().map(|operation| operation.seq) else {
+                return Ok(report);
+            };
+
+            let fetched = operations.len();
+            let applied = self.synx.apply_operations(operations).await?;
+            report.applied += applied.applied;
+            report.skipped += applied.skipped;
+
+            self.advance(SyncCursor {
+                pulled_seq: last_seq,
+                ..self.cursor()
+            })
+            .await?;
+            if fetched < BATCH_SIZE {
+                return Ok(report);
+            }
+        }
+    }
+
+    fn cursor(&self) -> SyncCursor {
+        self.cursor.unwrap_or_default()
+    }
+
+    async fn advance(&mut self, cursor: SyncCursor) -> Result<()> {
+        self.synx.save_sync_cursor(&self.remote, cursor).await?;
+        self.cursor = Some(cursor);
+        Ok(())
+    }
+
+    fn request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
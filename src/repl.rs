@@ -0,0 +1,190 @@
+//! A debugging console over a store: inspects threads and their messages,
+//! compares texts with the document embedder, and sends prompts to the
+//! summarizer. Commands changing threads are refused unless started with
+//! `--write`, though provider calls still record their usage.
+
+use anyhow::{anyhow, bail, Context, Result};
+use synx::Synx;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+const HELP: &str = indoc::indoc! {"
+    threads               lists threads with the start of their summary
+    thread <id>           prints a thread
+    messages <id>         prints the messages of a thread, oldest first
+    similarity <a> | <b>  compares the document embeddings of two texts
+    prompt <text>         prints the summarizer's answer to the text
+    delete <id>           deletes a thread, with --write
+    help                  prints this
+    quit                  exits
+"};
+
+/// Characters of each summary listed by `threads`.
+const SUMMARY_PREVIEW: usize = 60;
+
+pub struct Repl {
+    synx: Synx,
+    write: bool,
+}
+
+impl Repl {
+    pub fn new(synx: Synx, write: bool) -> Self {
+        Self { synx, write }
+    }
+
+    /// Runs commands line by line until `quit` or the end of the input,
+    /// printing their output, or their error without stopping.
+    pub async fn run(
+        &self,
+        input: impl AsyncBufRead + Unpin,
+        mut output: impl AsyncWrite + Unpin,
+    ) -> Result<()> {
+        let mut lines = input.lines();
+        loop {
+            output.write_all(b"synx> ").await?;
+            output.flush().await?;
+            let Some(line) = lines.next_line().await? else {
+                return Ok(());
+            };
+            let line = line.trim();
+            if matches!(line, "quit" | "exit") {
+                return Ok(());
+            }
+
+            let printed = match self.execute(line).await {
+                Ok(printed) => printed,
+                Err(e) => format!("error: {:#}", e),
+            };
+            if !printed.is_empty() {
+                output.write_all(printed.as_bytes()).await?;
+                output.write_all(b"\n").await?;
+            }
+        }
+    }
+
+    /// The output of a single command.
+    pub async fn execute(&self, line: &str) -> Result<String> {
+        let (command, argument) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(command, argument)| (command, argument.trim()));
+
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(HELP.trim_end().to_string()),
+            "threads" => Ok(self
+                .synx
+                .list_threads()
+                .await?
+                .into_iter()
+                .map(|thread| {
+                    let summary = thread.summary.unwrap_or_default();
+                    let mut preview = summary.chars().take(SUMMARY_PREVIEW).collect::<String>();
+                    if preview.len() < summary.len() {
+                        preview.push('…');
+                    }
+                    format!("{}  {}", thread.id, preview)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "thread" => {
+                let thread = self.synx.get_thread(thread_id(argument)?).await?;
+                Ok(serde_json::to_string_pretty(&thread)?)
+            }
+            "messages" => {
+                let messages = self
+                    .synx
+                    .get_messages(thread_id(argument)?, None, None)
+                    .await?
+                    .messages;
+                Ok(serde_json::to_string_pretty(&messages)?)
+            }
+            "similarity" => {
+                let (a, b) = argument
+                    .split_once('|')
+                    .ok_or_else(|| anyhow!("expected `similarity <a> | <b>`"))?;
+                let similarity = self.synx.text_similarity(a.trim(), b.trim()).await?;
+                Ok(format!("{:.4}", similarity))
+            }
+            "prompt" => {
+                if argument.is_empty() {
+                    bail!("expected `prompt <text>`");
+                }
+                self.synx.complete_prompt(argument).await
+            }
+            "delete" => {
+                if !self.write {
+                    bail!("the store is read-only, restart with --write to delete threads");
+                }
+                let thread_id = thread_id(argument)?;
+                self.synx.delete_thread(thread_id).await?;
+                Ok(format!("Deleted {}", thread_id))
+            }
+            _ => bail!("unknown command `{}`, see `help`", command),
+        }
+    }
+}
+
+fn thread_id(argument: &str) -> Result<Uuid> {
+    argument
+        .parse()
+        .with_context(|| format!("expected a thread id, got `{}`", argument))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use synx::testing::{FakeEmbedder, FakeSummarizer};
+    use synx_domain::thread::{CreateThread, SetSummary};
+    use synx_in_memory_database::SynxInMemory;
+
+    use super::*;
+
+    fn synx() -> Synx {
+        Synx::builder()
+            .with_db(Arc::new(SynxInMemory::new()))
+            .with_document_embedder(Arc::new(FakeEmbedder::new()))
+            .with_query_embedder(Arc::new(FakeEmbedder::new()))
+            .with_summarizer(Arc::new(FakeSummarizer))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn commands_changing_the_store_need_write() {
+        let synx = synx();
+        let thread = synx.create_thread(CreateThread::default()).await.unwrap();
+        synx.set_summary(
+            thread.id,
+            SetSummary {
+                summary: "The user asked for a pancake recipe.".to_string(),
+                locked: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let repl = Repl::new(synx.clone(), false);
+        let listed = repl.execute("threads").await.unwrap();
+        assert!(listed.starts_with(&thread.id.to_string()));
+        assert!(listed.ends_with("pancake recipe."));
+        assert_eq!(
+            repl.execute("similarity pancakes | pancakes")
+                .await
+                .unwrap(),
+            "1.0000"
+        );
+        assert!(repl.execute("delete not-an-id").await.is_err());
+        assert!(repl
+            .execute(&format!("delete {}", thread.id))
+            .await
+            .is_err());
+        assert!(repl.execute("frobnicate").await.is_err());
+
+        let repl = Repl::new(synx.clone(), true);
+        repl.execute(&format!("delete {}", thread.id))
+            .await
+            .unwrap();
+        assert!(synx.list_threads().await.unwrap().is_empty());
+    }
+}
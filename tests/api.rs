@@ -0,0 +1,1955 @@
+//! Drives the HTTP API end to end, through the real router, with the fake
+//! providers and the in-memory database.
+
+use std::{
+    future::IntoFuture,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use ferrochain::{
+    completion::{Completion, StreamEvent},
+    embedding::{Embedder, Embedding},
+    futures::Stream,
+    message::Message,
+};
+use http_body_util::BodyExt;
+use memory::{
+    api::{
+        auth::Identity,
+        compression::CompressionOptions,
+        routes::router,
+        thread_tokens::{thread_token_middleware, ThreadTokens},
+    },
+    client::Client,
+    domain::{message::CreateMessage, role::Role, thread::PendingSummary},
+    in_memory::SynxInMemory,
+    remote_sync::RemoteSync,
+    testing::{FakeEmbedder, FakeSummarizer},
+    Db, SimilarityMetric, Synx,
+};
+use serde_json::{json, Value};
+use synx::{
+    executor::{DeferredExecutor, TokioExecutor},
+    redaction::{RedactionStage, Redactor},
+    scheduler::Task,
+    shadow::{ShadowMode, ShadowSummarizer},
+    SynxBuilder,
+};
+use tower::ServiceExt;
+
+fn app() -> Router {
+    app_with(|builder| builder)
+}
+
+fn app_with(configure: impl FnOnce(SynxBuilder) -> SynxBuilder) -> Router {
+    let synx = configure(
+        Synx::builder()
+            .with_db(Arc::new(SynxInMemory::new()))
+            .with_document_embedder(Arc::new(FakeEmbedder::new()))
+            .with_query_embedder(Arc::new(FakeEmbedder::new()))
+            .with_summarizer(Arc::new(FakeSummarizer))
+            .with_executor(Arc::new(TokioExecutor)),
+    )
+    .build()
+    .unwrap();
+
+    router(synx)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Option<Value>) -> Response {
+    let request = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+
+    app.clone().oneshot(request.unwrap()).await.unwrap()
+}
+
+async fn json(response: Response) -> Value {
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+async fn create_thread(app: &Router) -> String {
+    let response = send(app, Method::POST, "/threads", None).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    json(response).await["id"].as_str().unwrap().to_string()
+}
+
+async fn create_message(app: &Router, thread_id: &str, text: &str) -> Value {
+    let response = send(
+        app,
+        Method::POST,
+        &format!("/threads/{}/messages", thread_id),
+        Some(json!({ "role": "user", "content": text })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    json(response).await
+}
+
+fn header<'a>(response: &'a Response, name: &str) -> &'a str {
+    response.headers()[name].to_str().unwrap()
+}
+
+/// Has every request made by a caller of the tenant, as the OIDC
+/// middleware would.
+fn as_tenant(app: Router, tenant: &'static str) -> Router {
+    app.layer(middleware::from_fn(
+        move |mut request: Request<Body>, next: Next| async move {
+            request.extensions_mut().insert(Identity {
+                subject: "user".to_string(),
+                tenant: Some(tenant.to_string()),
+                threads: None,
+            });
+            next.run(request).await
+        },
+    ))
+}
+
+#[tokio::test]
+async fn threads_are_created_read_and_deleted() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response).await["id"], thread_id.as_str());
+
+    let response = send(&app, Method::GET, "/threads", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response).await.as_array().unwrap().len(), 1);
+
+    let response = send(
+        &app,
+        Method::DELETE,
+        &format!("/threads/{}", thread_id),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn messages_are_created_updated_and_deleted() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    let message = create_message(&app, &thread_id, "What is the weather in Paris?").await;
+    let message_uri = format!(
+        "/threads/{}/messages/{}",
+        thread_id,
+        message["id"].as_str().unwrap()
+    );
+
+    let response = send(
+        &app,
+        Method::PUT,
+        &message_uri,
+        Some(json!({ "content": "What is the weather in Rome?" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages", thread_id),
+        None,
+    )
+    .await;
+    let messages = json(response).await;
+    assert_eq!(messages.as_array().unwrap().len(), 1);
+    assert_eq!(
+        messages[0]["content"][0]["text"],
+        "What is the weather in Rome?"
+    );
+
+    let response = send(&app, Method::DELETE, &message_uri, None).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages", thread_id),
+        None,
+    )
+    .await;
+    assert!(json(response).await.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn snapshots_carry_the_thread_and_its_latest_messages() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    for index in 0..3 {
+        create_message(&app, &thread_id, &format!("Message {}", index)).await;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/snapshot?messages=2", thread_id),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let snapshot = json(response).await;
+    assert_eq!(snapshot["id"], thread_id.as_str());
+    assert_eq!(snapshot["total_messages"], 3);
+    let messages = snapshot["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["content"][0]["text"], "Message 1");
+    assert_eq!(messages[1]["content"][0]["text"], "Message 2");
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/snapshot", uuid::Uuid::new_v4()),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn threads_are_fetched_in_a_batch() {
+    let app = app();
+    let first = create_thread(&app).await;
+    let second = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", second),
+        Some(json!({ "summary": "The user asked for a pancake recipe." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let missing = uuid::Uuid::new_v4().to_string();
+
+    let batch_get = |include_summaries: bool| {
+        send(
+            &app,
+            Method::POST,
+            "/threads/batch-get",
+            Some(json!({
+                "thread_ids": [second, missing, first],
+                "include_summaries": include_summaries,
+            })),
+        )
+    };
+    let threads = json(batch_get(false).await).await;
+    let threads = threads.as_array().unwrap();
+    assert_eq!(threads.len(), 2);
+    assert_eq!(threads[0]["id"], second.as_str());
+    assert_eq!(threads[1]["id"], first.as_str());
+    assert!(threads[0]["summary"].is_null());
+
+    let threads = json(batch_get(true).await).await;
+    assert_eq!(
+        threads[0]["summary"],
+        "The user asked for a pancake recipe."
+    );
+
+    let too_many = (0..101)
+        .map(|_| uuid::Uuid::new_v4().to_string())
+        .collect::<Vec<_>>();
+    let response = send(
+        &app,
+        Method::POST,
+        "/threads/batch-get",
+        Some(json!({ "thread_ids": too_many })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn messages_are_paginated() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    for index in 0..5 {
+        create_message(&app, &thread_id, &format!("Message {}", index)).await;
+    }
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages?limit=2&offset=2", thread_id),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-total-count"), "5");
+    assert_eq!(header(&response, "x-total-pages"), "3");
+    assert_eq!(header(&response, "x-offset"), "2");
+    assert_eq!(header(&response, "x-limit"), "2");
+    assert!(header(&response, "link").contains(&format!(
+        "</threads/{}/messages?offset=4&limit=2>; rel=\"next\"",
+        thread_id
+    )));
+
+    let messages = json(response).await;
+    assert_eq!(messages.as_array().unwrap().len(), 2);
+    assert_eq!(messages[0]["content"][0]["text"], "Message 2");
+}
+
+#[tokio::test]
+async fn search_ranks_threads_by_summary() {
+    let app = app();
+    let mut threads = Vec::new();
+    for summary in [
+        "The user asked about the weather forecast in Paris.",
+        "The user asked for a pancake recipe.",
+    ] {
+        let thread_id = create_thread(&app).await;
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": summary })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "weather in Paris", "thread_ids": threads })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let results = json(response).await;
+    assert_eq!(results.as_array().unwrap().len(), 2);
+    assert_eq!(results[0]["stored"]["id"], threads[0].as_str());
+}
+
+#[tokio::test]
+async fn search_results_are_shaped_on_request() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked for a pancake recipe." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let search = |shape: Value| {
+        let mut request = json!({ "query": "pancakes", "thread_ids": [thread_id] });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(shape.as_object().unwrap().clone());
+        send(&app, Method::POST, "/search", Some(request))
+    };
+
+    let results = json(search(json!({})).await).await;
+    let document = &results[0]["stored"]["document"];
+    assert_eq!(document["content"], "The user asked for a pancake recipe.");
+    assert!(document["metadata"].as_object().unwrap().is_empty());
+
+    let results =
+        json(search(json!({ "summary_max_chars": 8, "include_metadata": true })).await).await;
+    let document = &results[0]["stored"]["document"];
+    assert_eq!(document["content"], "The user");
+    assert!(document["metadata"]["updated_at"].is_u64());
+
+    let results = json(search(json!({ "include_summary": false })).await).await;
+    assert_eq!(results[0]["stored"]["document"]["content"], "");
+}
+
+#[tokio::test]
+async fn searches_choose_their_similarity_metric() {
+    let app = app_with(|builder| builder.with_similarity_metric(SimilarityMetric::Euclidean));
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked for a pancake recipe." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let search = |metric: Value| {
+        send(
+            &app,
+            Method::POST,
+            "/search",
+            Some(json!({ "query": "pancakes", "thread_ids": [thread_id], "metric": metric })),
+        )
+    };
+    let score = |results: Value| results[0]["score"].as_f64().unwrap();
+
+    let euclidean = score(json(search(Value::Null).await).await);
+    assert!(euclidean > 0.0 && euclidean <= 1.0);
+    let cosine = score(json(search(json!("cosine")).await).await);
+    assert!((cosine - euclidean).abs() > 1e-6);
+
+    let response = search(json!("manhattan")).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn titles_are_embedded_as_templated() {
+    let app = app_with(|builder| builder.with_embedding_template("{{TITLE}}\n{{SUMMARY}}"));
+    let mut threads = Vec::new();
+    for title in ["Invoice 4521", "Onboarding"] {
+        let thread_id = create_thread(&app).await;
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}", thread_id),
+            Some(json!({ "title": title })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": "The user asked about a payment." })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "invoice 4521", "thread_ids": threads })),
+    )
+    .await;
+    let results = json(response).await;
+    assert_eq!(results[0]["stored"]["id"], threads[0].as_str());
+    assert!(results[0]["score"].as_f64().unwrap() > results[1]["score"].as_f64().unwrap());
+}
+
+#[tokio::test]
+async fn searches_page_through_tied_results_by_id() {
+    let app = app();
+    let mut threads = Vec::new();
+    for _ in 0..5 {
+        let thread_id = create_thread(&app).await;
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": "The user asked for a pancake recipe." })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+    let thread_ids = threads.clone();
+    let search = move |page: Value| {
+        let app = app.clone();
+        let mut body = json!({ "query": "pancakes", "thread_ids": thread_ids });
+        body.as_object_mut()
+            .unwrap()
+            .extend(page.as_object().unwrap().clone());
+        async move { send(&app, Method::POST, "/search", Some(body)).await }
+    };
+
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    loop {
+        let response = search(json!({ "limit": 2, "cursor": cursor })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "x-total-count"), "5");
+        cursor = response
+            .headers()
+            .get("x-next-cursor")
+            .map(|cursor| cursor.to_str().unwrap().to_string());
+        for result in json(response).await.as_array().unwrap() {
+            paged.push(result["stored"]["id"].as_str().unwrap().to_string());
+        }
+        if cursor.is_none() {
+            break;
+        }
+    }
+    threads.sort();
+    assert_eq!(paged, threads);
+
+    let response = search(json!({ "limit": 2, "offset": 3 })).await;
+    let results = json(response).await;
+    assert_eq!(results.as_array().unwrap().len(), 2);
+    assert_eq!(results[0]["stored"]["id"], threads[3].as_str());
+
+    let response = search(json!({ "cursor": "not a cursor" })).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn cached_searches_follow_summary_updates() {
+    let app = app_with(|builder| builder.with_search_cache(16, Duration::from_secs(60)));
+    let set_summary = |thread_id: String, summary: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = send(
+                &app,
+                Method::PUT,
+                &format!("/threads/{}/summary", thread_id),
+                Some(json!({ "summary": summary })),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    };
+    let search = |threads: Vec<String>| {
+        let app = app.clone();
+        async move {
+            let response = send(
+                &app,
+                Method::POST,
+                "/search",
+                Some(json!({ "query": "weather in Paris", "thread_ids": threads })),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            json(response).await
+        }
+    };
+
+    let weather = create_thread(&app).await;
+    let pancakes = create_thread(&app).await;
+    set_summary(
+        weather.clone(),
+        "The user asked about the weather forecast in Paris.",
+    )
+    .await;
+    set_summary(pancakes.clone(), "The user asked for a pancake recipe.").await;
+    let threads = vec![weather.clone(), pancakes.clone()];
+
+    let first = search(threads.clone()).await;
+    assert_eq!(first[0]["stored"]["id"], weather.as_str());
+    assert_eq!(search(threads.clone()).await, first);
+
+    set_summary(weather, "The user asked for a pancake recipe.").await;
+    set_summary(
+        pancakes.clone(),
+        "The user asked about the weather in Paris.",
+    )
+    .await;
+    let results = search(threads).await;
+    assert_eq!(results[0]["stored"]["id"], pancakes.as_str());
+}
+
+#[tokio::test]
+async fn expanded_searches_fuse_the_rankings_of_paraphrases() {
+    let app = app();
+    let mut threads = Vec::new();
+    for summary in [
+        "The user asked about the weather forecast in Paris.",
+        "The user asked for a pancake recipe.",
+    ] {
+        let thread_id = create_thread(&app).await;
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": summary })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "weather in Paris", "thread_ids": threads, "expand": true })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let results = json(response).await;
+    assert_eq!(results.as_array().unwrap().len(), 2);
+    // Fused ranks score at most 1 / 61 for each of the rankings.
+    for result in results.as_array().unwrap() {
+        assert!(result["score"].as_f64().unwrap() <= 4.0 / 61.0 + 1e-6);
+    }
+}
+
+#[tokio::test]
+async fn reranked_searches_keep_every_result() {
+    let app = app();
+    let mut threads = Vec::new();
+    for summary in [
+        "The user asked about the weather forecast in Paris.",
+        "The user asked for a pancake recipe.",
+        "The user asked about rain in London.",
+    ] {
+        let thread_id = create_thread(&app).await;
+        let response = send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": summary })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "weather in Paris", "thread_ids": threads, "rerank": true })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut ids = json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["stored"]["id"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    ids.sort();
+    threads.sort();
+    assert_eq!(ids, threads);
+}
+
+#[tokio::test]
+async fn query_embeddings_are_cached_across_spellings() {
+    let app = app();
+    for query in ["Weather in Paris", "  weather   in PARIS "] {
+        let response = send(
+            &app,
+            Method::POST,
+            "/search",
+            Some(json!({ "query": query, "thread_ids": [] })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = send(&app, Method::GET, "/admin/usage", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let embedding_requests = json(response)
+        .await
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|usage| usage["thread_id"].is_null())
+        .map(|usage| usage["embedding_requests"].as_u64().unwrap())
+        .sum::<u64>();
+    assert_eq!(embedding_requests, 1);
+}
+
+/// Embeds like [`FakeEmbedder`] until told to fail.
+#[derive(Default)]
+struct FlakyEmbedder {
+    down: AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl Embedder for FlakyEmbedder {
+    async fn embed(&self, input: Vec<String>) -> anyhow::Result<Vec<Embedding>> {
+        if self.down.load(Ordering::SeqCst) {
+            anyhow::bail!("the provider is down");
+        }
+        FakeEmbedder::new().embed(input).await
+    }
+}
+
+#[tokio::test]
+async fn searches_degrade_to_cached_results_when_the_embedder_is_down() {
+    let query_embedder = Arc::new(FlakyEmbedder::default());
+    let synx = Synx::builder()
+        .with_db(Arc::new(SynxInMemory::new()))
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(query_embedder.clone())
+        .with_summarizer(Arc::new(FakeSummarizer))
+        .with_executor(Arc::new(TokioExecutor))
+        .with_search_cache(16, Duration::from_secs(60))
+        .with_query_embedding_cache(0, Duration::ZERO)
+        .build()
+        .unwrap();
+    let app = router(synx.clone());
+    let search = |query: &'static str, threads: Vec<String>| {
+        let app = app.clone();
+        async move {
+            send(
+                &app,
+                Method::POST,
+                "/search",
+                Some(json!({ "query": query, "thread_ids": threads })),
+            )
+            .await
+        }
+    };
+
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked about the weather in Paris." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = search("weather", vec![thread_id.clone()]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("x-search-degraded").is_none());
+    let fresh = json(response).await;
+    assert!(!synx.health().degraded);
+
+    query_embedder.down.store(true, Ordering::SeqCst);
+    // Made stale by the summary update, the cached results are served all
+    // the same.
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked about the weather in London." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = search("weather", vec![thread_id.clone()]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "x-search-degraded"), "true");
+    assert_eq!(json(response).await, fresh);
+
+    let response = search("rain", vec![thread_id]).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let health = synx.health();
+    assert!(health.degraded);
+    assert!(!health.query_embedder.healthy);
+    assert!(health.document_embedder.healthy);
+}
+
+/// Summarizes like [`FakeSummarizer`] until told to fail.
+#[derive(Default)]
+struct FlakySummarizer {
+    down: AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl Completion for FlakySummarizer {
+    async fn complete(
+        &self,
+        messages: Vec<Message>,
+    ) -> anyhow::Result<Pin<Box<dyn Stream<Item = anyhow::Result<StreamEvent>> + Send>>> {
+        if self.down.load(Ordering::SeqCst) {
+            anyhow::bail!("the provider is down");
+        }
+        FakeSummarizer.complete(messages).await
+    }
+}
+
+#[tokio::test]
+async fn failed_summaries_are_retried_once_the_summarizer_recovers() {
+    let summarizer = Arc::new(FlakySummarizer::default());
+    let executor = Arc::new(DeferredExecutor::new());
+    let synx = Synx::builder()
+        .with_db(Arc::new(SynxInMemory::new()))
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(summarizer.clone())
+        .with_executor(executor.clone())
+        .build()
+        .unwrap();
+    let app = router(synx.clone());
+    let thread_id = create_thread(&app).await;
+
+    summarizer.down.store(true, Ordering::SeqCst);
+    create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+    // Queued without calling the summarizer, deemed down.
+    create_message(&app, &thread_id, "World").await;
+    executor.run_until_idle().await;
+
+    assert!(!synx.health().summarizer.healthy);
+    assert_eq!(synx.analytics().pending_summaries, 2);
+    let response = send(&app, Method::GET, "/admin/analytics", None).await;
+    assert_eq!(json(response).await["pending_summaries"], 2);
+
+    // Probes with the oldest summary while the summarizer is deemed down.
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+    assert_eq!(synx.pending_summaries().await.unwrap()[0].attempts, 2);
+
+    summarizer.down.store(false, Ordering::SeqCst);
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+    assert!(synx.health().summarizer.healthy);
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+
+    assert!(synx.pending_summaries().await.unwrap().is_empty());
+    assert_eq!(synx.analytics().pending_summaries, 0);
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], "Hello\nWorld");
+}
+
+#[tokio::test]
+async fn summary_languages_are_kept_until_translations_succeed() {
+    let summarizer = Arc::new(FlakySummarizer::default());
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| {
+        builder
+            .with_summarizer(summarizer.clone())
+            .with_executor(executor.clone())
+    });
+    let thread_id = create_thread(&app).await;
+    create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+    let translate = || async {
+        send(
+            &app,
+            Method::POST,
+            &format!("/threads/{}/summary/translate", thread_id),
+            Some(json!({ "language": "French" })),
+        )
+        .await
+    };
+    let language = || async {
+        let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+        json(response).await["summary_language"].clone()
+    };
+
+    summarizer.down.store(true, Ordering::SeqCst);
+    let response = translate().await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(language().await, Value::Null);
+
+    summarizer.down.store(false, Ordering::SeqCst);
+    let response = translate().await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(language().await, "French");
+
+    let update = |body: Value| async {
+        send(
+            &app,
+            Method::PUT,
+            &format!("/threads/{}", thread_id),
+            Some(body),
+        )
+        .await
+    };
+    let response = update(json!({ "title": "Greetings" })).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(language().await, "French");
+    let response = update(json!({ "title": "Greetings", "summary_language": null })).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(language().await, Value::Null);
+}
+
+#[tokio::test]
+async fn summaries_failing_to_embed_are_retried() {
+    let embedder = Arc::new(FlakyEmbedder::default());
+    let executor = Arc::new(DeferredExecutor::new());
+    let synx = Synx::builder()
+        .with_db(Arc::new(SynxInMemory::new()))
+        .with_document_embedder(embedder.clone())
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(Arc::new(FakeSummarizer))
+        .with_executor(executor.clone())
+        .build()
+        .unwrap();
+    let app = router(synx.clone());
+    let thread_id = create_thread(&app).await;
+
+    embedder.down.store(true, Ordering::SeqCst);
+    let message = create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+
+    let pending = synx.pending_summaries().await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].message_id.to_string(), message["id"]);
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], Value::Null);
+
+    embedder.down.store(false, Ordering::SeqCst);
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+
+    assert!(synx.pending_summaries().await.unwrap().is_empty());
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], "Hello");
+}
+
+#[tokio::test]
+async fn reads_with_a_min_sequence_see_the_summaries_of_earlier_writes() {
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| builder.with_executor(executor.clone()));
+    let thread_id = create_thread(&app).await;
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/messages", thread_id),
+        Some(json!({ "role": "user", "content": "Hello" })),
+    )
+    .await;
+    let sequence = header(&response, "x-sequence").to_string();
+    let read = |min_sequence: &str| {
+        let request = Request::builder()
+            .uri(format!("/threads/{}", thread_id))
+            .header("x-min-sequence", min_sequence)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], Value::Null);
+    let waiting = tokio::spawn(read(&sequence));
+    tokio::task::yield_now().await;
+    assert!(!waiting.is_finished());
+
+    executor.run_until_idle().await;
+    let response = waiting.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response).await["summary"], "Hello");
+
+    let response = read("not-a-sequence").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Beyond the last sequence issued, or issued by a later run.
+    let (epoch, issued) = sequence.split_once('.').unwrap();
+    let epoch: u64 = epoch.parse().unwrap();
+    let issued: u64 = issued.parse().unwrap();
+    for token in [
+        format!("{}.{}", epoch, issued + 1),
+        format!("{}.{}", epoch + 1, issued),
+    ] {
+        let response = read(&token).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    // Issued by an earlier run, whose summaries are done or queued.
+    let response = read(&format!("{}.{}", epoch - 1, issued + 1))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn messages_matching_unsummarized_patterns_are_stored_but_not_summarized() {
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| {
+        builder
+            .with_executor(executor.clone())
+            .with_unsummarized_patterns(["^heartbeat$"])
+    });
+    let thread_id = create_thread(&app).await;
+
+    create_message(&app, &thread_id, "Hello").await;
+    create_message(&app, &thread_id, "heartbeat").await;
+    create_message(&app, &thread_id, "World").await;
+    executor.run_until_idle().await;
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], "Hello\nWorld");
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages", thread_id),
+        None,
+    )
+    .await;
+    assert_eq!(json(response).await.as_array().unwrap().len(), 3);
+
+    assert!(Synx::builder()
+        .with_unsummarized_patterns(["("])
+        .build()
+        .is_err());
+}
+
+#[tokio::test]
+async fn retried_summaries_of_summarized_messages_are_skipped() {
+    let db = Arc::new(SynxInMemory::new());
+    let executor = Arc::new(DeferredExecutor::new());
+    let synx = Synx::builder()
+        .with_db(db.clone())
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(Arc::new(FakeSummarizer))
+        .with_executor(executor.clone())
+        .build()
+        .unwrap();
+    let app = router(synx.clone());
+    let thread_id = create_thread(&app).await;
+    let message = create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+
+    // As if its summary had failed on another instance, or before a restart.
+    db.put_pending_summary(PendingSummary {
+        thread_id: thread_id.parse().unwrap(),
+        message_id: message["id"].as_str().unwrap().parse().unwrap(),
+        queued_at: 0,
+        attempts: 1,
+    })
+    .await
+    .unwrap();
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    let thread = json(response).await;
+    assert_eq!(thread["summary"], "Hello");
+    assert_eq!(
+        thread["summary_provenance"]["watermark"]["message_id"],
+        message["id"]
+    );
+}
+
+#[tokio::test]
+async fn retried_summaries_of_messages_the_summary_moved_past_are_folded_in() {
+    let summarizer = Arc::new(FlakySummarizer::default());
+    let executor = Arc::new(DeferredExecutor::new());
+    let synx = Synx::builder()
+        .with_db(Arc::new(SynxInMemory::new()))
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(summarizer.clone())
+        .with_executor(executor.clone())
+        .build()
+        .unwrap();
+    let app = router(synx.clone());
+    let probed = create_thread(&app).await;
+    let thread_id = create_thread(&app).await;
+
+    summarizer.down.store(true, Ordering::SeqCst);
+    create_message(&app, &probed, "Ping").await;
+    executor.run_until_idle().await;
+    tokio::time::sleep(Duration::from_millis(2)).await;
+    create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+    assert_eq!(synx.pending_summaries().await.unwrap().len(), 2);
+
+    // Recovers probing with the other thread's message, then summarizes a
+    // newer one while the failed one is still queued.
+    summarizer.down.store(false, Ordering::SeqCst);
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+    assert!(synx.health().summarizer.healthy);
+    tokio::time::sleep(Duration::from_millis(2)).await;
+    let newer = create_message(&app, &thread_id, "World").await;
+    executor.run_until_idle().await;
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    let thread = json(response).await;
+    assert_eq!(thread["summary"], "World");
+    let watermark = &thread["summary_provenance"]["watermark"];
+    assert_eq!(watermark["message_id"], newer["id"]);
+    assert_eq!(watermark["pending"].as_array().unwrap().len(), 1);
+
+    assert_eq!(synx.retry_pending_summaries().await.unwrap(), 1);
+    executor.run_until_idle().await;
+    assert!(synx.pending_summaries().await.unwrap().is_empty());
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    let thread = json(response).await;
+    assert_eq!(thread["summary"], "World\nHello");
+    let watermark = &thread["summary_provenance"]["watermark"];
+    assert_eq!(watermark["message_id"], newer["id"]);
+    assert_eq!(watermark.get("pending"), None);
+}
+
+#[tokio::test]
+async fn slow_searches_and_database_calls_are_counted() {
+    let app = app_with(|builder| builder.with_slow_query_threshold(Duration::ZERO));
+    let thread_id = create_thread(&app).await;
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "anything", "thread_ids": [thread_id] })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(&app, Method::GET, "/admin/analytics", None).await;
+    let slow_queries = &json(response).await["slow_queries"];
+    assert_eq!(slow_queries["search"], 1);
+    assert_eq!(slow_queries["create_thread"], 1);
+    assert_eq!(slow_queries["get_threads_with_embeddings"], 1);
+}
+
+#[tokio::test]
+async fn pruning_keeps_the_summaries_of_pruned_messages() {
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| builder.with_executor(executor.clone()));
+    let summarized = create_thread(&app).await;
+    create_message(&app, &summarized, "Hello").await;
+    executor.run_until_idle().await;
+    let unsummarized = create_thread(&app).await;
+    create_message(&app, &unsummarized, "World").await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    let response = send(&app, Method::POST, "/admin/prune", Some(json!({}))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/admin/prune",
+        Some(json!({ "older_than_days": 0 })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json(response).await;
+    assert_eq!(report["messages_pruned"], 1);
+    assert!(report["messages"][&summarized].is_array());
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", summarized), None).await;
+    let thread = json(response).await;
+    assert_eq!(thread["summary"], "Hello");
+    assert_eq!(thread["pruned_message_count"], 1);
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages", summarized),
+        None,
+    )
+    .await;
+    assert!(json(response).await.as_array().unwrap().is_empty());
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/threads/{}/messages", unsummarized),
+        None,
+    )
+    .await;
+    assert_eq!(json(response).await.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn schedules_report_their_next_run() {
+    let app = app_with(|builder| {
+        builder
+            .with_schedule(Task::Digest, "@daily".parse().unwrap())
+            .with_schedule(Task::RetrySummaries, "@every 1m".parse().unwrap())
+            .with_schedule(Task::Digest, "0 8 * * 1-5".parse().unwrap())
+    });
+
+    let response = send(&app, Method::GET, "/admin/schedules", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let schedules = json(response).await;
+    assert_eq!(schedules.as_array().unwrap().len(), 2);
+    assert_eq!(schedules[0]["task"], "retry-summaries");
+    assert_eq!(schedules[1]["task"], "digest");
+    assert_eq!(schedules[1]["schedule"], "0 8 * * 1-5");
+    assert!(schedules[1]["next_run_at"].as_u64().is_some());
+    assert!(schedules[1]["last_run_at"].is_null());
+    assert_eq!(schedules[1]["running"], false);
+}
+
+#[tokio::test]
+async fn stored_embeddings_are_reduced_once_configured() {
+    let db = Arc::new(SynxInMemory::new());
+    let full = app_with(|builder| builder.with_db(db.clone()));
+    let mut threads = Vec::new();
+    for summary in [
+        "The user asked about the weather forecast in Paris.",
+        "The user asked for a pancake recipe.",
+        "The user asked about the rain in London.",
+    ] {
+        let thread_id = create_thread(&full).await;
+        let response = send(
+            &full,
+            Method::PUT,
+            &format!("/threads/{}/summary", thread_id),
+            Some(json!({ "summary": summary })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        threads.push(thread_id);
+    }
+
+    let response = send(&full, Method::POST, "/admin/embeddings/reduce", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(
+        &full,
+        Method::GET,
+        "/admin/embeddings/calibration?dimensions=64",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let calibration = json(response).await;
+    assert_eq!(calibration["stored_dimensions"], 256);
+    assert_eq!(calibration["sampled"], 3);
+    let recall = calibration["recall"].as_f64().unwrap();
+    assert!((0.0..=1.0).contains(&recall));
+
+    let reduced = app_with(|builder| builder.with_db(db.clone()).with_embedding_reduction(64));
+    // Stored embeddings are truncated as they are searched until reduced.
+    let search = || {
+        send(
+            &reduced,
+            Method::POST,
+            "/search",
+            Some(json!({ "query": "weather in Paris", "thread_ids": threads })),
+        )
+    };
+    let before = json(search().await).await;
+    assert_eq!(before[0]["stored"]["id"], threads[0].as_str());
+
+    let tenant = as_tenant(reduced.clone(), "acme");
+    let response = send(&tenant, Method::POST, "/admin/embeddings/reduce", None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(
+        &tenant,
+        Method::GET,
+        "/admin/embeddings/calibration?dimensions=64",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = send(&reduced, Method::POST, "/admin/embeddings/reduce", None).await;
+    assert_eq!(json(response).await["reduced"], 3);
+    let response = send(&reduced, Method::POST, "/admin/embeddings/reduce", None).await;
+    assert_eq!(json(response).await["reduced"], 0);
+
+    assert_eq!(json(search().await).await, before);
+}
+
+#[tokio::test]
+async fn stored_embeddings_are_normalized_once_configured() {
+    let db = Arc::new(SynxInMemory::new());
+    let plain = app_with(|builder| builder.with_db(db.clone()));
+    let thread_id = create_thread(&plain).await;
+    let response = send(
+        &plain,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked for a pancake recipe." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // As stored by an embedder whose embeddings aren't unit length.
+    let id = thread_id.parse().unwrap();
+    let mut thread = db
+        .get_threads_with_embeddings(&[id])
+        .await
+        .unwrap()
+        .remove(0);
+    let embedding = thread.embedding.take().unwrap().to_vec();
+    thread.embedding = Some(Embedding::from(
+        embedding.iter().map(|x| x * 3.0).collect::<Vec<_>>(),
+    ));
+    db.put_thread(thread).await.unwrap();
+
+    let response = send(&plain, Method::POST, "/admin/embeddings/normalize", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let normalized = app_with(|builder| {
+        builder
+            .with_db(db.clone())
+            .with_embedding_normalization(true)
+    });
+    let score = |app: Router| async move {
+        let response = send(
+            &app,
+            Method::POST,
+            "/search",
+            Some(json!({ "query": "pancake recipe", "thread_ids": [thread_id] })),
+        )
+        .await;
+        json(response).await[0]["score"].as_f64().unwrap()
+    };
+    let cosine = score(plain.clone()).await;
+    assert!(cosine > 0.0);
+    // Taken for unit length, the stored embedding scores off until rewritten.
+    assert!((score(normalized.clone()).await - cosine * 3.0).abs() < 1e-4);
+
+    let response = send(
+        &as_tenant(normalized.clone(), "acme"),
+        Method::POST,
+        "/admin/embeddings/normalize",
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send(
+        &normalized,
+        Method::POST,
+        "/admin/embeddings/normalize",
+        None,
+    )
+    .await;
+    assert_eq!(json(response).await["normalized"], 1);
+    let response = send(
+        &normalized,
+        Method::POST,
+        "/admin/embeddings/normalize",
+        None,
+    )
+    .await;
+    assert_eq!(json(response).await["normalized"], 0);
+
+    assert!((score(normalized).await - cosine).abs() < 1e-4);
+}
+
+#[tokio::test]
+async fn threads_are_found_by_their_closest_segment() {
+    let db = Arc::new(SynxInMemory::new());
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| {
+        builder
+            .with_db(db.clone())
+            .with_executor(executor.clone())
+            .with_segment_embeddings(2)
+    });
+    let thread_id = create_thread(&app).await;
+    for text in [
+        "pancake recipe",
+        "the weather in paris",
+        "train tickets to rome",
+    ] {
+        create_message(&app, &thread_id, text).await;
+    }
+    executor.run_until_idle().await;
+
+    let threads = db
+        .get_threads_with_embeddings(&[thread_id.parse().unwrap()])
+        .await
+        .unwrap();
+    assert_eq!(threads[0].vectors.len(), 2);
+
+    // The summary holds every message, the last segment the third alone.
+    let response = send(
+        &app,
+        Method::POST,
+        "/search",
+        Some(json!({ "query": "train tickets to rome", "thread_ids": [thread_id] })),
+    )
+    .await;
+    let score = json(response).await[0]["score"].as_f64().unwrap();
+    assert!((score - 1.0).abs() < 1e-4);
+}
+
+#[tokio::test]
+async fn threads_are_streamed_as_ndjson() {
+    let app = app();
+    for _ in 0..3 {
+        create_thread(&app).await;
+    }
+
+    let request = Request::builder()
+        .uri("/threads")
+        .header(header::ACCEPT, "application/x-ndjson")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "content-type"), "application/x-ndjson");
+
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let lines = std::str::from_utf8(&bytes)
+        .unwrap()
+        .lines()
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    let listing = serde_json::from_str::<Value>(lines[0]).unwrap();
+    assert_eq!(listing["unread_count"], 0);
+}
+
+#[tokio::test]
+async fn export_streams_threads_and_messages() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    let message = create_message(&app, &thread_id, "Remember the milk").await;
+
+    let response = send(&app, Method::GET, "/admin/export", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "content-type"), "application/x-ndjson");
+
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let records = std::str::from_utf8(&bytes)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["type"], "thread");
+    assert_eq!(records[0]["id"], thread_id.as_str());
+    assert_eq!(records[1]["type"], "message");
+    assert_eq!(records[1]["id"], message["id"]);
+}
+
+#[tokio::test]
+async fn responses_are_compressed_as_negotiated() {
+    let app = app().layer(
+        CompressionOptions {
+            min_size: 0,
+            ..Default::default()
+        }
+        .layer(),
+    );
+    create_thread(&app).await;
+    let list = |accept_encoding: Option<&str>| {
+        let mut request = Request::builder().uri("/threads");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = list(Some("gzip")).await.unwrap();
+    assert_eq!(header(&response, "content-encoding"), "gzip");
+
+    let response = list(Some("gzip;q=0.5, br")).await.unwrap();
+    assert_eq!(header(&response, "content-encoding"), "br");
+
+    let response = list(None).await.unwrap();
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(json(response).await.as_array().unwrap().len(), 1);
+}
+
+async fn get_if_none_match(app: &Router, uri: &str, etag: &str) -> Response {
+    let request = Request::builder()
+        .uri(uri)
+        .header(header::IF_NONE_MATCH, etag)
+        .body(Body::empty())
+        .unwrap();
+
+    app.clone().oneshot(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn unchanged_reads_are_not_modified() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    let thread_uri = format!("/threads/{}", thread_id);
+    let messages_uri = format!("/threads/{}/messages", thread_id);
+
+    let response = send(&app, Method::GET, &thread_uri, None).await;
+    let etag = header(&response, "etag").to_string();
+    assert!(etag.starts_with("W/\""));
+
+    let response = get_if_none_match(&app, &thread_uri, &etag).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(header(&response, "etag"), etag);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.is_empty());
+
+    let response = send(
+        &app,
+        Method::PATCH,
+        &thread_uri,
+        Some(json!({ "pinned": true })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = get_if_none_match(&app, &thread_uri, &etag).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(header(&response, "etag"), etag);
+
+    let response = send(&app, Method::GET, &messages_uri, None).await;
+    let etag = header(&response, "etag").to_string();
+    let response = get_if_none_match(&app, &messages_uri, &etag).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    create_message(&app, &thread_id, "Something changed").await;
+    let response = get_if_none_match(&app, &messages_uri, &etag).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(header(&response, "etag"), etag);
+}
+
+#[tokio::test]
+async fn redactions_are_recorded_in_the_audit_log() {
+    let app = app_with(|builder| {
+        builder.with_redactor(
+            Redactor::builder()
+                .with_stage(RedactionStage::Storage)
+                .build()
+                .unwrap(),
+        )
+    });
+    let thread_id = create_thread(&app).await;
+    create_message(
+        &app,
+        &thread_id,
+        "Write to jane@example.com on 2024-10-15 10:30",
+    )
+    .await;
+
+    let response = send(
+        &app,
+        Method::GET,
+        &format!("/admin/audit?entity_id={}", thread_id),
+        None,
+    )
+    .await;
+    let entries = json(response).await;
+    let redactions = entries
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry["method"] == "REDACT")
+        .collect::<Vec<_>>();
+    assert_eq!(redactions.len(), 1);
+    assert_eq!(redactions[0]["route"], "storage/email/mask");
+}
+
+#[tokio::test]
+async fn streamed_messages_are_redacted_whole_once_done() {
+    let app = app_with(|builder| {
+        builder.with_redactor(
+            Redactor::builder()
+                .with_stage(RedactionStage::Storage)
+                .build()
+                .unwrap(),
+        )
+    });
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/messages", thread_id),
+        Some(json!({ "role": "assistant", "content": "Write to jane@exa", "status": "streaming" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let append_uri = format!(
+        "/threads/{}/messages/{}/append",
+        thread_id,
+        json(response).await["id"].as_str().unwrap()
+    );
+
+    let response = send(
+        &app,
+        Method::POST,
+        &append_uri,
+        Some(json!({ "text": "mple.com", "done": true })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let content = json(response).await["content"].to_string();
+    assert!(content.contains("Write to [EMAIL]"), "{}", content);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &append_uri,
+        Some(json!({ "text": "!" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn thread_tokens_only_reach_their_threads() {
+    let app = app().route_layer(middleware::from_fn_with_state(
+        Arc::new(ThreadTokens::new(b"secret")),
+        thread_token_middleware,
+    ));
+    let thread_id = create_thread(&app).await;
+    let other_thread_id = create_thread(&app).await;
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/tokens", thread_id),
+        Some(json!({ "ttl_secs": 600 })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let token = json(response).await["token"].as_str().unwrap().to_string();
+    let send_with_token = |method: Method, uri: String| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = send_with_token(Method::GET, format!("/threads/{}", thread_id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_with_token(Method::GET, format!("/threads/{}", other_thread_id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send_with_token(Method::GET, "/threads".to_string())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = send_with_token(Method::POST, format!("/threads/{}/tokens", thread_id))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/tokens", thread_id),
+        Some(json!({ "ttl_secs": 7 * 24 * 60 * 60 })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn admin_routes_are_forbidden_to_tenants() {
+    let app = app();
+    let tenant_app = as_tenant(app.clone(), "acme");
+
+    for uri in [
+        "/debug/database",
+        "/admin/audit",
+        "/admin/analytics",
+        "/admin/stats",
+        "/admin/schedules",
+        "/admin/export",
+        "/changes",
+    ] {
+        let response = send(&tenant_app, Method::GET, uri, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+        let response = send(&app, Method::GET, uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn shadow_summaries_are_hidden_from_other_tenants() {
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| {
+        builder
+            .with_executor(executor.clone())
+            .with_shadow_summarizer(ShadowSummarizer::new(
+                Arc::new(FakeSummarizer),
+                ShadowMode::Store,
+            ))
+    });
+    let thread_id = create_thread(&app).await;
+    create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+
+    let uri = format!("/admin/threads/{}/shadow-summary", thread_id);
+    let response = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&as_tenant(app, "acme"), Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn blobs_are_only_served_to_their_tenant() {
+    let app = app();
+    let acme = as_tenant(app.clone(), "acme");
+    let globex = as_tenant(app.clone(), "globex");
+    // Larger than axum's default body limit.
+    let data = vec![7u8; 3 * 1024 * 1024];
+    let upload = |app: &Router| {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/blobs")
+            .header(header::CONTENT_TYPE, "image/png")
+            .body(Body::from(data.clone()))
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let response = upload(&acme).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let blob = json(response).await;
+    assert_eq!(blob["size"], data.len());
+    let uri = format!("/blobs/{}", blob["hash"].as_str().unwrap());
+
+    let response = send(&acme, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.len(), data.len());
+    let response = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send(&globex, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // The same data is stored apart for another tenant.
+    let response = upload(&globex).await.unwrap();
+    assert_ne!(json(response).await["hash"], blob["hash"]);
+}
+
+#[tokio::test]
+async fn blobs_are_only_images_served_as_attachments() {
+    let app = app();
+    let upload = |mime_type: &str| {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/blobs")
+            .header(header::CONTENT_TYPE, mime_type)
+            .body(Body::from("<script>alert(1)</script>"))
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    for mime_type in ["text/html", "image/svg+xml"] {
+        let response = upload(mime_type).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    let response = upload("image/png").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let uri = format!("/blobs/{}", json(response).await["hash"].as_str().unwrap());
+    let response = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "content-type"), "image/png");
+    assert_eq!(header(&response, "x-content-type-options"), "nosniff");
+    assert_eq!(header(&response, "content-disposition"), "attachment");
+    assert!(header(&response, "cache-control").starts_with("private"));
+}
+
+#[tokio::test]
+async fn purged_content_is_gone_from_the_op_log_and_blobs() {
+    let executor = Arc::new(DeferredExecutor::new());
+    let app = app_with(|builder| {
+        builder
+            .with_executor(executor.clone())
+            .with_replica_id(uuid::Uuid::new_v4())
+    });
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/messages", thread_id),
+        Some(json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "my number is 555-0100" },
+                { "type": "image", "image": "data:image/png;base64,AQID" },
+            ],
+        })),
+    )
+    .await;
+    let purged = json(response).await;
+    let image = purged["content"][1]["image"].as_str().unwrap();
+    let blob_uri = format!("/blobs/{}", image.strip_prefix("blob:").unwrap());
+    create_message(&app, &thread_id, "Hello").await;
+    executor.run_until_idle().await;
+
+    let response = send(&app, Method::GET, "/changes", None).await;
+    let changes = json(response).await.to_string();
+    assert!(changes.contains("555-0100"));
+    let response = send(&app, Method::GET, &blob_uri, None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/admin/purge",
+        Some(json!({ "pattern": "555-\\d{4}" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = json(response).await;
+    assert_eq!(report["messages_deleted"], 1);
+    assert_eq!(report["blobs_deleted"], 1);
+
+    let response = send(&app, Method::GET, "/changes", None).await;
+    let changes = json(response).await;
+    assert!(!changes.to_string().contains("555-0100"));
+    assert!(changes
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|operation| operation["type"] == "purge"
+            && operation["message_ids"] == json!([purged["id"]])));
+    let response = send(&app, Method::GET, &blob_uri, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = send(&app, Method::GET, &format!("/threads/{}", thread_id), None).await;
+    assert_eq!(json(response).await["summary"], "Hello");
+}
+
+#[tokio::test]
+async fn errors_have_their_status_codes() {
+    let app = app();
+    let thread_id = create_thread(&app).await;
+    let missing = uuid::Uuid::new_v4();
+
+    let response = send(&app, Method::GET, &format!("/threads/{}", missing), None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = send(&app, Method::GET, "/threads/not-a-uuid", None).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send(
+        &app,
+        Method::POST,
+        &format!("/threads/{}/messages", thread_id),
+        Some(json!({ "role": "", "content": "Hello" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "" })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send(&app, Method::GET, "/nowhere", None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn replayed_changes_to_deleted_threads_are_skipped() {
+    // Summaries are left pending, so only the thread and the message are
+    // logged.
+    let app = app_with(|builder| {
+        builder
+            .with_executor(Arc::new(DeferredExecutor::new()))
+            .with_replica_id(uuid::Uuid::new_v4())
+    });
+    let thread_id = create_thread(&app).await;
+    create_message(&app, &thread_id, "Hello").await;
+    let response = send(&app, Method::GET, "/changes", None).await;
+    let puts = json(response).await;
+    assert_eq!(puts.as_array().unwrap().len(), 2);
+
+    let uri = format!("/threads/{}", thread_id);
+    let response = send(&app, Method::DELETE, &uri, None).await;
+    assert!(response.status().is_success());
+
+    // As if pulled again from a peer which hadn't seen the deletion.
+    let response = send(&app, Method::POST, "/sync/operations", Some(puts)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json(response).await, json!({ "applied": 0, "skipped": 2 }));
+    let response = send(&app, Method::GET, &uri, None).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sync_resumes_from_its_saved_cursor() {
+    let remote = app_with(|builder| builder.with_replica_id(uuid::Uuid::new_v4()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::serve(listener, remote.clone()).into_future());
+
+    let synx = Synx::builder()
+        .with_db(Arc::new(SynxInMemory::new()))
+        .with_document_embedder(Arc::new(FakeEmbedder::new()))
+        .with_query_embedder(Arc::new(FakeEmbedder::new()))
+        .with_summarizer(Arc::new(FakeSummarizer))
+        .with_replica_id(uuid::Uuid::new_v4())
+        .build()
+        .unwrap();
+    let local = router(synx.clone());
+    let thread_id = create_thread(&local).await;
+    create_thread(&remote).await;
+
+    RemoteSync::new(synx.clone(), &server, None)
+        .unwrap()
+        .sync()
+        .await
+        .unwrap();
+    let cursor = synx.sync_cursor(&server).await.unwrap();
+    assert_eq!(cursor.pushed_seq, 1);
+    assert_eq!(cursor.pulled_seq, 1);
+    let response = send(
+        &remote,
+        Method::GET,
+        &format!("/threads/{}", thread_id),
+        None,
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Restarted, it only exchanges what changed since.
+    create_thread(&remote).await;
+    RemoteSync::new(synx.clone(), &server, None)
+        .unwrap()
+        .sync()
+        .await
+        .unwrap();
+    // Its own thread, applied remotely at 2, isn't pulled back.
+    let cursor = synx.sync_cursor(&server).await.unwrap();
+    assert_eq!(cursor.pushed_seq, 1);
+    assert_eq!(cursor.pulled_seq, 3);
+    let response = send(&local, Method::GET, "/threads", None).await;
+    assert_eq!(json(response).await.as_array().unwrap().len(), 3);
+    let response = send(&remote, Method::GET, "/changes", None).await;
+    assert_eq!(json(response).await.as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn client_commands_talk_to_a_running_server() {
+    let app = app();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(axum::serve(listener, app.clone()).into_future());
+    let client = Client::remote(server, None);
+
+    let thread_id = create_thread(&app).await;
+    let response = send(
+        &app,
+        Method::PUT,
+        &format!("/threads/{}/summary", thread_id),
+        Some(json!({ "summary": "The user asked for a pancake recipe." })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let thread_id = thread_id.parse().unwrap();
+
+    let message = CreateMessage {
+        role: Role::from("user"),
+        content: "How many eggs?".to_string().into(),
+        parent_message_id: None,
+        metadata: Default::default(),
+        status: Default::default(),
+        memorize: false,
+    };
+    let message = client.add_message(thread_id, message).await.unwrap();
+    assert_eq!(message.thread_id, thread_id);
+
+    let threads = client.list_threads().await.unwrap();
+    assert_eq!(threads.len(), 1);
+    let details = client.show_thread(thread_id).await.unwrap();
+    assert_eq!(details.messages.len(), 1);
+    assert_eq!(details.messages[0].id, message.id);
+
+    let hits = client.search("pancakes", 10).await.unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].thread_id, thread_id.to_string());
+
+    client.delete_thread(thread_id).await.unwrap();
+    assert!(client.list_threads().await.unwrap().is_empty());
+}